@@ -0,0 +1,7 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use polodb_bson::Document;
+
+fuzz_target!(|data: &[u8]| {
+    let _ = Document::from_bytes(data);
+});
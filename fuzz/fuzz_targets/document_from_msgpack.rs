@@ -0,0 +1,8 @@
+#![no_main]
+use libfuzzer_sys::fuzz_target;
+use polodb_bson::Document;
+
+fuzz_target!(|data: &[u8]| {
+    let mut reader = data;
+    let _ = Document::from_msgpack(&mut reader);
+});
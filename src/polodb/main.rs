@@ -3,14 +3,14 @@ mod ipc;
 
 use crate::dumper::dump;
 use crate::ipc::{IPC, Connection};
-use polodb_core::Database;
+use polodb_core::{Database, SharedDatabase, Session};
+use polodb_core::db::CheckpointMode;
 use polodb_core::msg_ty::MsgTy;
+use polodb_core::restore::RestoreTarget;
 use clap::{Arg, App};
 use std::process::exit;
 use std::io::{Read, Write};
-use std::sync::Arc;
 use std::thread;
-use std::sync::Mutex;
 use std::sync::atomic::{AtomicI32, Ordering};
 use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
 use error_chain::error_chain;
@@ -19,6 +19,7 @@ use signal_hook::{iterator::Signals};
 #[cfg(unix)]
 use signal_hook::consts::TERM_SIGNALS;
 use std::path::{Path, PathBuf};
+use std::time::{Duration, UNIX_EPOCH};
 
 static CONN_COUNT: AtomicI32 = AtomicI32::new(0);
 
@@ -45,24 +46,23 @@ error_chain! {
 const HEAD: [u8; 4] = [0xFF, 0x00, 0xAA, 0xBB];
 const PING_HEAD: [u8; 4] = [0xFF, 0x00, 0xAA, 0xCC];
 
+// the queries of the connections run in parallel
 #[derive(Clone)]
 struct AppContext {
     socket_path: String,
-    db: Arc<Mutex<Option<Database>>>,
+    db: SharedDatabase,
 }
 
 impl AppContext {
 
-    fn new(socket_path: String, db: Database) -> AppContext {
+    fn new(socket_path: String, db: SharedDatabase) -> AppContext {
         AppContext {
             socket_path,
-            db: Arc::new(Mutex::new(Some(db))),
+            db,
         }
     }
 
-    fn handle_incoming_connection(&self, conn: &mut Connection) -> Result<bool> {
-        let mut db_guard = self.db.lock().unwrap();
-        let db = db_guard.as_mut().unwrap();
+    fn handle_incoming_connection(&self, session: &mut Session, conn: &mut Connection) -> Result<bool> {
         let mut header_buffer = [0u8; 4];
 
         conn.read_exact(&mut header_buffer)?;
@@ -84,7 +84,7 @@ impl AppContext {
 
         let mut ret_buffer = Vec::new();
 
-        let msg_ty_result = db.handle_request(conn, &mut ret_buffer);
+        let msg_ty_result = session.handle_request(conn, &mut ret_buffer);
         if let Err(err) = msg_ty_result {
             eprintln!("io error, exit: {}", err);
             return Ok(false);
@@ -109,7 +109,7 @@ impl AppContext {
 fn start_socket_server(path: Option<&str>, socket_addr: &str) {
     let db = match path {
         Some(path) => {
-            match Database::open_file(path) {
+            match SharedDatabase::open_file(path) {
                 Ok(db) => db,
                 Err(err) => {
                     eprintln!("open db {} failed: {}", path, err);
@@ -118,7 +118,7 @@ fn start_socket_server(path: Option<&str>, socket_addr: &str) {
             }
         },
         None => {
-            match Database::open_memory() {
+            match SharedDatabase::open_memory() {
                 Ok(db) => db,
                 Err(err) => {
                     eprintln!("open memory db failed: {}", err);
@@ -155,22 +155,23 @@ fn start_app_async(app: AppContext, socket_addr: &str) {
             let app = app.clone();
             thread::spawn(move || {
                 let mut moved_stream = stream;
-                loop {
-                    let result = app.handle_incoming_connection(&mut moved_stream);
+                let mut session = app.db.session();
+                let should_quit = loop {
+                    let result = app.handle_incoming_connection(&mut session, &mut moved_stream);
                     match result {
                         Ok(true) => {
                             continue;
                         },
 
                         Ok(false) => {
-                            safely_quit(app.clone());
+                            break true;
                         },
 
                         Err(err) => {
                             match err.0 {
                                 ErrorKind::Io(_) => {
                                     eprintln!("io error: {}", err);
-                                    break;
+                                    break false;
                                 }
                                 _ => {
                                     eprintln!("other error, continue: {}", err);
@@ -178,6 +179,11 @@ fn start_app_async(app: AppContext, socket_addr: &str) {
                             }
                         }
                     }
+                };
+                // the writer held by the session is released before quitting
+                drop(session);
+                if should_quit {
+                    safely_quit(app.clone());
                 }
                 if CONN_COUNT.fetch_sub(1, Ordering::SeqCst) <= 1 {
                     eprintln!("no connection, quit");
@@ -191,9 +197,11 @@ fn start_app_async(app: AppContext, socket_addr: &str) {
     _t.join().unwrap();
 }
 
+// the writes in progress are finished before the checkpoint
 fn safely_quit(app: AppContext) {
-    let mut db_guard = app.db.lock().unwrap();
-    *db_guard = None;
+    if let Err(err) = app.db.checkpoint(CheckpointMode::SkipIfBusy) {
+        eprintln!("checkpoint failed: {}", err);
+    }
     let _ = std::fs::remove_file(&app.socket_path);
     eprintln!("safely exit");
     exit(0);
@@ -260,6 +268,60 @@ fn main() {
                     .required(true)
             )
         )
+        .subcommand(App::new("check")
+            .about("check the integrity of the database")
+            .arg(
+                Arg::with_name("path")
+                    .index(1)
+                    .required(true)
+            )
+        )
+        .subcommand(App::new("vacuum")
+            .about("rebuild the database compactly, and shrink the file")
+            .arg(
+                Arg::with_name("path")
+                    .index(1)
+                    .required(true)
+            )
+        )
+        .subcommand(App::new("salvage")
+            .about("recover the documents of a damaged database into a new one")
+            .arg(
+                Arg::with_name("src")
+                    .index(1)
+                    .required(true)
+            )
+            .arg(
+                Arg::with_name("dst")
+                    .index(2)
+                    .required(true)
+            )
+        )
+        .subcommand(App::new("restore")
+            .about("restore a backup to a later transaction with the archived journal")
+            .arg(
+                Arg::with_name("base")
+                    .index(1)
+                    .required(true)
+            )
+            .arg(
+                Arg::with_name("archive")
+                    .index(2)
+                    .required(true)
+            )
+            .arg(
+                Arg::with_name("dst")
+                    .index(3)
+                    .required(true)
+            )
+            .arg(
+                Arg::with_name("until")
+                    .long("until")
+                    .value_name("@TIMESTAMP|FRAME")
+                    .help("the seconds since the unix epoch after '@', or the count of the frames")
+                    .takes_value(true)
+            )
+        )
         .arg(
             Arg::with_name("log")
                 .help("print log")
@@ -304,5 +366,122 @@ fn main() {
         return;
     }
 
+    if let Some(sub) = matches.subcommand_matches("check") {
+        let path = sub.value_of("path").expect("no input path");
+        check(path);
+        return;
+    }
+
+    if let Some(sub) = matches.subcommand_matches("vacuum") {
+        let path = sub.value_of("path").expect("no input path");
+        vacuum(path);
+        return;
+    }
+
+    if let Some(sub) = matches.subcommand_matches("salvage") {
+        let src = sub.value_of("src").expect("no input path");
+        let dst = sub.value_of("dst").expect("no output path");
+        salvage(src, dst);
+        return;
+    }
+
+    if let Some(sub) = matches.subcommand_matches("restore") {
+        let base = sub.value_of("base").expect("no base path");
+        let archive = sub.value_of("archive").expect("no archive path");
+        let dst = sub.value_of("dst").expect("no output path");
+        let until = match sub.value_of("until").map(parse_restore_target) {
+            Some(Some(until)) => until,
+            Some(None) => {
+                println!("invalid target, expect @TIMESTAMP or FRAME");
+                exit(7);
+            }
+            None => RestoreTarget::Latest,
+        };
+        restore(base, archive, dst, until);
+        return;
+    }
+
     println!("{}", matches.usage());
 }
+
+fn parse_restore_target(value: &str) -> Option<RestoreTarget> {
+    match value.strip_prefix('@') {
+        Some(seconds) => {
+            let seconds = seconds.parse::<u64>().ok()?;
+            Some(RestoreTarget::Time(UNIX_EPOCH + Duration::from_secs(seconds)))
+        }
+        None => value.parse::<u64>().ok().map(RestoreTarget::Frame),
+    }
+}
+
+fn restore(base: &str, archive: &str, dst: &str, until: RestoreTarget) {
+    if !Path::exists(base.as_ref()) {
+        println!("database not exist: {}", base);
+        exit(2);
+    }
+    match Database::restore(base, archive, dst, until) {
+        Ok(report) => {
+            println!("{}", report);
+            println!("{} transactions restored to {}", report.transactions, dst);
+        }
+        Err(err) => {
+            println!("{}", err);
+            exit(8);
+        }
+    }
+}
+
+fn vacuum(path: &str) {
+    if !Path::exists(path.as_ref()) {
+        println!("database not exist: {}", path);
+        exit(2);
+    }
+    let result = Database::open_file(path).and_then(|mut db| db.vacuum());
+    match result {
+        Ok(report) => {
+            println!("{} bytes reclaimed, {} -> {} bytes",
+                     report.reclaimed_bytes(), report.size_before, report.size_after);
+        }
+        Err(err) => {
+            println!("{}", err);
+            exit(8);
+        }
+    }
+}
+
+fn salvage(src: &str, dst: &str) {
+    if !Path::exists(src.as_ref()) {
+        println!("database not exist: {}", src);
+        exit(2);
+    }
+    match Database::salvage(src, dst) {
+        Ok(report) => {
+            println!("{}", report);
+            println!("{} documents recovered to {}", report.recovered_documents(), dst);
+        }
+        Err(err) => {
+            println!("{}", err);
+            exit(8);
+        }
+    }
+}
+
+fn check(path: &str) {
+    if !Path::exists(path.as_ref()) {
+        println!("database not exist: {}", path);
+        exit(2);
+    }
+    let result = Database::open_file(path).and_then(|mut db| db.check_integrity());
+    match result {
+        Ok(report) => {
+            println!("{}", report);
+            if !report.is_ok() {
+                exit(9);
+            }
+        }
+        Err(err) => {
+            println!("{}", err);
+            exit(8);
+        }
+    }
+}
@@ -380,6 +380,7 @@ impl Document {
 #[cfg(test)]
 mod tests {
     use crate::document::Document;
+    use crate::{ObjectIdMaker, Value};
     use std::fs::OpenOptions;
     use std::io::Read;
     use std::path::PathBuf;
@@ -442,6 +443,41 @@ mod tests {
         assert_eq!(buf.len(), 228);
     }
 
+    #[test]
+    fn test_msgpack_object_id() {
+        let mut maker = ObjectIdMaker::new();
+        let oid = maker.mk_object_id();
+        let doc = doc! {
+            "_id": oid.clone(),
+            "name": "PoloDB",
+        };
+
+        let mut buf = Vec::new();
+        doc.to_msgpack(&mut buf).expect("serial error");
+
+        let parsed_doc = Document::from_msgpack(&mut buf.as_slice()).expect("deserialize error");
+        match parsed_doc.get("_id").unwrap() {
+            Value::ObjectId(parsed_oid) => assert_eq!(parsed_oid.as_ref(), &oid),
+            _ => panic!("_id should be an ObjectId"),
+        }
+        assert_eq!(parsed_doc.get("name").unwrap().unwrap_string(), "PoloDB");
+    }
+
+    #[test]
+    fn test_msgpack_double() {
+        let doc = doc! {
+            "exact": 0.5,
+            "inexact": 0.1,
+        };
+
+        let mut buf = Vec::new();
+        doc.to_msgpack(&mut buf).expect("serial error");
+
+        let parsed_doc = Document::from_msgpack(&mut buf.as_slice()).expect("deserialize error");
+        assert_eq!(parsed_doc.get("exact").unwrap().unwrap_double(), 0.5);
+        assert_eq!(parsed_doc.get("inexact").unwrap().unwrap_double(), 0.1);
+    }
+
 }
 
 impl fmt::Display for Document {
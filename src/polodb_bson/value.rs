@@ -45,6 +45,7 @@ impl Value {
     pub fn value_cmp(&self, other: &Value) -> BsonResult<Ordering> {
         match (self, other) {
             (Value::Null, Value::Null) => Ok(Ordering::Equal),
+            (Value::Boolean(b1), Value::Boolean(b2)) => Ok(b1.cmp(b2)),
             (Value::Int(i1), Value::Int(i2)) => Ok(i1.cmp(i2)),
             (Value::String(str1), Value::String(str2)) => Ok(str1.cmp(str2)),
             (Value::ObjectId(oid1), Value::ObjectId(oid2)) => Ok(oid1.cmp(oid2)),
@@ -87,7 +88,8 @@ impl Value {
         match self {
             Value::Null => rmp::encode::write_nil(buf)?,
             Value::Double(fv) => {
-                if *fv <= (f32::MAX as f64) && *fv >= (f32::MIN as f64) {
+                // only the doubles kept exactly by f32 are written in f32
+                if ((*fv as f32) as f64) == *fv {
                     rmp::encode::write_f32(buf, *fv as f32)?;
                 } else {
                     rmp::encode::write_f64(buf, *fv)?;
@@ -304,9 +306,10 @@ impl Value {
             Marker::FixExt16 => {
                 let ty = bytes.read_i8()?;
                 if ty == ty_int::OBJECT_ID as i8 {
-                    let mut buf = [0; 12];
-                    bytes.read(&mut buf)?;
-                    let oid = ObjectId::deserialize(&buf)?;
+                    // 12 bytes of the id, padded with 4 zeros
+                    let mut buf = [0; 16];
+                    bytes.read_exact(&mut buf)?;
+                    let oid = ObjectId::deserialize(&buf[0..12])?;
                     Ok(Value::ObjectId(Rc::new(oid)))
                 } else {
                     Err(BsonErr::ParseError(format!("unexpected ext 16: {}", ty)))
@@ -580,7 +580,7 @@ fn error_code_of_db_err(err: &DbErr) -> i32 {
         DbErr::DataExist(_) => 16,
         DbErr::PageSpaceNotEnough => 17,
         DbErr::DataHasNoPrimaryKey => 18,
-        DbErr::ChecksumMismatch => 19,
+        DbErr::JournalChecksumMismatch => 19,
         DbErr::JournalPageSizeMismatch(_, _) => 20,
         DbErr::SaltMismatch => 21,
         DbErr::PageMagicMismatch(_) => 22,
@@ -612,5 +612,15 @@ fn error_code_of_db_err(err: &DbErr) -> i32 {
         DbErr::Multiple(_) => 48,
         DbErr::VersionMismatch(_) => 49,
         DbErr::EnumError(_) => 50,
+        DbErr::SavepointNotFound(_) => 51,
+        DbErr::NotSupported(_) => 52,
+        DbErr::DatabaseClosed => 53,
+        DbErr::DecompressionFailed(_) => 54,
+        DbErr::DecryptionFailed(_) => 55,
+        DbErr::ChecksumMismatch(_) => 56,
+        DbErr::InvalidPageSize(_) => 57,
+        DbErr::ReadOnly => 58,
+        DbErr::InvalidJournalFrames(_) => 59,
+        DbErr::InvalidRestoreTarget(_) => 60,
     }
 }
@@ -0,0 +1,567 @@
+use std::collections::VecDeque;
+use std::path::PathBuf;
+use std::pin::Pin;
+use std::sync::{Arc, mpsc};
+use std::task::{Context, Poll, Wake, Waker};
+use std::thread::{self, Thread};
+use futures_channel::{oneshot, mpsc as async_mpsc};
+use futures_core::Stream;
+use polodb_bson::Document;
+use crate::db::{Database, DbResult};
+use crate::{Config, DbErr, TransactionType};
+
+type Job = Box<dyn FnOnce(&mut Database) + Send>;
+
+// the count of the found documents buffered in a DocumentStream
+const FIND_BUFFER_SIZE: usize = 64;
+
+// the found documents are sent one by one by the thread of a stream,
+// or all at once by the worker
+type FoundBatch = Vec<DbResult<Vec<u8>>>;
+
+type FoundSender = async_mpsc::Sender<FoundBatch>;
+
+// the documents are not Send,
+// they are passed between the threads in msgpack
+fn encode_doc(doc: &Document) -> DbResult<Vec<u8>> {
+    let mut buffer = Vec::new();
+    doc.to_msgpack(&mut buffer)?;
+    Ok(buffer)
+}
+
+fn decode_doc(bytes: &[u8]) -> DbResult<Document> {
+    let mut reader = bytes;
+    let doc = Document::from_msgpack(&mut reader)?;
+    Ok(doc)
+}
+
+struct ThreadWaker(Thread);
+
+impl Wake for ThreadWaker {
+
+    fn wake(self: Arc<Self>) {
+        self.0.unpark();
+    }
+
+}
+
+// block the thread of the stream until it has room for the item,
+// return false if the stream is dropped
+fn send_blocking<T>(sender: &mut async_mpsc::Sender<T>, item: T) -> bool {
+    let waker = Waker::from(Arc::new(ThreadWaker(thread::current())));
+    let mut cx = Context::from_waker(&waker);
+    loop {
+        match sender.poll_ready(&mut cx) {
+            Poll::Ready(Ok(())) => return sender.start_send(item).is_ok(),
+            Poll::Ready(Err(_)) => return false,
+            Poll::Pending => thread::park(),
+        }
+    }
+}
+
+// start a read transaction on a new connection for a find stream,
+// None if the documents are read by the worker
+fn open_find_reader(db: &mut Database, name: &str) -> DbResult<Option<Database>> {
+    // the collection is created by the worker, the same as the other operations
+    db.collection(name)?;
+
+    // the uncommitted writes are not seen by the other connections
+    if db.is_in_transaction() {
+        return Ok(None);
+    }
+
+    let mut reader = match db.new_connection() {
+        Ok(reader) => reader,
+        Err(_) => return Ok(None),
+    };
+    reader.start_transaction(Some(TransactionType::Read))?;
+
+    Ok(Some(reader))
+}
+
+fn spawn_find_thread(mut reader: Database, name: String, query: Option<Vec<u8>>, sender: FoundSender) {
+    let mut thread_sender = sender.clone();
+    let result = thread::Builder::new()
+        .name("polodb-find".into())
+        .spawn(move || {
+            let result = (|| {
+                let query = query.as_deref().map(decode_doc).transpose()?;
+                let mut collection = reader.collection(&name)?;
+                collection.find_each(query.as_ref(), |doc| {
+                    let item = encode_doc(doc);
+                    let is_err = item.is_err();
+                    send_blocking(&mut thread_sender, vec![item]) && !is_err
+                })?;
+                reader.commit()
+            })();
+            if let Err(err) = result {
+                send_blocking(&mut thread_sender, vec![Err(err)]);
+            }
+        });
+
+    if let Err(err) = result {
+        let mut sender = sender;
+        let _ = sender.try_send(vec![Err(err.into())]);
+    }
+}
+
+///
+/// A database running on a dedicated thread,
+/// its methods return futures instead of blocking the executor.
+///
+/// The futures can be awaited on any runtime. The operations are
+/// executed one by one in the order they are called, the documents
+/// of a [find](AsyncCollection::find) stream are read while it's consumed.
+///
+/// The thread stops when the database and all the collections
+/// obtained from it are dropped.
+///
+/// # Example
+///
+/// ```rust
+/// use polodb_core::AsyncDatabase;
+/// use polodb_bson::doc;
+///
+/// futures_executor::block_on(async {
+///     let db = AsyncDatabase::open_memory().await.unwrap();
+///     let collection = db.collection("test");
+///     collection.insert(doc! { "name": "PoloDB" }.as_mut()).await.unwrap();
+///     assert_eq!(collection.count().await.unwrap(), 1);
+/// });
+/// ```
+#[derive(Clone)]
+pub struct AsyncDatabase {
+    sender: mpsc::Sender<Job>,
+}
+
+impl AsyncDatabase {
+
+    pub async fn open_file<P: Into<PathBuf>>(path: P) -> DbResult<AsyncDatabase> {
+        AsyncDatabase::open_file_with_config(path, Config::default()).await
+    }
+
+    pub async fn open_file_with_config<P: Into<PathBuf>>(path: P, config: Config) -> DbResult<AsyncDatabase> {
+        let path = path.into();
+        AsyncDatabase::spawn(move || Database::open_file_with_config(path, config)).await
+    }
+
+    pub async fn open_memory() -> DbResult<AsyncDatabase> {
+        AsyncDatabase::open_memory_with_config(Config::default()).await
+    }
+
+    pub async fn open_memory_with_config(config: Config) -> DbResult<AsyncDatabase> {
+        AsyncDatabase::spawn(move || Database::open_memory_wht_config(config)).await
+    }
+
+    async fn spawn<F>(open: F) -> DbResult<AsyncDatabase>
+    where
+        F: FnOnce() -> DbResult<Database> + Send + 'static
+    {
+        let (sender, receiver) = mpsc::channel::<Job>();
+        let (open_sender, open_receiver) = oneshot::channel();
+
+        thread::Builder::new()
+            .name("polodb-worker".into())
+            .spawn(move || {
+                let mut db = match open() {
+                    Ok(db) => {
+                        let _ = open_sender.send(Ok(()));
+                        db
+                    }
+                    Err(err) => {
+                        let _ = open_sender.send(Err(err));
+                        return;
+                    }
+                };
+
+                for job in receiver {
+                    job(&mut db);
+                }
+            })?;
+
+        open_receiver.await.map_err(|_| DbErr::DatabaseClosed)??;
+
+        Ok(AsyncDatabase { sender })
+    }
+
+    fn send_job(&self, job: Job) -> DbResult<()> {
+        self.sender.send(job).map_err(|_| DbErr::DatabaseClosed)
+    }
+
+    /// Run the function on the thread of the database.
+    ///
+    /// The sync API can be used in the function, for example,
+    /// to run several operations in a transaction.
+    pub async fn run<T, F>(&self, f: F) -> DbResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut Database) -> DbResult<T> + Send + 'static
+    {
+        let (sender, receiver) = oneshot::channel();
+        self.send_job(Box::new(move |db| {
+            let _ = sender.send(f(db));
+        }))?;
+        receiver.await.map_err(|_| DbErr::DatabaseClosed)?
+    }
+
+    /// Return a collection, which is created
+    /// when it's used for the first time if it doesn't exist.
+    pub fn collection(&self, name: &str) -> AsyncCollection {
+        AsyncCollection {
+            db: self.clone(),
+            name: name.into(),
+        }
+    }
+
+    pub async fn create_collection(&self, name: &str) -> DbResult<AsyncCollection> {
+        let col_name = name.to_string();
+        self.run(move |db| {
+            db.create_collection(&col_name)?;
+            Ok(())
+        }).await?;
+        Ok(self.collection(name))
+    }
+
+    pub async fn start_transaction(&self, ty: Option<TransactionType>) -> DbResult<()> {
+        self.run(move |db| db.start_transaction(ty)).await
+    }
+
+    pub async fn commit(&self) -> DbResult<()> {
+        self.run(|db| db.commit()).await
+    }
+
+    pub async fn rollback(&self) -> DbResult<()> {
+        self.run(|db| db.rollback()).await
+    }
+
+}
+
+/// The async version of [Collection](crate::db::Collection).
+#[derive(Clone)]
+pub struct AsyncCollection {
+    db:   AsyncDatabase,
+    name: String,
+}
+
+impl AsyncCollection {
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        &self.name
+    }
+
+    async fn run<T, F>(&self, f: F) -> DbResult<T>
+    where
+        T: Send + 'static,
+        F: FnOnce(&mut crate::db::Collection) -> DbResult<T> + Send + 'static
+    {
+        let name = self.name.clone();
+        self.db.run(move |db| {
+            let mut collection = db.collection(&name)?;
+            f(&mut collection)
+        }).await
+    }
+
+    /// Return a stream of all the documents in the collection, see [find](#method.find).
+    pub fn find_all(&self) -> DocumentStream {
+        self.find_stream(None)
+    }
+
+    /// Return a stream of the documents satisfy the query.
+    ///
+    /// The documents are read on a connection of the stream of its own,
+    /// in a read transaction started when the stream is created, so the
+    /// other operations go on while the stream is read. The query waits
+    /// when the stream is full, and stops if the stream is dropped.
+    ///
+    /// In a transaction, or in a memory database, which has no other
+    /// connections, the documents are all read at once.
+    pub fn find(&self, query: &Document) -> DocumentStream {
+        self.find_stream(Some(query))
+    }
+
+    fn find_stream(&self, query: Option<&Document>) -> DocumentStream {
+        let (mut sender, receiver) = async_mpsc::channel(FIND_BUFFER_SIZE);
+        let stream = DocumentStream {
+            receiver,
+            batch: VecDeque::new(),
+        };
+
+        // every sender has a slot, the errors below never block
+        let query = match query.map(encode_doc).transpose() {
+            Ok(query) => query,
+            Err(err) => {
+                let _ = sender.try_send(vec![Err(err)]);
+                return stream;
+            }
+        };
+
+        let name = self.name.clone();
+        let mut job_sender = sender.clone();
+        let result = self.db.send_job(Box::new(move |db| {
+            let reader = match open_find_reader(db, &name) {
+                Ok(reader) => reader,
+                Err(err) => {
+                    let _ = job_sender.try_send(vec![Err(err)]);
+                    return;
+                }
+            };
+
+            if let Some(reader) = reader {
+                spawn_find_thread(reader, name, query, job_sender);
+                return;
+            }
+
+            let mut batch = Vec::new();
+            let result = (|| {
+                let query = query.as_deref().map(decode_doc).transpose()?;
+                let mut collection = db.collection(&name)?;
+                collection.find_each(query.as_ref(), |doc| {
+                    let item = encode_doc(doc);
+                    let is_err = item.is_err();
+                    batch.push(item);
+                    !is_err
+                })
+            })();
+            if let Err(err) = result {
+                batch.push(Err(err));
+            }
+            let _ = job_sender.try_send(batch);
+        }));
+
+        if let Err(err) = result {
+            let _ = sender.try_send(vec![Err(err)]);
+        }
+
+        stream
+    }
+
+    /// Return the first document satisfies the query.
+    pub async fn find_one(&self, query: &Document) -> DbResult<Option<Document>> {
+        let query = encode_doc(query)?;
+        let result = self.run(move |collection| {
+            let query = decode_doc(&query)?;
+            collection.find_one(&query)?
+                .map(|doc| encode_doc(&doc))
+                .transpose()
+        }).await?;
+        result.map(|bytes| decode_doc(&bytes)).transpose()
+    }
+
+    pub async fn count(&self) -> DbResult<u64> {
+        self.run(|collection| collection.count()).await
+    }
+
+    pub async fn update(&self, query: Option<&Document>, update: &Document) -> DbResult<usize> {
+        let query = query.map(encode_doc).transpose()?;
+        let update = encode_doc(update)?;
+        self.run(move |collection| {
+            let query = query.as_deref().map(decode_doc).transpose()?;
+            let update = decode_doc(&update)?;
+            collection.update(query.as_ref(), &update)
+        }).await
+    }
+
+    /// Insert a document into the database.
+    /// The returning boolean value represents if the DB inserted a "_id" for you.
+    pub async fn insert(&self, doc: &mut Document) -> DbResult<bool> {
+        let bytes = encode_doc(doc)?;
+        let (inserted_id, bytes) = self.run(move |collection| {
+            let mut doc = decode_doc(&bytes)?;
+            let inserted_id = collection.insert(&mut doc)?;
+            Ok((inserted_id, encode_doc(&doc)?))
+        }).await?;
+        *doc = decode_doc(&bytes)?;
+        Ok(inserted_id)
+    }
+
+    pub async fn delete(&self, query: Option<&Document>) -> DbResult<usize> {
+        let query = query.map(encode_doc).transpose()?;
+        self.run(move |collection| {
+            let query = query.as_deref().map(decode_doc).transpose()?;
+            collection.delete(query.as_ref())
+        }).await
+    }
+
+}
+
+/// The documents found by [AsyncCollection::find].
+pub struct DocumentStream {
+    receiver: async_mpsc::Receiver<FoundBatch>,
+    batch:    VecDeque<DbResult<Vec<u8>>>,
+}
+
+impl Stream for DocumentStream {
+    type Item = DbResult<Document>;
+
+    fn poll_next(mut self: Pin<&mut Self>, cx: &mut Context<'_>) -> Poll<Option<Self::Item>> {
+        loop {
+            if let Some(item) = self.batch.pop_front() {
+                return Poll::Ready(Some(item.and_then(|bytes| decode_doc(&bytes))));
+            }
+
+            match Pin::new(&mut self.receiver).poll_next(cx) {
+                Poll::Ready(Some(batch)) => self.batch = batch.into(),
+                Poll::Ready(None) => return Poll::Ready(None),
+                Poll::Pending => return Poll::Pending,
+            }
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use futures_executor::block_on;
+    use futures_util::StreamExt;
+    use polodb_bson::doc;
+    use crate::{AsyncDatabase, DbErr, TransactionType};
+
+    #[test]
+    fn test_async_collection() {
+        block_on(async {
+            let db = AsyncDatabase::open_memory().await.unwrap();
+            let collection = db.create_collection("test").await.unwrap();
+
+            for i in 0..100 {
+                let mut doc = doc! { "content": i };
+                assert!(collection.insert(&mut doc).await.unwrap());
+                assert!(doc.pkey_id().is_some());
+            }
+            assert_eq!(collection.count().await.unwrap(), 100);
+
+            let found = collection.find_one(&doc! { "content": 10 }).await.unwrap().unwrap();
+            assert_eq!(found.get("content").unwrap().unwrap_int(), 10);
+
+            collection.update(
+                Some(&doc! { "content": 10 }),
+                &doc! { "$set": doc! { "content": 1000 } },
+            ).await.unwrap();
+            assert!(collection.find_one(&doc! { "content": 1000 }).await.unwrap().is_some());
+
+            let deleted = collection.delete(Some(&doc! { "content": 1000 })).await.unwrap();
+            assert_eq!(deleted, 1);
+
+            let docs: Vec<_> = collection.find_all().collect().await;
+            assert_eq!(docs.len(), 99);
+            assert!(docs.iter().all(|doc| doc.is_ok()));
+
+            let mut stream = collection.find(&doc! { "content": 20 });
+            let doc = stream.next().await.unwrap().unwrap();
+            assert_eq!(doc.get("content").unwrap().unwrap_int(), 20);
+            assert!(stream.next().await.is_none());
+        });
+    }
+
+    #[test]
+    fn test_async_double() {
+        block_on(async {
+            let db = AsyncDatabase::open_memory().await.unwrap();
+            let collection = db.collection("test");
+
+            let mut doc = doc! { "_id": 1, "value": 0.1 };
+            collection.insert(&mut doc).await.unwrap();
+            assert_eq!(doc.get("value").unwrap().unwrap_double(), 0.1);
+
+            let found = collection.find_one(&doc! { "_id": 1 }).await.unwrap().unwrap();
+            assert_eq!(found.get("value").unwrap().unwrap_double(), 0.1);
+
+            let found = collection.find(&doc! { "_id": 1 }).next().await.unwrap().unwrap();
+            assert_eq!(found.get("value").unwrap().unwrap_double(), 0.1);
+        });
+    }
+
+    #[test]
+    fn test_async_find_more_than_buffer() {
+        block_on(async {
+            let db = AsyncDatabase::open_memory().await.unwrap();
+            let collection = db.collection("test");
+
+            let count = super::FIND_BUFFER_SIZE * 3;
+            for i in 0..count {
+                collection.insert(doc! { "_id": i as i64 }.as_mut()).await.unwrap();
+            }
+
+            let docs: Vec<_> = collection.find_all().collect().await;
+            assert_eq!(docs.len(), count);
+
+            // the memory database reads the documents at once,
+            // the partly read stream doesn't block the worker
+            let mut stream = collection.find_all();
+            stream.next().await.unwrap().unwrap();
+            assert_eq!(collection.count().await.unwrap(), count as u64);
+            drop(stream);
+            assert_eq!(collection.count().await.unwrap(), count as u64);
+        });
+    }
+
+    #[test]
+    fn test_async_find_while_stream_is_full() {
+        let db_path = env::temp_dir().join("test-async-find-stream-full.db");
+        let mut journal_path = db_path.as_os_str().to_owned();
+        journal_path.push(".journal");
+        let _ = std::fs::remove_file(&db_path);
+        let _ = std::fs::remove_file(&journal_path);
+
+        block_on(async {
+            let db = AsyncDatabase::open_file(db_path).await.unwrap();
+            let collection = db.collection("test");
+
+            let count = super::FIND_BUFFER_SIZE * 3;
+            db.start_transaction(Some(TransactionType::Write)).await.unwrap();
+            for i in 0..count {
+                collection.insert(doc! { "_id": i as i64 }.as_mut()).await.unwrap();
+            }
+
+            // the uncommitted documents are read by the worker
+            let mut stream = collection.find_all();
+            stream.next().await.unwrap().unwrap();
+            assert_eq!(collection.count().await.unwrap(), count as u64);
+            drop(stream);
+            db.commit().await.unwrap();
+
+            // the thread of the stream waits on the full stream, not the worker
+            let mut stream = collection.find_all();
+            stream.next().await.unwrap().unwrap();
+            assert_eq!(collection.count().await.unwrap(), count as u64);
+            collection.insert(doc! { "_id": count as i64 }.as_mut()).await.unwrap();
+            assert_eq!(collection.count().await.unwrap(), count as u64 + 1);
+
+            // the stream reads the documents committed before it's created
+            let rest: Vec<_> = stream.collect().await;
+            assert_eq!(rest.len(), count - 1);
+            assert!(rest.iter().all(|doc| doc.is_ok()));
+
+            // the query on the full stream stops when it's dropped
+            let mut stream = collection.find_all();
+            stream.next().await.unwrap().unwrap();
+            drop(stream);
+            collection.delete(None).await.unwrap();
+            assert_eq!(collection.count().await.unwrap(), 0);
+        });
+    }
+
+    #[test]
+    fn test_async_transaction() {
+        block_on(async {
+            let db = AsyncDatabase::open_memory().await.unwrap();
+            let collection = db.collection("test");
+
+            db.start_transaction(Some(TransactionType::Write)).await.unwrap();
+            collection.insert(doc! { "_id": 1 }.as_mut()).await.unwrap();
+            db.rollback().await.unwrap();
+            assert_eq!(collection.count().await.unwrap(), 0);
+
+            let result: Result<(), DbErr> = db.run(|db| {
+                db.transaction(TransactionType::Write, |tx| {
+                    tx.collection("test")?.insert(doc! { "_id": 2 }.as_mut())?;
+                    Ok(())
+                })
+            }).await;
+            result.unwrap();
+            assert_eq!(collection.count().await.unwrap(), 1);
+        });
+    }
+
+}
@@ -1,20 +1,213 @@
-use crate::DbResult;
+use std::io::Write;
+use std::num::NonZeroU32;
+use std::sync::Arc;
+use crate::{DbResult, DbErr, Config};
+use crate::backend::PageCodec;
 use crate::page::RawPage;
 use crate::transaction::TransactionType;
+use crate::db::{CheckpointMode, CheckpointStats, JournalPosition};
+use crate::metrics::Metrics;
 
 #[derive(Debug, Copy, Clone)]
 pub(crate) struct AutoStartResult {
     pub auto_start: bool,
 }
 
-pub(crate) trait Backend {
+/// The storage of the pages of a database.
+///
+/// The pages are read and written in the transactions started by the database.
+/// The written pages are only visible to the transaction before it's committed,
+/// and are discarded when it's rolled back.
+/// The sizes are in bytes, which are multiples of the page size.
+pub trait Backend {
+    /// The size of the pages, a power of two between 1 KiB and 64 KiB.
+    fn page_size(&self) -> NonZeroU32;
+
+    /// Read a page, the page which is not written yet is filled with zeros.
     fn read_page(&self, page_id: u32) -> DbResult<RawPage>;
+
+    /// Write a page in the write transaction.
     fn write_page(&mut self, page: &RawPage) -> DbResult<()>;
+
+    /// Commit the current transaction.
     fn commit(&mut self) -> DbResult<()>;
+
+    /// The size of the database seen by the current transaction.
     fn db_size(&self) -> u64;
-    fn set_db_size(&mut self, size: u64) -> DbResult<()>;
+
+    /// Expand the database by the size in the write transaction.
+    fn expand_db_size(&mut self, size: u64) -> DbResult<()>;
+
+    /// Discard the pages after the size in the write transaction,
+    /// the database is shrunk when the transaction is committed.
+    fn truncate(&mut self, size: u64) -> DbResult<()>;
+
+    /// The type of the current transaction, `None` if there is no transaction.
     fn transaction_type(&self) -> Option<TransactionType>;
+
+    /// Upgrade the current read transaction to a write transaction,
+    /// fail with [DbErr::Busy] if it can not see the latest data.
     fn upgrade_read_transaction_to_write(&mut self) -> DbResult<()>;
+
+    /// Discard the changes of the current transaction.
     fn rollback(&mut self) -> DbResult<()>;
+
+    /// Start a transaction, fail with [DbErr::Busy]
+    /// if another connection is writing.
     fn start_transaction(&mut self, ty: TransactionType) -> DbResult<()>;
+
+    /// Remember the state of the current transaction.
+    fn savepoint(&mut self) -> DbResult<()>;
+
+    /// Go back to the state of the savepoint at the index,
+    /// the savepoint is kept.
+    fn rollback_to_savepoint(&mut self, index: usize) -> DbResult<()>;
+
+    /// Forget the savepoints from the index.
+    fn release_savepoint(&mut self, index: usize) -> DbResult<()>;
+}
+
+// the hooks of the backends of this crate for the connections, the journal,
+// the codec and vacuum, a custom backend has the defaults through CustomBackend
+pub(crate) trait BackendExt: Backend {
+    // return true once if another connection has committed
+    // since the last time this connection looked at the data
+    fn is_changed_by_others(&mut self) -> bool {
+        false
+    }
+
+    fn new_connection(&self) -> DbResult<Box<dyn BackendExt + Send>> {
+        Err(DbErr::NotSupported("multiple connections of the backend"))
+    }
+
+    // write the committed pages in the journal back to the storage,
+    // returns false if it's skipped in the SkipIfBusy mode
+    fn checkpoint(&mut self, _mode: CheckpointMode) -> DbResult<bool> {
+        Ok(true)
+    }
+
+    // the pages which are not written back by the checkpoint
+    fn checkpoint_stats(&self) -> DbResult<CheckpointStats> {
+        Ok(CheckpointStats::default())
+    }
+
+    // fill in the counters kept by the backend,
+    // which are shared by all the connections of the database
+    fn collect_metrics(&self, _metrics: &mut Metrics) {
+    }
+
+    // write the transactions committed in the journal after the position,
+    // returns the position after the committed frames, where the next export starts
+    fn export_journal_frames(&self, _since: JournalPosition, _writer: &mut dyn Write) -> DbResult<JournalPosition> {
+        Err(DbErr::NotSupported("exporting the journal of the backend"))
+    }
+
+    // the position of the journal seen by the read transaction,
+    // None if the backend has no journal
+    fn journal_position(&self) -> Option<JournalPosition> {
+        None
+    }
+
+    // how the pages are encoded in the storage
+    fn codec(&self) -> PageCodec {
+        PageCodec::plain(self.page_size())
+    }
+
+    // encode all the pages with the new codec,
+    // must be called out of transactions
+    fn set_codec(&mut self, _codec: PageCodec) -> DbResult<()> {
+        Err(DbErr::NotSupported("changing the codec of the backend"))
+    }
+
+    // open an empty database next to this one with the same codec,
+    // the compacted copy of this database is built in it by vacuum,
+    // None if the database is compacted in its own transaction
+    fn open_compacted(&self, _config: Arc<Config>) -> DbResult<Option<Box<dyn BackendExt + Send>>> {
+        Ok(None)
+    }
+
+    // replace the database with the compacted copy after it's closed,
+    // must be called out of transactions, returns the size of the new database
+    fn swap_compacted(&mut self) -> DbResult<u64> {
+        Err(DbErr::NotSupported("replacing the database of the backend"))
+    }
+}
+
+// a backend implemented out of this crate, which has none of the hooks
+pub(crate) struct CustomBackend(pub(crate) Box<dyn Backend + Send>);
+
+impl Backend for CustomBackend {
+
+    #[inline]
+    fn page_size(&self) -> NonZeroU32 {
+        self.0.page_size()
+    }
+
+    #[inline]
+    fn read_page(&self, page_id: u32) -> DbResult<RawPage> {
+        self.0.read_page(page_id)
+    }
+
+    #[inline]
+    fn write_page(&mut self, page: &RawPage) -> DbResult<()> {
+        self.0.write_page(page)
+    }
+
+    #[inline]
+    fn commit(&mut self) -> DbResult<()> {
+        self.0.commit()
+    }
+
+    #[inline]
+    fn db_size(&self) -> u64 {
+        self.0.db_size()
+    }
+
+    #[inline]
+    fn expand_db_size(&mut self, size: u64) -> DbResult<()> {
+        self.0.expand_db_size(size)
+    }
+
+    #[inline]
+    fn truncate(&mut self, size: u64) -> DbResult<()> {
+        self.0.truncate(size)
+    }
+
+    #[inline]
+    fn transaction_type(&self) -> Option<TransactionType> {
+        self.0.transaction_type()
+    }
+
+    #[inline]
+    fn upgrade_read_transaction_to_write(&mut self) -> DbResult<()> {
+        self.0.upgrade_read_transaction_to_write()
+    }
+
+    #[inline]
+    fn rollback(&mut self) -> DbResult<()> {
+        self.0.rollback()
+    }
+
+    #[inline]
+    fn start_transaction(&mut self, ty: TransactionType) -> DbResult<()> {
+        self.0.start_transaction(ty)
+    }
+
+    #[inline]
+    fn savepoint(&mut self) -> DbResult<()> {
+        self.0.savepoint()
+    }
+
+    #[inline]
+    fn rollback_to_savepoint(&mut self, index: usize) -> DbResult<()> {
+        self.0.rollback_to_savepoint(index)
+    }
+
+    #[inline]
+    fn release_savepoint(&mut self, index: usize) -> DbResult<()> {
+        self.0.release_savepoint(index)
+    }
+
 }
+
+impl BackendExt for CustomBackend {}
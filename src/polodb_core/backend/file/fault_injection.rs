@@ -0,0 +1,571 @@
+/**
+ * The files of the file backend are wrapped in the tests,
+ * so the faults can be injected at every I/O point.
+ *
+ * Every write, resizing and sync of a file is an I/O point,
+ * they are counted from the last reset.
+ * - A crash at an I/O point keeps some bytes of the write,
+ *   and all the I/O after it fails, as if the process died.
+ * - A power failure loses the data which is not synced to the disk.
+ *   The content of a file is recorded when it's synced,
+ *   a file which is never synced is lost entirely.
+ *   The writes after the last sync may also reach the disk
+ *   before the power is lost, any sector of them in any order,
+ *   see copy_with_unsynced.
+ * - A dropped sync succeeds, but nothing is recorded.
+ */
+use std::cell::RefCell;
+use std::collections::HashMap;
+use std::fs::{File, Metadata, OpenOptions};
+use std::io::{self, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const SECTOR_SIZE: u64 = 512;
+
+// the changes of a file after the last sync, in order
+#[derive(Clone)]
+enum Unsynced {
+    Write { offset: u64, data: Vec<u8> },
+    SetLen(u64),
+}
+
+#[derive(Default)]
+struct FaultState {
+    // the other tests read and write the files directly
+    enabled:      bool,
+
+    io_count:     usize,
+
+    // the I/O point, and the bytes of the write kept
+    crash_at:     Option<(usize, usize)>,
+    crashed:      bool,
+    drop_sync_at: Option<usize>,
+
+    synced:       HashMap<PathBuf, Vec<u8>>,
+    unsynced:     HashMap<PathBuf, Vec<Unsynced>>,
+}
+
+thread_local! {
+    static STATE: RefCell<FaultState> = RefCell::new(FaultState::default());
+}
+
+enum IoPoint {
+    Proceed,
+    Crash { keep: usize },
+    DropSync,
+}
+
+fn next_random(state: &mut u64) -> u64 {
+    // xorshift64
+    let mut x = *state;
+    x ^= x << 13;
+    x ^= x >> 7;
+    x ^= x << 17;
+    *state = x;
+    x
+}
+
+fn record_unsynced(path: &Path, change: Unsynced) {
+    STATE.with(|state| {
+        state.borrow_mut().unsynced.entry(path.to_path_buf()).or_default().push(change);
+    });
+}
+
+fn crashed_error() -> io::Error {
+    io::Error::other("the process has crashed")
+}
+
+fn is_enabled() -> bool {
+    STATE.with(|state| state.borrow().enabled)
+}
+
+fn check_crashed() -> io::Result<()> {
+    if STATE.with(|state| state.borrow().crashed) {
+        return Err(crashed_error());
+    }
+    Ok(())
+}
+
+fn enter_io_point(len: usize) -> io::Result<IoPoint> {
+    STATE.with(|state| {
+        let mut state = state.borrow_mut();
+        if state.crashed {
+            return Err(crashed_error());
+        }
+
+        let point = state.io_count;
+        state.io_count += 1;
+
+        match state.crash_at {
+            Some((at, keep)) if at == point => {
+                state.crashed = true;
+                return Ok(IoPoint::Crash { keep: keep.min(len) });
+            }
+            _ => (),
+        }
+
+        if state.drop_sync_at == Some(point) {
+            return Ok(IoPoint::DropSync);
+        }
+
+        Ok(IoPoint::Proceed)
+    })
+}
+
+// the faults and the synced files are cleared,
+// and the I/O points are counted from 0.
+// The files of the thread are only recorded after it's called
+pub(crate) fn reset() {
+    STATE.with(|state| *state.borrow_mut() = FaultState { enabled: true, ..FaultState::default() });
+}
+
+pub(crate) fn crash_at(point: usize, keep: usize) {
+    STATE.with(|state| state.borrow_mut().crash_at = Some((point, keep)));
+}
+
+// only the sync at the point is dropped
+pub(crate) fn drop_sync_at(point: usize) {
+    STATE.with(|state| state.borrow_mut().drop_sync_at = Some(point));
+}
+
+pub(crate) fn io_count() -> usize {
+    STATE.with(|state| state.borrow().io_count)
+}
+
+pub(crate) fn has_crashed() -> bool {
+    STATE.with(|state| state.borrow().crashed)
+}
+
+// copy the file to the place as if the machine lost power
+pub(crate) fn copy_synced(from: &Path, to: &Path) -> io::Result<()> {
+    let content = STATE.with(|state| state.borrow().synced.get(from).cloned());
+    match content {
+        Some(content) => std::fs::write(to, content),
+        None => match std::fs::remove_file(to) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        },
+    }
+}
+
+// copy the file as if the machine lost power after some of the unsynced
+// writes reached the disk, every sector of them and every resizing
+// is kept or not by the seed, which must not be zero
+pub(crate) fn copy_with_unsynced(from: &Path, to: &Path, seed: u64) -> io::Result<()> {
+    let (mut content, unsynced) = STATE.with(|state| {
+        let state = state.borrow();
+        (state.synced.get(from).cloned(), state.unsynced.get(from).cloned().unwrap_or_default())
+    });
+
+    let mut rng = seed;
+    for change in unsynced {
+        match change {
+            Unsynced::SetLen(len) => {
+                if next_random(&mut rng).is_multiple_of(2) {
+                    content.get_or_insert_with(Vec::new).resize(len as usize, 0);
+                }
+            }
+
+            Unsynced::Write { offset, data } => {
+                let mut start = 0;
+                while start < data.len() {
+                    let pos = offset + start as u64;
+                    let end = ((pos / SECTOR_SIZE + 1) * SECTOR_SIZE - offset).min(data.len() as u64) as usize;
+                    if next_random(&mut rng).is_multiple_of(2) {
+                        let content = content.get_or_insert_with(Vec::new);
+                        let content_end = pos as usize + (end - start);
+                        if content.len() < content_end {
+                            content.resize(content_end, 0);
+                        }
+                        content[(pos as usize)..content_end].copy_from_slice(&data[start..end]);
+                    }
+                    start = end;
+                }
+            }
+        }
+    }
+
+    match content {
+        Some(content) => std::fs::write(to, content),
+        None => match std::fs::remove_file(to) {
+            Err(err) if err.kind() != io::ErrorKind::NotFound => Err(err),
+            _ => Ok(()),
+        },
+    }
+}
+
+// copy the file as if it has been synced
+pub(crate) fn copy_durable(from: &Path, to: &Path) -> io::Result<()> {
+    let content = std::fs::read(from)?;
+    std::fs::write(to, &content)?;
+    STATE.with(|state| state.borrow_mut().synced.insert(to.to_path_buf(), content));
+    Ok(())
+}
+
+pub(crate) struct FaultyFile {
+    file: File,
+    path: PathBuf,
+}
+
+impl FaultyFile {
+
+    pub(crate) fn open(options: &OpenOptions, path: &Path) -> io::Result<FaultyFile> {
+        check_crashed()?;
+        Ok(FaultyFile {
+            file: options.open(path)?,
+            path: path.to_path_buf(),
+        })
+    }
+
+    #[inline]
+    pub(crate) fn inner(&self) -> &File {
+        &self.file
+    }
+
+    pub(crate) fn metadata(&self) -> io::Result<Metadata> {
+        check_crashed()?;
+        self.file.metadata()
+    }
+
+    pub(crate) fn set_len(&self, size: u64) -> io::Result<()> {
+        if !is_enabled() {
+            return self.file.set_len(size);
+        }
+        match enter_io_point(0)? {
+            IoPoint::Crash { .. } => Err(crashed_error()),
+            _ => {
+                self.file.set_len(size)?;
+                record_unsynced(&self.path, Unsynced::SetLen(size));
+                Ok(())
+            }
+        }
+    }
+
+    pub(crate) fn sync_data(&self) -> io::Result<()> {
+        if !is_enabled() {
+            return self.file.sync_data();
+        }
+        match enter_io_point(0)? {
+            IoPoint::Crash { .. } => Err(crashed_error()),
+            IoPoint::DropSync => Ok(()),
+            IoPoint::Proceed => {
+                self.file.sync_data()?;
+                let content = std::fs::read(&self.path)?;
+                STATE.with(|state| {
+                    let mut state = state.borrow_mut();
+                    state.synced.insert(self.path.clone(), content);
+                    state.unsynced.remove(&self.path);
+                });
+                Ok(())
+            }
+        }
+    }
+
+    #[inline]
+    pub(crate) fn sync_all(&self) -> io::Result<()> {
+        self.sync_data()
+    }
+
+}
+
+impl Read for FaultyFile {
+
+    fn read(&mut self, buf: &mut [u8]) -> io::Result<usize> {
+        check_crashed()?;
+        self.file.read(buf)
+    }
+
+}
+
+impl Write for FaultyFile {
+
+    fn write(&mut self, buf: &[u8]) -> io::Result<usize> {
+        if !is_enabled() {
+            return self.file.write(buf);
+        }
+        let offset = self.file.stream_position()?;
+        match enter_io_point(buf.len())? {
+            IoPoint::Crash { keep } => {
+                self.file.write_all(&buf[0..keep])?;
+                record_unsynced(&self.path, Unsynced::Write { offset, data: buf[0..keep].to_vec() });
+                Err(crashed_error())
+            }
+            _ => {
+                let size = self.file.write(buf)?;
+                record_unsynced(&self.path, Unsynced::Write { offset, data: buf[0..size].to_vec() });
+                Ok(size)
+            }
+        }
+    }
+
+    fn flush(&mut self) -> io::Result<()> {
+        check_crashed()?;
+        self.file.flush()
+    }
+
+}
+
+impl Seek for FaultyFile {
+
+    fn seek(&mut self, pos: SeekFrom) -> io::Result<u64> {
+        check_crashed()?;
+        self.file.seek(pos)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::collections::BTreeMap;
+    use std::collections::btree_map::Entry;
+    use std::env;
+    use std::path::{Path, PathBuf};
+    use polodb_bson::doc;
+    use crate::{Database, Config, DbResult, TransactionType, Synchronous};
+    use super::{reset, crash_at, drop_sync_at, io_count, has_crashed, copy_synced, copy_with_unsynced, copy_durable, next_random};
+
+    static TRANSACTION_COUNT: usize = 16;
+    static KEY_COUNT: u64 = 32;
+    static PADDING_SIZE: usize = 200;
+
+    // the content of the collection, _id -> value
+    type Snapshot = BTreeMap<i64, i64>;
+
+    #[derive(Clone, Copy, PartialEq, Eq)]
+    enum Fault {
+        // the process is killed, the written data is kept by the os
+        Crash,
+
+        // the machine loses power, only the synced data is kept
+        PowerLoss,
+
+        // the sync lies, and the machine loses power before the next I/O
+        DroppedSync,
+    }
+
+    // what the database is allowed to contain after the workload is stopped
+    struct Outcome {
+        // all the committed states, the first one is the empty collection
+        history:   Vec<Snapshot>,
+
+        // the state of the transaction which was being committed
+        in_commit: Option<Snapshot>,
+    }
+
+    fn mk_path(name: &str) -> PathBuf {
+        let mut path = env::temp_dir();
+        path.push(String::from(name) + ".db");
+        path
+    }
+
+    fn journal_path(db_path: &Path) -> PathBuf {
+        let mut path = db_path.as_os_str().to_owned();
+        path.push(".journal");
+        PathBuf::from(path)
+    }
+
+    fn mk_config(synchronous: Synchronous) -> Config {
+        Config {
+            // let the workload checkpoint a few times
+            journal_full_size: 8,
+            synchronous,
+            ..Default::default()
+        }
+    }
+
+    // the model is changed before the database,
+    // so it's the state to be committed if the operation fails
+    fn random_operation(db: &mut Database, pending: &mut Snapshot, rng: &mut u64) -> DbResult<()> {
+        let key = (next_random(rng) % KEY_COUNT) as i64;
+        let value = (next_random(rng) % 1000) as i64;
+        let mut collection = db.collection("test")?;
+
+        if let Entry::Vacant(entry) = pending.entry(key) {
+            entry.insert(value);
+            collection.insert(doc! {
+                "_id": key,
+                "value": value,
+                "padding": "x".repeat(PADDING_SIZE),
+            }.as_mut())?;
+        } else if next_random(rng).is_multiple_of(2) {
+            pending.insert(key, value);
+            collection.update(Some(&doc! {
+                "_id": key,
+            }), &doc! {
+                "$set": doc! {
+                    "value": value,
+                },
+            })?;
+        } else {
+            pending.remove(&key);
+            collection.delete(Some(&doc! {
+                "_id": key,
+            }))?;
+        }
+
+        Ok(())
+    }
+
+    // stopped at the first error, the database is dropped before returning
+    fn run_workload(path: &Path, config: Config, seed: u64) -> Outcome {
+        let mut rng = seed;
+        let mut history = vec![Snapshot::new()];
+
+        let mut db = match Database::open_file_with_config(path, config) {
+            Ok(db) => db,
+            Err(_) => return Outcome { history, in_commit: None },
+        };
+
+        for _ in 0..TRANSACTION_COUNT {
+            let mut pending = history.last().unwrap().clone();
+
+            // auto commit
+            if next_random(&mut rng).is_multiple_of(3) {
+                if random_operation(&mut db, &mut pending, &mut rng).is_err() {
+                    return Outcome { history, in_commit: Some(pending) };
+                }
+                history.push(pending);
+                continue;
+            }
+
+            if db.start_transaction(Some(TransactionType::Write)).is_err() {
+                return Outcome { history, in_commit: None };
+            }
+
+            let op_count = 1 + next_random(&mut rng) % 4;
+            for _ in 0..op_count {
+                if random_operation(&mut db, &mut pending, &mut rng).is_err() {
+                    return Outcome { history, in_commit: None };
+                }
+            }
+
+            if next_random(&mut rng).is_multiple_of(5) {
+                if db.rollback().is_err() {
+                    return Outcome { history, in_commit: None };
+                }
+            } else {
+                if db.commit().is_err() {
+                    return Outcome { history, in_commit: Some(pending) };
+                }
+                history.push(pending);
+            }
+        }
+
+        Outcome { history, in_commit: None }
+    }
+
+    fn read_snapshot(path: &Path) -> Snapshot {
+        let mut db = Database::open_file(path).unwrap();
+        let report = db.check_integrity().unwrap();
+        assert!(report.is_ok(), "{}", report);
+
+        let mut result = Snapshot::new();
+        for doc in db.collection("test").unwrap().find_all().unwrap() {
+            let key = doc.get("_id").unwrap().unwrap_int();
+            let value = doc.get("value").unwrap().unwrap_int();
+            result.insert(key, value);
+        }
+        result
+    }
+
+    fn inject_fault_at_every_io_point(name: &str, fault: Fault, synchronous: Synchronous, seed: u64) {
+        let base_path = mk_path(&format!("{}-base", name));
+        let path = mk_path(name);
+        let crashed_path = mk_path(&format!("{}-crashed", name));
+        let torn_path = mk_path(&format!("{}-torn", name));
+
+        let _ = std::fs::remove_file(&base_path);
+        let _ = std::fs::remove_file(journal_path(&base_path));
+        {
+            let mut db = Database::open_file(&base_path).unwrap();
+            db.create_collection("test").unwrap();
+        }
+
+        let run = |point: Option<usize>| -> Outcome {
+            reset();
+            copy_durable(&base_path, &path).unwrap();
+            let _ = std::fs::remove_file(journal_path(&path));
+
+            if let Some(point) = point {
+                match fault {
+                    Fault::Crash | Fault::PowerLoss => {
+                        // the bytes of a torn write
+                        let keep = point.wrapping_mul(2654435761) % 4200;
+                        crash_at(point, keep);
+                    }
+                    Fault::DroppedSync => {
+                        drop_sync_at(point);
+                        crash_at(point + 1, 0);
+                    }
+                }
+            }
+
+            run_workload(&path, mk_config(synchronous), seed)
+        };
+
+        let outcome = run(None);
+        let point_count = io_count();
+        assert!(!has_crashed());
+        assert!(outcome.history.len() > TRANSACTION_COUNT / 2);
+        assert_eq!(read_snapshot(&path), *outcome.history.last().unwrap());
+
+        for point in 0..point_count {
+            let outcome = run(Some(point));
+            assert!(fault == Fault::DroppedSync || has_crashed(), "no crash at {}", point);
+
+            // nothing after the last sync is kept, or some sectors of it are
+            let reopen_paths = if fault == Fault::Crash {
+                vec![path.clone()]
+            } else {
+                copy_synced(&path, &crashed_path).unwrap();
+                copy_synced(&journal_path(&path), &journal_path(&crashed_path)).unwrap();
+
+                let seed = seed ^ (point as u64 + 1).wrapping_mul(0x9e3779b97f4a7c15);
+                copy_with_unsynced(&path, &torn_path, seed).unwrap();
+                copy_with_unsynced(&journal_path(&path), &journal_path(&torn_path), !seed).unwrap();
+                vec![crashed_path.clone(), torn_path.clone()]
+            };
+
+            for reopen_path in reopen_paths {
+                reset();
+                let snapshot = read_snapshot(&reopen_path);
+                if outcome.in_commit.as_ref() == Some(&snapshot) {
+                    continue;
+                }
+
+                // only the last committed state is durable
+                let durable = match fault {
+                    Fault::Crash => true,
+                    Fault::PowerLoss => synchronous == Synchronous::Full,
+                    Fault::DroppedSync => false,
+                };
+                if durable {
+                    assert_eq!(snapshot, *outcome.history.last().unwrap(), "fault at {}, {}", point, reopen_path.display());
+                } else {
+                    assert!(outcome.history.contains(&snapshot), "fault at {}, {}", point, reopen_path.display());
+                }
+            }
+        }
+    }
+
+    #[test]
+    fn test_crash_at_every_io_point() {
+        inject_fault_at_every_io_point("test-crash-at-every-io-point", Fault::Crash, Synchronous::Normal, 0x9e3779b97f4a7c15);
+    }
+
+    #[test]
+    fn test_power_loss_at_every_io_point() {
+        inject_fault_at_every_io_point("test-power-loss-at-every-io-point", Fault::PowerLoss, Synchronous::Full, 0x2545f4914f6cdd1d);
+    }
+
+    #[test]
+    fn test_power_loss_synchronous_normal() {
+        inject_fault_at_every_io_point("test-power-loss-synchronous-normal", Fault::PowerLoss, Synchronous::Normal, 0x853c49e6748fea9b);
+    }
+
+    #[test]
+    fn test_dropped_sync_at_every_io_point() {
+        inject_fault_at_every_io_point("test-dropped-sync-at-every-io-point", Fault::DroppedSync, Synchronous::Full, 0xda3e39cb94b95bdb);
+    }
+
+}
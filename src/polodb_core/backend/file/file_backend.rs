@@ -1,32 +1,262 @@
-use std::fs::File;
-use std::num::{NonZeroU32, NonZeroU64};
-use std::cell::RefCell;
-use std::io::{SeekFrom, Seek, Read};
+use std::num::NonZeroU32;
+use std::io::{SeekFrom, Seek, Write};
 use std::path::{Path, PathBuf};
-use std::sync::Arc;
+use std::sync::{Arc, Mutex, MutexGuard};
+use std::sync::atomic::{AtomicU64, Ordering};
+use std::time::Instant;
+#[cfg(feature = "mmap")]
+use memmap2::Mmap;
 use super::journal_manager::JournalManager;
+use super::transaction_state::ReadSnapshot;
+use super::sync::{DbFile, open_file, std_file, sync_file};
 use crate::file_lock::{exclusive_lock_file, unlock_file};
-use crate::backend::Backend;
-use crate::{DbResult, DbErr, Config, SerializeType};
+use crate::backend::{Backend, BackendExt, PageCodec};
+use crate::{DbResult, DbErr, Config, SerializeType, Compression, Synchronous, AutoCheckpoint};
+use crate::db::{CheckpointMode, CheckpointStats, JournalPosition};
+use crate::metrics::Metrics;
 use crate::page::RawPage;
-use crate::page::header_page_wrapper::{HeaderPageWrapper, DATABASE_VERSION};
+use crate::page::header_page_wrapper::{HeaderPageWrapper, DATABASE_VERSION, FEATURES_DATABASE_VERSION};
 use crate::transaction::TransactionType;
 use crate::error::VersionMismatchError;
 
-pub(crate) struct FileBackend {
-    file:            RefCell<File>,
-    page_size:       NonZeroU32,
+// the files shared by all the connections of a database
+struct SharedFiles {
+    path:            PathBuf,
+    main_file:       DbFile,
     journal_manager: JournalManager,
     config:          Arc<Config>,
+
+    // mapped by the MmapBackend, unmapped before the main file is changed
+    #[cfg(feature = "mmap")]
+    main_map:        Option<Mmap>,
+
+    last_checkpoint: Instant,
+}
+
+impl SharedFiles {
+
+    fn should_checkpoint(&self) -> DbResult<bool> {
+        let frames = self.journal_manager.len() as u64;
+        if frames == 0 {
+            return Ok(false);
+        }
+        let result = match self.config.auto_checkpoint {
+            AutoCheckpoint::Frames => frames >= self.config.journal_full_size,
+            AutoCheckpoint::Bytes(bytes) => self.journal_manager.stats()?.journal_bytes >= bytes,
+            AutoCheckpoint::Interval(interval) => self.last_checkpoint.elapsed() >= interval,
+            AutoCheckpoint::Disabled => false,
+        };
+        Ok(result)
+    }
+
+    // the checkpoint is deferred if some readers are using the older frames,
+    // the last one of them will do it when the transaction is finished
+    fn try_checkpoint(&mut self) -> DbResult<()> {
+        if self.should_checkpoint()? && self.journal_manager.can_checkpoint() {
+            self.checkpoint_journal()?;
+            crate::polo_log!("checkpoint journal finished");
+        }
+        Ok(())
+    }
+
+    fn checkpoint(&mut self, mode: CheckpointMode) -> DbResult<bool> {
+        if !self.journal_manager.can_checkpoint() {
+            return match mode {
+                CheckpointMode::SkipIfBusy => Ok(false),
+                CheckpointMode::FailIfBusy => Err(DbErr::Busy),
+            };
+        }
+        self.checkpoint_journal()?;
+        Ok(true)
+    }
+
+    // write all the pages to a new file with the new codec,
+    // and replace the main file with it
+    fn rewrite(&mut self, new_codec: PageCodec) -> DbResult<()> {
+        if !self.journal_manager.is_idle() {
+            return Err(DbErr::Busy);
+        }
+        self.checkpoint_journal()?;
+
+        let old_codec = self.journal_manager.codec().clone();
+        let db_size = self.journal_manager.committed_db_size();
+        let page_count = db_size / (old_codec.page_size().get() as u64);
+
+        let mut tmp_path = self.path.as_os_str().to_owned();
+        tmp_path.push(".rewrite");
+        let tmp_path = PathBuf::from(tmp_path);
+        let mut tmp_file = open_file(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .read(true)
+                .truncate(true),
+            &tmp_path,
+        )?;
+
+        // the slots after the null page bar are never written
+        let header = HeaderPageWrapper::from_raw_page(old_codec.read_page(&mut self.main_file, 0, 0)?);
+        let used_pages = page_count.min(header.get_null_page_bar() as u64);
+
+        tmp_file.set_len(new_codec.file_size_of(db_size))?;
+        for page_id in 0..(used_pages as u32) {
+            let page = old_codec.read_page(&mut self.main_file, page_id, old_codec.slot_offset(page_id))?;
+            new_codec.write_page(&mut tmp_file, &page, new_codec.slot_offset(page_id))?;
+        }
+        tmp_file.sync_all()?;
+
+        exclusive_lock_file(std_file(&tmp_file))?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        let old_file = std::mem::replace(&mut self.main_file, tmp_file);
+        let _ = unlock_file(std_file(&old_file));
+
+        self.journal_manager.set_codec(new_codec)
+    }
+
+    // the compacted copy of the database is built in the file by vacuum
+    fn vacuum_path(&self) -> PathBuf {
+        let mut path = self.path.as_os_str().to_owned();
+        path.push(".vacuum");
+        PathBuf::from(path)
+    }
+
+    // replace the main file with the compacted copy,
+    // return the size of the new database
+    fn swap_compacted(&mut self) -> DbResult<u64> {
+        if !self.journal_manager.is_idle() {
+            return Err(DbErr::Busy);
+        }
+        self.checkpoint_journal()?;
+
+        let tmp_path = self.vacuum_path();
+        let tmp_file = open_file(
+            std::fs::OpenOptions::new()
+                .write(true)
+                .read(true),
+            &tmp_path,
+        )?;
+        tmp_file.sync_all()?;
+        let db_size = self.journal_manager.codec().db_size_of(tmp_file.metadata()?.len());
+
+        exclusive_lock_file(std_file(&tmp_file))?;
+        std::fs::rename(&tmp_path, &self.path)?;
+        let old_file = std::mem::replace(&mut self.main_file, tmp_file);
+        let _ = unlock_file(std_file(&old_file));
+
+        self.journal_manager.reset_db_size(db_size)?;
+        Ok(db_size)
+    }
+
+    // the file can not be resized while it's mapped on some platforms
+    fn checkpoint_journal(&mut self) -> DbResult<()> {
+        if let Some(archive_dir) = &self.config.archive_dir {
+            self.journal_manager.archive_frames(archive_dir)?;
+        }
+
+        #[cfg(feature = "mmap")]
+        {
+            self.main_map = None;
+        }
+        self.journal_manager.checkpoint_journal(&mut self.main_file)?;
+        self.last_checkpoint = Instant::now();
+        Ok(())
+    }
+
+    fn read_main_page(&mut self, page_id: u32) -> DbResult<RawPage> {
+        let codec = self.journal_manager.codec();
+
+        crate::polo_log!("read page from main file, id: {}", page_id);
+
+        let slot_offset = codec.slot_offset(page_id);
+        if self.main_file.seek(SeekFrom::End(0))? < slot_offset + codec.slot_size() {
+            return Ok(RawPage::new(page_id, codec.page_size()));
+        }
+
+        codec.read_page(&mut self.main_file, page_id, slot_offset)
+    }
+
+    #[cfg(feature = "mmap")]
+    fn read_mapped_page(&mut self, page_id: u32) -> DbResult<RawPage> {
+        if self.main_map.is_none() {
+            // the file is locked exclusively, and only changed with the lock of the files held
+            let map = unsafe { Mmap::map(std_file(&self.main_file))? };
+            self.main_map = Some(map);
+        }
+        let map = self.main_map.as_ref().unwrap();
+        let codec = self.journal_manager.codec();
+
+        let start = codec.slot_offset(page_id) as usize;
+        let end = start + codec.slot_size() as usize;
+        if map.len() < end {
+            return Ok(RawPage::new(page_id, codec.page_size()));
+        }
+
+        codec.decode(page_id, map[start..end].to_vec())
+    }
+
+}
+
+impl Drop for SharedFiles {
+
+    fn drop(&mut self) {
+        let _ = unlock_file(std_file(&self.main_file));
+        let result = self.checkpoint_journal();
+        if result.is_ok() {
+            let path = self.journal_manager.path();
+            let _ = std::fs::remove_file(path);
+        }
+    }
+
 }
 
-struct InitDbResult {
-    db_file_size: u64,
+struct SharedBackend {
+    files:        Mutex<SharedFiles>,
+
+    // increased after every write transaction is committed
+    data_version: AtomicU64,
+}
+
+impl SharedBackend {
+
+    fn lock_files(&self) -> MutexGuard<'_, SharedFiles> {
+        self.files.lock().expect("the files of the database are poisoned")
+    }
+
+}
+
+enum ConnectionState {
+    Read {
+        snapshot:        Box<ReadSnapshot>,
+        // savepoints created before the transaction is upgraded
+        savepoint_count: usize,
+    },
+    Write,
+}
+
+// A connection to the database file.
+//
+// The readers read the snapshots pinned when their transactions started,
+// only one connection can write at the same time.
+pub(crate) struct FileBackend {
+    shared:       Arc<SharedBackend>,
+    page_size:    NonZeroU32,
+    state:        Option<ConnectionState>,
+
+    // the data version when the transaction started
+    view_version: u64,
+
+    // the data version the page cache of this connection is based on
+    seen_version: u64,
+}
+
+pub(super) struct InitDbResult {
+    pub(super) db_file_size: u64,
+    pub(super) codec:        PageCodec,
 }
 
 impl FileBackend {
 
-    fn mk_journal_path(db_path: &Path) -> PathBuf {
+    pub(super) fn mk_journal_path(db_path: &Path) -> PathBuf {
         let mut buf = db_path.to_path_buf();
         let filename = buf.file_name().unwrap().to_str().unwrap();
         let new_filename = String::from(filename) + ".journal";
@@ -34,14 +264,25 @@ impl FileBackend {
         buf
     }
 
-    pub(crate) fn open(path: &Path, page_size: NonZeroU32, config: Arc<Config>) -> DbResult<FileBackend> {
-        let mut file = std::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .read(true)
-            .open(path)?;
+    pub(crate) fn open(path: &Path, config: Arc<Config>) -> DbResult<FileBackend> {
+        FileBackend::open_with_codec(path, config, None)
+    }
 
-        match exclusive_lock_file(&file) {
+    // the codec of a new file is given, instead of made from the config
+    fn open_with_codec(path: &Path, config: Arc<Config>, new_codec: Option<PageCodec>) -> DbResult<FileBackend> {
+        let page_size = match &new_codec {
+            Some(codec) => codec.page_size(),
+            None => NonZeroU32::new(config.page_size).ok_or(DbErr::InvalidPageSize(config.page_size))?,
+        };
+        let mut file = open_file(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .read(true),
+            path,
+        )?;
+
+        match exclusive_lock_file(std_file(&file)) {
             Err(DbErr::Busy) => {
                 return Err(DbErr::DatabaseOccupied);
             }
@@ -51,58 +292,100 @@ impl FileBackend {
             _ => (),
         };
 
-        let init_result = FileBackend::init_db(
-            &mut file,
-            page_size,
-            config.init_block_count,
-            config.serialize_type == SerializeType::Default,
-        )?;
+        let init_result = FileBackend::init_db(&mut file, page_size, &config, new_codec)?;
+        let codec = init_result.codec;
+        let page_size = codec.page_size();
 
         let journal_file_path: PathBuf = FileBackend::mk_journal_path(path);
         let journal_manager = JournalManager::open(
-            &journal_file_path, page_size, init_result.db_file_size
+            &journal_file_path, codec, init_result.db_file_size, config.synchronous, config.clock,
         )?;
 
-        Ok(FileBackend {
-            file: RefCell::new(file),
-            page_size,
+        let files = SharedFiles {
+            path: path.to_path_buf(),
+            main_file: file,
             journal_manager,
             config,
+            #[cfg(feature = "mmap")]
+            main_map: None,
+            last_checkpoint: Instant::now(),
+        };
+        let shared = SharedBackend {
+            files: Mutex::new(files),
+            data_version: AtomicU64::new(0),
+        };
+
+        Ok(FileBackend {
+            shared: Arc::new(shared),
+            page_size,
+            state: None,
+            view_version: 0,
+            seen_version: 0,
         })
     }
 
-    fn force_write_first_block(file: &mut File, page_size: NonZeroU32) -> std::io::Result<RawPage> {
-        let wrapper = HeaderPageWrapper::init(0, page_size);
-        wrapper.0.sync_to_file(file, 0)?;
+    fn force_write_first_block(file: &mut DbFile, codec: &PageCodec, compression: Compression) -> std::io::Result<RawPage> {
+        let mut wrapper = HeaderPageWrapper::init(0, codec.page_size());
+        wrapper.set_compression(compression.to_header());
+        codec.write_page(file, &wrapper.0, 0)?;
+
+        // the empty meta page, an empty slot is taken as damaged,
+        // a database of one page has no room for it
+        let meta_page_id = wrapper.get_meta_page_id();
+        let slot_offset = codec.slot_offset(meta_page_id);
+        if slot_offset < file.metadata()?.len() {
+            codec.write_page(file, &RawPage::new(meta_page_id, codec.page_size()), slot_offset)?;
+        }
         Ok(wrapper.0)
     }
 
-    fn init_db(file: &mut File, page_size: NonZeroU32, init_block_count: NonZeroU64, check_db_version: bool) -> DbResult<InitDbResult> {
+    fn init_db(file: &mut DbFile, page_size: NonZeroU32, config: &Config, new_codec: Option<PageCodec>) -> DbResult<InitDbResult> {
         let meta = file.metadata()?;
         let file_len = meta.len();
+        let key = config.encryption_key.as_ref();
         if file_len == 0 {
-            let expected_file_size: u64 = (page_size.get() as u64) * init_block_count.get();
-            file.set_len(expected_file_size)?;
-            FileBackend::force_write_first_block(file, page_size)?;
-            Ok(InitDbResult { db_file_size: expected_file_size })
-        } else if file_len % page_size.get() as u64 == 0 {
-            if check_db_version {
-                FileBackend::check_db_version(file)?;
+            let codec = new_codec.unwrap_or_else(|| PageCodec::new(page_size, key, config.page_checksum));
+            let expected_file_size: u64 = (page_size.get() as u64) * config.init_block_count.get();
+            file.set_len(codec.file_size_of(expected_file_size))?;
+            FileBackend::force_write_first_block(file, &codec, config.compression)?;
+            if config.synchronous != Synchronous::Off {
+                sync_file(file)?;
             }
-            Ok(InitDbResult { db_file_size: file_len })
-        } else {
-            Err(DbErr::NotAValidDatabase)
+            return Ok(InitDbResult { db_file_size: expected_file_size, codec });
         }
+
+        FileBackend::open_db(file, page_size, config)
     }
 
-    fn check_db_version(file: &mut File) -> DbResult<()> {
-        let mut version = [0u8; 4];
-        file.seek(SeekFrom::Start(32))?;
-        file.read_exact(&mut version)?;
+    // the settings of the codec of an existing file are read from the header page
+    pub(super) fn open_db(file: &mut DbFile, page_size: NonZeroU32, config: &Config) -> DbResult<InitDbResult> {
+        let file_len = file.metadata()?.len();
+        let key = config.encryption_key.as_ref();
+        if file_len == 0 {
+            return Err(DbErr::NotAValidDatabase);
+        }
 
-        if version != DATABASE_VERSION {
+        let codec = match config.serialize_type {
+            SerializeType::Default => PageCodec::detect(file, page_size, key)?,
+            _ => PageCodec::new(page_size, key, false),
+        };
+        if file_len % codec.slot_size() != 0 {
+            return Err(DbErr::NotAValidDatabase);
+        }
+        if config.serialize_type == SerializeType::Default {
+            FileBackend::check_db_version(file, &codec)?;
+        }
+        Ok(InitDbResult { db_file_size: codec.db_size_of(file_len), codec })
+    }
+
+    fn check_db_version(file: &mut DbFile, codec: &PageCodec) -> DbResult<()> {
+        let first_page = codec.read_page(file, 0, 0)?;
+        let version = HeaderPageWrapper::from_raw_page(first_page).get_version();
+
+        // the files without the new features are still 0.0.2.0
+        if version != DATABASE_VERSION && version != FEATURES_DATABASE_VERSION {
             let err = VersionMismatchError {
-                expect_version: DATABASE_VERSION,
+                expect_version: FEATURES_DATABASE_VERSION,
                 actual_version: version,
             };
             return Err(DbErr::VersionMismatch(Box::new(err)))
@@ -112,81 +395,329 @@ impl FileBackend {
     }
 
     #[inline]
-    fn is_journal_full(&self) -> bool {
-        (self.journal_manager.len() as u64) >= self.config.journal_full_size
+    fn is_writer(&self) -> bool {
+        matches!(self.state, Some(ConnectionState::Write))
     }
 
-}
+    fn current_view_version(&self) -> u64 {
+        match self.state {
+            Some(_) => self.view_version,
+            None => self.shared.data_version.load(Ordering::SeqCst),
+        }
+    }
 
-impl Backend for FileBackend {
+    // the size of the database seen by the current transaction
+    fn visible_db_size(&self, files: &SharedFiles) -> u64 {
+        match &self.state {
+            Some(ConnectionState::Write) => files.journal_manager.record_db_size(),
+            Some(ConnectionState::Read { snapshot, .. }) => snapshot.db_file_size,
+            None => files.journal_manager.committed_db_size(),
+        }
+    }
 
-    fn read_page(&self, page_id: u32) -> DbResult<RawPage> {
-        if let Some(page) = self.journal_manager.read_page(page_id)? {
-            return Ok(page);
+    pub(super) fn connect(&self) -> FileBackend {
+        FileBackend {
+            shared: self.shared.clone(),
+            page_size: self.page_size,
+            state: None,
+            view_version: 0,
+            seen_version: 0,
         }
+    }
 
+    // the pages not in the journal are read from the main file,
+    // or the mapping of it
+    pub(super) fn read_page_from(
+        &self,
+        page_id: u32,
+        #[cfg_attr(not(feature = "mmap"), allow(unused_variables))] mapped: bool,
+    ) -> DbResult<RawPage> {
+        let mut files = self.shared.lock_files();
+
+        // the pages after the end are discarded by a truncation,
+        // they are still in the journal and the main file before the checkpoint
         let offset = (page_id as u64) * (self.page_size.get() as u64);
-        let mut result = RawPage::new(page_id, self.page_size);
-        let mut main_file = self.file.borrow_mut();
+        if offset + (self.page_size.get() as u64) > self.visible_db_size(&files) {
+            return Ok(RawPage::new(page_id, self.page_size));
+        }
 
-        crate::polo_log!("read page from main file, id: {}", page_id);
+        let journal_page = match &self.state {
+            Some(ConnectionState::Write) => files.journal_manager.read_page(page_id)?,
+            Some(ConnectionState::Read { snapshot, .. }) =>
+                files.journal_manager.read_snapshot_page(snapshot, page_id)?,
+            None => files.journal_manager.read_committed_page(page_id)?,
+        };
+        if let Some(page) = journal_page {
+            return Ok(page);
+        }
 
-        if main_file.seek(SeekFrom::End(0))? >= offset + (self.page_size.get() as u64) {
-            result.read_from_file(&mut main_file, offset)?;
+        #[cfg(feature = "mmap")]
+        if mapped {
+            return files.read_mapped_page(page_id);
         }
+        files.read_main_page(page_id)
+    }
 
-        Ok(result)
+    fn finish_read(&mut self, snapshot: &ReadSnapshot) -> DbResult<()> {
+        let mut files = self.shared.lock_files();
+        files.journal_manager.finish_read(snapshot);
+        files.try_checkpoint()
+    }
+
+}
+
+impl Backend for FileBackend {
+
+    fn page_size(&self) -> NonZeroU32 {
+        self.page_size
+    }
+
+    fn read_page(&self, page_id: u32) -> DbResult<RawPage> {
+        self.read_page_from(page_id, false)
     }
 
     fn write_page(&mut self, page: &RawPage) -> DbResult<()> {
-        self.journal_manager.append_raw_page(page)
+        if !self.is_writer() {
+            return Err(DbErr::CannotWriteDbWithoutTransaction);
+        }
+        self.shared.lock_files().journal_manager.append_raw_page(page)
     }
 
     fn commit(&mut self) -> DbResult<()> {
-        let mut main_db = self.file.borrow_mut();
-        self.journal_manager.commit()?;
-        if self.is_journal_full() {
-            self.journal_manager.checkpoint_journal(&mut main_db)?;
-            crate::polo_log!("checkpoint journal finished");
+        match self.state.take() {
+            Some(ConnectionState::Write) => {
+                let mut files = self.shared.lock_files();
+                files.journal_manager.commit()?;
+                let version = self.shared.data_version.fetch_add(1, Ordering::SeqCst) + 1;
+                self.seen_version = version;
+                files.try_checkpoint()
+            }
+
+            Some(ConnectionState::Read { snapshot, .. }) => self.finish_read(&snapshot),
+
+            None => Err(DbErr::CannotWriteDbWithoutTransaction),
         }
-        Ok(())
     }
 
     fn db_size(&self) -> u64 {
-        self.journal_manager.record_db_size()
+        let files = self.shared.lock_files();
+        self.visible_db_size(&files)
     }
 
-    fn set_db_size(&mut self, size: u64) -> DbResult<()> {
-        self.journal_manager.expand_db_size(size)
+    fn expand_db_size(&mut self, size: u64) -> DbResult<()> {
+        if !self.is_writer() {
+            return Err(DbErr::CannotWriteDbWithoutTransaction);
+        }
+        self.shared.lock_files().journal_manager.expand_db_size(size)
+    }
+
+    fn truncate(&mut self, size: u64) -> DbResult<()> {
+        if !self.is_writer() {
+            return Err(DbErr::CannotWriteDbWithoutTransaction);
+        }
+        self.shared.lock_files().journal_manager.truncate_db_size(size)
     }
 
     fn transaction_type(&self) -> Option<TransactionType> {
-        self.journal_manager.transaction_type()
+        self.state.as_ref().map(|state| match state {
+            ConnectionState::Read { .. } => TransactionType::Read,
+            ConnectionState::Write => TransactionType::Write,
+        })
     }
 
+    // the snapshot must be the latest one, otherwise the transaction
+    // would write on the data which has been changed by another connection
     fn upgrade_read_transaction_to_write(&mut self) -> DbResult<()> {
-        self.journal_manager.upgrade_read_transaction_to_write()
+        let mut files = self.shared.lock_files();
+
+        let (snapshot, savepoint_count) = match &self.state {
+            Some(ConnectionState::Read { snapshot, savepoint_count }) => (snapshot, *savepoint_count),
+            _ => panic!("can not upgrade transaction because there is no read transaction"),
+        };
+
+        if !files.journal_manager.is_latest_snapshot(snapshot) {
+            return Err(DbErr::Busy);
+        }
+
+        files.journal_manager.start_write_transaction()?;
+        files.journal_manager.finish_read(snapshot);
+        for _ in 0..savepoint_count {
+            files.journal_manager.savepoint()?;
+        }
+
+        self.state = Some(ConnectionState::Write);
+        Ok(())
     }
 
     fn rollback(&mut self) -> DbResult<()> {
-        self.journal_manager.rollback()
+        match self.state.take() {
+            Some(ConnectionState::Write) => self.shared.lock_files().journal_manager.rollback(),
+            Some(ConnectionState::Read { snapshot, .. }) => self.finish_read(&snapshot),
+            None => Err(DbErr::RollbackNotInTransaction),
+        }
     }
 
     fn start_transaction(&mut self, ty: TransactionType) -> DbResult<()> {
-        self.journal_manager.start_transaction(ty)
+        if self.state.is_some() {
+            return Err(DbErr::StartTransactionInAnotherTransaction);
+        }
+
+        let mut files = self.shared.lock_files();
+        let state = match ty {
+            TransactionType::Read => {
+                let snapshot = files.journal_manager.start_read();
+                ConnectionState::Read {
+                    snapshot: Box::new(snapshot),
+                    savepoint_count: 0,
+                }
+            }
+
+            TransactionType::Write => {
+                files.journal_manager.start_write_transaction()?;
+                ConnectionState::Write
+            }
+
+        };
+        // the writers commit with the lock held
+        let view_version = self.shared.data_version.load(Ordering::SeqCst);
+        drop(files);
+
+        self.view_version = view_version;
+        self.state = Some(state);
+
+        Ok(())
+    }
+
+    fn savepoint(&mut self) -> DbResult<()> {
+        match &mut self.state {
+            Some(ConnectionState::Write) => self.shared.lock_files().journal_manager.savepoint(),
+            Some(ConnectionState::Read { savepoint_count, .. }) => {
+                *savepoint_count += 1;
+                Ok(())
+            }
+            None => Err(DbErr::CannotWriteDbWithoutTransaction),
+        }
+    }
+
+    fn rollback_to_savepoint(&mut self, index: usize) -> DbResult<()> {
+        match &mut self.state {
+            Some(ConnectionState::Write) => self.shared.lock_files().journal_manager.rollback_to_savepoint(index),
+            Some(ConnectionState::Read { savepoint_count, .. }) => {
+                *savepoint_count = index + 1;
+                Ok(())
+            }
+            None => Err(DbErr::RollbackNotInTransaction),
+        }
+    }
+
+    fn release_savepoint(&mut self, index: usize) -> DbResult<()> {
+        match &mut self.state {
+            Some(ConnectionState::Write) => self.shared.lock_files().journal_manager.release_savepoint(index),
+            Some(ConnectionState::Read { savepoint_count, .. }) => *savepoint_count = index,
+            None => (),
+        }
+        Ok(())
     }
 
 }
 
+impl BackendExt for FileBackend {
+
+    fn is_changed_by_others(&mut self) -> bool {
+        let version = self.current_view_version();
+        if version == self.seen_version {
+            return false;
+        }
+        self.seen_version = version;
+        true
+    }
+
+    fn new_connection(&self) -> DbResult<Box<dyn BackendExt + Send>> {
+        Ok(Box::new(self.connect()))
+    }
+
+    fn checkpoint(&mut self, mode: CheckpointMode) -> DbResult<bool> {
+        self.shared.lock_files().checkpoint(mode)
+    }
+
+    fn checkpoint_stats(&self) -> DbResult<CheckpointStats> {
+        self.shared.lock_files().journal_manager.stats()
+    }
+
+    fn collect_metrics(&self, metrics: &mut Metrics) {
+        self.shared.lock_files().journal_manager.collect_metrics(metrics)
+    }
+
+    fn export_journal_frames(&self, since: JournalPosition, writer: &mut dyn Write) -> DbResult<JournalPosition> {
+        self.shared.lock_files().journal_manager.export_frames(since, writer)
+    }
+
+    fn journal_position(&self) -> Option<JournalPosition> {
+        match &self.state {
+            Some(ConnectionState::Read { snapshot, .. }) => Some(snapshot.position),
+            _ => None,
+        }
+    }
+
+    fn codec(&self) -> PageCodec {
+        self.shared.lock_files().journal_manager.codec().clone()
+    }
+
+    fn set_codec(&mut self, codec: PageCodec) -> DbResult<()> {
+        if self.state.is_some() {
+            return Err(DbErr::Busy);
+        }
+        self.shared.lock_files().rewrite(codec)
+    }
+
+    // a database file left by a crashed vacuum is not a valid copy,
+    // it's removed with its journal
+    fn open_compacted(&self, config: Arc<Config>) -> DbResult<Option<Box<dyn BackendExt + Send>>> {
+        if self.state.is_some() {
+            return Err(DbErr::Busy);
+        }
+
+        let (tmp_path, codec) = {
+            let files = self.shared.lock_files();
+            (files.vacuum_path(), files.journal_manager.codec().clone())
+        };
+        for path in [FileBackend::mk_journal_path(&tmp_path), tmp_path.clone()] {
+            match std::fs::remove_file(&path) {
+                Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+                _ => (),
+            }
+        }
+
+        let backend = FileBackend::open_with_codec(&tmp_path, config, Some(codec))?;
+        Ok(Some(Box::new(backend)))
+    }
+
+    // the copy is made in the last transaction of this connection,
+    // it's out of date if another connection has committed since then
+    fn swap_compacted(&mut self) -> DbResult<u64> {
+        if self.state.is_some() {
+            return Err(DbErr::Busy);
+        }
+
+        let mut files = self.shared.lock_files();
+        if self.shared.data_version.load(Ordering::SeqCst) != self.view_version {
+            let _ = std::fs::remove_file(files.vacuum_path());
+            return Err(DbErr::Busy);
+        }
+
+        let db_size = files.swap_compacted()?;
+        self.shared.data_version.fetch_add(1, Ordering::SeqCst);
+        Ok(db_size)
+    }
+
+}
+
+// the files are closed when the last connection is dropped
 impl Drop for FileBackend {
 
     fn drop(&mut self) {
-        let mut main_db = self.file.borrow_mut();
-        let _ = unlock_file(&main_db);
-        let result = self.journal_manager.checkpoint_journal(&mut main_db);
-        if result.is_ok() {
-            let path = self.journal_manager.path();
-            let _ = std::fs::remove_file(path);
+        if self.state.is_some() {
+            let _ = self.rollback();
         }
     }
 
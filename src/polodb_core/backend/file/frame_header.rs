@@ -6,6 +6,11 @@ pub(super) struct FrameHeader {
     // page_id * offset represents the real offset from the beginning
     pub(super) page_id:       u32,  // offset 0
 
+    // usually 0
+    // the seconds since the unix epoch when the transaction is committed,
+    // if this frame is the final commit of a transaction
+    pub(super) commit_time:   u32,  // offset 4
+
     // usually 0
     // if this frame is the final commit of a transaction
     // this field represents the read db_size
@@ -25,6 +30,9 @@ impl FrameHeader {
 
         let page_id = u32::from_be_bytes(buffer);
 
+        buffer.copy_from_slice(&bytes[4..8]);
+        let commit_time = u32::from_be_bytes(buffer);
+
         let mut buffer: [u8; 8] = [0; 8];
         buffer.copy_from_slice(&bytes[8..16]);
         let db_size = u64::from_be_bytes(buffer);
@@ -39,6 +47,7 @@ impl FrameHeader {
 
         FrameHeader {
             page_id,
+            commit_time,
             db_size,
             salt1, salt2
         }
@@ -49,6 +58,9 @@ impl FrameHeader {
         let page_id_be = self.page_id.to_be_bytes();
         buffer[0..4].copy_from_slice(&page_id_be);
 
+        let commit_time_be = self.commit_time.to_be_bytes();
+        buffer[4..8].copy_from_slice(&commit_time_be);
+
         let db_size_be = self.db_size.to_be_bytes();
         buffer[8..16].copy_from_slice(&db_size_be);
 
@@ -0,0 +1,212 @@
+use std::io::{Read, Write};
+use std::path::{Path, PathBuf};
+use std::num::NonZeroU32;
+use super::frame_header::FrameHeader;
+use crate::backend::{PageCodec, crc64};
+use crate::page::RawPage;
+use crate::db::JournalPosition;
+use crate::{DbResult, DbErr};
+
+static HEADER_DESP: &str        = "PoloDB Journal Frames v0.2";
+static SEGMENT_EXTENSION: &str  = "frames";
+const HEADER_SIZE: u64       = 80;
+const FRAME_HEADER_SIZE: u64 = 40;
+
+// name:        32 bytes
+// version:     4bytes(offset 32)
+// page_size:   4bytes(offset 36) the size of the slot of an encrypted page
+// salt_1:      4bytes(offset 40)
+// salt_2:      4bytes(offset 44)
+// since:       4bytes(offset 48) the index of the first frame in the journal
+// frame_count: 4bytes(offset 52)
+// from:        12bytes(offset 56) the position of the journal where the frames continue
+// checksum before 72:   8bytes(offset 72)
+// frames begin: 80 bytes, the same as the frames in the journal
+pub(super) struct FramesHeader {
+    pub(super) from:        JournalPosition,
+    pub(super) slot_size:   u32,
+    pub(super) salt1:       u32,
+    pub(super) salt2:       NonZeroU32,
+    pub(super) since:       u32,
+    pub(super) frame_count: u32,
+}
+
+impl FramesHeader {
+
+    pub(super) fn write_to(&self, writer: &mut dyn Write) -> std::io::Result<()> {
+        let mut header64: [u8; HEADER_SIZE as usize] = [0; HEADER_SIZE as usize];
+
+        let title_bytes = HEADER_DESP.as_bytes();
+        header64[0..title_bytes.len()].copy_from_slice(title_bytes);
+        header64[32..36].copy_from_slice(&[0, 0, 2, 0]);
+        header64[36..40].copy_from_slice(&self.slot_size.to_be_bytes());
+        header64[40..44].copy_from_slice(&self.salt1.to_be_bytes());
+        header64[44..48].copy_from_slice(&self.salt2.get().to_be_bytes());
+        header64[48..52].copy_from_slice(&self.since.to_be_bytes());
+        header64[52..56].copy_from_slice(&self.frame_count.to_be_bytes());
+        header64[56..60].copy_from_slice(&self.from.salt1.to_be_bytes());
+        header64[60..64].copy_from_slice(&self.from.salt2.to_be_bytes());
+        header64[64..68].copy_from_slice(&self.from.frame.to_be_bytes());
+
+        let checksum = crc64(&header64[0..72]);
+        header64[72..80].copy_from_slice(&checksum.to_be_bytes());
+
+        writer.write_all(&header64)
+    }
+
+    fn read_from(reader: &mut dyn Read) -> DbResult<FramesHeader> {
+        let mut header64: [u8; HEADER_SIZE as usize] = [0; HEADER_SIZE as usize];
+        reader.read_exact(&mut header64)?;
+
+        let mut buffer: [u8; 8] = [0; 8];
+        buffer.copy_from_slice(&header64[72..80]);
+        if crc64(&header64[0..72]) != u64::from_be_bytes(buffer) {
+            return Err(DbErr::JournalChecksumMismatch);
+        }
+
+        let title_bytes = HEADER_DESP.as_bytes();
+        if &header64[0..title_bytes.len()] != title_bytes {
+            return Err(DbErr::InvalidJournalFrames("not a stream of journal frames"));
+        }
+
+        let read_u32 = |offset: usize| {
+            let mut buffer: [u8; 4] = [0; 4];
+            buffer.copy_from_slice(&header64[offset..(offset + 4)]);
+            u32::from_be_bytes(buffer)
+        };
+
+        let salt2 = NonZeroU32::new(read_u32(44))
+            .ok_or(DbErr::InvalidJournalFrames("the salt is zero"))?;
+
+        Ok(FramesHeader {
+            from: JournalPosition {
+                salt1: read_u32(56),
+                salt2: read_u32(60),
+                frame: read_u32(64),
+            },
+            slot_size: read_u32(36),
+            salt1: read_u32(40),
+            salt2,
+            since: read_u32(48),
+            frame_count: read_u32(52),
+        })
+    }
+
+}
+
+// the pages of a transaction committed by another database
+pub(crate) struct JournalTransaction {
+    pub(crate) pages:       Vec<RawPage>,
+    pub(crate) db_size:     u64,
+
+    // the seconds since the unix epoch, 0 if it's unknown
+    pub(crate) commit_time: u32,
+}
+
+// the transactions of a stream, and the positions of the journal
+// before and after them
+pub(crate) struct JournalFrames {
+    pub(crate) from:         JournalPosition,
+    pub(crate) to:           JournalPosition,
+    pub(crate) transactions: Vec<JournalTransaction>,
+}
+
+// the segments of the archive are named by the sequence numbers,
+// they are in order when they are sorted by name
+pub(crate) fn list_archived_segments(archive_dir: &Path) -> DbResult<Vec<PathBuf>> {
+    let mut result = vec![];
+    for entry in std::fs::read_dir(archive_dir)? {
+        let path = entry?.path();
+        if path.extension().is_some_and(|ext| ext == SEGMENT_EXTENSION) {
+            result.push(path);
+        }
+    }
+    result.sort();
+    Ok(result)
+}
+
+pub(super) fn next_segment_path(archive_dir: &Path) -> DbResult<PathBuf> {
+    let last_sequence = list_archived_segments(archive_dir)?
+        .last()
+        .and_then(|path| path.file_stem()?.to_str()?.parse::<u64>().ok())
+        .unwrap_or(0);
+    Ok(archive_dir.join(format!("{:016}.{}", last_sequence + 1, SEGMENT_EXTENSION)))
+}
+
+/**
+ * read and check all the frames exported by JournalManager::export_frames,
+ * nothing is returned if any of them is damaged,
+ * so they can be applied atomically
+ */
+pub(crate) fn read_journal_frames(reader: &mut dyn Read, codec: &PageCodec) -> DbResult<JournalFrames> {
+    let header = FramesHeader::read_from(reader)?;
+
+    let expected_slot_size = codec.slot_size() as u32;
+    if header.slot_size != expected_slot_size {
+        return Err(DbErr::JournalPageSizeMismatch(header.slot_size, expected_slot_size));
+    }
+
+    // the frames are exported from the position, or from the beginning
+    // of the journal if the position is the end of the last one
+    let continues = (header.from.salt1 == header.salt1 && header.from.salt2 == header.salt2.get()
+        && header.from.frame == header.since) || header.since == 0;
+    if !continues {
+        return Err(DbErr::InvalidJournalFrames("the frames don't continue from the position"));
+    }
+
+    let frame_size = (codec.slot_size() + FRAME_HEADER_SIZE) as usize;
+    let mut result = vec![];
+    let mut pages = vec![];
+
+    for _ in 0..header.frame_count {
+        let mut bytes = vec![0; frame_size];
+        reader.read_exact(&mut bytes)?;
+
+        let mut buffer: [u8; 8] = [0; 8];
+        buffer.copy_from_slice(&bytes[24..32]);
+        let checksum1 = u64::from_be_bytes(buffer);
+
+        buffer.copy_from_slice(&bytes[32..40]);
+        let checksum2 = u64::from_be_bytes(buffer);
+
+        if crc64(&bytes[0..24]) != checksum1 {
+            return Err(DbErr::JournalChecksumMismatch);
+        }
+
+        let slot = bytes.split_off(FRAME_HEADER_SIZE as usize);
+        if crc64(&slot) != checksum2 {
+            return Err(DbErr::JournalChecksumMismatch);
+        }
+
+        let frame_header = FrameHeader::from_bytes(&bytes[0..24]);
+        if frame_header.salt1 != header.salt1 || frame_header.salt2 != header.salt2 {
+            return Err(DbErr::SaltMismatch);
+        }
+
+        pages.push(codec.decode(frame_header.page_id, slot)?);
+
+        // is a commit frame
+        if frame_header.db_size != 0 {
+            result.push(JournalTransaction {
+                pages: std::mem::take(&mut pages),
+                db_size: frame_header.db_size,
+                commit_time: frame_header.commit_time,
+            });
+        }
+    }
+
+    if !pages.is_empty() {
+        return Err(DbErr::InvalidJournalFrames("the last transaction is not committed"));
+    }
+
+    let to = match header.since + header.frame_count {
+        0 => header.from,
+        frame => JournalPosition { salt1: header.salt1, salt2: header.salt2.get(), frame },
+    };
+
+    Ok(JournalFrames {
+        from: header.from,
+        to,
+        transactions: result,
+    })
+}
@@ -1,19 +1,23 @@
-use std::fs::File;
 use std::path::{Path, PathBuf};
 use std::collections::BTreeMap;
 use std::io::{Seek, Write, SeekFrom, Read};
 use std::cell::{Cell, RefCell};
 use std::num::NonZeroU32;
+use std::time::{SystemTime, UNIX_EPOCH};
 use getrandom::getrandom;
-use crc64fast::Digest;
-use super::transaction_state::TransactionState;
+use super::transaction_state::{TransactionState, ReadSnapshot};
 use super::frame_header::FrameHeader;
+use super::journal_frames::{FramesHeader, next_segment_path};
 use crate::transaction::TransactionType;
 use crate::page::RawPage;
-use crate::DbResult;
+use crate::backend::{PageCodec, crc64};
+use crate::{DbResult, Synchronous};
 use crate::error::DbErr;
 use crate::file_lock::*;
 use crate::dump::{JournalDump, JournalFrameDump};
+use crate::db::{CheckpointStats, JournalPosition};
+use crate::metrics::{self, Metrics};
+use super::sync::{DbFile, open_file, std_file, sync_file};
 
 static HEADER_DESP: &str       = "PoloDB Journal v0.2";
 const JOURNAL_DATA_BEGIN: u64 = 64;
@@ -21,20 +25,25 @@ const FRAME_HEADER_SIZE: u64  = 40;
 
 // name:       32 bytes
 // version:    4bytes(offset 32)
-// page_size:  4bytes(offset 36)
+// page_size:  4bytes(offset 36) the size of the slot of an encrypted page
 // salt_1:     4bytes(offset 40)
 // salt_2:     4bytes(offset 44)
 // checksum before 48:   8bytes(offset 48)
 // data begin: 64 bytes
 pub(super) struct JournalManager {
     file_path:         PathBuf,
-    journal_file:      RefCell<File>,
+    journal_file:      RefCell<DbFile>,
     version:           [u8; 4],
     page_size:         NonZeroU32,
+    codec:             PageCodec,
     salt1:             u32,
     salt2:             NonZeroU32,
     transaction_state: Option<Box<TransactionState>>,
 
+    // the states of the transaction when the savepoints are created,
+    // the frame count of each state is the marker to truncate the journal
+    savepoints:        Vec<TransactionState>,
+
     // origin_state
     db_file_size:      u64,
 
@@ -43,8 +52,34 @@ pub(super) struct JournalManager {
 
     // count of all frames
     count:             u32,
+
+    // reader_id => frame count of the snapshot,
+    // the frames committed after the oldest snapshot can not be checkpointed
+    readers:           BTreeMap<u64, u32>,
+    next_reader_id:    u64,
+
+    // increased after every checkpoint, a snapshot taken
+    // before the checkpoint reads the main file only
+    generation:        u64,
+
+    // the end of the journal before the last checkpoint,
+    // the exports continue from it in the new journal
+    previous:          Option<JournalPosition>,
+
+    // the counters since the journal is opened
+    frames_appended:   u64,
+    checkpoints:       u64,
+
+    // the file is not changed when it's opened in read-only mode
+    read_only:         bool,
+
+    synchronous:       Synchronous,
+
+    // the commit times of the frames are read from it
+    clock:             fn() -> SystemTime,
 }
 
+
 fn generate_a_salt() -> u32 {
     let mut buf: [u8; 4] = [0; 4];
     getrandom(&mut buf).unwrap();
@@ -59,50 +94,101 @@ fn generate_a_nonzero_salt() -> NonZeroU32 {
     NonZeroU32::new(salt).unwrap()
 }
 
-fn crc64(bytes: &[u8]) -> u64 {
-    let mut c = Digest::new();
-    c.write(bytes);
-    c.sum64()
-}
-
 impl JournalManager {
 
-    pub(super) fn open(path: &Path, page_size: NonZeroU32, db_file_size: u64) -> DbResult<JournalManager> {
-        let journal_file = std::fs::OpenOptions::new()
-            .create(true)
-            .write(true)
-            .read(true)
-            .open(path)?;
+    pub(super) fn open(
+        path: &Path, codec: PageCodec, db_file_size: u64,
+        synchronous: Synchronous, clock: fn() -> SystemTime,
+    ) -> DbResult<JournalManager> {
+        let journal_file = open_file(
+            std::fs::OpenOptions::new()
+                .create(true)
+                .write(true)
+                .read(true),
+            path,
+        )?;
         let meta = journal_file.metadata()?;
 
-        let file_path: PathBuf = path.to_path_buf();
-        let mut result = JournalManager {
-            file_path,
+        let mut result = JournalManager::new(path, journal_file, codec, db_file_size, false, synchronous, clock);
+
+        // a journal without frames is initialized again,
+        // the header may be written with the codec before the pages are rewritten
+        let mut file_len = meta.len();
+        if file_len > JOURNAL_DATA_BEGIN && !result.read_and_check_header(file_len)? {
+            result.journal_file.borrow().set_len(0)?;
+            file_len = 0;
+        }
+        if file_len <= JOURNAL_DATA_BEGIN {
+            result.init_header_to_file()?;
+        }
+
+        {
+            let mut journal_file = result.journal_file.borrow_mut();
+            journal_file.seek(SeekFrom::Start(JOURNAL_DATA_BEGIN))?;
+        }
+        result.load_all_pages(file_len)?;
+
+        Ok(result)
+    }
+
+    // the committed frames of an existing journal,
+    // the journal is not created if there is none
+    pub(super) fn open_read_only(path: &Path, codec: PageCodec, db_file_size: u64) -> DbResult<Option<JournalManager>> {
+        let journal_file = match open_file(std::fs::OpenOptions::new().read(true), path) {
+            Ok(file) => file,
+            Err(err) if err.kind() == std::io::ErrorKind::NotFound => return Ok(None),
+            Err(err) => return Err(err.into()),
+        };
+        let meta = journal_file.metadata()?;
+        if meta.len() <= JOURNAL_DATA_BEGIN {
+            return Ok(None);
+        }
+
+        let mut result = JournalManager::new(path, journal_file, codec, db_file_size, true, Synchronous::Off, SystemTime::now);
+        if !result.read_and_check_header(meta.len())? {
+            return Ok(None);
+        }
+
+        {
+            let mut journal_file = result.journal_file.borrow_mut();
+            journal_file.seek(SeekFrom::Start(JOURNAL_DATA_BEGIN))?;
+        }
+        result.load_all_pages(meta.len())?;
+
+        Ok(Some(result))
+    }
+
+    fn new(
+        path: &Path, journal_file: DbFile, codec: PageCodec, db_file_size: u64,
+        read_only: bool, synchronous: Synchronous, clock: fn() -> SystemTime,
+    ) -> JournalManager {
+        JournalManager {
+            file_path: path.to_path_buf(),
             journal_file: RefCell::new(journal_file),
             version: [0, 0, 1, 0],
-            page_size,
+            page_size: codec.page_size(),
+            codec,
             db_file_size,
             salt1: generate_a_salt(),
             salt2: generate_a_nonzero_salt(),
             transaction_state: None,
+            savepoints: Vec::new(),
 
             offset_map: BTreeMap::new(),
             count: 0,
-        };
 
-        if meta.len() == 0 {  // init the file
-            result.init_header_to_file()?;
-        } else {
-            result.read_and_check_from_file()?;
-        }
+            readers: BTreeMap::new(),
+            next_reader_id: 0,
+            generation: 0,
+            previous: None,
 
-        {
-            let mut journal_file = result.journal_file.borrow_mut();
-            journal_file.seek(SeekFrom::Start(JOURNAL_DATA_BEGIN))?;
-        }
-        result.load_all_pages(meta.len())?;
+            frames_appended: 0,
+            checkpoints: 0,
 
-        Ok(result)
+            read_only,
+            synchronous,
+            clock,
+        }
     }
 
     fn init_header_to_file(&mut self) -> DbResult<()> {
@@ -120,8 +206,9 @@ impl JournalManager {
         // copy version
         header48[32..36].copy_from_slice(&self.version);
 
-        // write page_size
-        let page_size_be = self.page_size.get().to_be_bytes();
+        // write page_size, the journal of an encrypted database
+        // can not be read without the key
+        let page_size_be = (self.codec.slot_size() as u32).to_be_bytes();
         header48[36..40].copy_from_slice(&page_size_be);
 
         let salt_1_be = self.salt1.to_be_bytes();
@@ -140,6 +227,12 @@ impl JournalManager {
         journal_file.seek(SeekFrom::Start(48))?;
         journal_file.write_all(&checksum_be)?;
 
+        // the header is synced before any frame is appended after it,
+        // a torn header followed by valid frames is a damaged journal
+        if self.synchronous != Synchronous::Off {
+            sync_file(&journal_file)?;
+        }
+
         Ok(())
     }
 
@@ -153,23 +246,22 @@ impl JournalManager {
         let checksum = crc64(&header48);
         let checksum_from_file = self.read_checksum_from_file()?;
         if checksum != checksum_from_file {
-            return Err(DbErr::ChecksumMismatch);
+            return Err(DbErr::JournalChecksumMismatch);
         }
 
         // copy version
         self.version.copy_from_slice(&header48[32..36]);
 
-        self.page_size = NonZeroU32::new({
+        {
             let mut buffer: [u8; 4] = [0; 4];
             buffer.copy_from_slice(&header48[36..40]);
             let actual_page_size = u32::from_be_bytes(buffer);
 
-            if actual_page_size != self.page_size.get() {
-                return Err(DbErr::JournalPageSizeMismatch(actual_page_size, self.page_size.get()));
+            let expected_page_size = self.codec.slot_size() as u32;
+            if actual_page_size != expected_page_size {
+                return Err(DbErr::JournalPageSizeMismatch(actual_page_size, expected_page_size));
             }
-
-            actual_page_size
-        }).unwrap();
+        }
 
         let mut buffer: [u8; 4] = [0; 4];
         buffer.copy_from_slice(&header48[40..44]);
@@ -182,6 +274,47 @@ impl JournalManager {
         Ok(())
     }
 
+    // a torn header is only dropped if no valid frame follows it,
+    // otherwise the journal is damaged and kept for the user
+    fn read_and_check_header(&mut self, file_len: u64) -> DbResult<bool> {
+        match self.read_and_check_from_file() {
+            Ok(()) => Ok(true),
+            Err(DbErr::JournalChecksumMismatch) => {
+                if self.first_frame_is_valid(file_len)? {
+                    return Err(DbErr::JournalChecksumMismatch);
+                }
+                Ok(false)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    // the salts of a torn header can't be trusted,
+    // so only the checksums of the frame are checked
+    fn first_frame_is_valid(&self, file_len: u64) -> DbResult<bool> {
+        let frame_size = self.full_frame_size();
+        if file_len < JOURNAL_DATA_BEGIN + frame_size {
+            return Ok(false);
+        }
+
+        let mut bytes: Vec<u8> = vec![0; frame_size as usize];
+        {
+            let mut journal_file = self.journal_file.borrow_mut();
+            journal_file.seek(SeekFrom::Start(JOURNAL_DATA_BEGIN))?;
+            journal_file.read_exact(&mut bytes)?;
+        }
+
+        let mut buffer: [u8; 8] = [0; 8];
+        buffer.copy_from_slice(&bytes[24..32]);
+        let checksum1 = u64::from_be_bytes(buffer);
+
+        buffer.copy_from_slice(&bytes[32..40]);
+        let checksum2 = u64::from_be_bytes(buffer);
+
+        Ok(crc64(&bytes[0..24]) == checksum1
+            && crc64(&bytes[(FRAME_HEADER_SIZE as usize)..]) == checksum2)
+    }
+
     fn read_checksum_from_file(&self) -> DbResult<u64> {
         let mut journal_file = self.journal_file.borrow_mut();
         journal_file.seek(SeekFrom::Start(48))?;
@@ -198,7 +331,7 @@ impl JournalManager {
 
     #[inline]
     fn full_frame_size(&self) -> u64 {
-        (self.page_size.get() as u64) + FRAME_HEADER_SIZE
+        self.codec.slot_size() + FRAME_HEADER_SIZE
     }
 
     fn load_all_pages(&mut self, file_size: u64) -> DbResult<()> {
@@ -225,7 +358,10 @@ impl JournalManager {
             match self.check_and_load_frame(current_pos, &buffer, &is_commit) {
                 Ok(()) => (),
                 Err(DbErr::SaltMismatch) |
-                Err(DbErr::ChecksumMismatch) => {
+                Err(DbErr::JournalChecksumMismatch) => {
+                    if self.read_only {
+                        break;
+                    }
                     let mut journal_file = self.journal_file.borrow_mut();
                     journal_file.set_len(current_pos)?;  // trim the tail
                     journal_file.seek(SeekFrom::End(0))?;  // recover position
@@ -256,7 +392,11 @@ impl JournalManager {
 
     fn recover_file_and_state(&mut self) -> DbResult<()> {
         self.transaction_state = None;
-        let frame_size = FRAME_HEADER_SIZE + (self.page_size.get() as u64);
+        self.savepoints.clear();
+        if self.read_only {
+            return Ok(());
+        }
+        let frame_size = self.full_frame_size();
         let expected_journal_file_size = JOURNAL_DATA_BEGIN + frame_size * (self.count as u64);
         let mut journal_file = self.journal_file.borrow_mut();
         journal_file.set_len(expected_journal_file_size)?;
@@ -265,7 +405,6 @@ impl JournalManager {
     }
 
     fn check_and_load_frame(&mut self, current_pos: u64, bytes: &[u8], is_commit: &Cell<bool>) -> DbResult<()> {
-        let frame_header = FrameHeader::from_bytes(&bytes[0..24]);
         let checksum1 = {
             let mut buffer: [u8; 8] = [0; 8];
             buffer.copy_from_slice(&bytes[24..32]);
@@ -281,38 +420,47 @@ impl JournalManager {
         let actual_header_checksum = crc64(&bytes[0..24]);
 
         if actual_header_checksum != checksum1 {
-            return Err(DbErr::ChecksumMismatch);
+            return Err(DbErr::JournalChecksumMismatch);
         }
 
         let actual_page_checksum = crc64(&bytes[(FRAME_HEADER_SIZE as usize)..]);
 
         if actual_page_checksum != checksum2 {
-            return Err(DbErr::ChecksumMismatch);
+            return Err(DbErr::JournalChecksumMismatch);
         }
 
+        // a torn frame is not parsed
+        let frame_header = FrameHeader::from_bytes(&bytes[0..24]);
         if frame_header.salt1 != self.salt1 || frame_header.salt2 != self.salt2 {
             return Err(DbErr::SaltMismatch);
         }
 
-        // load frame
-        self.offset_map.insert(frame_header.page_id, current_pos);
+        // load frame, it's visible after the transaction is committed
+        let state = self.transaction_state.as_mut().unwrap();
+        state.offset_map.insert(frame_header.page_id, current_pos);
 
         // is a commit frame
         if frame_header.db_size != 0 {
-            self.db_file_size = frame_header.db_size;
+            state.db_file_size = frame_header.db_size;
             is_commit.set(true);
         }
         Ok(())
     }
 
-    fn merge_transaction_state(&mut self) -> TransactionType {
+    fn merge_transaction_state(&mut self) {
         let state = self.transaction_state.take().unwrap();
+        self.savepoints.clear();
         for (page_id, offset) in &state.offset_map {
             self.offset_map.insert(*page_id, *offset);
         }
         self.db_file_size = state.db_file_size;
         self.count = state.frame_count;
-        state.ty
+
+        // the frames of the pages discarded by a truncation
+        // should not be written back to the main file
+        let page_size = self.page_size.get() as u64;
+        let db_file_size = self.db_file_size;
+        self.offset_map.retain(|page_id, _| (*page_id as u64 + 1) * page_size <= db_file_size);
     }
 
     pub(super) fn expand_db_size(&mut self, size: u64) -> DbResult<()> {
@@ -323,6 +471,21 @@ impl JournalManager {
         Err(DbErr::CannotWriteDbWithoutTransaction)
     }
 
+    pub(super) fn truncate_db_size(&mut self, size: u64) -> DbResult<()> {
+        match &mut self.transaction_state {
+            Some(state) if state.ty == TransactionType::Write => {
+                state.db_file_size = size;
+                Ok(())
+            }
+            _ => Err(DbErr::CannotWriteDbWithoutTransaction),
+        }
+    }
+
+    #[inline]
+    pub(super) fn committed_db_size(&self) -> u64 {
+        self.db_file_size
+    }
+
     pub(super) fn record_db_size(&self) -> u64 {
         match &self.transaction_state {
             Some(state) => state.db_file_size,
@@ -339,6 +502,9 @@ impl JournalManager {
         let mut frame_header = FrameHeader::from_bytes(&data);
 
         frame_header.db_size = self.db_file_size;
+        frame_header.commit_time = (self.clock)()
+            .duration_since(UNIX_EPOCH)
+            .map_or(0, |duration| duration.as_secs() as u32);
 
         // update header
         let mut header24: [u8; 24] = [0; 24];
@@ -360,7 +526,7 @@ impl JournalManager {
     // checksum1:    8 bytes(offset 24)  header24 checksum
     // checksum2:    8 bytes(offset 32)  page checksum
     // data_begin:   page size(offset 40)
-    fn append_frame_header(journal_file: &mut File, frame_header: &FrameHeader, checksum2: u64) -> std::io::Result<()> {
+    fn append_frame_header(journal_file: &mut DbFile, frame_header: &FrameHeader, checksum2: u64) -> std::io::Result<()> {
         let mut header24: [u8; 24] = [0; 24];
         frame_header.to_bytes(&mut header24);
 
@@ -383,26 +549,29 @@ impl JournalManager {
         };
 
         let mut journal_file = self.journal_file.borrow_mut();
-        let start_pos: u64 = JOURNAL_DATA_BEGIN + (state.frame_count as u64) * (self.page_size.get() as u64 + FRAME_HEADER_SIZE);
+        let start_pos: u64 = JOURNAL_DATA_BEGIN + (state.frame_count as u64) * self.full_frame_size();
         journal_file.seek(SeekFrom::Start(start_pos))?;
 
         let frame_header = FrameHeader {
             page_id: raw_page.page_id,
+            commit_time: 0,
             db_size: 0,
             salt1: self.salt1,
             salt2: self.salt2,
         };
 
         // calculate checksum of page data
-        let checksum2 = crc64(&raw_page.data);
+        let data = self.codec.encode(raw_page);
+        let checksum2 = crc64(&data);
 
         JournalManager::append_frame_header(&mut journal_file, &frame_header, checksum2)?;
 
-        journal_file.write_all(&raw_page.data)?;
+        journal_file.write_all(&data)?;
 
         let state = self.transaction_state.as_mut().unwrap();
         state.offset_map.insert(raw_page.page_id, start_pos);
         state.frame_count += 1;
+        metrics::increase(&mut self.frames_appended, metrics::JOURNAL_FRAMES, 1);
 
         let expected_db_size = (raw_page.page_id as u64 + 1) * (self.page_size.get() as u64);
         if expected_db_size > state.db_file_size {
@@ -414,64 +583,154 @@ impl JournalManager {
         Ok(())
     }
 
-    pub(crate) fn read_page(&self, page_id: u32) -> std::io::Result<Option<RawPage>> {
-        let offset = match &self.transaction_state {
+    // the uncommitted frames of the writer are visible
+    fn writer_page_offset(&self, page_id: u32) -> Option<u64> {
+        self.transaction_state
+            .as_ref()
+            .and_then(|state| state.offset_map.get(&page_id))
+            .or_else(|| self.offset_map.get(&page_id))
+            .copied()
+    }
 
-            // currently in transaction state
-            // find it in state firstly
-            Some(state) => {
-                match state.offset_map.get(&page_id) {
-                    Some(offset) => *offset,
+    fn read_frame_page(&self, page_id: u32, offset: u64) -> DbResult<RawPage> {
+        let data_offset = offset + FRAME_HEADER_SIZE;
 
-                    // not found in transaction_state
-                    // find offset in original journal
-                    None => {
-                        match self.offset_map.get(&page_id) {
-                            Some(offset) => *offset,
-                            None => return Ok(None),
-                        }
-                    }
-                }
-            }
+        let mut journal_file = self.journal_file.borrow_mut();
+        let result = self.codec.read_page(&mut *journal_file, page_id, data_offset)?;
 
-            None => {
-                match self.offset_map.get(&page_id) {
-                    Some(offset) => *offset,
-                    None => return Ok(None),
-                }
-            }
+        crate::polo_log!("read page from journal, page_id: {}, data_offset:\t\t0x{:0>8X}", page_id, offset);
+
+        Ok(result)
+    }
+
+    pub(crate) fn read_page(&self, page_id: u32) -> DbResult<Option<RawPage>> {
+        match self.writer_page_offset(page_id) {
+            Some(offset) => self.read_frame_page(page_id, offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub(super) fn read_committed_page(&self, page_id: u32) -> DbResult<Option<RawPage>> {
+        match self.offset_map.get(&page_id) {
+            Some(offset) => self.read_frame_page(page_id, *offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    pub(super) fn read_snapshot_page(&self, snapshot: &ReadSnapshot, page_id: u32) -> DbResult<Option<RawPage>> {
+        // the frames of the snapshot have been checkpointed,
+        // and the main file has not been changed since then
+        if snapshot.generation != self.generation {
+            return Ok(None);
+        }
+
+        match snapshot.offset_map.get(&page_id) {
+            Some(offset) => self.read_frame_page(page_id, *offset).map(Some),
+            None => Ok(None),
+        }
+    }
+
+    // the position after the frame at the index, the beginning
+    // of the journal is the end of the last one
+    pub(super) fn position_at(&self, frame: u32) -> JournalPosition {
+        match self.previous {
+            Some(previous) if frame == 0 => previous,
+            _ => JournalPosition {
+                salt1: self.salt1,
+                salt2: self.salt2.get(),
+                frame,
+            },
+        }
+    }
 
+    // the committed frames after the position are copied as they are,
+    // another database checks and applies them by read_journal_frames
+    pub(super) fn export_frames(&self, from: JournalPosition, writer: &mut dyn Write) -> DbResult<JournalPosition> {
+        let since = if from.salt1 == self.salt1 && from.salt2 == self.salt2.get() && from.frame <= self.count {
+            from.frame
+        } else if Some(from) == self.previous {
+            0
+        } else {
+            return Err(DbErr::InvalidJournalFrames("the frames after the position have been checkpointed"));
         };
 
-        let data_offset = offset + FRAME_HEADER_SIZE;
+        let header = FramesHeader {
+            from,
+            slot_size: self.codec.slot_size() as u32,
+            salt1: self.salt1,
+            salt2: self.salt2,
+            since,
+            frame_count: self.count - since,
+        };
+        header.write_to(writer)?;
 
+        let frame_size = self.full_frame_size();
         let mut journal_file = self.journal_file.borrow_mut();
-        let mut result = RawPage::new(page_id, self.page_size);
-        result.read_from_file(&mut journal_file, data_offset)?;
+        journal_file.seek(SeekFrom::Start(JOURNAL_DATA_BEGIN + (since as u64) * frame_size))?;
 
-        crate::polo_log!("read page from journal, page_id: {}, data_offset:\t\t0x{:0>8X}", page_id, offset);
+        let frames_size = ((self.count - since) as u64) * frame_size;
+        let copied = std::io::copy(&mut (&mut *journal_file).take(frames_size), writer)?;
+        if copied != frames_size {
+            return Err(DbErr::JournalChecksumMismatch);
+        }
 
-        Ok(Some(result))
+        Ok(self.position_at(self.count))
     }
 
-    pub(crate) fn checkpoint_journal(&mut self, db_file: &mut File) -> DbResult<()> {
+    // the frames are kept in a new segment of the archive before the journal is cleared,
+    // the segment is renamed after it's written, so it's never seen half written
+    pub(super) fn archive_frames(&self, archive_dir: &Path) -> DbResult<()> {
+        if self.count == 0 {
+            return Ok(());
+        }
+
+        std::fs::create_dir_all(archive_dir)?;
+        let segment_path = next_segment_path(archive_dir)?;
+        let tmp_path = segment_path.with_extension("tmp");
+        {
+            let mut segment_file = open_file(
+                std::fs::OpenOptions::new().create(true).write(true).truncate(true),
+                &tmp_path,
+            )?;
+            self.export_frames(self.position_at(0), &mut segment_file)?;
+            if self.synchronous != Synchronous::Off {
+                sync_file(&segment_file)?;
+            }
+        }
+        std::fs::rename(&tmp_path, &segment_path)?;
+
+        Ok(())
+    }
+
+    // the pages must be on the disk before the journal is cleared,
+    // and the frames must be on the disk before the pages are overwritten,
+    // a power failure in the middle is recovered from the journal
+    pub(crate) fn checkpoint_journal(&mut self, db_file: &mut DbFile) -> DbResult<()> {
         debug_assert!(self.transaction_state.is_none());
 
-        db_file.set_len(self.db_file_size)?;
+        if self.synchronous != Synchronous::Off {
+            sync_file(&self.journal_file.borrow())?;
+        }
+
+        db_file.set_len(self.codec.file_size_of(self.db_file_size))?;
 
+        // the slots are copied as they are, they are encrypted in the same way
         {
             let mut journal_file = self.journal_file.borrow_mut();
             for (page_id, offset) in &self.offset_map {
                 let data_offset = offset + FRAME_HEADER_SIZE;
 
-                let mut result = RawPage::new(*page_id, self.page_size);
-                result.read_from_file(&mut journal_file, data_offset)?;
+                let slot = self.codec.read_slot(&mut *journal_file, data_offset)?;
 
-                result.sync_to_file(db_file, (*page_id as u64) * (self.page_size.get() as u64))?;
+                db_file.seek(SeekFrom::Start(self.codec.slot_offset(*page_id)))?;
+                db_file.write_all(&slot)?;
             }
         }
 
         db_file.flush()?;  // only checkpoint flush the file
+        if self.synchronous != Synchronous::Off {
+            sync_file(db_file)?;
+        }
 
         self.checkpoint_finished()
     }
@@ -488,55 +747,102 @@ impl JournalManager {
         {
             let journal_file = self.journal_file.borrow();
             journal_file.set_len(64)?;  // truncate file to 64 bytes
+
+            // the frames of the last generation must not be found after a power failure,
+            // they are older than the pages in the main file
+            if self.synchronous != Synchronous::Off {
+                sync_file(&journal_file)?;
+            }
         }
 
         // clear all data
+        self.previous = Some(self.position_at(self.count));
         self.count = 0;
 
         self.offset_map.clear();
 
+        // all the readers are reading the latest data,
+        // which is in the main file now
+        for frame_count in self.readers.values_mut() {
+            *frame_count = 0;
+        }
+        self.generation += 1;
+        metrics::increase(&mut self.checkpoints, metrics::CHECKPOINTS, 1);
+
         self.plus_salt1();
         self.salt2 = generate_a_nonzero_salt();
         self.write_header_to_file()
     }
 
-    pub(crate) fn start_transaction(&mut self, ty: TransactionType) -> DbResult<()> {
+    // only one connection can write at the same time
+    pub(crate) fn start_write_transaction(&mut self) -> DbResult<()> {
         if self.transaction_state.is_some() {
-            return Err(DbErr::StartTransactionInAnotherTransaction);
+            return Err(DbErr::Busy);
         }
 
-        let mut journal_file = self.journal_file.borrow_mut();
-        match ty {
-            TransactionType::Read => {
-                shared_lock_file(&mut journal_file)?;
-            }
+        {
+            let journal_file = self.journal_file.borrow();
+            exclusive_lock_file(std_file(&journal_file))?;
+        }
 
-            TransactionType::Write => {
-                exclusive_lock_file(&mut journal_file)?;
-            }
+        self.new_write_state();
 
+        Ok(())
+    }
+
+    // pin the committed frames, the uncommitted frames
+    // of the writer are not visible to the snapshot
+    pub(super) fn start_read(&mut self) -> ReadSnapshot {
+        let reader_id = self.next_reader_id;
+        self.next_reader_id += 1;
+        self.readers.insert(reader_id, self.count);
+
+        ReadSnapshot {
+            reader_id,
+            generation: self.generation,
+            frame_count: self.count,
+            position: self.position_at(self.count),
+            db_file_size: self.db_file_size,
+            offset_map: self.offset_map.clone(),
         }
+    }
 
-        let new_state = Box::new(
-            TransactionState::new(ty, self.count, self.db_file_size)
-        );
-        self.transaction_state = Some(new_state);
+    pub(super) fn finish_read(&mut self, snapshot: &ReadSnapshot) {
+        self.readers.remove(&snapshot.reader_id);
+    }
 
-        Ok(())
+    // no transaction has been committed since the snapshot was taken
+    pub(super) fn is_latest_snapshot(&self, snapshot: &ReadSnapshot) -> bool {
+        snapshot.generation == self.generation && snapshot.frame_count == self.count
+    }
+
+    // no transaction is using the journal
+    pub(super) fn is_idle(&self) -> bool {
+        self.transaction_state.is_none() && self.readers.is_empty()
+    }
+
+    // the frames can only be written back when no reader is using the older ones
+    pub(super) fn can_checkpoint(&self) -> bool {
+        self.transaction_state.is_none() &&
+            self.readers.values().all(|frame_count| *frame_count == self.count)
     }
 
     pub(crate) fn commit(&mut self) -> DbResult<()> {
-        if self.transaction_state.is_none() {
-            return Err(DbErr::CannotWriteDbWithoutTransaction);
-        }
+        let has_new_frames = match &self.transaction_state {
+            Some(state) => state.frame_count > self.count,
+            None => return Err(DbErr::CannotWriteDbWithoutTransaction),
+        };
 
-        let transaction_ty = self.merge_transaction_state();
-        if transaction_ty == TransactionType::Write {
+        self.merge_transaction_state();
+        if has_new_frames {
             self.update_last_frame()?;
+            if self.synchronous == Synchronous::Full {
+                sync_file(&self.journal_file.borrow())?;
+            }
         }
         {
-            let mut journal_file = self.journal_file.borrow_mut();
-            unlock_file(&mut journal_file)?;
+            let journal_file = self.journal_file.borrow();
+            unlock_file(std_file(&journal_file))?;
         }
 
         Ok(())
@@ -548,24 +854,70 @@ impl JournalManager {
         }
 
         self.recover_file_and_state()?;
+        {
+            let journal_file = self.journal_file.borrow();
+            unlock_file(std_file(&journal_file))?;
+        }
+
+        Ok(())
+    }
+
+    pub(crate) fn savepoint(&mut self) -> DbResult<()> {
+        let state = match &self.transaction_state {
+            Some(state) => state.as_ref().clone(),
+            None => return Err(DbErr::CannotWriteDbWithoutTransaction),
+        };
+        self.savepoints.push(state);
+        Ok(())
+    }
+
+    // the savepoint itself is kept after rolling back,
+    // the frames appended after it are trimmed from the journal
+    pub(crate) fn rollback_to_savepoint(&mut self, index: usize) -> DbResult<()> {
+        if self.transaction_state.is_none() {
+            return Err(DbErr::RollbackNotInTransaction);
+        }
+
+        self.savepoints.truncate(index + 1);
+        let state = self.savepoints[index].clone();
+
+        let expected_journal_file_size = JOURNAL_DATA_BEGIN + self.full_frame_size() * (state.frame_count as u64);
         {
             let mut journal_file = self.journal_file.borrow_mut();
-            unlock_file(&mut journal_file)?;
+            journal_file.set_len(expected_journal_file_size)?;
+            journal_file.seek(SeekFrom::End(0))?;
         }
 
+        self.transaction_state = Some(Box::new(state));
+
         Ok(())
     }
 
-    pub(crate) fn upgrade_read_transaction_to_write(&mut self) -> DbResult<()> {
-        debug_assert!(self.transaction_state.is_some(), "can not upgrade transaction because there is no transaction");
+    #[inline]
+    pub(crate) fn release_savepoint(&mut self, index: usize) {
+        self.savepoints.truncate(index);
+    }
 
-        let mut journal_file = self.journal_file.borrow_mut();
-        exclusive_lock_file(&mut journal_file)?;
+    #[inline]
+    pub(super) fn codec(&self) -> &PageCodec {
+        &self.codec
+    }
 
-        let new_state = Box::new(
-            TransactionState::new(TransactionType::Write, self.count, self.db_file_size)
-        );
-        self.transaction_state = Some(new_state);
+    // the journal must be empty, it's written with the new codec from now on
+    pub(super) fn set_codec(&mut self, codec: PageCodec) -> DbResult<()> {
+        if !self.is_idle() || self.count != 0 {
+            return Err(DbErr::Busy);
+        }
+        self.codec = codec;
+        self.write_header_to_file()
+    }
+
+    // the journal must be empty, the main file is replaced by vacuum
+    pub(super) fn reset_db_size(&mut self, db_size: u64) -> DbResult<()> {
+        if !self.is_idle() || self.count != 0 {
+            return Err(DbErr::Busy);
+        }
+        self.db_file_size = db_size;
         Ok(())
     }
 
@@ -579,8 +931,17 @@ impl JournalManager {
         self.count
     }
 
-    pub(crate) fn transaction_type(&self) -> Option<TransactionType> {
-        self.transaction_state.as_ref().map(|state| state.ty)
+    pub(super) fn stats(&self) -> DbResult<CheckpointStats> {
+        let journal_bytes = self.journal_file.borrow().metadata()?.len();
+        Ok(CheckpointStats {
+            journal_frames: self.count,
+            journal_bytes,
+        })
+    }
+
+    pub(super) fn collect_metrics(&self, metrics: &mut Metrics) {
+        metrics.journal_frames_appended = self.frames_appended;
+        metrics.checkpoints = self.checkpoints;
     }
 
     #[allow(dead_code)]
@@ -605,7 +966,7 @@ impl JournalManager {
 
         for index in 0..self.count {
             let frame_header_offset: u64 =
-                JOURNAL_DATA_BEGIN + (self.page_size.get() as u64 + FRAME_HEADER_SIZE) * (index as u64);
+                JOURNAL_DATA_BEGIN + self.full_frame_size() * (index as u64);
 
             let mut header_buffer: [u8; FRAME_HEADER_SIZE as usize] = [0; FRAME_HEADER_SIZE as usize];
             journal_file.seek(SeekFrom::Start(frame_header_offset))?;
@@ -629,9 +990,12 @@ impl JournalManager {
 #[cfg(test)]
 mod tests {
     use std::num::NonZeroU32;
+    use std::time::SystemTime;
     use crate::page::RawPage;
-    use crate::TransactionType;
+    use crate::page::header_page_wrapper::{FEATURES_OFFSET, FEATURE_PAGE_CHECKSUM, VERSION_OFFSET, required_version};
+    use crate::backend::PageCodec;
     use crate::backend::file::journal_manager::JournalManager;
+    use crate::{DbErr, Synchronous};
 
     static TEST_PAGE_LEN: u32 = 100;
 
@@ -645,6 +1009,14 @@ mod tests {
             }
         }
 
+        // the features and the version of the header page are maintained by the codec
+        if page_id == 0 {
+            page.data[FEATURES_OFFSET as usize + 3] &= !(FEATURE_PAGE_CHECKSUM as u8);
+            let version = required_version(&page.data, false);
+            let offset = VERSION_OFFSET as usize;
+            page.data[offset..(offset + 4)].copy_from_slice(&version);
+        }
+
         page
     }
 
@@ -652,10 +1024,10 @@ mod tests {
     fn test_journal() {
         let _ = std::fs::remove_file("/tmp/test-journal");
         let mut journal_manager = JournalManager::open(
-            "/tmp/test-journal".as_ref(), NonZeroU32::new(4096).unwrap(), 4096
+            "/tmp/test-journal".as_ref(), PageCodec::new(NonZeroU32::new(4096).unwrap(), None, false), 4096, Synchronous::Normal, SystemTime::now
         ).unwrap();
 
-        journal_manager.start_transaction(TransactionType::Write).unwrap();
+        journal_manager.start_write_transaction().unwrap();
 
         let mut ten_pages = Vec::with_capacity(TEST_PAGE_LEN as usize);
 
@@ -687,10 +1059,10 @@ mod tests {
         let mem_count;
         {
             let mut journal_manager = JournalManager::open(
-                TEST_FILE.as_ref(), NonZeroU32::new(4096).unwrap(), 4096
+                TEST_FILE.as_ref(), PageCodec::new(NonZeroU32::new(4096).unwrap(), None, false), 4096, Synchronous::Normal, SystemTime::now
             ).unwrap();
 
-            journal_manager.start_transaction(TransactionType::Write).unwrap();
+            journal_manager.start_write_transaction().unwrap();
 
             let mut ten_pages = Vec::with_capacity(TEST_PAGE_LEN as usize);
 
@@ -707,9 +1079,86 @@ mod tests {
         }
 
         let journal_manager = JournalManager::open(
-            TEST_FILE.as_ref(), NonZeroU32::new(4096).unwrap(), 4096
+            TEST_FILE.as_ref(), PageCodec::new(NonZeroU32::new(4096).unwrap(), None, false), 4096, Synchronous::Normal, SystemTime::now
         ).unwrap();
         assert_eq!(mem_count, journal_manager.count);
     }
 
+    #[test]
+    fn test_damaged_header() {
+        const TEST_FILE: &str = "/tmp/test-journal-damaged-header";
+
+        let codec = || PageCodec::new(NonZeroU32::new(4096).unwrap(), None, false);
+        let damage_header = || {
+            let mut bytes = std::fs::read(TEST_FILE).unwrap();
+            bytes[48] ^= 0xFF;
+            std::fs::write(TEST_FILE, &bytes).unwrap();
+            bytes
+        };
+
+        let _ = std::fs::remove_file(TEST_FILE);
+        {
+            let mut journal_manager = JournalManager::open(
+                TEST_FILE.as_ref(), codec(), 4096, Synchronous::Normal, SystemTime::now
+            ).unwrap();
+
+            journal_manager.start_write_transaction().unwrap();
+            journal_manager.append_raw_page(&make_raw_page(1)).unwrap();
+            journal_manager.commit().unwrap();
+        }
+
+        // the committed frames are kept
+        let bytes = damage_header();
+        let err = JournalManager::open(TEST_FILE.as_ref(), codec(), 4096, Synchronous::Normal, SystemTime::now);
+        assert!(matches!(err, Err(DbErr::JournalChecksumMismatch)));
+        assert_eq!(std::fs::read(TEST_FILE).unwrap(), bytes);
+
+        // a torn header without frames is initialized again
+        let file = std::fs::OpenOptions::new().write(true).open(TEST_FILE).unwrap();
+        file.set_len(super::JOURNAL_DATA_BEGIN + 10).unwrap();
+        drop(file);
+        damage_header();
+        let journal_manager = JournalManager::open(
+            TEST_FILE.as_ref(), codec(), 4096, Synchronous::Normal, SystemTime::now
+        ).unwrap();
+        assert_eq!(journal_manager.count, 0);
+    }
+
+    #[test]
+    fn test_snapshot() {
+        const TEST_FILE: &str = "/tmp/test-journal-snapshot";
+
+        let _ = std::fs::remove_file(TEST_FILE);
+        let mut journal_manager = JournalManager::open(
+            TEST_FILE.as_ref(), PageCodec::new(NonZeroU32::new(4096).unwrap(), None, false), 4096, Synchronous::Normal, SystemTime::now
+        ).unwrap();
+
+        let old_page = make_raw_page(1);
+        journal_manager.start_write_transaction().unwrap();
+        journal_manager.append_raw_page(&old_page).unwrap();
+        journal_manager.commit().unwrap();
+
+        let snapshot = journal_manager.start_read();
+
+        let new_page = make_raw_page(1);
+        journal_manager.start_write_transaction().unwrap();
+        journal_manager.append_raw_page(&new_page).unwrap();
+
+        // uncommitted frames are only visible to the writer
+        let page = journal_manager.read_committed_page(1).unwrap().unwrap();
+        assert_eq!(page.data, old_page.data);
+
+        journal_manager.commit().unwrap();
+
+        let page = journal_manager.read_snapshot_page(&snapshot, 1).unwrap().unwrap();
+        assert_eq!(page.data, old_page.data);
+        let page = journal_manager.read_committed_page(1).unwrap().unwrap();
+        assert_eq!(page.data, new_page.data);
+
+        assert!(!journal_manager.is_latest_snapshot(&snapshot));
+        assert!(!journal_manager.can_checkpoint());
+        journal_manager.finish_read(&snapshot);
+        assert!(journal_manager.can_checkpoint());
+    }
+
 }
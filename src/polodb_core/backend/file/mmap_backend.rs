@@ -0,0 +1,141 @@
+use std::io::Write;
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::sync::Arc;
+use super::file_backend::FileBackend;
+use crate::backend::{Backend, BackendExt, PageCodec};
+use crate::{DbResult, Config};
+use crate::page::RawPage;
+use crate::transaction::TransactionType;
+use crate::db::{CheckpointMode, CheckpointStats, JournalPosition};
+use crate::metrics::Metrics;
+
+// A connection to the database file, which reads the pages
+// from a mapping of the main file instead of reading the file.
+// A page is copied out of the mapping, the saving is the system call.
+//
+// The transactions and the writes are the same as the FileBackend,
+// they go through the journal.
+pub(crate) struct MmapBackend {
+    inner: FileBackend,
+}
+
+impl MmapBackend {
+
+    pub(crate) fn open(path: &Path, config: Arc<Config>) -> DbResult<MmapBackend> {
+        let inner = FileBackend::open(path, config)?;
+        Ok(MmapBackend { inner })
+    }
+
+}
+
+impl Backend for MmapBackend {
+
+    fn page_size(&self) -> NonZeroU32 {
+        self.inner.page_size()
+    }
+
+    fn read_page(&self, page_id: u32) -> DbResult<RawPage> {
+        self.inner.read_page_from(page_id, true)
+    }
+
+    fn write_page(&mut self, page: &RawPage) -> DbResult<()> {
+        self.inner.write_page(page)
+    }
+
+    fn commit(&mut self) -> DbResult<()> {
+        self.inner.commit()
+    }
+
+    fn db_size(&self) -> u64 {
+        self.inner.db_size()
+    }
+
+    fn expand_db_size(&mut self, size: u64) -> DbResult<()> {
+        self.inner.expand_db_size(size)
+    }
+
+    fn truncate(&mut self, size: u64) -> DbResult<()> {
+        self.inner.truncate(size)
+    }
+
+    fn transaction_type(&self) -> Option<TransactionType> {
+        self.inner.transaction_type()
+    }
+
+    fn upgrade_read_transaction_to_write(&mut self) -> DbResult<()> {
+        self.inner.upgrade_read_transaction_to_write()
+    }
+
+    fn rollback(&mut self) -> DbResult<()> {
+        self.inner.rollback()
+    }
+
+    fn start_transaction(&mut self, ty: TransactionType) -> DbResult<()> {
+        self.inner.start_transaction(ty)
+    }
+
+    fn savepoint(&mut self) -> DbResult<()> {
+        self.inner.savepoint()
+    }
+
+    fn rollback_to_savepoint(&mut self, index: usize) -> DbResult<()> {
+        self.inner.rollback_to_savepoint(index)
+    }
+
+    fn release_savepoint(&mut self, index: usize) -> DbResult<()> {
+        self.inner.release_savepoint(index)
+    }
+
+}
+
+impl BackendExt for MmapBackend {
+
+    fn is_changed_by_others(&mut self) -> bool {
+        self.inner.is_changed_by_others()
+    }
+
+    fn new_connection(&self) -> DbResult<Box<dyn BackendExt + Send>> {
+        let connection = MmapBackend {
+            inner: self.inner.connect(),
+        };
+        Ok(Box::new(connection))
+    }
+
+    fn checkpoint(&mut self, mode: CheckpointMode) -> DbResult<bool> {
+        self.inner.checkpoint(mode)
+    }
+
+    fn checkpoint_stats(&self) -> DbResult<CheckpointStats> {
+        self.inner.checkpoint_stats()
+    }
+
+    fn collect_metrics(&self, metrics: &mut Metrics) {
+        self.inner.collect_metrics(metrics)
+    }
+
+    fn export_journal_frames(&self, since: JournalPosition, writer: &mut dyn Write) -> DbResult<JournalPosition> {
+        self.inner.export_journal_frames(since, writer)
+    }
+
+    fn journal_position(&self) -> Option<JournalPosition> {
+        self.inner.journal_position()
+    }
+
+    fn codec(&self) -> PageCodec {
+        self.inner.codec()
+    }
+
+    fn set_codec(&mut self, codec: PageCodec) -> DbResult<()> {
+        self.inner.set_codec(codec)
+    }
+
+    fn open_compacted(&self, config: Arc<Config>) -> DbResult<Option<Box<dyn BackendExt + Send>>> {
+        self.inner.open_compacted(config)
+    }
+
+    fn swap_compacted(&mut self) -> DbResult<u64> {
+        self.inner.swap_compacted()
+    }
+
+}
@@ -2,6 +2,17 @@ mod frame_header;
 pub(crate) mod pagecache;
 mod transaction_state;
 mod journal_manager;
+mod journal_frames;
 mod file_backend;
+#[cfg(feature = "mmap")]
+mod mmap_backend;
+mod read_only_backend;
+pub(crate) mod sync;
+#[cfg(test)]
+pub(crate) mod fault_injection;
 
 pub(crate) use file_backend::FileBackend;
+pub(crate) use journal_frames::{read_journal_frames, list_archived_segments, JournalFrames, JournalTransaction};
+#[cfg(feature = "mmap")]
+pub(crate) use mmap_backend::MmapBackend;
+pub(crate) use read_only_backend::ReadOnlyBackend;
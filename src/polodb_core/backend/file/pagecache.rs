@@ -2,6 +2,7 @@ use std::num::NonZeroU32;
 use lru::LruCache;
 use std::alloc::{alloc, dealloc, Layout};
 use crate::page::RawPage;
+use crate::metrics;
 
 pub(crate) struct PageCache {
     page_count: usize,
@@ -9,14 +10,12 @@ pub(crate) struct PageCache {
     layout:     Layout,
     data:       *mut u8,
     lru_map:    LruCache<u32, u32>,
+    hits:       u64,
+    misses:     u64,
 }
 
 impl PageCache {
 
-    pub fn new_default(page_size: NonZeroU32) -> PageCache {
-        Self::new(1024, page_size)
-    }
-
     pub fn new(page_count: usize, page_size: NonZeroU32) -> PageCache {
         let cache_size = page_count * (page_size.get() as usize);
 
@@ -31,15 +30,21 @@ impl PageCache {
             layout,
             data,
             lru_map: LruCache::new(page_count),
+            hits: 0,
+            misses: 0,
         }
     }
 
     pub(crate) fn get_from_cache(&mut self, page_id: u32) -> Option<RawPage> {
         let index = match self.lru_map.get(&page_id) {
-            Some(index) => index,
-            None => return None,
+            Some(index) => *index,
+            None => {
+                metrics::increase(&mut self.misses, metrics::CACHE_MISSES, 1);
+                return None;
+            }
         };
-        let offset: usize = (*index as usize) * (self.page_size.get() as usize);
+        metrics::increase(&mut self.hits, metrics::CACHE_HITS, 1);
+        let offset: usize = (index as usize) * (self.page_size.get() as usize);
         let mut result = RawPage::new(page_id, self.page_size);
         unsafe {
             result.copy_from_ptr(self.data.add(offset as usize));
@@ -47,6 +52,21 @@ impl PageCache {
         Some(result)
     }
 
+    // the pages are dropped, but the counters are kept
+    pub(crate) fn clear(&mut self) {
+        self.lru_map.clear();
+    }
+
+    #[inline]
+    pub(crate) fn hits(&self) -> u64 {
+        self.hits
+    }
+
+    #[inline]
+    pub(crate) fn misses(&self) -> u64 {
+        self.misses
+    }
+
     #[inline]
     fn distribute_new_index(&mut self) -> u32 {
         if self.lru_map.len() < self.page_count {  // is not full
@@ -154,4 +174,22 @@ mod tests {
         }
     }
 
+    #[test]
+    fn page_cache_counters() {
+        let mut page_cache = PageCache::new(3, NonZeroU32::new(4096).unwrap());
+        page_cache.insert_to_cache(&make_raw_page(1));
+
+        assert!(page_cache.get_from_cache(1).is_some());
+        assert!(page_cache.get_from_cache(2).is_none());
+
+        page_cache.clear();
+        assert!(page_cache.get_from_cache(1).is_none());
+
+        page_cache.insert_to_cache(&make_raw_page(2));
+        assert!(page_cache.get_from_cache(2).is_some());
+
+        assert_eq!(page_cache.hits(), 2);
+        assert_eq!(page_cache.misses(), 2);
+    }
+
 }
@@ -0,0 +1,227 @@
+use std::num::NonZeroU32;
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard};
+use super::file_backend::FileBackend;
+use super::journal_manager::JournalManager;
+use super::sync::{DbFile, open_file, std_file};
+use crate::file_lock::{shared_lock_file, unlock_file};
+use crate::backend::{Backend, BackendExt, PageCodec};
+use crate::{DbResult, DbErr, Config};
+use crate::page::RawPage;
+use crate::transaction::TransactionType;
+use crate::db::{CheckpointMode, CheckpointStats};
+
+// the files are not changed by anyone while the shared lock is held,
+// so all the transactions see the same data
+struct ReadOnlyFiles {
+    main_file:       DbFile,
+    codec:           PageCodec,
+
+    // the frames committed but not checkpointed by the last writer
+    journal_manager: Option<JournalManager>,
+}
+
+impl ReadOnlyFiles {
+
+    fn db_size(&self) -> u64 {
+        match &self.journal_manager {
+            Some(journal_manager) => journal_manager.committed_db_size(),
+            None => self.codec.db_size_of(self.main_file.metadata().map_or(0, |meta| meta.len())),
+        }
+    }
+
+}
+
+impl Drop for ReadOnlyFiles {
+
+    fn drop(&mut self) {
+        let _ = unlock_file(std_file(&self.main_file));
+    }
+
+}
+
+// A connection to a database file which is opened in read-only mode.
+//
+// The journal is not created, and the files are only locked with shared locks,
+// so the other processes can read the database at the same time.
+pub(crate) struct ReadOnlyBackend {
+    files:       Arc<Mutex<ReadOnlyFiles>>,
+    page_size:   NonZeroU32,
+    transaction: bool,
+}
+
+impl ReadOnlyBackend {
+
+    pub(crate) fn open(path: &Path, config: Arc<Config>) -> DbResult<ReadOnlyBackend> {
+        let page_size = NonZeroU32::new(config.page_size).ok_or(DbErr::InvalidPageSize(config.page_size))?;
+        let mut file = open_file(std::fs::OpenOptions::new().read(true), path)?;
+
+        match shared_lock_file(std_file(&file)) {
+            Err(DbErr::Busy) => {
+                return Err(DbErr::DatabaseOccupied);
+            }
+            Err(err) => {
+                return Err(err);
+            },
+            _ => (),
+        };
+
+        let init_result = FileBackend::open_db(&mut file, page_size, &config)?;
+        let codec = init_result.codec;
+
+        let journal_file_path = FileBackend::mk_journal_path(path);
+        let journal_manager = JournalManager::open_read_only(
+            &journal_file_path, codec.clone(), init_result.db_file_size
+        )?;
+
+        let files = ReadOnlyFiles {
+            main_file: file,
+            codec: codec.clone(),
+            journal_manager,
+        };
+
+        Ok(ReadOnlyBackend {
+            files: Arc::new(Mutex::new(files)),
+            page_size: codec.page_size(),
+            transaction: false,
+        })
+    }
+
+    fn lock_files(&self) -> MutexGuard<'_, ReadOnlyFiles> {
+        self.files.lock().expect("the files of the database are poisoned")
+    }
+
+}
+
+impl Backend for ReadOnlyBackend {
+
+    fn page_size(&self) -> NonZeroU32 {
+        self.page_size
+    }
+
+    fn read_page(&self, page_id: u32) -> DbResult<RawPage> {
+        let mut files = self.lock_files();
+
+        let offset = (page_id as u64) * (self.page_size.get() as u64);
+        if offset + (self.page_size.get() as u64) > files.db_size() {
+            return Ok(RawPage::new(page_id, self.page_size));
+        }
+
+        if let Some(journal_manager) = &files.journal_manager {
+            if let Some(page) = journal_manager.read_committed_page(page_id)? {
+                return Ok(page);
+            }
+        }
+
+        let ReadOnlyFiles { main_file, codec, .. } = &mut *files;
+        codec.read_page(main_file, page_id, codec.slot_offset(page_id))
+    }
+
+    fn write_page(&mut self, _page: &RawPage) -> DbResult<()> {
+        Err(DbErr::ReadOnly)
+    }
+
+    fn commit(&mut self) -> DbResult<()> {
+        if !self.transaction {
+            return Err(DbErr::CannotWriteDbWithoutTransaction);
+        }
+        self.transaction = false;
+        Ok(())
+    }
+
+    fn db_size(&self) -> u64 {
+        self.lock_files().db_size()
+    }
+
+    fn expand_db_size(&mut self, _size: u64) -> DbResult<()> {
+        Err(DbErr::ReadOnly)
+    }
+
+    fn truncate(&mut self, _size: u64) -> DbResult<()> {
+        Err(DbErr::ReadOnly)
+    }
+
+    fn transaction_type(&self) -> Option<TransactionType> {
+        if self.transaction {
+            Some(TransactionType::Read)
+        } else {
+            None
+        }
+    }
+
+    fn upgrade_read_transaction_to_write(&mut self) -> DbResult<()> {
+        Err(DbErr::ReadOnly)
+    }
+
+    fn rollback(&mut self) -> DbResult<()> {
+        if !self.transaction {
+            return Err(DbErr::RollbackNotInTransaction);
+        }
+        self.transaction = false;
+        Ok(())
+    }
+
+    fn start_transaction(&mut self, ty: TransactionType) -> DbResult<()> {
+        if ty == TransactionType::Write {
+            return Err(DbErr::ReadOnly);
+        }
+        if self.transaction {
+            return Err(DbErr::StartTransactionInAnotherTransaction);
+        }
+        self.transaction = true;
+        Ok(())
+    }
+
+    // nothing is changed in the read transactions
+    fn savepoint(&mut self) -> DbResult<()> {
+        if !self.transaction {
+            return Err(DbErr::CannotWriteDbWithoutTransaction);
+        }
+        Ok(())
+    }
+
+    fn rollback_to_savepoint(&mut self, _index: usize) -> DbResult<()> {
+        if !self.transaction {
+            return Err(DbErr::RollbackNotInTransaction);
+        }
+        Ok(())
+    }
+
+    fn release_savepoint(&mut self, _index: usize) -> DbResult<()> {
+        Ok(())
+    }
+
+}
+
+impl BackendExt for ReadOnlyBackend {
+
+    fn new_connection(&self) -> DbResult<Box<dyn BackendExt + Send>> {
+        let connection = ReadOnlyBackend {
+            files: self.files.clone(),
+            page_size: self.page_size,
+            transaction: false,
+        };
+        Ok(Box::new(connection))
+    }
+
+    fn checkpoint(&mut self, _mode: CheckpointMode) -> DbResult<bool> {
+        Err(DbErr::ReadOnly)
+    }
+
+    fn checkpoint_stats(&self) -> DbResult<CheckpointStats> {
+        let files = self.lock_files();
+        match &files.journal_manager {
+            Some(journal_manager) => journal_manager.stats(),
+            None => Ok(CheckpointStats::default()),
+        }
+    }
+
+    fn codec(&self) -> PageCodec {
+        self.lock_files().codec.clone()
+    }
+
+    fn set_codec(&mut self, _codec: PageCodec) -> DbResult<()> {
+        Err(DbErr::ReadOnly)
+    }
+
+}
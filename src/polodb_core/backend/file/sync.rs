@@ -0,0 +1,31 @@
+use std::fs::OpenOptions;
+use std::path::Path;
+
+// the files are wrapped in the tests, so the faults can be injected,
+// they are only recorded in the crash-consistency tests, see fault_injection::reset
+#[cfg(not(test))]
+pub(crate) type DbFile = std::fs::File;
+#[cfg(test)]
+pub(crate) type DbFile = super::fault_injection::FaultyFile;
+
+pub(super) fn open_file(options: &OpenOptions, path: &Path) -> std::io::Result<DbFile> {
+    #[cfg(not(test))]
+    return options.open(path);
+    #[cfg(test)]
+    return DbFile::open(options, path);
+}
+
+// the file of std, which is needed by the locks and the mapping
+#[inline]
+pub(super) fn std_file(file: &DbFile) -> &std::fs::File {
+    #[cfg(not(test))]
+    return file;
+    #[cfg(test)]
+    return file.inner();
+}
+
+// all the files are synced here, so the tests can simulate a power failure
+#[inline]
+pub(super) fn sync_file(file: &DbFile) -> std::io::Result<()> {
+    file.sync_data()
+}
@@ -1,6 +1,8 @@
 use std::collections::BTreeMap;
 use crate::transaction::TransactionType;
+use crate::db::JournalPosition;
 
+#[derive(Clone)]
 pub(super) struct TransactionState {
     pub(super) ty: TransactionType,
     pub(super) offset_map: BTreeMap<u32, u64>,
@@ -20,3 +22,13 @@ impl TransactionState {
     }
 
 }
+
+// the committed state of the journal pinned by a read transaction
+pub(super) struct ReadSnapshot {
+    pub(super) reader_id: u64,
+    pub(super) generation: u64,
+    pub(super) frame_count: u32,
+    pub(super) position: JournalPosition,
+    pub(super) db_file_size: u64,
+    pub(super) offset_map: BTreeMap<u32, u64>,
+}
@@ -1,10 +1,11 @@
 use std::num::{NonZeroU32, NonZeroU64};
 use std::collections::BTreeMap;
-use crate::backend::Backend;
-use crate::{DbResult, TransactionType, DbErr};
+use crate::backend::{Backend, BackendExt, PageCodec};
+use crate::{DbResult, TransactionType, DbErr, Compression};
 use crate::page::RawPage;
 use crate::page::header_page_wrapper::HeaderPageWrapper;
 
+#[derive(Clone)]
 struct Transaction {
     ty: TransactionType,
     offset_map: BTreeMap<u32, RawPage>,
@@ -24,49 +25,110 @@ impl Transaction {
 }
 pub(crate) struct MemoryBackend {
     page_size:   NonZeroU32,
+    codec:       PageCodec,
+    // the pages are encoded in the same way as the files
     data:        Vec<u8>,
     transaction: Option<Transaction>,
+    savepoints:  Vec<Transaction>,
 }
 
 impl MemoryBackend {
 
-    fn force_write_first_block(data: &mut Vec<u8>, page_size: NonZeroU32) {
-        let wrapper = HeaderPageWrapper::init(0, page_size);
-        let wrapper_size = wrapper.0.data.len();
-        data[0..wrapper_size].copy_from_slice(&wrapper.0.data);
+    fn force_write_first_block(data: &mut Vec<u8>, codec: &PageCodec, compression: Compression) {
+        let mut wrapper = HeaderPageWrapper::init(0, codec.page_size());
+        wrapper.set_compression(compression.to_header());
+        let slot = codec.encode(&wrapper.0);
+        data[0..slot.len()].copy_from_slice(&slot);
+
+        // the empty meta page, an empty slot is taken as damaged,
+        // a database of one page has no room for it
+        let meta_page_id = wrapper.get_meta_page_id();
+        let start = codec.slot_offset(meta_page_id) as usize;
+        if start < data.len() {
+            let slot = codec.encode(&RawPage::new(meta_page_id, codec.page_size())).into_owned();
+            data[start..(start + slot.len())].copy_from_slice(&slot);
+        }
     }
 
-    pub(crate) fn new(page_size: NonZeroU32, init_block_count: NonZeroU64) -> MemoryBackend {
-        let data_len = init_block_count.get() * (page_size.get() as u64);
+    pub(crate) fn new(codec: PageCodec, init_block_count: NonZeroU64, compression: Compression) -> MemoryBackend {
+        let page_size = codec.page_size();
+        let data_len = init_block_count.get() * codec.slot_size();
         let data_len = data_len as usize;
         let mut data = vec![0; data_len];
-        MemoryBackend::force_write_first_block(&mut data, page_size);
+        MemoryBackend::force_write_first_block(&mut data, &codec, compression);
         MemoryBackend {
             page_size,
+            codec,
             data,
             transaction: None,
+            savepoints: Vec::new(),
         }
     }
 
+    // copy the pages seen by a read transaction of another backend,
+    // they are encoded in the same way
+    pub(crate) fn load_from(source: &mut dyn BackendExt) -> DbResult<MemoryBackend> {
+        let codec = source.codec();
+        source.start_transaction(TransactionType::Read)?;
+        let result = MemoryBackend::read_all_pages(source, &codec);
+        source.rollback()?;
+        let data = result?;
+
+        Ok(MemoryBackend {
+            page_size: codec.page_size(),
+            codec,
+            data,
+            transaction: None,
+            savepoints: Vec::new(),
+        })
+    }
+
+    fn read_all_pages(source: &dyn BackendExt, codec: &PageCodec) -> DbResult<Vec<u8>> {
+        let page_count = source.db_size() / (codec.page_size().get() as u64);
+
+        // the slots after the null page bar are never written
+        let header = HeaderPageWrapper::from_raw_page(source.read_page(0)?);
+        let used_pages = page_count.min(header.get_null_page_bar() as u64);
+
+        let mut data = Vec::with_capacity((page_count * codec.slot_size()) as usize);
+        for page_id in 0..(used_pages as u32) {
+            let page = source.read_page(page_id)?;
+            data.extend_from_slice(&codec.encode(&page));
+        }
+        data.resize((page_count * codec.slot_size()) as usize, 0);
+        Ok(data)
+    }
+
     fn merge_transaction(&mut self) {
         let state = self.transaction.take().unwrap();
+        self.savepoints.clear();
         let db_file_size = state.db_file_size;
-        self.data.resize(db_file_size as usize, 0);
+        self.data.resize(self.codec.file_size_of(db_file_size) as usize, 0);
         for (key, value) in state.offset_map {
-            let page_size = self.page_size.get() as usize;
-            let start = (key as usize) * page_size;
-            let end = start + page_size;
-            self.data[start..end].copy_from_slice(&value.data);
+            let slot = self.codec.encode(&value);
+            let start = self.codec.slot_offset(key) as usize;
+            let end = start + slot.len();
+            self.data[start..end].copy_from_slice(&slot);
         }
     }
 
+    #[inline]
+    fn committed_db_size(&self) -> u64 {
+        self.codec.db_size_of(self.data.len() as u64)
+    }
+
     fn recover_file_and_state(&mut self) {
         self.transaction = None;
+        self.savepoints.clear();
     }
 
 }
 
 impl Backend for MemoryBackend {
+    fn page_size(&self) -> NonZeroU32 {
+        self.page_size
+    }
+
     fn read_page(&self, page_id: u32) -> DbResult<RawPage> {
         if let Some(transaction) = &self.transaction {
             if let Some(page) = transaction.offset_map.get(&page_id) {
@@ -74,13 +136,16 @@ impl Backend for MemoryBackend {
             }
         }
 
-        let data_index = (page_id as usize) * (self.page_size.get() as usize);
-        let mut result = RawPage::new(page_id, self.page_size);
+        // the page is not written yet, or discarded by a truncation
+        let end = (page_id as u64 + 1) * (self.page_size.get() as u64);
+        if end > self.db_size() || end > self.committed_db_size() {
+            return Ok(RawPage::new(page_id, self.page_size));
+        }
 
-        let end = data_index + (self.page_size.get() as usize);
-        result.data.copy_from_slice(&self.data[data_index..end]);
+        let start = self.codec.slot_offset(page_id) as usize;
+        let slot = self.data[start..(start + self.codec.slot_size() as usize)].to_vec();
 
-        Ok(result)
+        self.codec.decode(page_id, slot)
     }
 
     fn write_page(&mut self, page: &RawPage) -> DbResult<()> {
@@ -113,11 +178,32 @@ impl Backend for MemoryBackend {
     }
 
     fn db_size(&self) -> u64 {
-        self.data.len() as u64
+        match &self.transaction {
+            Some(state) => state.db_file_size,
+            None => self.committed_db_size(),
+        }
     }
 
-    fn set_db_size(&mut self, _size: u64) -> DbResult<()> {
-        todo!()
+    fn expand_db_size(&mut self, size: u64) -> DbResult<()> {
+        match &mut self.transaction {
+            Some(state) if state.ty == TransactionType::Write => {
+                state.db_file_size += size;
+                Ok(())
+            }
+            _ => Err(DbErr::CannotWriteDbWithoutTransaction),
+        }
+    }
+
+    fn truncate(&mut self, size: u64) -> DbResult<()> {
+        let page_size = self.page_size.get() as u64;
+        match &mut self.transaction {
+            Some(state) if state.ty == TransactionType::Write => {
+                state.db_file_size = size;
+                state.offset_map.retain(|page_id, _| (*page_id as u64 + 1) * page_size <= size);
+                Ok(())
+            }
+            _ => Err(DbErr::CannotWriteDbWithoutTransaction),
+        }
     }
 
     fn transaction_type(&self) -> Option<TransactionType> {
@@ -125,7 +211,7 @@ impl Backend for MemoryBackend {
     }
 
     fn upgrade_read_transaction_to_write(&mut self) -> DbResult<()> {
-        let db_size = self.data.len() as u64;
+        let db_size = self.committed_db_size();
         let new_state = Transaction::new(TransactionType::Write, db_size);
         self.transaction = Some(new_state);
         Ok(())
@@ -143,12 +229,65 @@ impl Backend for MemoryBackend {
         if self.transaction.is_some() {
             return Err(DbErr::Busy);
         }
-        let db_size = self.data.len() as u64;
+        let db_size = self.committed_db_size();
         let new_state = Transaction::new(ty, db_size);
         self.transaction = Some(new_state);
 
         Ok(())
     }
+
+    fn savepoint(&mut self) -> DbResult<()> {
+        let state = match &self.transaction {
+            Some(state) => state.clone(),
+            None => return Err(DbErr::CannotWriteDbWithoutTransaction),
+        };
+        self.savepoints.push(state);
+        Ok(())
+    }
+
+    // the transaction may be upgraded after the savepoint,
+    // it's still a write transaction after rolling back
+    fn rollback_to_savepoint(&mut self, index: usize) -> DbResult<()> {
+        let ty = match &self.transaction {
+            Some(state) => state.ty,
+            None => return Err(DbErr::RollbackNotInTransaction),
+        };
+        self.savepoints.truncate(index + 1);
+        let mut state = self.savepoints[index].clone();
+        state.ty = ty;
+        self.transaction = Some(state);
+        Ok(())
+    }
+
+    fn release_savepoint(&mut self, index: usize) -> DbResult<()> {
+        self.savepoints.truncate(index);
+        Ok(())
+    }
+}
+
+impl BackendExt for MemoryBackend {
+    fn is_changed_by_others(&mut self) -> bool {
+        false
+    }
+
+    fn new_connection(&self) -> DbResult<Box<dyn BackendExt + Send>> {
+        Err(DbErr::NotSupported("connecting to a memory database twice"))
+    }
+
+    fn codec(&self) -> PageCodec {
+        self.codec.clone()
+    }
+
+    fn set_codec(&mut self, codec: PageCodec) -> DbResult<()> {
+        if self.transaction.is_some() {
+            return Err(DbErr::Busy);
+        }
+
+        self.data = MemoryBackend::read_all_pages(self, &codec)?;
+        self.codec = codec;
+
+        Ok(())
+    }
 }
 
 #[cfg(test)]
@@ -156,7 +295,8 @@ mod tests {
     use crate::{Config, TransactionType};
     use crate::page::RawPage;
     use crate::backend::memory::MemoryBackend;
-    use crate::backend::Backend;
+    use crate::backend::{Backend, PageCodec};
+    use crate::page::header_page_wrapper::{FEATURES_OFFSET, FEATURE_PAGE_CHECKSUM, VERSION_OFFSET, required_version};
     use std::num::NonZeroU32;
 
     fn make_raw_page(page_id: u32) -> RawPage {
@@ -169,6 +309,14 @@ mod tests {
             }
         }
 
+        // the features and the version of the header page are maintained by the codec
+        if page_id == 0 {
+            page.data[FEATURES_OFFSET as usize + 3] &= !(FEATURE_PAGE_CHECKSUM as u8);
+            let version = required_version(&page.data, false);
+            let offset = VERSION_OFFSET as usize;
+            page.data[offset..(offset + 4)].copy_from_slice(&version);
+        }
+
         page
     }
 
@@ -177,9 +325,8 @@ mod tests {
     #[test]
     fn test_commit() {
         let config = Config::default();
-        let mut backend = MemoryBackend::new(
-            NonZeroU32::new(4096).unwrap(), config.init_block_count
-        );
+        let codec = PageCodec::new(NonZeroU32::new(4096).unwrap(), None, config.page_checksum);
+        let mut backend = MemoryBackend::new(codec, config.init_block_count, config.compression);
 
         let mut ten_pages = Vec::with_capacity(TEST_PAGE_LEN as usize);
 
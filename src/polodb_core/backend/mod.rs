@@ -1,6 +1,17 @@
+//! The storage of the pages.
+//!
+//! The pages are stored in the files or the memory by default,
+//! implement [Backend] to store them somewhere else, and open the database
+//! with [Database::open_with_backend](crate::Database::open_with_backend).
+
 
 pub(crate) mod file;
 mod backend;
 pub(crate) mod memory;
+mod page_codec;
 
-pub(crate) use backend::{Backend, AutoStartResult};
+pub use backend::Backend;
+pub(crate) use backend::{AutoStartResult, BackendExt, CustomBackend};
+pub(crate) use page_codec::PageCodec;
+pub(crate) use page_codec::{crc64, check_key_supported};
+pub use crate::page::RawPage;
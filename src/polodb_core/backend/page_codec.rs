@@ -0,0 +1,414 @@
+use std::borrow::Cow;
+use std::io::{Seek, SeekFrom, Write, Read};
+use std::num::NonZeroU32;
+#[cfg(feature = "encryption")]
+use chacha20poly1305::{ChaCha20Poly1305, Key, Nonce, Tag, KeyInit, AeadInPlace};
+use crc64fast::Digest;
+#[cfg(feature = "encryption")]
+use getrandom::getrandom;
+use crate::DbResult;
+use crate::error::DbErr;
+use crate::page::{RawPage, MIN_PAGE_SIZE, MAX_PAGE_SIZE, is_valid_page_size};
+use crate::page::header_page_wrapper::{
+    HeaderPageWrapper, FEATURES_OFFSET, FEATURE_PAGE_CHECKSUM, PAGE_SIZE_OFFSET, VERSION_OFFSET, required_version,
+};
+
+const NONCE_SIZE: usize    = 12;
+const TAG_SIZE: usize      = 16;
+const CHECKSUM_SIZE: usize = 8;
+
+pub(crate) fn crc64(bytes: &[u8]) -> u64 {
+    let mut c = Digest::new();
+    c.write(bytes);
+    c.sum64()
+}
+
+#[cfg(feature = "encryption")]
+type Cipher = ChaCha20Poly1305;
+
+// no cipher can be made without the feature
+#[cfg(not(feature = "encryption"))]
+#[derive(Clone)]
+enum Cipher {}
+
+pub(crate) fn check_key_supported(key: Option<&[u8; 32]>) -> DbResult<()> {
+    match key {
+        Some(_) if !cfg!(feature = "encryption") =>
+            Err(DbErr::NotSupported("encryption without the feature \"encryption\"")),
+        _ => Ok(()),
+    }
+}
+
+#[cfg(feature = "encryption")]
+fn new_cipher(key: &[u8; 32]) -> Cipher {
+    ChaCha20Poly1305::new(Key::from_slice(key))
+}
+
+#[cfg(not(feature = "encryption"))]
+fn new_cipher(_key: &[u8; 32]) -> Cipher {
+    unreachable!("encryption is not supported")
+}
+
+// append the nonce and the tag to the ciphertext
+#[cfg(feature = "encryption")]
+fn encrypt(cipher: &Cipher, page_id: u32, data: &mut Vec<u8>) {
+    let mut nonce = [0u8; NONCE_SIZE];
+    getrandom(&mut nonce).unwrap();
+
+    let tag = cipher.encrypt_in_place_detached(
+        Nonce::from_slice(&nonce), &page_id.to_le_bytes(), data)
+        .expect("failed to encrypt the page");
+    data.extend_from_slice(&nonce);
+    data.extend_from_slice(tag.as_slice());
+}
+
+#[cfg(not(feature = "encryption"))]
+fn encrypt(cipher: &Cipher, _page_id: u32, _data: &mut Vec<u8>) {
+    match *cipher {}
+}
+
+#[cfg(feature = "encryption")]
+fn decrypt(cipher: &Cipher, page_id: u32, page_size: usize, data: &mut Vec<u8>) -> DbResult<()> {
+    let nonce_and_tag = data.split_off(page_size);
+    let (nonce, tag) = nonce_and_tag.split_at(NONCE_SIZE);
+    cipher.decrypt_in_place_detached(
+        Nonce::from_slice(nonce), &page_id.to_le_bytes(), data, Tag::from_slice(tag))
+        .map_err(|_| DbErr::DecryptionFailed(page_id))
+}
+
+#[cfg(not(feature = "encryption"))]
+fn decrypt(cipher: &Cipher, _page_id: u32, _page_size: usize, _data: &mut Vec<u8>) -> DbResult<()> {
+    match *cipher {}
+}
+
+/**
+ * How the pages are stored in the files.
+ *
+ * A page is stored in a larger slot if it's encrypted, or checksummed:
+ * | page size            | 12 bytes | 16 bytes | 8 bytes  |
+ * | ciphertext/plaintext | nonce    | tag      | checksum |
+ *
+ * The page id is the associated data, so a page can not be moved
+ * to another place. The checksum is the crc64 of the bytes before it.
+ * The sizes recorded in the database are the sizes of the plain pages,
+ * the sizes of the files are the sizes of the slots.
+ *
+ * The checksum flag is recorded in the features of the header page,
+ * it's set when the header page is encoded.
+ *
+ * A page is written when it's allocated, so an empty slot is only
+ * valid after the null page bar, and it's rejected when it's decoded.
+ */
+#[derive(Clone)]
+pub(crate) struct PageCodec {
+    page_size: NonZeroU32,
+    cipher:    Option<Cipher>,
+    checksum:  bool,
+}
+
+impl PageCodec {
+
+    // the codec of a backend which stores the pages as they are,
+    // without encryption or checksums
+    pub(crate) fn plain(page_size: NonZeroU32) -> PageCodec {
+        PageCodec::new(page_size, None, false)
+    }
+
+    pub(crate) fn new(page_size: NonZeroU32, key: Option<&[u8; 32]>, checksum: bool) -> PageCodec {
+        PageCodec {
+            page_size,
+            cipher: key.map(new_cipher),
+            checksum,
+        }
+    }
+
+    // read the header page to find out the page size and the features of an existing file,
+    // the checksum is after the header page, so it's not needed.
+    // The page size of an encrypted file is unknown before the header page
+    // is decrypted, the preferred one is tried first.
+    pub(crate) fn detect<F: Read + Seek>(file: &mut F, preferred_page_size: NonZeroU32, key: Option<&[u8; 32]>) -> DbResult<PageCodec> {
+        let file_len = file.seek(SeekFrom::End(0))?;
+        let candidates: Vec<u32> = match key {
+            Some(_) => {
+                let mut sizes = vec![preferred_page_size.get()];
+                let mut size = MIN_PAGE_SIZE;
+                while size <= MAX_PAGE_SIZE {
+                    if size != preferred_page_size.get() {
+                        sizes.push(size);
+                    }
+                    size *= 2;
+                }
+                sizes
+            }
+            None => {
+                if file_len < (PAGE_SIZE_OFFSET + 4) as u64 {
+                    return Err(DbErr::NotAValidDatabase);
+                }
+                let mut buffer = [0u8; 4];
+                file.seek(SeekFrom::Start(PAGE_SIZE_OFFSET as u64))?;
+                file.read_exact(&mut buffer)?;
+                vec![u32::from_be_bytes(buffer)]
+            }
+        };
+
+        let mut result = Err(DbErr::NotAValidDatabase);
+        for page_size in candidates {
+            if !is_valid_page_size(page_size) {
+                continue;
+            }
+            let codec = PageCodec::new(NonZeroU32::new(page_size).unwrap(), key, false);
+            if file_len < codec.slot_size() {
+                continue;
+            }
+            match codec.read_page(file, 0, 0) {
+                Ok(page) => {
+                    let header = HeaderPageWrapper::from_raw_page(page);
+                    let checksum = header.get_features() & FEATURE_PAGE_CHECKSUM != 0;
+                    return Ok(codec.with_checksum(checksum));
+                }
+                Err(err @ DbErr::DecryptionFailed(_)) => result = Err(err),
+                Err(err) => return Err(err),
+            }
+        }
+        result
+    }
+
+    pub(crate) fn with_key(&self, key: Option<&[u8; 32]>) -> PageCodec {
+        PageCodec::new(self.page_size, key, self.checksum)
+    }
+
+    pub(crate) fn with_checksum(&self, checksum: bool) -> PageCodec {
+        PageCodec {
+            page_size: self.page_size,
+            cipher: self.cipher.clone(),
+            checksum,
+        }
+    }
+
+    #[inline]
+    pub(crate) fn checksum(&self) -> bool {
+        self.checksum
+    }
+
+    #[inline]
+    pub(crate) fn page_size(&self) -> NonZeroU32 {
+        self.page_size
+    }
+
+    #[inline]
+    pub(crate) fn slot_size(&self) -> u64 {
+        let mut overhead = 0;
+        if self.cipher.is_some() {
+            overhead += NONCE_SIZE + TAG_SIZE;
+        }
+        if self.checksum {
+            overhead += CHECKSUM_SIZE;
+        }
+        self.page_size.get() as u64 + overhead as u64
+    }
+
+    #[inline]
+    pub(crate) fn file_size_of(&self, db_size: u64) -> u64 {
+        db_size / (self.page_size.get() as u64) * self.slot_size()
+    }
+
+    #[inline]
+    pub(crate) fn db_size_of(&self, file_size: u64) -> u64 {
+        file_size / self.slot_size() * (self.page_size.get() as u64)
+    }
+
+    #[inline]
+    pub(crate) fn slot_offset(&self, page_id: u32) -> u64 {
+        (page_id as u64) * self.slot_size()
+    }
+
+    pub(crate) fn encode<'a>(&self, page: &'a RawPage) -> Cow<'a, [u8]> {
+        let mut slot = Cow::Borrowed(page.data.as_slice());
+        if page.page_id == 0 {
+            self.set_features(&mut slot);
+        }
+
+        if let Some(cipher) = &self.cipher {
+            let mut data = Vec::with_capacity(self.slot_size() as usize);
+            data.extend_from_slice(&slot);
+            encrypt(cipher, page.page_id, &mut data);
+            slot = Cow::Owned(data);
+        }
+
+        if self.checksum {
+            let checksum = crc64(&slot);
+            slot.to_mut().extend_from_slice(&checksum.to_be_bytes());
+        }
+
+        slot
+    }
+
+    // the features and the version of the header page follow the codec
+    fn set_features(&self, data: &mut Cow<'_, [u8]>) {
+        let offset = FEATURES_OFFSET as usize;
+        let mut buffer = [0u8; 4];
+        buffer.copy_from_slice(&data[offset..(offset + 4)]);
+        let features = u32::from_be_bytes(buffer);
+
+        let expected = if self.checksum {
+            features | FEATURE_PAGE_CHECKSUM
+        } else {
+            features & !FEATURE_PAGE_CHECKSUM
+        };
+        if features != expected {
+            data.to_mut()[offset..(offset + 4)].copy_from_slice(&expected.to_be_bytes());
+        }
+
+        let offset = VERSION_OFFSET as usize;
+        let version = required_version(data, self.cipher.is_some());
+        if data[offset..(offset + 4)] != version {
+            data.to_mut()[offset..(offset + 4)].copy_from_slice(&version);
+        }
+    }
+
+    pub(crate) fn decode(&self, page_id: u32, slot: Vec<u8>) -> DbResult<RawPage> {
+        let mut result = RawPage::new(page_id, self.page_size);
+        if self.cipher.is_none() && !self.checksum {
+            result.data = slot;
+            return Ok(result);
+        }
+
+        let mut data = slot;
+        if self.checksum {
+            let checksum_offset = data.len() - CHECKSUM_SIZE;
+            let mut buffer = [0u8; CHECKSUM_SIZE];
+            buffer.copy_from_slice(&data[checksum_offset..]);
+            data.truncate(checksum_offset);
+            if crc64(&data) != u64::from_be_bytes(buffer) {
+                return Err(DbErr::ChecksumMismatch(page_id));
+            }
+        }
+
+        let cipher = match &self.cipher {
+            Some(cipher) => cipher,
+            None => {
+                result.data = data;
+                return Ok(result);
+            }
+        };
+
+        decrypt(cipher, page_id, self.page_size.get() as usize, &mut data)?;
+
+        result.data = data;
+        Ok(result)
+    }
+
+    pub(crate) fn read_slot<F: Read + Seek>(&self, file: &mut F, offset: u64) -> std::io::Result<Vec<u8>> {
+        let mut slot = vec![0u8; self.slot_size() as usize];
+        file.seek(SeekFrom::Start(offset))?;
+        file.read_exact(&mut slot)?;
+        Ok(slot)
+    }
+
+    pub(crate) fn read_page<F: Read + Seek>(&self, file: &mut F, page_id: u32, offset: u64) -> DbResult<RawPage> {
+        let slot = self.read_slot(file, offset)?;
+        self.decode(page_id, slot)
+    }
+
+    pub(crate) fn write_page<F: Write + Seek>(&self, file: &mut F, page: &RawPage, offset: u64) -> std::io::Result<()> {
+        let slot = self.encode(page);
+        file.seek(SeekFrom::Start(offset))?;
+        file.write_all(&slot)
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::num::NonZeroU32;
+    use crate::page::RawPage;
+    use crate::page::header_page_wrapper::{
+        HeaderPageWrapper, FEATURE_PAGE_CHECKSUM, DATABASE_VERSION, FEATURES_DATABASE_VERSION,
+    };
+    use crate::db::JournalPosition;
+    use crate::DbErr;
+    use super::PageCodec;
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_encode_and_decode() {
+        let page_size = NonZeroU32::new(4096).unwrap();
+        let codec = PageCodec::new(page_size, Some(&[7u8; 32]), false);
+
+        let mut page = RawPage::new(3, page_size);
+        page.put_str("hello world");
+
+        let slot = codec.encode(&page).into_owned();
+        assert_eq!(slot.len() as u64, codec.slot_size());
+        assert!(!slot.windows(11).any(|window| window == b"hello world"));
+
+        let decoded = codec.decode(3, slot.clone()).unwrap();
+        assert_eq!(decoded.data, page.data);
+
+        // the page can not be moved
+        assert!(matches!(codec.decode(4, slot.clone()), Err(DbErr::DecryptionFailed(4))));
+
+        let other = PageCodec::new(page_size, Some(&[8u8; 32]), false);
+        assert!(matches!(other.decode(3, slot), Err(DbErr::DecryptionFailed(3))));
+
+        // an empty slot is not taken as an empty page
+        let empty = vec![0; codec.slot_size() as usize];
+        assert!(matches!(codec.decode(5, empty), Err(DbErr::DecryptionFailed(5))));
+    }
+
+    #[test]
+    fn test_checksum() {
+        let page_size = NonZeroU32::new(4096).unwrap();
+        let codec = PageCodec::new(page_size, None, true);
+
+        let mut page = RawPage::new(3, page_size);
+        page.put_str("hello world");
+
+        let mut slot = codec.encode(&page).into_owned();
+        assert_eq!(slot.len() as u64, codec.slot_size());
+        assert_eq!(codec.decode(3, slot.clone()).unwrap().data, page.data);
+
+        slot[100] ^= 1;
+        assert!(matches!(codec.decode(3, slot), Err(DbErr::ChecksumMismatch(3))));
+
+        // the flag is set in the header page
+        let header = HeaderPageWrapper::init(0, page_size);
+        let slot = codec.encode(&header.0).into_owned();
+        let decoded = HeaderPageWrapper::from_raw_page(codec.decode(0, slot).unwrap());
+        assert_eq!(decoded.get_features() & FEATURE_PAGE_CHECKSUM, FEATURE_PAGE_CHECKSUM);
+
+        let plain = codec.with_checksum(false);
+        let slot = plain.encode(&decoded.0).into_owned();
+        let decoded = HeaderPageWrapper::from_raw_page(plain.decode(0, slot).unwrap());
+        assert_eq!(decoded.get_features() & FEATURE_PAGE_CHECKSUM, 0);
+    }
+
+    #[test]
+    fn test_header_version() {
+        let page_size = NonZeroU32::new(4096).unwrap();
+        let codec = PageCodec::new(page_size, None, false);
+        let encode_header = |codec: &PageCodec, header: &HeaderPageWrapper| {
+            let slot = codec.encode(&header.0).into_owned();
+            HeaderPageWrapper::from_raw_page(codec.decode(0, slot).unwrap()).get_version()
+        };
+
+        let mut header = HeaderPageWrapper::init(0, page_size);
+        assert_eq!(encode_header(&codec, &header), DATABASE_VERSION);
+        assert_eq!(encode_header(&codec.with_checksum(true), &header), FEATURES_DATABASE_VERSION);
+
+        header.set_compression(1);
+        assert_eq!(encode_header(&codec, &header), FEATURES_DATABASE_VERSION);
+        header.set_compression(0);
+
+        header.set_journal_position(Some(JournalPosition { salt1: 1, salt2: 2, frame: 3 }));
+        assert_eq!(encode_header(&codec, &header), FEATURES_DATABASE_VERSION);
+        header.set_journal_position(None);
+        assert_eq!(encode_header(&codec, &header), DATABASE_VERSION);
+
+        let large_page_size = NonZeroU32::new(8192).unwrap();
+        let header = HeaderPageWrapper::init(0, large_page_size);
+        let codec = PageCodec::new(large_page_size, None, false);
+        assert_eq!(encode_header(&codec, &header), FEATURES_DATABASE_VERSION);
+    }
+
+}
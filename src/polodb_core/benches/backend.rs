@@ -0,0 +1,71 @@
+use std::num::NonZeroUsize;
+use std::path::PathBuf;
+use criterion::{criterion_group, criterion_main, Criterion, BenchmarkId};
+use polodb_bson::doc;
+use polodb_core::{Database, Config};
+
+const DOC_COUNT: i64 = 10000;
+
+fn db_path(name: &str) -> PathBuf {
+    let mut db_path = std::env::temp_dir();
+    db_path.push(format!("bench-backend-{}.db", name));
+    db_path
+}
+
+// a small page cache, so most of the reads reach the backend
+fn bench_config(mmap: bool) -> Config {
+    Config {
+        cache_size_pages: NonZeroUsize::new(16).unwrap(),
+        mmap,
+        ..Default::default()
+    }
+}
+
+fn prepare_db(name: &str, mmap: bool) -> Database {
+    let db_path = db_path(name);
+    let _ = std::fs::remove_file(&db_path);
+
+    let mut db = Database::open_file_with_config(&db_path, bench_config(mmap)).unwrap();
+    db.start_transaction(None).unwrap();
+    let mut collection = db.create_collection("test").unwrap();
+    for i in 0..DOC_COUNT {
+        let content = "x".repeat(100 + (i % 100) as usize);
+        collection.insert(doc! { "_id": i, "content": content }.as_mut()).unwrap();
+    }
+    db.commit().unwrap();
+    drop(db);
+
+    // the pages are checkpointed to the main file when it's closed
+    Database::open_file_with_config(&db_path, bench_config(mmap)).unwrap()
+}
+
+fn read_pages(c: &mut Criterion) {
+    let mut group = c.benchmark_group("read_pages");
+
+    for (name, mmap) in [("file", false), ("mmap", true)] {
+        let mut db = prepare_db(name, mmap);
+        let mut collection = db.collection("test").unwrap();
+
+        group.bench_function(BenchmarkId::new("find_one", name), |b| {
+            let mut id = 0;
+            b.iter(|| {
+                // jump around, so the pages are not in the cache
+                id = (id + 7919) % DOC_COUNT;
+                collection.find_one(&doc! { "_id": id }).unwrap().unwrap()
+            })
+        });
+
+        group.bench_function(BenchmarkId::new("scan", name), |b| {
+            b.iter(|| collection.count().unwrap())
+        });
+
+        drop(collection);
+        drop(db);
+        let _ = std::fs::remove_file(db_path(name));
+    }
+
+    group.finish();
+}
+
+criterion_group!(benches, read_pages);
+criterion_main!(benches);
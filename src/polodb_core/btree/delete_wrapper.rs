@@ -12,7 +12,6 @@ use crate::doc_serializer::SerializeType;
 use crate::data_ticket::DataTicket;
 
 struct DeleteBackwardItem {
-    is_leaf:        bool,
     child_size:     usize,
     deleted_ticket: Box<DataTicket>,
 }
@@ -109,40 +108,17 @@ impl<'a> BTreePageDeleteWrapper<'a> {
                 let page_id = current_btree_node.indexes[idx];
                 let backward_item_opt = self.delete_item_on_subtree(pid, page_id, id)?;  // recursively delete
 
-                if let Some(backward_item) = backward_item_opt {
-                    let mut current_item_size = current_btree_node.content.len();
-
-                    if !self.is_content_size_satisfied(backward_item.child_size) {
-                        if backward_item.is_leaf {
-                            let borrow_ok = self.try_borrow_brothers(idx, current_btree_node.borrow_mut())?;
-                            if !borrow_ok {
-                                if current_btree_node.content.len() == 1 {
-                                    let _opt = self.try_merge_head(*current_btree_node)?;
-                                    debug_assert!(_opt);
-                                } else {
-                                    self.merge_leaves(idx, current_btree_node.borrow_mut())?;
-                                    current_item_size = current_btree_node.content.len();
-                                    self.write_btree(*current_btree_node);
-                                }
-                            } else {
-                                self.write_btree(*current_btree_node);
-                            }
-                        } else {
-                            // let current_btree_node = self.get_btree_by_pid(pid, parent_pid)?;
-                            if current_btree_node.content.len() == 1 {
-                                let _opt = self.try_merge_head(*current_btree_node)?;
-                                debug_assert!(_opt);
-                            }
-                        }
+                match backward_item_opt {
+                    Some(backward_item) => {
+                        let child_size = self.balance_subtree(idx, current_btree_node, backward_item.child_size, false)?;
+                        Ok(Some(DeleteBackwardItem {
+                            child_size,
+                            deleted_ticket: backward_item.deleted_ticket,
+                        }))
                     }
-                    return Ok(Some(DeleteBackwardItem {
-                        is_leaf: false,
-                        child_size: current_item_size,
-                        deleted_ticket: backward_item.deleted_ticket,
-                    }))
-                }
 
-                Ok(None)
+                    None => Ok(None),
+                }
             }
 
             // find the target node
@@ -151,92 +127,65 @@ impl<'a> BTreePageDeleteWrapper<'a> {
             SearchKeyResult::Node(idx) => {
                 if current_btree_node.is_leaf() {
                     let backward_item = self.delete_item_on_leaf(current_btree_node, idx)?;
-                    Ok(Some(backward_item))
-                } else {
-                    let deleted_ticket = Box::new(current_btree_node.content[idx].data_ticket.clone());
-
-                    let current_pid = current_btree_node.pid;
-                    let subtree_pid = current_btree_node.indexes[idx + 1];
-                    let next_item = self.find_min_element_in_subtree(subtree_pid, current_pid)?;
-                    current_btree_node.content[idx] = next_item.clone();
-                    let mut current_item_size = current_btree_node.content.len();
-                    self.write_btree(*current_btree_node);
-
-                    let backward_opt = self.delete_item_on_subtree(current_pid, subtree_pid, &next_item.key)?;
-                    match backward_opt {
-                        Some(backward_item) => {
-                            if !self.is_content_size_satisfied(backward_item.child_size) {
-                                if backward_item.is_leaf {  // borrow or merge leaves
-                                    let mut current_btree_node = self.get_btree_by_pid(pid, parent_pid)?;
-                                    let borrow_ok = self.try_borrow_brothers(idx + 1, current_btree_node.borrow_mut())?;
-                                    if !borrow_ok {
-                                        // self.merge_leaves(idx + 1, current_btree_node.borrow_mut())?;
-                                        // current_item_size = current_btree_node.content.len();
-
-                                        if current_btree_node.content.len() == 1 {
-                                            let _opt = self.try_merge_head(*current_btree_node)?;
-                                            debug_assert!(_opt);
-                                        } else {
-                                            self.merge_leaves(idx + 1, current_btree_node.borrow_mut())?;
-                                            current_item_size = current_btree_node.content.len();
-                                            self.write_btree(*current_btree_node);
-                                        }
-                                    } else {
-                                        self.write_btree(*current_btree_node);
-                                    }
-                                } else {
-                                    let current_btree_node = self.get_btree_by_pid(pid, parent_pid)?;
-                                    if current_btree_node.content.len() == 1 {
-                                        let _opt = self.try_merge_head(*current_btree_node)?;
-                                        debug_assert!(_opt);
-                                    }
-                                }
-                            }
-
-                            Ok(Some(DeleteBackwardItem {
-                                is_leaf: false,
-                                child_size: current_item_size,
-                                deleted_ticket,
-                            }))
-                        }
-
-                        None => Ok(None),
-                    }
+                    return Ok(Some(backward_item));
                 }
+
+                let deleted_ticket = Box::new(current_btree_node.content[idx].data_ticket.clone());
+
+                let subtree_pid = current_btree_node.indexes[idx + 1];
+                let next_item = self.find_min_element_in_subtree(subtree_pid, pid)?;
+                current_btree_node.content[idx] = next_item.clone();
+
+                let backward_item = self.delete_item_on_subtree(pid, subtree_pid, &next_item.key)?
+                    .expect("the min element of the subtree is not found");
+
+                let child_size = self.balance_subtree(idx + 1, current_btree_node, backward_item.child_size, true)?;
+                Ok(Some(DeleteBackwardItem {
+                    child_size,
+                    deleted_ticket,
+                }))
             }
         }
     }
 
-    fn try_merge_head(&mut self, parent_btree_node: BTreeNode) -> DbResult<bool> {
-        let left_pid = parent_btree_node.indexes[0];
-        let right_pid = parent_btree_node.indexes[1];
-
-        let left_node = self.get_btree_by_pid(left_pid, parent_btree_node.pid)?;
-        let right_node = self.get_btree_by_pid(right_pid, parent_btree_node.pid)?;
-
-        if left_node.content.len() + right_node.content.len() + 1 > self.base.item_size as usize {
-            return Ok(false);
+    // the subtree on [node_idx] has child_size items after deleting,
+    // borrow an item from the brothers or merge with one of them if it's too small,
+    // return the size of the current node
+    fn balance_subtree(&mut self, node_idx: usize, mut current_btree_node: Box<BTreeNode>, child_size: usize, mut is_dirty: bool) -> DbResult<usize> {
+        if !self.is_content_size_satisfied(child_size) {
+            if !self.try_borrow_brothers(node_idx, current_btree_node.borrow_mut())? {
+                self.merge_brothers(node_idx, current_btree_node.borrow_mut())?;
+            }
+            is_dirty = true;
         }
 
-        let mut new_content = Vec::with_capacity(self.base.item_size as usize);
-        let mut new_indexes = Vec::with_capacity((self.base.item_size + 1) as usize);
+        // the root has only one child, the child becomes the root
+        if current_btree_node.parent_pid == 0 && current_btree_node.is_empty() {
+            self.collapse_root(*current_btree_node)?;
+            return Ok(0);
+        }
 
-        new_content.extend_from_slice(&left_node.content);
-        new_indexes.extend_from_slice(&left_node.indexes);
+        let current_item_size = current_btree_node.content.len();
+        if is_dirty {
+            self.write_btree(*current_btree_node);
+        }
 
-        new_content.push(parent_btree_node.content[0].clone());
+        Ok(current_item_size)
+    }
 
-        new_content.extend_from_slice(&right_node.content);
-        new_indexes.extend_from_slice(&right_node.indexes);
+    // the page id of the root is kept,
+    // so the child is moved to the page of the root
+    fn collapse_root(&mut self, root_node: BTreeNode) -> DbResult<()> {
+        let child_pid = root_node.indexes[0];
+        let child_node = self.get_btree_by_pid(child_pid, root_node.pid)?;
 
-        self.base.page_handler.free_pages(&[left_pid, right_pid])?;
+        let new_root = root_node.clone_with_contents(child_node.content, child_node.indexes);
 
-        // move
-        let new_node: Box<BTreeNode> = Box::new(parent_btree_node.clone_with_contents(new_content, new_indexes));
+        self.base.page_handler.free_page(child_pid)?;
 
-        self.write_btree(*new_node);
+        self.write_btree(new_root);
 
-        Ok(true)
+        Ok(())
     }
 
     fn try_borrow_brothers(&mut self, node_idx: usize, current_btree_node: &mut BTreeNode) -> DbResult<bool> {
@@ -246,7 +195,7 @@ impl<'a> BTreePageDeleteWrapper<'a> {
         // node on [node_idx] is borrowed
         let subtree_pid = current_btree_node.indexes[node_idx];  // subtree need to shift
 
-        let (left_opt, right_opt) = self.get_brothers_id(&current_btree_node, node_idx);
+        let (left_opt, right_opt) = self.get_brothers_id(current_btree_node, node_idx);
 
         let left_node_opt = match left_opt {
             Some(pid) => Some(self.get_btree_by_pid(pid, current_pid)?),
@@ -273,50 +222,45 @@ impl<'a> BTreePageDeleteWrapper<'a> {
             },
         };
 
-        let mut subtree_node = self.get_btree_by_pid(subtree_pid, current_pid)?;
-
-        // if max_brother_size satisfies the number, shift one item the middle child
-        // if NOT, merge the brother the the middle child
-        if self.is_content_size_satisfied(max_brother_size) {
-            let replace_item = if is_brother_right { // middle <-(item)- right
-                let mut shift_node = right_node_opt.unwrap();
-                let (_, right_head_content) = shift_node.shift_head();
-
-                subtree_node.insert_back(current_btree_node.content[node_idx].clone(), 0);
-
-                self.write_btree(*shift_node);
-                self.write_btree(*subtree_node);
+        // the brother should be still satisfied after an item is borrowed
+        if max_brother_size == 0 || !self.is_content_size_satisfied(max_brother_size - 1) {
+            return Ok(false);
+        }
 
-                right_head_content
-            } else {  // left -(item)-> middle
-                let mut shift_node = left_node_opt.unwrap();
-                let (left_last_content, _) = shift_node.shift_last();
+        let mut subtree_node = self.get_btree_by_pid(subtree_pid, current_pid)?;
 
-                subtree_node.insert_head(0, current_btree_node.content[node_idx].clone());
+        // the item in the parent goes down, and the item of the brother goes up,
+        // the child of the brother is moved with it
+        if is_brother_right { // middle <-(item)- right
+            let mut shift_node = right_node_opt.unwrap();
+            let (right_head_index, right_head_content) = shift_node.shift_head();
 
-                self.write_btree(*shift_node);
-                self.write_btree(*subtree_node);
+            subtree_node.insert_back(current_btree_node.content[node_idx].clone(), right_head_index);
+            current_btree_node.content[node_idx] = right_head_content;
 
-                left_last_content
-            };
+            self.write_btree(*shift_node);
+        } else {  // left -(item)-> middle
+            let mut shift_node = left_node_opt.unwrap();
+            let (left_last_content, left_last_index) = shift_node.shift_last();
 
-            // shift complete
-            current_btree_node.content[node_idx] = replace_item;
+            subtree_node.insert_head(left_last_index, current_btree_node.content[node_idx - 1].clone());
+            current_btree_node.content[node_idx - 1] = left_last_content;
 
-            return Ok(true);
+            self.write_btree(*shift_node);
         }
 
-        Ok(false)
-    }
+        self.write_btree(*subtree_node);
 
-    // merge the nth elements of the current_btree_node
-    fn merge_leaves(&mut self, node_idx: usize, current_btree_node: &mut BTreeNode) -> DbResult<()> {
-        debug_assert!(current_btree_node.content.len() > 1);
+        Ok(true)
+    }
 
+    // merge the subtree on [node_idx] with one of its brothers,
+    // the item between them in the current node goes down
+    fn merge_brothers(&mut self, node_idx: usize, current_btree_node: &mut BTreeNode) -> DbResult<()> {
         let current_pid = current_btree_node.pid;
         let subtree_pid = current_btree_node.indexes[node_idx];  // subtree need to shift
 
-        let (left_opt, right_opt) = self.get_brothers_id(&current_btree_node, node_idx);
+        let (left_opt, right_opt) = self.get_brothers_id(current_btree_node, node_idx);
 
         let left_node_opt = match left_opt {
             Some(pid) => Some(self.get_btree_by_pid(pid, current_pid)?),
@@ -342,7 +286,7 @@ impl<'a> BTreePageDeleteWrapper<'a> {
 
         };
 
-        let mut subtree_node = self.get_btree_by_pid(subtree_pid, current_pid)?;
+        let subtree_node = self.get_btree_by_pid(subtree_pid, current_pid)?;
         if !is_brother_right {  // left
             let mut left_node = left_node_opt.unwrap();
 
@@ -353,21 +297,19 @@ impl<'a> BTreePageDeleteWrapper<'a> {
             current_btree_node.content.remove(node_idx - 1);
             current_btree_node.indexes.remove(node_idx);
 
-            debug_assert_eq!(current_btree_node.indexes[node_idx], subtree_node.pid);
-
             self.base.page_handler.free_page(subtree_node.pid)?;
 
             self.write_btree(*left_node);
         } else {  // right
             let right_node = right_node_opt.unwrap();
+            let mut subtree_node = subtree_node;
+
+            debug_assert_eq!(current_btree_node.indexes[node_idx + 1], right_node.pid);
 
             subtree_node.content.push(current_btree_node.content[node_idx].clone());
             subtree_node.content.extend_from_slice(&right_node.content);
-
             subtree_node.indexes.extend_from_slice(&right_node.indexes);
 
-            debug_assert_eq!(current_btree_node.indexes[node_idx + 1], right_node.pid);
-
             current_btree_node.content.remove(node_idx);
             current_btree_node.indexes.remove(node_idx + 1);
 
@@ -382,6 +324,7 @@ impl<'a> BTreePageDeleteWrapper<'a> {
     fn erase_item(&mut self, item: &DataTicket) -> DbResult<Rc<Document>> {
         let bytes = self.base.page_handler.free_data_ticket(&item)?;
         debug_assert!(!bytes.is_empty(), "bytes is empty");
+        let bytes = crate::compression::decompress(self.base.page_handler.compression, &bytes)?;
         let mut my_ref: &[u8] = bytes.as_ref();
         let doc = crate::doc_serializer::deserialize(self.serialize_type, &mut my_ref)?;
         Ok(Rc::new(doc))
@@ -418,7 +361,6 @@ impl<'a> BTreePageDeleteWrapper<'a> {
         self.base.write_btree_node(&btree_node)?;
 
         Ok(DeleteBackwardItem {
-            is_leaf: true,
             child_size: remain_content_len,
             deleted_ticket,
         })
@@ -163,7 +163,7 @@ impl BTreeNode {
         BTreeNode::from_raw_lossy(page, parent_pid, item_size, page_handler)
     }
 
-    fn parse_node_data_item(page: &RawPage, begin_offset: u32, page_handler: &mut PageHandler) -> DbResult<BTreeNodeDataItem> {
+    pub(crate) fn parse_node_data_item(page: &RawPage, begin_offset: u32, page_handler: &mut PageHandler) -> DbResult<BTreeNodeDataItem> {
         let is_complex = page.get_u8(begin_offset + 4);
         if is_complex != 0 {
             return BTreeNode::parse_complex_data_item(page, begin_offset, page_handler);
@@ -194,9 +194,9 @@ impl BTreeNode {
             ty_int::INT => {
                 let value_begin_offset = (begin_offset + 6) as usize;
 
-                let (int_value, _) = vli::decode_u64(&page.data[value_begin_offset..])?;
+                let (int_value, _) = vli::decode(&page.data[value_begin_offset..])?;
 
-                Value::Int(int_value as i64)
+                Value::Int(int_value)
             }
 
             ty_int::STRING => {
@@ -221,9 +221,8 @@ impl BTreeNode {
                     offset += 1;
                 }
 
-                let str = unsafe {
-                    String::from_utf8_unchecked(buffer)
-                };
+                // the page may be damaged
+                let str = std::str::from_utf8(&buffer)?;
 
                 str.into()
             }
@@ -254,7 +253,7 @@ impl BTreeNode {
     }
 
     #[inline]
-    fn parse_data_item_ticket(page: &RawPage, begin_offset: u32) -> DataTicket {
+    pub(crate) fn parse_data_item_ticket(page: &RawPage, begin_offset: u32) -> DataTicket {
         let ticket_bytes = (begin_offset + 6 + 12) as usize;
         let ticket_bytes = &page.data[ticket_bytes..(ticket_bytes + 6)];
         DataTicket::from_bytes(ticket_bytes)
@@ -271,12 +270,10 @@ impl BTreeNode {
         page.seek(2);
         page.put_u16(items_len);
 
-        if let Some(_first) = self.content.first() {
-            page.seek(4);
-
-            let left_id = self.indexes.first().expect("get first left id failed");
-            page.put_u32(*left_id);
-        };
+        // an internal node may have no items but one child while it's being balanced
+        page.seek(4);
+        let left_id = self.indexes.first().expect("get first left id failed");
+        page.put_u32(*left_id);
 
         let mut index = 0;
         while index < self.content.len() {
@@ -447,3 +444,220 @@ pub struct BTreeNodeDataItem {
     pub(crate) key:          Value,
     pub(crate) data_ticket:  DataTicket,
 }
+
+#[cfg(test)]
+mod tests {
+    use std::cmp::Ordering;
+    use std::collections::BTreeMap;
+    use proptest::prelude::*;
+    use polodb_bson::{Value, ObjectId, doc};
+    use crate::{Database, Config, DbErr};
+
+    // the smallest page, so the nodes are split and merged frequently
+    static PAGE_SIZE: u32 = 1024;
+
+    #[derive(Debug, Clone)]
+    enum Op {
+        Insert(u16),
+        Delete(u16),
+        Find(u16),
+    }
+
+    fn op_strategy(key_count: u16) -> impl Strategy<Value = Op> {
+        prop_oneof![
+            3 => (0..key_count).prop_map(Op::Insert),
+            2 => (0..key_count).prop_map(Op::Delete),
+            1 => (0..key_count).prop_map(Op::Find),
+        ]
+    }
+
+    fn int_key(index: u16) -> Value {
+        Value::Int(index as i64 * 7 - 300)
+    }
+
+    // the keys longer than 12 bytes are stored out of the node
+    fn string_key(index: u16) -> Value {
+        let width = 1 + (index as usize) % 20;
+        Value::from(format!("{:0width$}", index, width = width))
+    }
+
+    fn object_id_key(index: u16) -> Value {
+        let mut bytes = [0; 12];
+        bytes[0..8].copy_from_slice(&(1_600_000_000_000 + (index as u64 % 5)).to_be_bytes());
+        bytes[8..12].copy_from_slice(&(index as u32).to_be_bytes());
+        ObjectId::deserialize(&bytes).unwrap().into()
+    }
+
+    fn boolean_key(index: u16) -> Value {
+        Value::Boolean(index % 2 == 1)
+    }
+
+    // run the operations on a collection and a BTreeMap,
+    // the collection is checked against the map after every operation
+    fn check_against_model(ops: &[Op], mk_key: fn(u16) -> Value) -> Result<(), TestCaseError> {
+        let config = Config {
+            page_size: PAGE_SIZE,
+            ..Default::default()
+        };
+        let mut db = Database::open_memory_wht_config(config).unwrap();
+        let mut collection = db.create_collection("test").unwrap();
+
+        // the index of the key -> value
+        let mut model: BTreeMap<u16, i64> = BTreeMap::new();
+
+        for (step, op) in ops.iter().enumerate() {
+            let value = step as i64;
+            match op {
+                Op::Insert(index) => {
+                    let result = collection.insert(doc! {
+                        "_id": mk_key(*index),
+                        "value": value,
+                    }.as_mut());
+                    if model.contains_key(index) {
+                        prop_assert!(matches!(result, Err(DbErr::DataExist(_))), "step {}: {:?}", step, op);
+                    } else {
+                        prop_assert!(result.is_ok(), "step {}: {:?}", step, op);
+                        model.insert(*index, value);
+                    }
+                }
+
+                Op::Delete(index) => {
+                    let count = collection.delete(Some(&doc! {
+                        "_id": mk_key(*index),
+                    })).unwrap();
+                    let expected = model.remove(index).map_or(0, |_| 1);
+                    prop_assert_eq!(count, expected, "step {}: {:?}", step, op);
+                }
+
+                Op::Find(index) => {
+                    let doc = collection.find_one(&doc! {
+                        "_id": mk_key(*index),
+                    }).unwrap();
+                    let value = doc.map(|doc| doc.get("value").unwrap().unwrap_int());
+                    prop_assert_eq!(value, model.get(index).copied(), "step {}: {:?}", step, op);
+                }
+            }
+        }
+
+        // the items are iterated in the order of the keys
+        let mut expected: Vec<(Value, i64)> = model.iter()
+            .map(|(index, value)| (mk_key(*index), *value))
+            .collect();
+        expected.sort_by(|(key1, _), (key2, _)| key1.value_cmp(key2).unwrap());
+
+        let actual = collection.find_all().unwrap();
+        prop_assert_eq!(actual.len(), expected.len());
+        for (doc, (key, value)) in actual.iter().zip(expected.iter()) {
+            let actual_key = doc.get("_id").unwrap();
+            prop_assert_eq!(actual_key.value_cmp(key).unwrap(), Ordering::Equal, "{} != {}", actual_key, key);
+            prop_assert_eq!(doc.get("value").unwrap().unwrap_int(), *value);
+        }
+
+        prop_assert_eq!(collection.count().unwrap(), model.len() as u64);
+        let report = db.check_integrity().unwrap();
+        prop_assert!(report.is_ok(), "{}", report);
+
+        Ok(())
+    }
+
+    // the negative ints are written with a flag byte,
+    // which must be read back by the same decoding
+    #[test]
+    fn test_negative_int_keys() {
+        let db_path = std::env::temp_dir().join("test-btree-negative-int-keys.db");
+        let _ = std::fs::remove_file(&db_path);
+        let mut journal_path = db_path.as_os_str().to_owned();
+        journal_path.push(".journal");
+        let _ = std::fs::remove_file(&journal_path);
+
+        let mut keys: Vec<i64> = (-300..300).map(|i| i * 7).collect();
+        keys.extend_from_slice(&[-128, -16384, -(1 << 40), i64::MIN + 1]);
+
+        let config = Config {
+            page_size: PAGE_SIZE,
+            ..Default::default()
+        };
+        {
+            let mut db = Database::open_file_with_config(&db_path, config.clone()).unwrap();
+            let mut collection = db.create_collection("test").unwrap();
+            for key in keys.iter().rev() {
+                collection.insert(doc! { "_id": *key }.as_mut()).unwrap();
+            }
+        }
+
+        keys.sort();
+        let mut db = Database::open_file_with_config(&db_path, config).unwrap();
+        let mut collection = db.collection("test").unwrap();
+        for key in &keys {
+            let doc = collection.find_one(&doc! { "_id": *key }).unwrap().unwrap();
+            assert_eq!(doc.get("_id").unwrap().unwrap_int(), *key);
+        }
+
+        let actual: Vec<i64> = collection.find_all().unwrap().iter()
+            .map(|doc| doc.get("_id").unwrap().unwrap_int())
+            .collect();
+        assert_eq!(actual, keys);
+        assert!(db.check_integrity().unwrap().is_ok());
+    }
+
+    // the keys are deleted out of order, so many of them are in the internal nodes
+    // when they are deleted, and the nodes are merged up to the root
+    #[test]
+    fn test_delete_internal_keys() {
+        const COUNT: i64 = 2000;
+        let config = Config {
+            page_size: PAGE_SIZE,
+            ..Default::default()
+        };
+        let mut db = Database::open_memory_wht_config(config).unwrap();
+        let mut collection = db.create_collection("test").unwrap();
+        for key in 0..COUNT {
+            collection.insert(doc! { "_id": key }.as_mut()).unwrap();
+        }
+
+        let mut remaining: BTreeMap<i64, ()> = (0..COUNT).map(|key| (key, ())).collect();
+        for step in 0..COUNT {
+            let key = step * 769 % COUNT;
+            let count = collection.delete(Some(&doc! { "_id": key })).unwrap();
+            assert_eq!(count, 1, "key {}", key);
+            remaining.remove(&key);
+
+            if step % 100 == 0 {
+                let actual: Vec<i64> = collection.find_all().unwrap().iter()
+                    .map(|doc| doc.get("_id").unwrap().unwrap_int())
+                    .collect();
+                let expected: Vec<i64> = remaining.keys().copied().collect();
+                assert_eq!(actual, expected, "step {}", step);
+            }
+        }
+
+        assert_eq!(collection.count().unwrap(), 0);
+        let report = db.check_integrity().unwrap();
+        assert!(report.is_ok(), "{}", report);
+    }
+
+    proptest! {
+        #![proptest_config(ProptestConfig::with_cases(32))]
+
+        #[test]
+        fn test_btree_int_keys(ops in prop::collection::vec(op_strategy(400), 1..1200)) {
+            check_against_model(&ops, int_key)?;
+        }
+
+        #[test]
+        fn test_btree_string_keys(ops in prop::collection::vec(op_strategy(400), 1..1200)) {
+            check_against_model(&ops, string_key)?;
+        }
+
+        #[test]
+        fn test_btree_object_id_keys(ops in prop::collection::vec(op_strategy(400), 1..1200)) {
+            check_against_model(&ops, object_id_key)?;
+        }
+
+        #[test]
+        fn test_btree_boolean_keys(ops in prop::collection::vec(op_strategy(2), 1..50)) {
+            check_against_model(&ops, boolean_key)?;
+        }
+    }
+
+}
@@ -0,0 +1,80 @@
+use std::borrow::Cow;
+use crate::{DbResult, DbErr};
+
+/// The compression of the documents stored in the data pages
+/// and the large data pages.
+///
+/// It's recorded in the header page when the database is created,
+/// the setting of an existing file can not be changed.
+#[derive(Debug, Clone, Copy, Eq, PartialEq, Default)]
+pub enum Compression {
+    #[default]
+    None,
+
+    /// Requires the feature `compression`.
+    Lz4,
+}
+
+impl Compression {
+
+    pub(crate) fn from_header(value: u32) -> DbResult<Compression> {
+        match value {
+            0 => Ok(Compression::None),
+            1 => Ok(Compression::Lz4),
+            _ => Err(DbErr::NotAValidDatabase),
+        }
+    }
+
+    pub(crate) fn to_header(self) -> u32 {
+        match self {
+            Compression::None => 0,
+            Compression::Lz4 => 1,
+        }
+    }
+
+    pub(crate) fn check_supported(self) -> DbResult<()> {
+        match self {
+            Compression::None => Ok(()),
+            Compression::Lz4 if cfg!(feature = "compression") => Ok(()),
+            Compression::Lz4 => Err(DbErr::NotSupported("lz4 compression without the feature \"compression\"")),
+        }
+    }
+
+}
+
+pub(crate) fn compress(compression: Compression, bytes: Vec<u8>) -> Vec<u8> {
+    match compression {
+        Compression::None => bytes,
+
+        #[cfg(feature = "compression")]
+        Compression::Lz4 => lz4_flex::compress_prepend_size(&bytes),
+
+        #[cfg(not(feature = "compression"))]
+        Compression::Lz4 => unreachable!("compression is not supported"),
+    }
+}
+
+pub(crate) fn decompress(compression: Compression, bytes: &[u8]) -> DbResult<Cow<'_, [u8]>> {
+    match compression {
+        Compression::None => Ok(Cow::Borrowed(bytes)),
+
+        #[cfg(feature = "compression")]
+        Compression::Lz4 => {
+            // the ratio of lz4 is less than 255,
+            // a larger size means the data is damaged
+            if bytes.len() < 4 {
+                return Err(DbErr::DecompressionFailed("the data is too short".into()));
+            }
+            let size = u32::from_le_bytes([bytes[0], bytes[1], bytes[2], bytes[3]]) as usize;
+            if size > (bytes.len() - 4) * 255 {
+                return Err(DbErr::DecompressionFailed(format!("the size {} is too large", size)));
+            }
+            let result = lz4_flex::decompress_size_prepended(bytes)
+                .map_err(|err| DbErr::DecompressionFailed(err.to_string()))?;
+            Ok(Cow::Owned(result))
+        }
+
+        #[cfg(not(feature = "compression"))]
+        Compression::Lz4 => Err(DbErr::NotSupported("lz4 compression without the feature \"compression\"")),
+    }
+}
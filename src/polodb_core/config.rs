@@ -1,11 +1,111 @@
 
 use crate::doc_serializer::SerializeType;
-use std::num::NonZeroU64;
+use crate::compression::Compression;
+use std::num::{NonZeroU64, NonZeroUsize};
+use std::time::{Duration, SystemTime};
+use std::path::PathBuf;
 
+/// When the files are synced to the disk.
+///
+/// A crash of the process loses nothing committed in any mode,
+/// the modes are different when the machine loses power.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Synchronous {
+    /// Never sync, it's left to the operating system.
+    /// The database may be damaged by a power failure.
+    Off,
+
+    /// Sync the journal and the database file when the journal is checkpointed.
+    /// The transactions committed after the last checkpoint
+    /// may be lost by a power failure.
+    Normal,
+
+    /// Also sync the journal when a transaction is committed,
+    /// the committed transactions are never lost.
+    Full,
+}
+
+/// When the journal is written back to the database file automatically,
+/// see [Database::checkpoint](crate::Database::checkpoint) to do it manually.
+///
+/// It's checked when a transaction is finished, and deferred
+/// if other connections are reading the older data.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AutoCheckpoint {
+    /// When the journal has [Config::journal_full_size] frames.
+    Frames,
+
+    /// When the journal file is larger than the bytes.
+    Bytes(u64),
+
+    /// When the time has passed since the last checkpoint.
+    Interval(Duration),
+
+    /// Only when the database is closed.
+    Disabled,
+}
+
+#[derive(Clone)]
 pub struct Config {
     pub init_block_count:  NonZeroU64,
     pub journal_full_size: u64,
+
+    /// When the journal is checkpointed automatically, see [AutoCheckpoint].
+    pub auto_checkpoint:   AutoCheckpoint,
+
     pub serialize_type:    SerializeType,
+
+    /// The size of the pages of a new database, a power of two
+    /// between 1 KiB and 64 KiB. The larger pages are better
+    /// for the large documents.
+    /// Only used when the database is created,
+    /// the setting in the header page is used for an existing file.
+    pub page_size:         u32,
+
+    /// The count of the pages cached by every connection.
+    pub cache_size_pages:  NonZeroUsize,
+
+    /// Only used when the database is created,
+    /// the setting in the header page is used for an existing file.
+    pub compression:       Compression,
+
+    /// The key to encrypt the pages of the database and the journal,
+    /// see [Database::rekey](crate::Database::rekey) to change it.
+    /// Requires the feature "encryption".
+    pub encryption_key:    Option<[u8; 32]>,
+
+    /// Append a checksum to every page of a new database, which is verified
+    /// when the page is read. It's recorded in the header page when the database
+    /// is created, see [Database::set_page_checksum](crate::Database::set_page_checksum)
+    /// to change it for an existing one.
+    pub page_checksum:     bool,
+
+    /// Read the pages from a memory mapping of the database file,
+    /// which saves the system calls on the read-heavy workloads.
+    /// The pages are still copied out of the mapping, and cached
+    /// like the pages read from the file.
+    /// The writes still go through the journal.
+    /// Not used by the memory databases and the read-only ones.
+    /// Requires the feature "mmap".
+    pub mmap:              bool,
+
+    /// Open the database file in read-only mode, which works on a read-only
+    /// filesystem. The journal is not created, and the file is only locked
+    /// with a shared lock, so other processes can read it at the same time.
+    /// The writes fail with [DbErr::ReadOnly](crate::DbErr::ReadOnly).
+    pub read_only:         bool,
+
+    /// When the files are synced to the disk, see [Synchronous].
+    pub synchronous:       Synchronous,
+
+    /// Keep the journal in a new segment in the directory at every checkpoint,
+    /// instead of discarding it. A backup can be brought to any transaction
+    /// committed later by [Database::restore](crate::Database::restore).
+    pub archive_dir:       Option<PathBuf>,
+
+    /// The clock of the commit times recorded in the journal,
+    /// which are the targets of [Database::restore](crate::Database::restore).
+    pub clock:             fn() -> SystemTime,
 }
 
 impl Default for Config {
@@ -14,7 +114,18 @@ impl Default for Config {
         Config {
             init_block_count:  NonZeroU64::new(16).unwrap(),
             journal_full_size: 1000,
+            auto_checkpoint:   AutoCheckpoint::Frames,
             serialize_type:    SerializeType::Default,
+            page_size:         4096,
+            cache_size_pages:  NonZeroUsize::new(1024).unwrap(),
+            compression:       Compression::None,
+            encryption_key:    None,
+            page_checksum:     false,
+            mmap:              false,
+            read_only:         false,
+            synchronous:       Synchronous::Normal,
+            archive_dir:       None,
+            clock:             SystemTime::now,
         }
     }
 
@@ -1,5 +1,6 @@
 use std::borrow::Borrow;
-use std::path::Path;
+use std::path::{Path, PathBuf};
+use std::io::{Read, Write};
 use std::num::NonZeroU32;
 use std::sync::Arc;
 use std::rc::Rc;
@@ -10,18 +11,23 @@ use super::TransactionType;
 use crate::page_handler::PageHandler;
 use crate::Config;
 use crate::vm::{SubProgram, VM, VmState};
-use crate::db::DbResult;
+use crate::db::{DbResult, BackupProgress, VacuumReport, CheckpointMode, CheckpointStats, JournalPosition};
 use crate::meta_doc_helper::{meta_doc_key, MetaDocEntry};
 use crate::index_ctx::{IndexCtx, merge_options_into_default};
 use crate::btree::*;
 use crate::transaction::TransactionState;
-use crate::backend::file::FileBackend;
+use crate::backend::file::{FileBackend, ReadOnlyBackend, read_journal_frames, JournalFrames, JournalTransaction};
+#[cfg(feature = "mmap")]
+use crate::backend::file::MmapBackend;
 use crate::backend::memory::MemoryBackend;
-use crate::page::RawPage;
+use crate::page::{RawPage, is_valid_page_size};
 use crate::db_handle::DbHandle;
+use crate::integrity::{IntegrityChecker, IntegrityReport};
+use crate::stats::{StatsCollector, DatabaseStats, CollectionStats};
+use crate::metrics::Metrics;
 use crate::dump::{FullDump, PageDump, OverflowDataPageDump, DataPageDump, FreeListPageDump, BTreePageDump};
 use crate::page::header_page_wrapper::HeaderPageWrapper;
-use crate::backend::Backend;
+use crate::backend::{Backend, BackendExt, CustomBackend, PageCodec, check_key_supported};
 
 macro_rules! try_multiple {
     ($err: expr, $action: expr) => {
@@ -83,22 +89,96 @@ pub struct CollectionMeta {
 impl DbContext {
 
     pub fn open_file(path: &Path, config: Config) -> DbResult<DbContext> {
-        let page_size = NonZeroU32::new(4096).unwrap();
+        DbContext::check_page_size(config.page_size)?;
+        check_key_supported(config.encryption_key.as_ref())?;
 
         let config = Arc::new(config);
-        let backend = Box::new(FileBackend::open(path, page_size, config.clone())?);
-        DbContext::open_with_backend(backend, page_size, config)
+        let backend: Box<dyn BackendExt + Send> = if config.read_only {
+            Box::new(ReadOnlyBackend::open(path, config.clone())?)
+        } else if config.mmap {
+            DbContext::open_mmap_backend(path, config.clone())?
+        } else {
+            Box::new(FileBackend::open(path, config.clone())?)
+        };
+        DbContext::from_backend(backend, config)
+    }
+
+    #[cfg(feature = "mmap")]
+    fn open_mmap_backend(path: &Path, config: Arc<Config>) -> DbResult<Box<dyn BackendExt + Send>> {
+        Ok(Box::new(MmapBackend::open(path, config)?))
+    }
+
+    #[cfg(not(feature = "mmap"))]
+    fn open_mmap_backend(_path: &Path, _config: Arc<Config>) -> DbResult<Box<dyn BackendExt + Send>> {
+        Err(DbErr::NotSupported("memory mapping without the feature \"mmap\""))
     }
 
     pub fn open_memory(config: Config) -> DbResult<DbContext> {
-        let page_size = NonZeroU32::new(4096).unwrap();
+        DbContext::check_page_size(config.page_size)?;
+        check_key_supported(config.encryption_key.as_ref())?;
+
+        let page_size = NonZeroU32::new(config.page_size).unwrap();
+        let config = Arc::new(config);
+        let codec = PageCodec::new(page_size, config.encryption_key.as_ref(), config.page_checksum);
+        let backend = Box::new(MemoryBackend::new(codec, config.init_block_count, config.compression));
+        DbContext::from_backend(backend, config)
+    }
+
+    // the journal of the file is applied, and checkpointed when it's closed
+    pub fn open_file_into_memory(path: &Path, config: Config) -> DbResult<DbContext> {
+        DbContext::check_page_size(config.page_size)?;
+        check_key_supported(config.encryption_key.as_ref())?;
+
+        // don't create a new file
+        std::fs::metadata(path)?;
+
         let config = Arc::new(config);
-        let backend = Box::new(MemoryBackend::new(page_size, config.init_block_count));
-        DbContext::open_with_backend(backend, page_size, config)
+        let mut file_backend = FileBackend::open(path, config.clone())?;
+        let backend = Box::new(MemoryBackend::load_from(&mut file_backend)?);
+        drop(file_backend);
+
+        DbContext::from_backend(backend, config)
     }
 
-    fn open_with_backend(backend: Box<dyn Backend + Send>, page_size: NonZeroU32, config: Arc<Config>) -> DbResult<DbContext> {
-        let page_handler = PageHandler::new(backend, page_size, config.clone())?;
+    pub fn open_with_backend(backend: Box<dyn Backend + Send>, config: Config) -> DbResult<DbContext> {
+        let mut backend: Box<dyn BackendExt + Send> = Box::new(CustomBackend(backend));
+        DbContext::check_page_size(backend.page_size().get())?;
+
+        if backend.db_size() == 0 {
+            DbContext::init_backend(backend.as_mut(), &config)?;
+        }
+        DbContext::from_backend(backend, Arc::new(config))
+    }
+
+    // the file and memory backends write the header page when they are created,
+    // a custom backend is empty at first
+    fn init_backend(backend: &mut dyn BackendExt, config: &Config) -> DbResult<()> {
+        let mut wrapper = HeaderPageWrapper::init(0, backend.page_size());
+        wrapper.set_compression(config.compression.to_header());
+
+        // the empty meta page, an empty slot is taken as damaged
+        let meta_page = RawPage::new(wrapper.get_meta_page_id(), backend.page_size());
+
+        backend.start_transaction(TransactionType::Write)?;
+        let result = backend.write_page(&wrapper.0)
+            .and_then(|_| backend.write_page(&meta_page))
+            .and_then(|_| backend.commit());
+        if result.is_err() {
+            let _ = backend.rollback();
+        }
+        result
+    }
+
+    #[inline]
+    fn check_page_size(page_size: u32) -> DbResult<()> {
+        if !is_valid_page_size(page_size) {
+            return Err(DbErr::InvalidPageSize(page_size));
+        }
+        Ok(())
+    }
+
+    fn from_backend(backend: Box<dyn BackendExt + Send>, config: Arc<Config>) -> DbResult<DbContext> {
+        let page_handler = PageHandler::new(backend, config.clone())?;
 
         let obj_id_maker = ObjectIdMaker::new();
 
@@ -113,9 +193,20 @@ impl DbContext {
         let meta_source = ctx.get_meta_source()?;
         ctx.meta_version = meta_source.meta_version;
 
+        ctx.page_handler.load_compression()?;
+
         Ok(ctx)
     }
 
+    /**
+     * another connection shares the files with this one,
+     * and has its own transaction and page cache
+     */
+    pub fn new_connection(&self) -> DbResult<DbContext> {
+        let backend = self.page_handler.new_connection()?;
+        DbContext::from_backend(backend, self.config.clone())
+    }
+
     /**
      * when the database rollback,
      * the cached meta_version maybe rollback, so read it again
@@ -262,10 +353,24 @@ impl DbContext {
 
         doc.insert(meta_doc_key::FLAGS.into(), Value::Int(0));
 
+        self.insert_meta_doc(&mut meta_source, &doc)?;
+
+        meta_source.meta_id_counter += 1;
+        meta_source.meta_version += 1;
+
+        self.update_meta_source(&meta_source)?;
+
+        Ok(CollectionMeta {
+            id: collection_id,
+            meta_version: meta_source.meta_version,
+        })
+    }
+
+    fn insert_meta_doc(&mut self, meta_source: &mut MetaSource, doc: &Document) -> DbResult<()> {
         let mut btree_wrapper = BTreePageInsertWrapper::new(
             &mut self.page_handler, meta_source.meta_pid);
 
-        let insert_result = btree_wrapper.insert_item(&doc, false)?;
+        let insert_result = btree_wrapper.insert_item(doc, false)?;
 
         // if a backward item returns, it's saying that the btree has been "rotated".
         // the center node of the btree has been changed.
@@ -281,15 +386,7 @@ impl DbContext {
             meta_source.meta_pid = new_root_id;
         }
 
-        meta_source.meta_id_counter += 1;
-        meta_source.meta_version += 1;
-
-        self.update_meta_source(&meta_source)?;
-
-        Ok(CollectionMeta {
-            id: collection_id,
-            meta_version: meta_source.meta_version,
-        })
+        Ok(())
     }
 
     fn update_meta_source(&mut self, meta_source: &MetaSource) -> DbResult<()> {
@@ -307,6 +404,11 @@ impl DbContext {
         (self.page_handler.page_size.get() - HEADER_SIZE) / ITEM_SIZE
     }
 
+    #[cfg(feature = "fuzzing")]
+    pub(crate) fn parse_btree_node(&mut self, page: &RawPage) -> DbResult<BTreeNode> {
+        BTreeNode::from_raw(page, 0, self.item_size(), &mut self.page_handler)
+    }
+
     pub(crate) fn make_handle(&mut self, program: SubProgram) -> DbHandle {
         let vm = VM::new(&mut self.page_handler, Box::new(program));
         DbHandle::new(vm)
@@ -492,11 +594,31 @@ impl DbContext {
     pub fn find(&mut self, col_id: u32, meta_version: u32, query: Option<&Document>) -> DbResult<DbHandle> {
         self.check_meta_version(meta_version)?;
 
+        // the collection should be found in the same snapshot the query runs in
+        let start_result = self.page_handler.auto_start_transaction(TransactionType::Read)?;
+
+        let subprogram = match self.compile_find_program(col_id, query) {
+            Ok(subprogram) => subprogram,
+            Err(err) => {
+                try_multiple!(err, self.page_handler.auto_rollback());
+                return Err(err);
+            }
+        };
+
+        let mut handle = self.make_handle(subprogram);
+        if start_result.auto_start {
+            handle.set_rollback_on_drop(true);
+        }
+
+        Ok(handle)
+    }
+
+    fn compile_find_program(&mut self, col_id: u32, query: Option<&Document>) -> DbResult<SubProgram> {
         let meta_source = self.get_meta_source()?;
         let collection_meta = self.find_collection_root_pid_by_id(
             0, meta_source.meta_pid, col_id)?;
 
-        let subprogram = match query {
+        match query {
             Some(query) => SubProgram::compile_query(
                 &collection_meta,
                 collection_meta.doc_ref(),
@@ -504,11 +626,7 @@ impl DbContext {
                 true
             ),
             None => SubProgram::compile_query_all(&collection_meta, true),
-        }?;
-
-        let handle = self.make_handle(subprogram);
-
-        Ok(handle)
+        }
     }
 
     pub fn update(&mut self, col_id: u32, meta_version: u32, query: Option<&Document>, update: &Document) -> DbResult<usize> {
@@ -695,6 +813,14 @@ impl DbContext {
 
     pub fn count(&mut self, col_id: u32, meta_version: u32) -> DbResult<u64> {
         self.check_meta_version(meta_version)?;
+        self.page_handler.auto_start_transaction(TransactionType::Read)?;
+
+        let result = try_db_op!(self, self.internal_count(col_id));
+
+        Ok(result)
+    }
+
+    fn internal_count(&mut self, col_id: u32) -> DbResult<u64> {
         let meta_source = self.get_meta_source()?;
         let collection_meta = self.find_collection_root_pid_by_id(
             0, meta_source.meta_pid, col_id)?;
@@ -754,11 +880,421 @@ impl DbContext {
         Ok(())
     }
 
+    #[inline]
+    pub(crate) fn is_in_transaction(&self) -> bool {
+        self.page_handler.transaction_state() != TransactionState::NoTrans
+    }
+
+    /// A new transaction is started if there is no transaction,
+    /// releasing the savepoint will commit it.
+    pub fn savepoint(&mut self, name: &str) -> DbResult<()> {
+        let starts_transaction = self.page_handler.transaction_state() == TransactionState::NoTrans;
+        if starts_transaction {
+            self.start_transaction(Some(TransactionType::Write))?;
+        }
+
+        let result = self.page_handler.savepoint(name, starts_transaction);
+        if let Err(err) = result {
+            if starts_transaction {
+                try_multiple!(err, self.rollback());
+            }
+            return Err(err);
+        }
+
+        Ok(())
+    }
+
+    pub fn rollback_to(&mut self, name: &str) -> DbResult<()> {
+        self.page_handler.rollback_to_savepoint(name)?;
+        self.reset_meta_version()
+    }
+
+    pub fn release(&mut self, name: &str) -> DbResult<()> {
+        let starts_transaction = self.page_handler.release_savepoint(name)?;
+        if starts_transaction {
+            self.commit()?;
+        }
+        Ok(())
+    }
+
     #[inline]
     pub fn object_id_maker(&mut self) -> &mut ObjectIdMaker {
         &mut self.obj_id_maker
     }
 
+    /**
+     * copy all the pages seen by a read transaction to a new file,
+     * the pages in the journal are included,
+     * the backup is encrypted with the same key,
+     * and continues from the position of the journal as a replica
+     */
+    pub fn backup_to<F>(&mut self, path: &Path, progress: F) -> DbResult<()>
+    where
+        F: FnMut(BackupProgress)
+    {
+        self.page_handler.auto_start_transaction(TransactionType::Read)?;
+
+        try_db_op!(self, self.internal_backup_to(path, progress));
+
+        Ok(())
+    }
+
+    // the backup is written to a temporary file, which is renamed when it's complete,
+    // so a crash never leaves a partial backup which looks valid
+    fn internal_backup_to<F>(&mut self, path: &Path, progress: F) -> DbResult<()>
+    where
+        F: FnMut(BackupProgress)
+    {
+        let mut tmp_path = path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let tmp_path = PathBuf::from(tmp_path);
+
+        if let Err(err) = self.write_backup(&tmp_path, progress) {
+            let _ = std::fs::remove_file(&tmp_path);
+            return Err(err);
+        }
+
+        // the journal of an old database in the same place
+        // would be applied to the backup when it's opened
+        let mut journal_path = path.as_os_str().to_owned();
+        journal_path.push(".journal");
+        match std::fs::remove_file(&journal_path) {
+            Err(err) if err.kind() != std::io::ErrorKind::NotFound => return Err(err.into()),
+            _ => (),
+        }
+
+        std::fs::rename(&tmp_path, path)?;
+
+        Ok(())
+    }
+
+    fn write_backup<F>(&mut self, path: &Path, mut progress: F) -> DbResult<()>
+    where
+        F: FnMut(BackupProgress)
+    {
+        let page_size = self.page_handler.page_size.get() as u64;
+        let db_size = self.page_handler.db_size();
+
+        // the slots after the null page bar are never written
+        let header = HeaderPageWrapper::from_raw_page(self.page_handler.pipeline_read_page(0)?);
+        let total_pages = (db_size / page_size).min(header.get_null_page_bar() as u64);
+
+        let mut file = std::fs::OpenOptions::new()
+            .create(true)
+            .write(true)
+            .truncate(true)
+            .open(path)?;
+
+        let codec = self.page_handler.codec();
+        for page_id in 0..total_pages {
+            let mut page = self.page_handler.pipeline_read_page(page_id as u32)?;
+            if page_id == 0 {
+                let mut header = HeaderPageWrapper::from_raw_page(page);
+                header.set_journal_position(self.page_handler.journal_position());
+                page = header.0;
+            }
+            codec.write_page(&mut file, &page, codec.slot_offset(page_id as u32))?;
+
+            progress(BackupProgress {
+                copied_pages: page_id + 1,
+                total_pages,
+            });
+        }
+
+        file.set_len(codec.file_size_of(db_size))?;
+        file.sync_all()?;
+
+        Ok(())
+    }
+
+    /**
+     * encrypt all the pages with the new key,
+     * or decrypt them if the key is None
+     */
+    pub fn rekey(&mut self, key: Option<&[u8; 32]>) -> DbResult<()> {
+        check_key_supported(key)?;
+        let codec = self.page_handler.codec().with_key(key);
+        self.set_codec(codec)
+    }
+
+    /**
+     * append the checksums to all the pages, or remove them
+     */
+    pub fn set_page_checksum(&mut self, enabled: bool) -> DbResult<()> {
+        let codec = self.page_handler.codec();
+        if codec.checksum() == enabled {
+            return Ok(());
+        }
+        self.set_codec(codec.with_checksum(enabled))
+    }
+
+    pub fn checkpoint(&mut self, mode: CheckpointMode) -> DbResult<bool> {
+        self.page_handler.checkpoint(mode)
+    }
+
+    pub fn checkpoint_stats(&mut self) -> DbResult<CheckpointStats> {
+        self.page_handler.checkpoint_stats()
+    }
+
+    #[inline]
+    pub fn metrics(&self) -> DbResult<Metrics> {
+        self.page_handler.metrics()
+    }
+
+    pub fn export_journal_frames(&mut self, since: JournalPosition, writer: &mut dyn Write) -> DbResult<JournalPosition> {
+        self.page_handler.export_journal_frames(since, writer)
+    }
+
+    // the position of the primary where the replica continues
+    pub fn journal_position(&mut self) -> DbResult<Option<JournalPosition>> {
+        self.page_handler.auto_start_transaction(TransactionType::Read)?;
+
+        let header = try_db_op!(self, self.page_handler.pipeline_read_page(0));
+
+        Ok(HeaderPageWrapper::from_raw_page(header).get_journal_position())
+    }
+
+    /**
+     * all the frames are checked before they are applied,
+     * and the transactions are applied in one write transaction,
+     * with the position of the replica in the header page
+     */
+    pub fn apply_journal_frames(&mut self, reader: &mut dyn Read) -> DbResult<usize> {
+        let frames = read_journal_frames(reader, &self.page_handler.codec())?;
+
+        self.page_handler.auto_start_transaction(TransactionType::Write)?;
+
+        try_db_op!(self, self.internal_apply_journal_frames(&frames));
+
+        // the collections may be created by the transactions
+        self.reset_meta_version()?;
+
+        Ok(frames.transactions.len())
+    }
+
+    fn internal_apply_journal_frames(&mut self, frames: &JournalFrames) -> DbResult<()> {
+        let header = HeaderPageWrapper::from_raw_page(self.page_handler.pipeline_read_page(0)?);
+        match header.get_journal_position() {
+            Some(position) if position == frames.from => (),
+            Some(_) => return Err(DbErr::InvalidJournalFrames("the frames don't continue from the replica")),
+            None => return Err(DbErr::InvalidJournalFrames("not a replica, see backup_to")),
+        }
+
+        self.internal_apply_journal_transactions(&frames.transactions, frames.to)
+    }
+
+    // the position of the journal after the transactions is kept as a replica
+    pub(crate) fn apply_journal_transactions(&mut self, transactions: &[JournalTransaction], to: JournalPosition) -> DbResult<()> {
+        self.page_handler.auto_start_transaction(TransactionType::Write)?;
+
+        try_db_op!(self, self.internal_apply_journal_transactions(transactions, to));
+
+        // the collections may be created by the transactions
+        self.reset_meta_version()
+    }
+
+    fn internal_apply_journal_transactions(&mut self, transactions: &[JournalTransaction], to: JournalPosition) -> DbResult<()> {
+        for transaction in transactions {
+            self.page_handler.apply_foreign_pages(&transaction.pages, transaction.db_size)?;
+        }
+
+        // the header page may be replaced by the transactions
+        let mut header = HeaderPageWrapper::from_raw_page(self.page_handler.pipeline_read_page(0)?);
+        header.set_journal_position(Some(to));
+        self.page_handler.pipeline_write_page(&header.0)
+    }
+
+    #[inline]
+    pub(crate) fn codec(&self) -> PageCodec {
+        self.page_handler.codec()
+    }
+
+    fn set_codec(&mut self, codec: PageCodec) -> DbResult<()> {
+        if self.page_handler.transaction_type().is_some() {
+            return Err(DbErr::Busy);
+        }
+        self.page_handler.set_codec(codec)
+    }
+
+    /**
+     * rebuild all the collections in a new database next to the database file,
+     * which replaces the file when it's finished,
+     * the database in the memory is rebuilt in the memory,
+     * and written back to replace the pages of this one
+     */
+    pub fn vacuum(&mut self) -> DbResult<VacuumReport> {
+        // the compression is kept, because the header page is copied,
+        // the frames of the copy are not archived
+        let config = Arc::new(Config {
+            compression: self.page_handler.compression,
+            archive_dir: None,
+            ..(*self.config).clone()
+        });
+
+        if let Some(backend) = self.page_handler.open_compacted(config.clone())? {
+            return self.vacuum_into_file(backend, config);
+        }
+
+        self.page_handler.auto_start_transaction(TransactionType::Write)?;
+
+        let report = try_db_op!(self, self.internal_vacuum(config));
+
+        Ok(report)
+    }
+
+    fn vacuum_into_file(&mut self, backend: Box<dyn BackendExt + Send>, config: Arc<Config>) -> DbResult<VacuumReport> {
+        let size_before = self.page_handler.db_size();
+        let mut compact = DbContext::from_backend(backend, config)?;
+
+        // the other connections can't write while the database is copied,
+        // the transaction is rolled back because nothing is written
+        self.page_handler.auto_start_transaction(TransactionType::Write)?;
+        let result = compact.page_handler.start_transaction(TransactionType::Write)
+            .and_then(|_| self.copy_into_compact(&mut compact))
+            .and_then(|_| compact.truncate_to_used_pages())
+            .and_then(|_| compact.page_handler.commit());
+        if let Err(err) = result {
+            try_multiple!(err, self.page_handler.auto_rollback());
+            return Err(err);
+        }
+        self.page_handler.auto_rollback()?;
+
+        // the journal of the copy is written back when it's closed
+        drop(compact);
+        let size_after = self.page_handler.swap_compacted()?;
+
+        Ok(VacuumReport {
+            size_before,
+            size_after,
+        })
+    }
+
+    fn internal_vacuum(&mut self, config: Arc<Config>) -> DbResult<VacuumReport> {
+        let page_size = self.page_handler.page_size;
+        let size_before = self.page_handler.db_size();
+
+        // the pages are encrypted again when they are written back
+        let codec = PageCodec::new(page_size, None, false);
+        let backend = Box::new(MemoryBackend::new(codec, config.init_block_count, config.compression));
+        let mut compact = DbContext::from_backend(backend, config)?;
+        compact.page_handler.start_transaction(TransactionType::Write)?;
+
+        self.copy_into_compact(&mut compact)?;
+
+        let first_page = compact.page_handler.get_first_page()?;
+        let page_count = HeaderPageWrapper::from_raw_page(first_page).get_null_page_bar();
+        for page_id in 0..page_count {
+            let page = compact.page_handler.pipeline_read_page(page_id)?;
+            self.page_handler.pipeline_write_page(&page)?;
+        }
+
+        let size_after = (page_count as u64) * (page_size.get() as u64);
+        self.page_handler.truncate(size_after)?;
+
+        Ok(VacuumReport {
+            size_before,
+            size_after,
+        })
+    }
+
+    // the pages from the null page bar are not used
+    fn truncate_to_used_pages(&mut self) -> DbResult<()> {
+        let first_page = self.page_handler.get_first_page()?;
+        let page_count = HeaderPageWrapper::from_raw_page(first_page).get_null_page_bar();
+        let size = (page_count as u64) * (self.page_handler.page_size.get() as u64);
+        self.page_handler.truncate(size)
+    }
+
+    fn copy_into_compact(&mut self, compact: &mut DbContext) -> DbResult<()> {
+        let meta_source = self.get_meta_source()?;
+
+        for meta_doc in self.query_all_meta()? {
+            let col_id = meta_doc.get(meta_doc_key::ID).unwrap().unwrap_int() as u32;
+            compact.copy_collection_meta(meta_doc.as_ref())?;
+
+            let collection_meta = MetaDocEntry::from_doc(meta_doc);
+            let subprogram = SubProgram::compile_query_all(&collection_meta, true)?;
+
+            let mut handle = self.make_handle(subprogram);
+            handle.set_rollback_on_drop(false);
+            handle.step()?;
+
+            while handle.has_row() {
+                let mut doc = handle.get().unwrap_document().as_ref().clone();
+                compact.internal_insert(col_id, &mut doc)?;
+
+                handle.step()?;
+            }
+        }
+
+        // the ids of the collections and the meta version are not changed,
+        // so the collections opened before are still valid
+        let mut compact_meta_source = compact.get_meta_source()?;
+        compact_meta_source.meta_id_counter = meta_source.meta_id_counter;
+        compact_meta_source.meta_version = meta_source.meta_version;
+        compact.update_meta_source(&compact_meta_source)
+    }
+
+    // the meta doc with the same id, but the btrees of the collection are empty
+    fn copy_collection_meta(&mut self, meta_doc: &Document) -> DbResult<()> {
+        let mut meta_source = self.get_meta_source()?;
+        let mut doc = meta_doc.clone();
+
+        let root_pid = self.page_handler.alloc_page_id()?;
+        doc.insert(meta_doc_key::ROOT_PID.into(), Value::Int(root_pid as i64));
+
+        if let Some(Value::Document(indexes)) = meta_doc.get(meta_doc_key::INDEXES) {
+            let mut new_indexes = Document::new_without_id();
+            for (key, options) in indexes.iter() {
+                let mut options = options.unwrap_document().as_ref().clone();
+                let root_pid = self.page_handler.alloc_page_id()?;
+                options.insert(meta_doc_key::index::ROOT_PID.into(), Value::Int(root_pid as i64));
+                new_indexes.insert(key.clone(), Value::Document(Rc::new(options)));
+            }
+            doc.insert(meta_doc_key::INDEXES.into(), Value::Document(Rc::new(new_indexes)));
+        }
+
+        self.insert_meta_doc(&mut meta_source, &doc)?;
+        self.update_meta_source(&meta_source)
+    }
+
+    pub fn check_integrity(&mut self) -> DbResult<IntegrityReport> {
+        self.page_handler.auto_start_transaction(TransactionType::Read)?;
+
+        let serialize_type = self.config.serialize_type;
+        let checker = IntegrityChecker::new(&mut self.page_handler, serialize_type);
+        let report = try_db_op!(self, checker.check());
+
+        Ok(report)
+    }
+
+    pub fn stats(&mut self) -> DbResult<DatabaseStats> {
+        self.page_handler.auto_start_transaction(TransactionType::Read)?;
+
+        let serialize_type = self.config.serialize_type;
+        let collector = StatsCollector::new(&mut self.page_handler, serialize_type);
+        let stats = try_db_op!(self, collector.database_stats());
+
+        Ok(stats)
+    }
+
+    pub fn collection_stats(&mut self, col_id: u32, meta_version: u32) -> DbResult<CollectionStats> {
+        self.check_meta_version(meta_version)?;
+        self.page_handler.auto_start_transaction(TransactionType::Read)?;
+
+        let result = try_db_op!(self, self.internal_collection_stats(col_id));
+
+        Ok(result)
+    }
+
+    fn internal_collection_stats(&mut self, col_id: u32) -> DbResult<CollectionStats> {
+        let meta_source = self.get_meta_source()?;
+        let collection_meta = self.find_collection_root_pid_by_id(
+            0, meta_source.meta_pid, col_id)?;
+        let collector = StatsCollector::new(&mut self.page_handler, self.config.serialize_type);
+        collector.collection_stats(collection_meta.doc_ref())
+    }
+
     pub fn dump(&mut self) -> DbResult<FullDump> {
         let first_page = self.page_handler.pipeline_read_page(0)?;
         let first_page_wrapper = HeaderPageWrapper::from_raw_page(first_page);
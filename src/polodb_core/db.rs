@@ -2,7 +2,9 @@ use std::convert::TryFrom;
 use std::rc::Rc;
 use std::path::Path;
 use std::io::{Read, Write};
+use std::panic::{self, AssertUnwindSafe};
 use std::sync::atomic::{AtomicBool, Ordering};
+use std::time::Duration;
 use polodb_bson::{Document, ObjectId, Value};
 use byteorder::{self, BigEndian, ReadBytesExt, WriteBytesExt};
 use super::error::DbErr;
@@ -11,9 +13,18 @@ use crate::Config;
 use crate::context::DbContext;
 use crate::{DbHandle, TransactionType};
 use crate::dump::FullDump;
+use crate::integrity::IntegrityReport;
+use crate::stats::{DatabaseStats, CollectionStats};
+use crate::metrics::Metrics;
+use crate::salvage::SalvageReport;
+use crate::restore::{RestoreTarget, RestoreReport};
+use crate::backend::Backend;
 
 pub(crate) static SHOULD_LOG: AtomicBool = AtomicBool::new(false);
 
+// the name of the savepoint used by a nested transaction
+static NESTED_TRANSACTION_SAVEPOINT: &str = "<transaction>";
+
 fn consume_handle_to_vec(handle: &mut DbHandle, result: &mut Vec<Rc<Document>>) -> DbResult<()> {
     handle.step()?;
 
@@ -55,6 +66,8 @@ macro_rules! unwrap_str_or {
 /// | $ne | Matches all values that are not equal to a specified value. |
 /// | $nin | Matches none of the values specified in an array. |
 ///
+/// The values of the same type are compared, `false` is less than `true`.
+///
 /// ## Logical operation:
 ///
 /// | Name | Description |
@@ -139,6 +152,26 @@ impl<'a>  Collection<'a> {
         Ok(Some(result))
     }
 
+    /// Call the function with every document satisfies the query,
+    /// stop when it returns false.
+    #[cfg(feature = "async")]
+    pub(crate) fn find_each<F>(&mut self, query: Option<&Document>, mut f: F) -> DbResult<()>
+    where
+        F: FnMut(&Document) -> bool
+    {
+        let mut handle = self.db.ctx.find(self.id, self.meta_version, query)?;
+        handle.step()?;
+
+        while handle.has_row() {
+            if !f(handle.get().unwrap_document()) {
+                return Ok(());
+            }
+            handle.step()?;
+        }
+
+        Ok(())
+    }
+
     pub fn name(&self) -> &str {
         &self.name
     }
@@ -149,6 +182,11 @@ impl<'a>  Collection<'a> {
         self.db.ctx.count(self.id, self.meta_version)
     }
 
+    /// Return the statistics of the collection, see [Database::stats].
+    pub fn stats(&mut self) -> DbResult<CollectionStats> {
+        self.db.ctx.collection_stats(self.id, self.meta_version)
+    }
+
     /// When query is `None`, all the data in the collection will be updated.
     /// Basically the same as [MongoDB](https://docs.mongodb.com/manual/reference/operator/update-field/).
     ///
@@ -196,6 +234,205 @@ impl<'a>  Collection<'a> {
 
 }
 
+/// A transaction started by [Database::transaction].
+///
+/// The collections obtained from it borrow the transaction,
+/// so they can not escape from the closure.
+pub struct Transaction<'a> {
+    db: &'a mut Database,
+}
+
+impl<'a> Transaction<'a> {
+
+    /// Return an exist collection. If the collection is not exists,
+    /// a new collection will be created.
+    #[inline]
+    pub fn collection(&mut self, col_name: &str) -> DbResult<Collection> {
+        self.db.collection(col_name)
+    }
+
+    #[inline]
+    pub fn create_collection(&mut self, name: &str) -> DbResult<Collection> {
+        self.db.create_collection(name)
+    }
+
+    #[inline]
+    pub fn mk_object_id(&mut self) -> ObjectId {
+        self.db.mk_object_id()
+    }
+
+    /// Run the closure in a nested transaction, which is a savepoint
+    /// of this one. The changes of the closure are undone if it
+    /// returns `Err` or panics, and this transaction goes on.
+    ///
+    /// ```rust
+    /// use polodb_core::{Database, TransactionType, DbErr, DbResult};
+    /// use polodb_bson::doc;
+    ///
+    /// let mut db = Database::open_memory().unwrap();
+    /// db.transaction(TransactionType::Write, |tx| {
+    ///     tx.collection("test")?.insert(doc! { "_id": 1 }.as_mut())?;
+    ///     let inner: DbResult<()> = tx.transaction(|tx| {
+    ///         tx.collection("test")?.insert(doc! { "_id": 2 }.as_mut())?;
+    ///         Err(DbErr::Busy)
+    ///     });
+    ///     assert!(inner.is_err());
+    ///     Ok(())
+    /// }).unwrap();
+    ///
+    /// assert_eq!(db.collection("test").unwrap().count().unwrap(), 1);
+    /// ```
+    pub fn transaction<T, F>(&mut self, f: F) -> DbResult<T>
+    where
+        F: FnOnce(&mut Transaction) -> DbResult<T>
+    {
+        self.db.run_in_savepoint(f)
+    }
+
+}
+
+/// A read transaction started by [SharedDatabase::read](crate::SharedDatabase::read).
+///
+/// The collections are only looked up, a collection which
+/// doesn't exist is not created.
+pub struct ReadTransaction<'a> {
+    db: &'a mut Database,
+}
+
+impl<'a> ReadTransaction<'a> {
+
+    /// Return an exist collection. If the collection is not exists,
+    /// [DbErr::CollectionNotFound] is returned.
+    pub fn collection(&mut self, col_name: &str) -> DbResult<ReadCollection> {
+        let info = self.db.ctx.get_collection_meta_by_name(col_name)?;
+        Ok(ReadCollection {
+            inner: Collection::new(self.db, info.id, info.meta_version, col_name),
+        })
+    }
+
+}
+
+/// A collection obtained from a [ReadTransaction], which can only be queried.
+pub struct ReadCollection<'a> {
+    inner: Collection<'a>,
+}
+
+impl<'a> ReadCollection<'a> {
+
+    #[inline]
+    pub fn find_all(&mut self) -> DbResult<Vec<Rc<Document>>> {
+        self.inner.find_all()
+    }
+
+    #[inline]
+    pub fn find(&mut self, query: &Document) -> DbResult<Vec<Rc<Document>>> {
+        self.inner.find(query)
+    }
+
+    #[inline]
+    pub fn find_one(&mut self, query: &Document) -> DbResult<Option<Rc<Document>>> {
+        self.inner.find_one(query)
+    }
+
+    #[inline]
+    pub fn name(&self) -> &str {
+        self.inner.name()
+    }
+
+    #[inline]
+    pub fn count(&mut self) -> DbResult<u64> {
+        self.inner.count()
+    }
+
+    #[inline]
+    pub fn stats(&mut self) -> DbResult<CollectionStats> {
+        self.inner.stats()
+    }
+
+}
+
+/// How [Database::transaction_with_retry] retries a transaction
+/// failed with [DbErr::Busy].
+///
+/// The backoff is doubled after every retry, until it reaches `max_backoff`.
+#[derive(Debug, Clone, Copy)]
+pub struct RetryPolicy {
+    pub max_retries:     u32,
+    pub initial_backoff: Duration,
+    pub max_backoff:     Duration,
+}
+
+impl Default for RetryPolicy {
+
+    fn default() -> Self {
+        RetryPolicy {
+            max_retries:     5,
+            initial_backoff: Duration::from_millis(10),
+            max_backoff:     Duration::from_secs(1),
+        }
+    }
+
+}
+
+/// The progress reported by [Database::backup_to] after every page is copied.
+#[derive(Debug, Clone, Copy)]
+pub struct BackupProgress {
+    pub copied_pages: u64,
+    pub total_pages:  u64,
+}
+
+/// A position in the journal of a database, after the frames applied by a replica,
+/// see [Database::export_journal_frames].
+///
+/// The frames are numbered from 0 again after a checkpoint, the salts
+/// of the journal tell them apart.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct JournalPosition {
+    pub(crate) salt1: u32,
+    pub(crate) salt2: u32,
+    pub(crate) frame: u32,
+}
+
+/// The sizes of the database before and after [Database::vacuum].
+#[derive(Debug, Clone, Copy)]
+pub struct VacuumReport {
+    pub size_before: u64,
+    pub size_after:  u64,
+}
+
+/// How [Database::checkpoint] deals with the other connections.
+///
+/// The journal is written back to the database file as a whole and truncated
+/// in both modes, it can not be done while another connection is writing,
+/// or reading the older data. The checkpoint never waits for them.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CheckpointMode {
+    /// Do nothing if the journal can not be written back.
+    SkipIfBusy,
+
+    /// Fail with [DbErr::Busy] if the journal can not be written back.
+    FailIfBusy,
+}
+
+/// The journal which is not written back to the database file yet.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct CheckpointStats {
+    /// The committed frames in the journal.
+    pub journal_frames: u32,
+
+    /// The size of the journal file.
+    pub journal_bytes:  u64,
+}
+
+impl VacuumReport {
+
+    #[inline]
+    pub fn reclaimed_bytes(&self) -> u64 {
+        self.size_before.saturating_sub(self.size_after)
+    }
+
+}
+
 ///
 /// API wrapper for Rust-level
 ///
@@ -223,6 +460,11 @@ impl<'a>  Collection<'a> {
 /// If you don't start it manually, a transaction will be automatically started
 /// in your every operation.
 ///
+/// [transaction]: #method.transaction
+///
+/// The [transaction] method runs a closure in a transaction, which is committed
+/// when the closure returns `Ok`, and rolled back when it returns `Err` or panics.
+///
 /// # Example
 ///
 /// ```rust
@@ -263,6 +505,15 @@ impl Database {
         Database::open_file_with_config(path, Config::default())
     }
 
+    /// Open a database file in read-only mode, see [Config::read_only].
+    pub fn open_file_read_only<P: AsRef<Path>>(path: P) -> DbResult<Database>  {
+        let config = Config {
+            read_only: true,
+            ..Default::default()
+        };
+        Database::open_file_with_config(path, config)
+    }
+
     pub fn open_file_with_config<P: AsRef<Path>>(path: P, config: Config) -> DbResult<Database>  {
         let ctx = DbContext::open_file(path.as_ref(), config)?;
         let rc_ctx = Box::new(ctx);
@@ -272,6 +523,58 @@ impl Database {
         })
     }
 
+    #[inline]
+    pub(crate) fn is_in_transaction(&self) -> bool {
+        self.ctx.is_in_transaction()
+    }
+
+    /// Open another connection to the same database file.
+    ///
+    /// The connections can be moved to other threads. A read transaction
+    /// sees the data committed before it started, even if another connection
+    /// is writing at the same time. Only one connection can write at once,
+    /// the others get `DbErr::Busy` when starting a write transaction.
+    ///
+    /// Memory databases don't support multiple connections.
+    pub fn new_connection(&self) -> DbResult<Database> {
+        let ctx = self.ctx.new_connection()?;
+        Ok(Database {
+            ctx: Box::new(ctx),
+        })
+    }
+
+    pub fn open_file_into_memory<P: AsRef<Path>>(path: P) -> DbResult<Database> {
+        Database::open_file_into_memory_with_config(path, Config::default())
+    }
+
+    /// Load a whole database file into the memory.
+    ///
+    /// The committed data in the journal is included. The file is not changed
+    /// by the database in the memory, see [Database::save_memory_to] to write it back.
+    /// The file must not be opened by another process when it's loaded.
+    pub fn open_file_into_memory_with_config<P: AsRef<Path>>(path: P, config: Config) -> DbResult<Database> {
+        let ctx = DbContext::open_file_into_memory(path.as_ref(), config)?;
+        let rc_ctx = Box::new(ctx);
+
+        Ok(Database {
+            ctx: rc_ctx,
+        })
+    }
+
+    /// Open a database stored by a custom [Backend](crate::backend::Backend).
+    ///
+    /// The header page is written if the backend is empty.
+    /// The page size is decided by the [page_size](crate::backend::Backend::page_size)
+    /// of the backend, and the encryption and the checksums in the config are not used.
+    pub fn open_with_backend<B: Backend + Send + 'static>(backend: B, config: Config) -> DbResult<Database> {
+        let ctx = DbContext::open_with_backend(Box::new(backend), config)?;
+        let rc_ctx = Box::new(ctx);
+
+        Ok(Database {
+            ctx: rc_ctx,
+        })
+    }
+
     pub fn open_memory() -> DbResult<Database> {
         Database::open_memory_wht_config(Config::default())
     }
@@ -315,6 +618,230 @@ impl Database {
         Ok(Collection::new(self, info.id, info.meta_version, col_name))
     }
 
+    /// Back up the database to a new file while it's in use.
+    ///
+    /// The backup is taken in a read transaction, so the other connections
+    /// can still write. The pages in the journal are copied as well,
+    /// the backup is a single file which has no journal.
+    ///
+    /// The backup is written to the path with `.tmp` appended, which
+    /// replaces the file at the path when it's complete.
+    ///
+    /// ```rust
+    /// use polodb_core::Database;
+    ///
+    /// let mut db = Database::open_memory().unwrap();
+    /// db.backup_to("/tmp/test-backup-polo.db", |progress| {
+    ///     println!("{}/{}", progress.copied_pages, progress.total_pages);
+    /// }).unwrap();
+    /// ```
+    pub fn backup_to<P, F>(&mut self, path: P, progress: F) -> DbResult<()>
+    where
+        P: AsRef<Path>,
+        F: FnMut(BackupProgress)
+    {
+        self.ctx.backup_to(path.as_ref(), progress)
+    }
+
+    /// Write an in-memory database to a file,
+    /// which can be opened by [Database::open_file].
+    ///
+    /// The file is replaced if it exists. The pages are encrypted
+    /// with the key of this database.
+    ///
+    /// ```rust
+    /// use polodb_core::Database;
+    ///
+    /// let mut db = Database::open_memory().unwrap();
+    /// db.collection("test").unwrap();
+    /// db.save_memory_to("/tmp/test-save-memory-polo.db").unwrap();
+    /// ```
+    pub fn save_memory_to<P: AsRef<Path>>(&mut self, path: P) -> DbResult<()> {
+        self.ctx.backup_to(path.as_ref(), |_| ())
+    }
+
+    /// Encrypt the database and its journal with a new key,
+    /// or decrypt them if the key is `None`.
+    ///
+    /// All the pages are rewritten to a new file, which replaces the old one.
+    /// It fails with [DbErr::Busy] if a transaction is running
+    /// on any connection of the database.
+    /// The new key must be set in [Config::encryption_key] when the database is opened again.
+    pub fn rekey(&mut self, new_key: Option<[u8; 32]>) -> DbResult<()> {
+        self.ctx.rekey(new_key.as_ref())
+    }
+
+    /// Append a checksum to every page of the database, or remove them.
+    ///
+    /// It's the way to enable the checksums of the databases created without
+    /// [Config::page_checksum]. All the pages are rewritten to a new file like
+    /// [Database::rekey], and a damaged page is read as [DbErr::ChecksumMismatch]
+    /// from then on.
+    pub fn set_page_checksum(&mut self, enabled: bool) -> DbResult<()> {
+        self.ctx.set_page_checksum(enabled)
+    }
+
+    /// Write the journal back to the database file, and truncate the journal.
+    ///
+    /// Returns false if it's skipped in the [SkipIfBusy](CheckpointMode::SkipIfBusy) mode.
+    /// It's done automatically according to [Config::auto_checkpoint](crate::Config::auto_checkpoint),
+    /// call it to do the heavy work at a better time.
+    ///
+    /// ```rust
+    /// use polodb_core::Database;
+    /// use polodb_core::db::CheckpointMode;
+    ///
+    /// let mut db = Database::open_file("/tmp/test-checkpoint-polo.db").unwrap();
+    /// db.collection("test").unwrap();
+    /// assert!(db.checkpoint(CheckpointMode::FailIfBusy).unwrap());
+    /// assert_eq!(db.checkpoint_stats().unwrap().journal_frames, 0);
+    /// ```
+    pub fn checkpoint(&mut self, mode: CheckpointMode) -> DbResult<bool> {
+        self.ctx.checkpoint(mode)
+    }
+
+    pub fn checkpoint_stats(&mut self) -> DbResult<CheckpointStats> {
+        self.ctx.checkpoint_stats()
+    }
+
+    /// Export the transactions committed in the journal to a replica,
+    /// see [Database::apply_journal_frames].
+    ///
+    /// The frames after the position `since` are written, which is usually
+    /// [Database::journal_position] of the replica. Returns the position after
+    /// the committed frames, which is the `since` of the next export.
+    ///
+    /// The journal is cleared by the checkpoints, the frames should be exported before that,
+    /// with [AutoCheckpoint::Disabled](crate::AutoCheckpoint::Disabled) and [Database::checkpoint].
+    /// Only the frames of the last checkpoint can be continued in the new journal,
+    /// an error is returned if more frames have been checkpointed since the position.
+    /// The position before the checkpoint is forgotten when the database is closed.
+    pub fn export_journal_frames<W: Write>(&mut self, since: JournalPosition, writer: &mut W) -> DbResult<JournalPosition> {
+        self.ctx.export_journal_frames(since, writer)
+    }
+
+    /// The position of the primary after the frames applied to the replica,
+    /// None if the database is not a replica made by [Database::backup_to].
+    pub fn journal_position(&mut self) -> DbResult<Option<JournalPosition>> {
+        self.ctx.journal_position()
+    }
+
+    /// Apply the transactions exported by [Database::export_journal_frames] of another database.
+    ///
+    /// The replica is a backup of the database by [Database::backup_to],
+    /// the exports must continue from the [Database::journal_position] of the replica,
+    /// otherwise an error is returned.
+    /// Nothing is applied if any frame is damaged.
+    /// Returns the count of the applied transactions.
+    ///
+    /// ```rust
+    /// use polodb_core::{Database, Config, AutoCheckpoint};
+    /// use polodb_core::db::CheckpointMode;
+    /// use polodb_bson::doc;
+    ///
+    /// let config = Config {
+    ///     auto_checkpoint: AutoCheckpoint::Disabled,
+    ///     ..Default::default()
+    /// };
+    /// # let _ = std::fs::remove_file("/tmp/test-primary-polo.db");
+    /// let mut primary = Database::open_file_with_config("/tmp/test-primary-polo.db", config).unwrap();
+    /// primary.create_collection("test").unwrap();
+    /// primary.checkpoint(CheckpointMode::FailIfBusy).unwrap();
+    /// primary.backup_to("/tmp/test-replica-polo.db", |_| ()).unwrap();
+    /// let mut replica = Database::open_file("/tmp/test-replica-polo.db").unwrap();
+    ///
+    /// primary.collection("test").unwrap().insert(doc! { "_id": 1 }.as_mut()).unwrap();
+    /// let mut frames = vec![];
+    /// let position = replica.journal_position().unwrap().unwrap();
+    /// primary.export_journal_frames(position, &mut frames).unwrap();
+    /// replica.apply_journal_frames(&mut frames.as_slice()).unwrap();
+    ///
+    /// assert_eq!(replica.collection("test").unwrap().count().unwrap(), 1);
+    /// ```
+    pub fn apply_journal_frames<R: Read>(&mut self, reader: &mut R) -> DbResult<usize> {
+        self.ctx.apply_journal_frames(reader)
+    }
+
+    /// Rebuild the collections compactly, and shrink the database.
+    ///
+    /// The pages freed by deleting the documents and dropping the collections
+    /// are reclaimed, the half-empty data pages are merged.
+    ///
+    /// The compacted copy is built in a temporary file next to the database file,
+    /// and replaces the file when it's finished, so the journal doesn't grow.
+    /// It must be called out of transactions, and fails with [DbErr::Busy]
+    /// if another connection is reading, or has written while it's copied.
+    /// A database in the memory is rewritten in its own write transaction.
+    pub fn vacuum(&mut self) -> DbResult<VacuumReport> {
+        self.ctx.vacuum()
+    }
+
+    /// Walk all the pages used by the database, and check if they are damaged.
+    ///
+    /// The damages are returned in the report, an error is returned
+    /// only if the pages can not be read.
+    pub fn check_integrity(&mut self) -> DbResult<IntegrityReport> {
+        self.ctx.check_integrity()
+    }
+
+    /// Walk all the pages used by the database, and sum up the
+    /// page counts, the document counts and the sizes.
+    ///
+    /// All the B-trees are read, so it's as slow as [Database::check_integrity].
+    pub fn stats(&mut self) -> DbResult<DatabaseStats> {
+        self.ctx.stats()
+    }
+
+    /// Return a snapshot of the counters of the pages, the transactions and the vm.
+    ///
+    /// With the `metrics` feature, the counters are also reported
+    /// to the recorder installed in the facade of the `metrics` crate.
+    pub fn metrics(&self) -> DbResult<Metrics> {
+        self.ctx.metrics()
+    }
+
+    /// Recover the documents from a damaged database file into a new one.
+    ///
+    /// The pages of `src` are read directly, so it works even if the file
+    /// can not be opened. The documents which can not be attributed to a
+    /// collection are saved in [LOST_AND_FOUND](crate::salvage::LOST_AND_FOUND).
+    /// The indexes are not recreated, and the journal of `src` is not applied.
+    ///
+    /// `dst` must not exist. The encrypted databases are not supported.
+    pub fn salvage<P: AsRef<Path>, Q: AsRef<Path>>(src: P, dst: Q) -> DbResult<SalvageReport> {
+        crate::salvage::salvage(src.as_ref(), dst.as_ref())
+    }
+
+    pub fn restore<P, Q, R>(base: P, archive_dir: Q, dst: R, until: RestoreTarget) -> DbResult<RestoreReport>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+        R: AsRef<Path>,
+    {
+        Database::restore_with_config(base, archive_dir, dst, until, Config::default())
+    }
+
+    /// Restore a backup to a transaction committed later,
+    /// by replaying the journal archived by [Config::archive_dir](crate::Config::archive_dir).
+    ///
+    /// `base` is copied to `dst` before the archived transactions are applied,
+    /// it should be a backup made by [Database::backup_to] after the archive is started.
+    /// The replay starts at the position of the journal kept in the backup,
+    /// an error is returned if the target is before it.
+    ///
+    /// `dst` must not exist, it's removed if the restore fails.
+    /// The key of an encrypted database is in the config.
+    pub fn restore_with_config<P, Q, R>(
+        base: P, archive_dir: Q, dst: R, until: RestoreTarget, config: Config,
+    ) -> DbResult<RestoreReport>
+    where
+        P: AsRef<Path>,
+        Q: AsRef<Path>,
+        R: AsRef<Path>,
+    {
+        crate::restore::restore(base.as_ref(), archive_dir.as_ref(), dst.as_ref(), until, config)
+    }
+
     #[inline]
     pub fn dump(&mut self) -> DbResult<FullDump> {
         self.ctx.dump()
@@ -345,6 +872,171 @@ impl Database {
         self.ctx.rollback()
     }
 
+    /// Run the closure in a transaction.
+    ///
+    /// The transaction is committed if the closure returns `Ok`,
+    /// and rolled back if it returns `Err` or panics.
+    ///
+    /// If the database is already in a transaction, the closure runs
+    /// in a savepoint of it instead.
+    ///
+    /// ```rust
+    /// use polodb_core::{Database, TransactionType};
+    /// use polodb_bson::doc;
+    ///
+    /// let mut db = Database::open_memory().unwrap();
+    /// db.transaction(TransactionType::Write, |tx| {
+    ///     let mut collection = tx.collection("test")?;
+    ///     collection.insert(doc! { "name": "PoloDB" }.as_mut())?;
+    ///     Ok(())
+    /// }).unwrap();
+    /// ```
+    pub fn transaction<T, F>(&mut self, ty: TransactionType, f: F) -> DbResult<T>
+    where
+        F: FnOnce(&mut Transaction) -> DbResult<T>
+    {
+        if self.ctx.is_in_transaction() {
+            return self.run_in_savepoint(f);
+        }
+
+        self.start_transaction(Some(ty))?;
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut tx = Transaction { db: self };
+            f(&mut tx)
+        }));
+
+        match result {
+            Ok(Ok(value)) => {
+                if let Err(err) = self.commit() {
+                    if self.ctx.is_in_transaction() {
+                        if let Err(rollback_err) = self.rollback() {
+                            return Err(err.add(rollback_err));
+                        }
+                    }
+                    return Err(err);
+                }
+                Ok(value)
+            }
+
+            Ok(Err(err)) => {
+                if let Err(rollback_err) = self.rollback() {
+                    return Err(err.add(rollback_err));
+                }
+                Err(err)
+            }
+
+            Err(payload) => {
+                let _ = self.rollback();
+                panic::resume_unwind(payload)
+            }
+
+        }
+    }
+
+    /// The same as [transaction](#method.transaction),
+    /// but the transaction is retried according to the policy
+    /// when it fails with [DbErr::Busy].
+    ///
+    /// If the database is already in a transaction, it's not retried,
+    /// the outer transaction would hold the lock while waiting.
+    pub fn transaction_with_retry<T, F>(&mut self, ty: TransactionType, policy: RetryPolicy, mut f: F) -> DbResult<T>
+    where
+        F: FnMut(&mut Transaction) -> DbResult<T>
+    {
+        if self.ctx.is_in_transaction() {
+            return self.run_in_savepoint(f);
+        }
+
+        let mut backoff = policy.initial_backoff;
+        let mut retries: u32 = 0;
+
+        loop {
+            match self.transaction(ty, &mut f) {
+                Err(DbErr::Busy) if retries < policy.max_retries => {
+                    std::thread::sleep(backoff);
+                    backoff = std::cmp::min(backoff * 2, policy.max_backoff);
+                    retries += 1;
+                }
+
+                result => return result,
+            }
+        }
+    }
+
+    // the collections are looked up without being created,
+    // see SharedDatabase::read
+    pub(crate) fn read_transaction<T, F>(&mut self, f: F) -> DbResult<T>
+    where
+        F: FnOnce(&mut ReadTransaction) -> DbResult<T>
+    {
+        self.transaction(TransactionType::Read, |tx| {
+            let mut read_tx = ReadTransaction { db: tx.db };
+            f(&mut read_tx)
+        })
+    }
+
+    fn run_in_savepoint<T, F>(&mut self, f: F) -> DbResult<T>
+    where
+        F: FnOnce(&mut Transaction) -> DbResult<T>
+    {
+        self.savepoint(NESTED_TRANSACTION_SAVEPOINT)?;
+
+        let result = panic::catch_unwind(AssertUnwindSafe(|| {
+            let mut tx = Transaction { db: self };
+            f(&mut tx)
+        }));
+
+        match result {
+            Ok(Ok(value)) => {
+                self.release(NESTED_TRANSACTION_SAVEPOINT)?;
+                Ok(value)
+            }
+
+            Ok(Err(err)) => {
+                let recover_result = self.rollback_to(NESTED_TRANSACTION_SAVEPOINT)
+                    .and_then(|_| self.release(NESTED_TRANSACTION_SAVEPOINT));
+                if let Err(recover_err) = recover_result {
+                    return Err(err.add(recover_err));
+                }
+                Err(err)
+            }
+
+            Err(payload) => {
+                if self.rollback_to(NESTED_TRANSACTION_SAVEPOINT).is_ok() {
+                    let _ = self.release(NESTED_TRANSACTION_SAVEPOINT);
+                }
+                panic::resume_unwind(payload)
+            }
+
+        }
+    }
+
+    /// Create a savepoint with the name in the current transaction.
+    /// Savepoints can be nested, a name can be used more than once,
+    /// the newest one is referred to.
+    ///
+    /// If there is no transaction, a write transaction will be started,
+    /// and it will be committed when the savepoint is released.
+    #[inline]
+    pub fn savepoint(&mut self, name: &str) -> DbResult<()> {
+        self.ctx.savepoint(name)
+    }
+
+    /// Undo all the changes after the savepoint.
+    /// The savepoint is kept, the savepoints created after it are released.
+    #[inline]
+    pub fn rollback_to(&mut self, name: &str) -> DbResult<()> {
+        self.ctx.rollback_to(name)
+    }
+
+    /// Remove the savepoint and the savepoints created after it,
+    /// the changes are kept in the transaction.
+    #[inline]
+    pub fn release(&mut self, name: &str) -> DbResult<()> {
+        self.ctx.release(name)
+    }
+
     #[allow(dead_code)]
     pub(crate) fn query_all_meta(&mut self) -> DbResult<Vec<Rc<Document>>> {
         self.ctx.query_all_meta()
@@ -711,10 +1403,22 @@ mod tests {
     use std::rc::Rc;
     use std::env;
     use polodb_bson::{Document, Value, doc};
-    use crate::{Database, Config, DbResult, DbErr};
-    use std::io::Read;
-    use std::path::PathBuf;
+    use crate::{Database, Config, Compression, DbResult, DbErr, TransactionType, Synchronous, AutoCheckpoint};
+    use crate::db::{RetryPolicy, CheckpointMode};
+    use crate::restore::RestoreTarget;
+    use crate::integrity::{IntegrityIssue, PageKind};
+    use crate::salvage::{SalvageReport, LOST_AND_FOUND};
+    use std::io::{Read, Write, Seek, SeekFrom};
+    use std::time::{Duration, SystemTime, UNIX_EPOCH};
+    use std::path::{Path, PathBuf};
     use std::fs::File;
+    use std::num::{NonZeroU32, NonZeroUsize};
+    use std::collections::BTreeMap;
+    use std::sync::{Arc, Mutex};
+    use std::sync::atomic::{AtomicU64, Ordering};
+    use crate::backend::{Backend, RawPage};
+    use crate::backend::file::fault_injection::{copy_synced, reset};
+    use crate::page::header_page_wrapper::HeaderPageWrapper;
 
     static TEST_SIZE: usize = 1000;
 
@@ -925,10 +1629,1984 @@ mod tests {
     }
 
     #[test]
-    fn test_create_collection_with_number_pkey() {
-        let mut db = {
-            let mut db = prepare_db("test-number-pkey").unwrap();
-            let mut collection = db.create_collection("test").unwrap();
+    fn test_savepoint() {
+        [Some("test-savepoint"), None].iter().for_each(|value| {
+            let mut db = match value {
+                Some(name) => prepare_db(name).unwrap(),
+                None => Database::open_memory().unwrap()
+            };
+            db.create_collection("test").unwrap();
+
+            db.start_transaction(Some(TransactionType::Write)).unwrap();
+
+            let mut collection = db.collection("test").unwrap();
+            for i in 0..5 {
+                collection.insert(doc! { "_id": i }.as_mut()).unwrap();
+            }
+
+            db.savepoint("a").unwrap();
+
+            let mut collection = db.collection("test").unwrap();
+            for i in 5..10 {
+                collection.insert(doc! { "_id": i }.as_mut()).unwrap();
+            }
+
+            db.savepoint("b").unwrap();
+
+            let mut collection = db.collection("test").unwrap();
+            for i in 10..15 {
+                collection.insert(doc! { "_id": i }.as_mut()).unwrap();
+            }
+            assert_eq!(collection.count().unwrap(), 15);
+
+            db.rollback_to("b").unwrap();
+            assert_eq!(db.collection("test").unwrap().count().unwrap(), 10);
+
+            db.rollback_to("a").unwrap();
+            assert_eq!(db.collection("test").unwrap().count().unwrap(), 5);
+
+            // "b" is released by rolling back to "a"
+            assert!(matches!(db.rollback_to("b"), Err(DbErr::SavepointNotFound(_))));
+
+            let mut collection = db.collection("test").unwrap();
+            for i in 20..25 {
+                collection.insert(doc! { "_id": i }.as_mut()).unwrap();
+            }
+
+            db.release("a").unwrap();
+            assert!(matches!(db.release("a"), Err(DbErr::SavepointNotFound(_))));
+
+            db.commit().unwrap();
+
+            let mut collection = db.collection("test").unwrap();
+            assert_eq!(collection.count().unwrap(), 10);
+            assert!(collection.find_one(&doc! { "_id": 7 }).unwrap().is_none());
+            assert!(collection.find_one(&doc! { "_id": 22 }).unwrap().is_some());
+        });
+    }
+
+    #[test]
+    fn test_savepoint_before_upgrade() {
+        [Some("test-savepoint-before-upgrade"), None].iter().for_each(|value| {
+            let mut db = match value {
+                Some(name) => prepare_db(name).unwrap(),
+                None => Database::open_memory().unwrap()
+            };
+            db.create_collection("test").unwrap();
+
+            // starts as a read transaction
+            db.start_transaction(None).unwrap();
+            db.savepoint("read").unwrap();
+
+            // upgraded to a write transaction
+            let mut collection = db.collection("test").unwrap();
+            collection.insert(doc! { "_id": 1, "content": "x".repeat(100) }.as_mut()).unwrap();
+
+            db.rollback_to("read").unwrap();
+            let mut collection = db.collection("test").unwrap();
+            assert_eq!(collection.count().unwrap(), 0);
+            for i in 2..5 {
+                collection.insert(doc! { "_id": i, "content": "x".repeat(100) }.as_mut()).unwrap();
+            }
+            db.commit().unwrap();
+
+            let mut collection = db.collection("test").unwrap();
+            assert_eq!(collection.count().unwrap(), 3);
+            assert!(collection.find_one(&doc! { "_id": 1 }).unwrap().is_none());
+            assert!(db.check_integrity().unwrap().is_ok());
+        });
+    }
+
+    #[test]
+    fn test_savepoint_without_transaction() {
+        let mut db = prepare_db("test-savepoint-without-transaction").unwrap();
+        db.create_collection("test").unwrap();
+
+        db.savepoint("outer").unwrap();
+        let mut collection = db.collection("test").unwrap();
+        collection.insert(doc! { "_id": 1 }.as_mut()).unwrap();
+
+        db.savepoint("inner").unwrap();
+        let mut collection = db.collection("test").unwrap();
+        collection.insert(doc! { "_id": 2 }.as_mut()).unwrap();
+        db.rollback_to("inner").unwrap();
+        db.release("inner").unwrap();
+
+        // the transaction is committed
+        db.release("outer").unwrap();
+        assert!(matches!(db.commit(), Err(DbErr::CannotWriteDbWithoutTransaction)));
+
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_transaction_closure() {
+        let mut db = prepare_db("test-transaction-closure").unwrap();
+
+        let inserted = db.transaction(TransactionType::Write, |tx| {
+            let mut collection = tx.collection("test")?;
+            for i in 0..10 {
+                collection.insert(doc! { "_id": i }.as_mut())?;
+            }
+            collection.count()
+        }).unwrap();
+        assert_eq!(inserted, 10);
+
+        let result: DbResult<()> = db.transaction(TransactionType::Write, |tx| {
+            let mut collection = tx.collection("test")?;
+            collection.insert(doc! { "_id": 100 }.as_mut())?;
+            Err(DbErr::ParseError("abort".into()))
+        });
+        assert!(matches!(result, Err(DbErr::ParseError(_))));
+
+        let panic_result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            let _: DbResult<()> = db.transaction(TransactionType::Write, |tx| {
+                let mut collection = tx.collection("test")?;
+                collection.insert(doc! { "_id": 101 }.as_mut())?;
+                panic!("abort in transaction");
+            });
+        }));
+        assert!(panic_result.is_err());
+
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), 10);
+    }
+
+    #[test]
+    fn test_nested_transaction_closure() {
+        let mut db = Database::open_memory().unwrap();
+
+        db.transaction(TransactionType::Write, |tx| {
+            tx.collection("test")?.insert(doc! { "_id": 1 }.as_mut())?;
+
+            let inner: DbResult<()> = tx.transaction(|tx| {
+                tx.collection("test")?.insert(doc! { "_id": 2 }.as_mut())?;
+                Err(DbErr::ParseError("abort inner".into()))
+            });
+            assert!(inner.is_err());
+
+            tx.transaction(|tx| {
+                tx.collection("test")?.insert(doc! { "_id": 3 }.as_mut())?;
+                Ok(())
+            })
+        }).unwrap();
+
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), 2);
+        assert!(collection.find_one(&doc! { "_id": 2 }).unwrap().is_none());
+    }
+
+    #[test]
+    fn test_retry_in_outer_transaction() {
+        let mut db = Database::open_memory().unwrap();
+        let policy = RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_secs(10),
+            max_backoff: Duration::from_secs(10),
+        };
+
+        db.start_transaction(Some(TransactionType::Write)).unwrap();
+        db.collection("test").unwrap().insert(doc! { "_id": 1 }.as_mut()).unwrap();
+
+        let mut attempts = 0;
+        let result: DbResult<()> = db.transaction_with_retry(TransactionType::Write, policy, |tx| {
+            attempts += 1;
+            tx.collection("test")?.insert(doc! { "_id": 2 }.as_mut())?;
+            Err(DbErr::Busy)
+        });
+        assert!(matches!(result, Err(DbErr::Busy)));
+        assert_eq!(attempts, 1);
+
+        db.commit().unwrap();
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_transaction_retry_on_busy() {
+        let mut db = Database::open_memory().unwrap();
+        let policy = RetryPolicy {
+            max_retries: 3,
+            initial_backoff: Duration::from_millis(1),
+            max_backoff: Duration::from_millis(2),
+        };
+
+        let mut attempts = 0;
+        db.transaction_with_retry(TransactionType::Write, policy, |tx| {
+            attempts += 1;
+            tx.collection("test")?.insert(doc! { "_id": attempts }.as_mut())?;
+            if attempts < 3 {
+                return Err(DbErr::Busy);
+            }
+            Ok(())
+        }).unwrap();
+        assert_eq!(attempts, 3);
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), 1);
+
+        let mut attempts = 0;
+        let result: DbResult<()> = db.transaction_with_retry(TransactionType::Write, policy, |_tx| {
+            attempts += 1;
+            Err(DbErr::Busy)
+        });
+        assert!(matches!(result, Err(DbErr::Busy)));
+        assert_eq!(attempts, 4);
+    }
+
+    #[test]
+    fn test_snapshot_read() {
+        let mut db = create_and_return_db_with_items("test-snapshot-read", 10);
+        let mut reader = db.new_connection().unwrap();
+
+        reader.start_transaction(Some(TransactionType::Read)).unwrap();
+        assert_eq!(reader.collection("test").unwrap().count().unwrap(), 10);
+
+        // the writer is not blocked by the reader
+        let mut collection = db.collection("test").unwrap();
+        for i in 0..10 {
+            collection.insert(doc! { "content": i }.as_mut()).unwrap();
+        }
+
+        assert_eq!(reader.collection("test").unwrap().count().unwrap(), 10);
+        reader.commit().unwrap();
+        assert_eq!(reader.collection("test").unwrap().count().unwrap(), 20);
+    }
+
+    #[test]
+    fn test_single_writer() {
+        let mut db = create_and_return_db_with_items("test-single-writer", 10);
+        let mut other = db.new_connection().unwrap();
+
+        db.start_transaction(Some(TransactionType::Write)).unwrap();
+        db.collection("test").unwrap().insert(doc! { "content": "db" }.as_mut()).unwrap();
+
+        let result = other.collection("test").unwrap().insert(doc! { "content": "other" }.as_mut());
+        assert!(matches!(result, Err(DbErr::Busy)));
+        assert_eq!(other.collection("test").unwrap().count().unwrap(), 10);
+
+        db.commit().unwrap();
+        other.collection("test").unwrap().insert(doc! { "content": "other" }.as_mut()).unwrap();
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), 12);
+    }
+
+    #[test]
+    fn test_upgrade_outdated_snapshot() {
+        let mut db = create_and_return_db_with_items("test-upgrade-outdated-snapshot", 10);
+        let mut other = db.new_connection().unwrap();
+
+        other.start_transaction(None).unwrap();
+        assert_eq!(other.collection("test").unwrap().count().unwrap(), 10);
+
+        db.collection("test").unwrap().insert(doc! { "content": "db" }.as_mut()).unwrap();
+
+        // writing on an outdated snapshot would lose the update of db
+        let result = other.collection("test").unwrap().insert(doc! { "content": "other" }.as_mut());
+        assert!(matches!(result, Err(DbErr::Busy)));
+        other.rollback().unwrap();
+
+        other.collection("test").unwrap().insert(doc! { "content": "other" }.as_mut()).unwrap();
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), 12);
+    }
+
+    #[test]
+    fn test_checkpoint_deferred_by_reader() {
+        let config = Config {
+            journal_full_size: 1,
+            ..Default::default()
+        };
+        let mut db = prepare_db_with_config("test-checkpoint-deferred", config).unwrap();
+        db.create_collection("test").unwrap();
+        let journal_path = mk_journal_path("test-checkpoint-deferred");
+        assert_eq!(std::fs::metadata(&journal_path).unwrap().len(), 64);
+
+        let mut reader = db.new_connection().unwrap();
+        reader.start_transaction(Some(TransactionType::Read)).unwrap();
+        assert_eq!(reader.collection("test").unwrap().count().unwrap(), 0);
+
+        let mut collection = db.collection("test").unwrap();
+        for i in 0..100 {
+            collection.insert(doc! { "_id": i }.as_mut()).unwrap();
+        }
+        assert!(std::fs::metadata(&journal_path).unwrap().len() > 64);
+        assert_eq!(reader.collection("test").unwrap().count().unwrap(), 0);
+
+        // the last reader checkpoints the journal
+        reader.commit().unwrap();
+        assert_eq!(std::fs::metadata(&journal_path).unwrap().len(), 64);
+        assert_eq!(reader.collection("test").unwrap().count().unwrap(), 100);
+    }
+
+    #[test]
+    fn test_read_while_writing_in_another_thread() {
+        const BATCH_SIZE: i64 = 10;
+        const BATCH_COUNT: i64 = 20;
+
+        // checkpoint frequently while the reader is reading
+        let config = Config {
+            journal_full_size: 10,
+            ..Default::default()
+        };
+        let mut db = prepare_db_with_config("test-read-while-writing", config).unwrap();
+        db.create_collection("test").unwrap();
+        let mut reader = db.new_connection().unwrap();
+
+        let writer = std::thread::spawn(move || {
+            for batch in 0..BATCH_COUNT {
+                db.transaction(TransactionType::Write, |tx| {
+                    let mut collection = tx.collection("test")?;
+                    for i in 0..BATCH_SIZE {
+                        collection.insert(doc! { "_id": batch * BATCH_SIZE + i }.as_mut())?;
+                    }
+                    Ok(())
+                }).unwrap();
+            }
+        });
+
+        // every snapshot contains whole batches
+        loop {
+            let count = reader.collection("test").unwrap().count().unwrap() as i64;
+            assert_eq!(count % BATCH_SIZE, 0);
+            if count == BATCH_SIZE * BATCH_COUNT {
+                break;
+            }
+        }
+
+        writer.join().unwrap();
+    }
+
+    #[test]
+    fn test_new_connection_of_memory_db() {
+        let db = Database::open_memory().unwrap();
+        assert!(matches!(db.new_connection(), Err(DbErr::NotSupported(_))));
+    }
+
+    #[test]
+    fn test_backup() {
+        let mut db = create_and_return_db_with_items("test-backup", TEST_SIZE);
+        let mut other = db.new_connection().unwrap();
+        let backup_path = mk_db_path("test-backup-copy");
+
+        // the writes during the backup are not included
+        let mut last_progress = None;
+        db.backup_to(&backup_path, |progress| {
+            if progress.copied_pages == 1 {
+                other.collection("test").unwrap().insert(doc! { "content": "new" }.as_mut()).unwrap();
+            }
+            last_progress = Some(progress);
+        }).unwrap();
+
+        let last_progress = last_progress.unwrap();
+        assert_eq!(last_progress.copied_pages, last_progress.total_pages);
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), TEST_SIZE as u64 + 1);
+
+        let mut tmp_path = backup_path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        assert!(!PathBuf::from(tmp_path).exists());
+
+        let mut backup_db = Database::open_file(&backup_path).unwrap();
+        let mut collection = backup_db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), TEST_SIZE as u64);
+        assert_eq!(collection.find_all().unwrap().len(), TEST_SIZE);
+    }
+
+    #[test]
+    fn test_failed_backup_keeps_destination() {
+        let mut db = create_and_return_db_with_items("test-failed-backup", TEST_SIZE);
+        let backup_path = mk_db_path("test-failed-backup-copy");
+        std::fs::write(&backup_path, b"old").unwrap();
+
+        // the temporary file can't be created
+        let mut tmp_path = backup_path.as_os_str().to_owned();
+        tmp_path.push(".tmp");
+        let _ = std::fs::remove_dir(&tmp_path);
+        std::fs::create_dir(&tmp_path).unwrap();
+        assert!(db.backup_to(&backup_path, |_| ()).is_err());
+        assert_eq!(std::fs::read(&backup_path).unwrap(), b"old");
+        std::fs::remove_dir(&tmp_path).unwrap();
+
+        // the backup stops in the middle
+        let result = std::panic::catch_unwind(std::panic::AssertUnwindSafe(|| {
+            db.backup_to(&backup_path, |progress| {
+                if progress.copied_pages == 2 {
+                    panic!("stop the backup");
+                }
+            })
+        }));
+        assert!(result.is_err());
+        assert_eq!(std::fs::read(&backup_path).unwrap(), b"old");
+
+        db.backup_to(&backup_path, |_| ()).unwrap();
+        let mut backup_db = Database::open_file(&backup_path).unwrap();
+        assert_eq!(backup_db.collection("test").unwrap().count().unwrap(), TEST_SIZE as u64);
+    }
+
+    #[test]
+    fn test_save_memory_to() {
+        let db_path = mk_db_path("test-save-memory");
+        let _ = std::fs::remove_file(&db_path);
+
+        let mut db = Database::open_memory().unwrap();
+        let mut collection = db.collection("test").unwrap();
+        for i in 0..TEST_SIZE as i64 {
+            collection.insert(doc! { "_id": i, "content": i.to_string() }.as_mut()).unwrap();
+        }
+        db.save_memory_to(&db_path).unwrap();
+
+        let mut file_db = Database::open_file(&db_path).unwrap();
+        let mut collection = file_db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), TEST_SIZE as u64);
+        let doc = collection.find_one(&doc! { "_id": 10 }).unwrap().unwrap();
+        assert_eq!(doc.get("content").unwrap().unwrap_string(), "10");
+        assert!(file_db.check_integrity().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_open_file_into_memory() {
+        let db_path = mk_db_path("test-open-file-into-memory");
+        let saved_path = mk_db_path("test-open-file-into-memory-saved");
+        let _ = std::fs::remove_file(&saved_path);
+        drop(create_and_return_db_with_items("test-open-file-into-memory", TEST_SIZE));
+        let file_len = std::fs::metadata(&db_path).unwrap().len();
+
+        let mut db = Database::open_file_into_memory(&db_path).unwrap();
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), TEST_SIZE as u64);
+        for _ in 0..TEST_SIZE {
+            collection.insert(doc! { "content": "new" }.as_mut()).unwrap();
+        }
+
+        // the file is not locked or changed
+        let mut file_db = Database::open_file(&db_path).unwrap();
+        assert_eq!(file_db.collection("test").unwrap().count().unwrap(), TEST_SIZE as u64);
+        drop(file_db);
+        assert_eq!(std::fs::metadata(&db_path).unwrap().len(), file_len);
+
+        db.save_memory_to(&saved_path).unwrap();
+        let mut saved_db = Database::open_file(&saved_path).unwrap();
+        assert_eq!(saved_db.collection("test").unwrap().count().unwrap(), TEST_SIZE as u64 * 2);
+
+        // a missing file is not created
+        let missing_path = mk_db_path("test-open-file-into-memory-missing");
+        let _ = std::fs::remove_file(&missing_path);
+        assert!(matches!(Database::open_file_into_memory(&missing_path), Err(DbErr::IOErr(_))));
+        assert!(!missing_path.exists());
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_open_encrypted_file_into_memory() {
+        let db_path = mk_db_path("test-open-encrypted-file-into-memory");
+        let mut db = prepare_db_with_config("test-open-encrypted-file-into-memory", encrypted_config([1; 32])).unwrap();
+        insert_secrets(&mut db).unwrap();
+        drop(db);
+
+        let result = Database::open_file_into_memory_with_config(&db_path, encrypted_config([2; 32]));
+        assert!(matches!(result, Err(DbErr::DecryptionFailed(0))));
+
+        let mut db = Database::open_file_into_memory_with_config(&db_path, encrypted_config([1; 32])).unwrap();
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), TEST_SIZE as u64);
+    }
+
+    #[test]
+    fn test_read_only() {
+        let db_path = mk_db_path("test-read-only");
+        let journal_path = mk_journal_path("test-read-only");
+        drop(create_and_return_db_with_items("test-read-only", TEST_SIZE));
+        let content = std::fs::read(&db_path).unwrap();
+
+        let mut db = Database::open_file_read_only(&db_path).unwrap();
+        assert!(!journal_path.exists());
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), TEST_SIZE as u64);
+        assert!(matches!(collection.insert(doc! { "content": "new" }.as_mut()), Err(DbErr::ReadOnly)));
+        assert!(matches!(db.create_collection("other"), Err(DbErr::ReadOnly)));
+        assert!(matches!(db.start_transaction(Some(TransactionType::Write)), Err(DbErr::ReadOnly)));
+        assert!(matches!(db.vacuum(), Err(DbErr::ReadOnly)));
+        assert!(matches!(db.rekey(None), Err(DbErr::ReadOnly)));
+
+        // can not be upgraded to write
+        db.start_transaction(None).unwrap();
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), TEST_SIZE as u64);
+        let result = db.collection("test").unwrap().delete(None);
+        assert!(matches!(result, Err(DbErr::ReadOnly)));
+        db.rollback().unwrap();
+
+        // the readers share the file, the writers can not open it
+        let mut other = Database::open_file_read_only(&db_path).unwrap();
+        assert_eq!(other.collection("test").unwrap().count().unwrap(), TEST_SIZE as u64);
+        let mut connection = db.new_connection().unwrap();
+        assert_eq!(connection.collection("test").unwrap().count().unwrap(), TEST_SIZE as u64);
+        assert!(matches!(Database::open_file(&db_path), Err(DbErr::DatabaseOccupied)));
+
+        drop(connection);
+        drop(other);
+        drop(db);
+        assert!(!journal_path.exists());
+        assert_eq!(std::fs::read(&db_path).unwrap(), content);
+    }
+
+    #[test]
+    fn test_read_only_with_journal() {
+        let copy_path = mk_db_path("test-read-only-journal-copy");
+        let copy_journal_path = mk_journal_path("test-read-only-journal-copy");
+
+        // the committed frames are still in the journal when the files are copied
+        let db = create_and_return_db_with_items("test-read-only-journal", TEST_SIZE);
+        std::fs::copy(mk_db_path("test-read-only-journal"), &copy_path).unwrap();
+        std::fs::copy(mk_journal_path("test-read-only-journal"), &copy_journal_path).unwrap();
+        drop(db);
+        let journal = std::fs::read(&copy_journal_path).unwrap();
+        assert!(journal.len() > 64);
+
+        let mut db = Database::open_file_read_only(&copy_path).unwrap();
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), TEST_SIZE as u64);
+        assert!(db.check_integrity().unwrap().is_ok());
+        drop(db);
+
+        // the journal is not checkpointed
+        assert_eq!(std::fs::read(&copy_journal_path).unwrap(), journal);
+
+        let mut db = Database::open_file(&copy_path).unwrap();
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), TEST_SIZE as u64);
+    }
+
+    #[test]
+    fn test_read_only_missing_file() {
+        let db_path = mk_db_path("test-read-only-missing");
+        let _ = std::fs::remove_file(&db_path);
+        assert!(matches!(Database::open_file_read_only(&db_path), Err(DbErr::IOErr(_))));
+        assert!(!db_path.exists());
+    }
+
+    // the copy of the database after a power failure
+    fn insert_and_lose_power(db_name: &str, config: Config) -> Database {
+        let copy_name = format!("{}-crashed", db_name);
+        reset();
+        let mut db = prepare_db_with_config(db_name, config).unwrap();
+        let mut collection = db.create_collection("test").unwrap();
+        for i in 0..20 {
+            collection.insert(doc! { "_id": i }.as_mut()).unwrap();
+        }
+
+        copy_synced(&mk_db_path(db_name), &mk_db_path(&copy_name)).unwrap();
+        copy_synced(&mk_journal_path(db_name), &mk_journal_path(&copy_name)).unwrap();
+        drop(db);
+
+        Database::open_file(mk_db_path(&copy_name)).unwrap()
+    }
+
+    #[test]
+    fn test_synchronous_full() {
+        let config = Config {
+            synchronous: Synchronous::Full,
+            ..Default::default()
+        };
+        let mut db = insert_and_lose_power("test-synchronous-full", config);
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), 20);
+        assert!(db.check_integrity().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_synchronous_normal() {
+        // the transactions in the journal are lost
+        let mut db = insert_and_lose_power("test-synchronous-normal", Config::default());
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), 0);
+        assert!(db.check_integrity().unwrap().is_ok());
+
+        // the checkpointed ones are kept
+        let config = Config {
+            journal_full_size: 1,
+            ..Default::default()
+        };
+        let mut db = insert_and_lose_power("test-synchronous-normal-checkpoint", config);
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), 20);
+        assert!(db.check_integrity().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_synchronous_off() {
+        let config = Config {
+            journal_full_size: 1,
+            synchronous: Synchronous::Off,
+            ..Default::default()
+        };
+        let mut db = insert_and_lose_power("test-synchronous-off", config);
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_checkpoint() {
+        let mut db = create_and_return_db_with_items("test-checkpoint", 10);
+        let stats = db.checkpoint_stats().unwrap();
+        assert!(stats.journal_frames > 0);
+        assert!(stats.journal_bytes > 64);
+
+        assert!(db.checkpoint(CheckpointMode::SkipIfBusy).unwrap());
+        let stats = db.checkpoint_stats().unwrap();
+        assert_eq!(stats.journal_frames, 0);
+        assert_eq!(stats.journal_bytes, 64);
+
+        drop(db);
+        let mut db = Database::open_file(mk_db_path("test-checkpoint")).unwrap();
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), 10);
+    }
+
+    #[test]
+    fn test_checkpoint_with_reader() {
+        let mut db = create_and_return_db_with_items("test-checkpoint-with-reader", 10);
+        let mut reader = db.new_connection().unwrap();
+        reader.start_transaction(Some(TransactionType::Read)).unwrap();
+        assert_eq!(reader.collection("test").unwrap().count().unwrap(), 10);
+
+        // the reader is using the older frames
+        db.collection("test").unwrap().insert(doc! { "content": "new" }.as_mut()).unwrap();
+        let frames = db.checkpoint_stats().unwrap().journal_frames;
+        assert!(!db.checkpoint(CheckpointMode::SkipIfBusy).unwrap());
+        assert!(matches!(db.checkpoint(CheckpointMode::FailIfBusy), Err(DbErr::Busy)));
+        assert_eq!(db.checkpoint_stats().unwrap().journal_frames, frames);
+
+        reader.commit().unwrap();
+        assert!(db.checkpoint(CheckpointMode::FailIfBusy).unwrap());
+        assert_eq!(reader.collection("test").unwrap().count().unwrap(), 11);
+    }
+
+    fn insert_with_auto_checkpoint(db_name: &str, auto_checkpoint: AutoCheckpoint) -> Database {
+        let config = Config {
+            auto_checkpoint,
+            ..Default::default()
+        };
+        let mut db = prepare_db_with_config(db_name, config).unwrap();
+        let mut collection = db.create_collection("test").unwrap();
+        for i in 0..20 {
+            collection.insert(doc! { "_id": i }.as_mut()).unwrap();
+        }
+        db
+    }
+
+    #[test]
+    fn test_auto_checkpoint_bytes() {
+        let mut db = insert_with_auto_checkpoint("test-auto-checkpoint-bytes", AutoCheckpoint::Bytes(1024 * 1024));
+        let stats = db.checkpoint_stats().unwrap();
+        assert!(stats.journal_frames > 0);
+        assert!(stats.journal_bytes < 1024 * 1024);
+
+        let mut db = insert_with_auto_checkpoint("test-auto-checkpoint-bytes-small", AutoCheckpoint::Bytes(1));
+        assert_eq!(db.checkpoint_stats().unwrap().journal_frames, 0);
+    }
+
+    #[test]
+    fn test_auto_checkpoint_interval() {
+        let mut db = insert_with_auto_checkpoint("test-auto-checkpoint-interval", AutoCheckpoint::Interval(Duration::ZERO));
+        assert_eq!(db.checkpoint_stats().unwrap().journal_frames, 0);
+
+        let mut db = insert_with_auto_checkpoint("test-auto-checkpoint-interval-hour", AutoCheckpoint::Interval(Duration::from_secs(3600)));
+        assert!(db.checkpoint_stats().unwrap().journal_frames > 0);
+    }
+
+    #[test]
+    fn test_auto_checkpoint_disabled() {
+        let mut db = insert_with_auto_checkpoint("test-auto-checkpoint-disabled", AutoCheckpoint::Disabled);
+        assert!(db.checkpoint_stats().unwrap().journal_frames > 0);
+        assert!(db.checkpoint(CheckpointMode::SkipIfBusy).unwrap());
+        assert_eq!(db.checkpoint_stats().unwrap().journal_frames, 0);
+    }
+
+    fn ship_journal_frames(primary: &mut Database, replica: &mut Database) -> Vec<u8> {
+        let mut frames = vec![];
+        let since = replica.journal_position().unwrap().unwrap();
+        let next = primary.export_journal_frames(since, &mut frames).unwrap();
+        replica.apply_journal_frames(&mut frames.as_slice()).unwrap();
+        assert_eq!(replica.journal_position().unwrap(), Some(next));
+
+        let to_strings = |db: &mut Database| -> Vec<String> {
+            let docs = db.collection("test").unwrap().find_all().unwrap();
+            docs.iter().map(|doc| doc.to_string()).collect()
+        };
+        assert_eq!(to_strings(primary), to_strings(replica));
+        assert!(replica.check_integrity().unwrap().is_ok());
+
+        frames
+    }
+
+    #[test]
+    fn test_journal_shipping() {
+        let config = Config {
+            auto_checkpoint: AutoCheckpoint::Disabled,
+            ..Default::default()
+        };
+        let mut primary = prepare_db_with_config("test-journal-shipping", config).unwrap();
+        let mut collection = primary.create_collection("test").unwrap();
+        for i in 0..10 {
+            collection.insert(doc! { "_id": i, "content": i.to_string() }.as_mut()).unwrap();
+        }
+        primary.checkpoint(CheckpointMode::FailIfBusy).unwrap();
+
+        let replica_path = mk_db_path("test-journal-shipping-replica");
+        primary.backup_to(&replica_path, |_| ()).unwrap();
+        let mut replica = Database::open_file(&replica_path).unwrap();
+
+        let mut collection = primary.collection("test").unwrap();
+        for i in 10..1000 {
+            collection.insert(doc! { "_id": i, "content": i.to_string() }.as_mut()).unwrap();
+        }
+        collection.update(Some(&doc! { "_id": 5 }), &doc! { "$set": doc! { "content": "updated" } }).unwrap();
+        let shipped = ship_journal_frames(&mut primary, &mut replica);
+
+        // the same frames can't be applied twice
+        let result = replica.apply_journal_frames(&mut shipped.as_slice());
+        assert!(matches!(result, Err(DbErr::InvalidJournalFrames(_))));
+
+        // only the frames after the last export
+        let mut collection = primary.collection("test").unwrap();
+        collection.delete(Some(&doc! { "_id": 6 })).unwrap();
+        primary.create_collection("other").unwrap();
+        ship_journal_frames(&mut primary, &mut replica);
+        assert!(replica.collection("other").is_ok());
+
+        // the frames continue in the journal after a checkpoint
+        primary.checkpoint(CheckpointMode::FailIfBusy).unwrap();
+        primary.collection("test").unwrap().delete(None).unwrap();
+        ship_journal_frames(&mut primary, &mut replica);
+        assert_eq!(replica.collection("test").unwrap().count().unwrap(), 0);
+    }
+
+    #[test]
+    fn test_journal_shipping_out_of_order() {
+        let config = Config {
+            auto_checkpoint: AutoCheckpoint::Disabled,
+            ..Default::default()
+        };
+        let mut primary = prepare_db_with_config("test-journal-shipping-out-of-order", config).unwrap();
+        primary.create_collection("test").unwrap();
+        primary.checkpoint(CheckpointMode::FailIfBusy).unwrap();
+
+        let replica_path = mk_db_path("test-journal-shipping-out-of-order-replica");
+        primary.backup_to(&replica_path, |_| ()).unwrap();
+        let mut replica = Database::open_file(&replica_path).unwrap();
+        let position = replica.journal_position().unwrap().unwrap();
+
+        // an export is skipped
+        primary.collection("test").unwrap().insert(doc! { "_id": 1 }.as_mut()).unwrap();
+        let skipped = primary.export_journal_frames(position, &mut vec![]).unwrap();
+        primary.collection("test").unwrap().insert(doc! { "_id": 2 }.as_mut()).unwrap();
+        let mut frames = vec![];
+        primary.export_journal_frames(skipped, &mut frames).unwrap();
+        let result = replica.apply_journal_frames(&mut frames.as_slice());
+        assert!(matches!(result, Err(DbErr::InvalidJournalFrames(_))));
+        assert_eq!(replica.collection("test").unwrap().count().unwrap(), 0);
+
+        // the frames after the position are not in the journal any more
+        primary.checkpoint(CheckpointMode::FailIfBusy).unwrap();
+        let result = primary.export_journal_frames(position, &mut vec![]);
+        assert!(matches!(result, Err(DbErr::InvalidJournalFrames(_))));
+
+        // a database which is not a replica
+        let mut other = prepare_db("test-journal-shipping-out-of-order-other").unwrap();
+        other.create_collection("test").unwrap();
+        let result = other.apply_journal_frames(&mut frames.as_slice());
+        assert!(matches!(result, Err(DbErr::InvalidJournalFrames(_))));
+    }
+
+    #[test]
+    fn test_apply_damaged_journal_frames() {
+        let config = Config {
+            auto_checkpoint: AutoCheckpoint::Disabled,
+            ..Default::default()
+        };
+        let mut primary = prepare_db_with_config("test-apply-damaged-journal-frames", config).unwrap();
+        primary.create_collection("test").unwrap();
+        primary.checkpoint(CheckpointMode::FailIfBusy).unwrap();
+
+        let replica_path = mk_db_path("test-apply-damaged-journal-frames-replica");
+        primary.backup_to(&replica_path, |_| ()).unwrap();
+        let mut replica = Database::open_file(&replica_path).unwrap();
+
+        let mut collection = primary.collection("test").unwrap();
+        for i in 0..100 {
+            collection.insert(doc! { "_id": i }.as_mut()).unwrap();
+        }
+        let mut frames = vec![];
+        let position = replica.journal_position().unwrap().unwrap();
+        primary.export_journal_frames(position, &mut frames).unwrap();
+
+        // the first transactions are not applied either
+        let last = frames.len() - 1;
+        frames[last] ^= 0xFF;
+        let result = replica.apply_journal_frames(&mut frames.as_slice());
+        assert!(matches!(result, Err(DbErr::JournalChecksumMismatch)));
+        assert_eq!(replica.collection("test").unwrap().count().unwrap(), 0);
+
+        frames[last] ^= 0xFF;
+        frames.truncate(last);
+        let result = replica.apply_journal_frames(&mut frames.as_slice());
+        assert!(matches!(result, Err(DbErr::IOErr(_))));
+
+        let mut other = prepare_db("test-apply-damaged-journal-frames-other").unwrap();
+        let result = other.apply_journal_frames(&mut b"PoloDB".as_ref());
+        assert!(matches!(result, Err(DbErr::IOErr(_))));
+    }
+
+    // the commit time of the frames in seconds
+    static COMMIT_TIME: AtomicU64 = AtomicU64::new(0);
+
+    fn commit_clock() -> SystemTime {
+        UNIX_EPOCH + Duration::from_secs(COMMIT_TIME.load(Ordering::SeqCst))
+    }
+
+    #[test]
+    fn test_restore() {
+        let archive_dir = env::temp_dir().join("test-restore-archive");
+        let _ = std::fs::remove_dir_all(&archive_dir);
+        let config = Config {
+            auto_checkpoint: AutoCheckpoint::Disabled,
+            archive_dir: Some(archive_dir.clone()),
+            clock: commit_clock,
+            ..Default::default()
+        };
+        let mut db = prepare_db_with_config("test-restore", config).unwrap();
+        COMMIT_TIME.store(1000, Ordering::SeqCst);
+        let mut collection = db.create_collection("test").unwrap();
+        for i in 0..10 {
+            collection.insert(doc! { "_id": i }.as_mut()).unwrap();
+        }
+        db.checkpoint(CheckpointMode::FailIfBusy).unwrap();
+
+        let base_path = mk_db_path("test-restore-base");
+        db.backup_to(&base_path, |_| ()).unwrap();
+
+        COMMIT_TIME.store(2000, Ordering::SeqCst);
+        let mut collection = db.collection("test").unwrap();
+        for i in 10..20 {
+            collection.insert(doc! { "_id": i }.as_mut()).unwrap();
+        }
+        db.checkpoint(CheckpointMode::FailIfBusy).unwrap();
+
+        // the commit time is recorded in seconds
+        let before_delete = UNIX_EPOCH + Duration::from_secs(2000);
+        COMMIT_TIME.store(3000, Ordering::SeqCst);
+        db.collection("test").unwrap().delete(None).unwrap();
+        drop(db);
+        assert_eq!(std::fs::read_dir(&archive_dir).unwrap().count(), 3);
+
+        let restore_to = |name: &str, until: RestoreTarget| {
+            let dst_path = mk_db_path(name);
+            let _ = std::fs::remove_file(&dst_path);
+            let report = Database::restore(&base_path, &archive_dir, &dst_path, until).unwrap();
+            let mut db = Database::open_file(&dst_path).unwrap();
+            assert!(db.check_integrity().unwrap().is_ok());
+            let count = db.collection("test").unwrap().count().unwrap();
+            (report, count)
+        };
+
+        let (latest, count) = restore_to("test-restore-latest", RestoreTarget::Latest);
+        assert_eq!(count, 0);
+        assert_eq!(latest.segments, 2);
+        assert!(latest.last_commit_time.unwrap() > before_delete);
+
+        let (report, count) = restore_to("test-restore-time", RestoreTarget::Time(before_delete));
+        assert_eq!(count, 20);
+        assert_eq!(report.transactions, latest.transactions - 1);
+        assert!(report.last_commit_time.unwrap() <= before_delete);
+
+        let (report, count) = restore_to("test-restore-frame", RestoreTarget::Frame(latest.frames - 1));
+        assert_eq!(count, 20);
+        assert_eq!(report.segments, 1);
+
+        // the replay starts at the backup
+        let (report, count) = restore_to("test-restore-at-base", RestoreTarget::Time(UNIX_EPOCH + Duration::from_secs(1000)));
+        assert_eq!(count, 10);
+        assert_eq!(report.transactions, 0);
+
+        let result = Database::restore(&base_path, &archive_dir, mk_db_path("test-restore-frame"), RestoreTarget::Latest);
+        assert!(matches!(result, Err(DbErr::IOErr(_))));
+
+        for until in [RestoreTarget::Time(UNIX_EPOCH + Duration::from_secs(999)), RestoreTarget::Frame(1)] {
+            let dst_path = mk_db_path("test-restore-before-base");
+            let _ = std::fs::remove_file(&dst_path);
+            let result = Database::restore(&base_path, &archive_dir, &dst_path, until);
+            assert!(matches!(result, Err(DbErr::InvalidRestoreTarget(_))));
+
+            // a half-restored database is removed
+            assert!(!dst_path.exists());
+        }
+    }
+
+    #[test]
+    fn test_vacuum() {
+        let db_path = mk_db_path("test-vacuum");
+        let mut db = create_and_return_db_with_items("test-vacuum", TEST_SIZE);
+        {
+            let mut garbage = db.create_collection("garbage").unwrap();
+            for i in 0..TEST_SIZE {
+                garbage.insert(doc! { "_id": i as i64, "content": "x".repeat(200) }.as_mut()).unwrap();
+            }
+            assert_eq!(garbage.delete(None).unwrap(), TEST_SIZE);
+        }
+
+        db.start_transaction(None).unwrap();
+        assert!(matches!(db.vacuum(), Err(DbErr::Busy)));
+        db.rollback().unwrap();
+
+        let report = db.vacuum().unwrap();
+        assert!(report.reclaimed_bytes() > 0);
+        assert!(db.check_integrity().unwrap().is_ok());
+
+        // the copy is swapped in, instead of written through the journal
+        assert_eq!(db.checkpoint_stats().unwrap().journal_frames, 0);
+        assert_eq!(std::fs::metadata(&db_path).unwrap().len(), report.size_after);
+        let mut tmp_path = db_path.as_os_str().to_owned();
+        tmp_path.push(".vacuum");
+        assert!(!std::path::Path::new(&tmp_path).exists());
+
+        let mut garbage = db.collection("garbage").unwrap();
+        assert_eq!(garbage.count().unwrap(), 0);
+        garbage.insert(doc! { "_id": 1 }.as_mut()).unwrap();
+
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), TEST_SIZE as u64);
+        let doc = collection.find_one(&doc! { "content": "3" }).unwrap().unwrap();
+        assert_eq!(doc.get("content").unwrap().unwrap_string(), "3");
+        collection.insert(doc! { "content": "new" }.as_mut()).unwrap();
+
+        drop(db);
+        let file_len = std::fs::metadata(&db_path).unwrap().len();
+        assert!(file_len < report.size_before);
+
+        let mut db = Database::open_file(&db_path).unwrap();
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), TEST_SIZE as u64 + 1);
+        assert_eq!(db.collection("garbage").unwrap().count().unwrap(), 1);
+    }
+
+    #[test]
+    fn test_vacuum_memory_db() {
+        let mut db = Database::open_memory().unwrap();
+        let mut collection = db.create_collection("test").unwrap();
+        for i in 0..TEST_SIZE {
+            collection.insert(doc! { "_id": i as i64 }.as_mut()).unwrap();
+        }
+        collection.delete(Some(&doc! { "_id": 10 })).unwrap();
+
+        db.vacuum().unwrap();
+
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), TEST_SIZE as u64 - 1);
+        assert!(collection.find_one(&doc! { "_id": 11 }).unwrap().is_some());
+    }
+
+    #[test]
+    fn test_check_integrity() {
+        let mut db = create_and_return_db_with_items("test-check-integrity", TEST_SIZE);
+        {
+            let mut collection = db.create_collection("numbers").unwrap();
+            collection.create_index(&doc! { "content": 1 }, None).unwrap();
+            for i in 0..TEST_SIZE {
+                let content = format!("{}{}", i, "x".repeat(i % 300));
+                collection.insert(doc! { "_id": i as i64, "content": content }.as_mut()).unwrap();
+            }
+            for i in 0..10 {
+                collection.delete(Some(&doc! { "_id": i as i64 })).unwrap();
+            }
+            let mut large = doc! { "_id": TEST_SIZE as i64 };
+            large.insert("data".into(), Value::from(vec![1u8; 10000]));
+            collection.insert(&mut large).unwrap();
+        }
+
+        db.create_collection("empty").unwrap();
+
+        let report = db.check_integrity().unwrap();
+        assert!(report.is_ok(), "{}", report);
+        assert_eq!(report.collection_count, 3);
+        assert_eq!(report.document_count, (TEST_SIZE * 2 - 10 + 1) as u64);
+    }
+
+    #[test]
+    fn test_check_integrity_of_damaged_db() {
+        let db_path = mk_db_path("test-check-damaged");
+        let db = create_and_return_db_with_items("test-check-damaged", TEST_SIZE);
+        drop(db);
+
+        // the first root of the collection, which is a btree node now
+        {
+            let mut file = std::fs::OpenOptions::new().write(true).open(&db_path).unwrap();
+            file.seek(SeekFrom::Start(2 * 4096)).unwrap();
+            file.write_all(&[0xFF, 0x03]).unwrap();
+        }
+
+        let mut db = Database::open_file(&db_path).unwrap();
+        let report = db.check_integrity().unwrap();
+        assert!(!report.is_ok());
+        assert!(report.issues.iter().any(|issue| matches!(issue,
+            IntegrityIssue::PageMagicMismatch { page_id: 2, expected: PageKind::BTree, .. })), "{}", report);
+        assert!(report.issues.iter().any(|issue| matches!(issue, IntegrityIssue::UnreferencedData { .. })), "{}", report);
+    }
+
+    #[test]
+    fn test_stats() {
+        let mut db = create_and_return_db_with_items("test-stats", TEST_SIZE);
+        {
+            let mut collection = db.create_collection("numbers").unwrap();
+            collection.create_index(&doc! { "user_id": 1 }, None).unwrap();
+            for i in 0..TEST_SIZE {
+                collection.insert(doc! { "_id": i as i64, "user_id": i as i64 }.as_mut()).unwrap();
+            }
+            for i in 0..10 {
+                collection.delete(Some(&doc! { "_id": i as i64 })).unwrap();
+            }
+            let mut large = doc! { "_id": TEST_SIZE as i64 };
+            large.insert("content".into(), Value::from(vec![1u8; 10000]));
+            collection.insert(&mut large).unwrap();
+        }
+        db.create_collection("empty").unwrap();
+
+        let stats = db.stats().unwrap();
+        assert_eq!(stats.page_size, 4096);
+        assert_eq!(stats.collections.len(), 3);
+        assert_eq!(stats.document_count, (TEST_SIZE * 2 - 10 + 1) as u64);
+        assert!(stats.avg_document_size > 0);
+        assert!(stats.data_page_fill_ratio > 0.0 && stats.data_page_fill_ratio <= 1.0);
+        assert!(stats.pages.large_data >= 3);
+
+        // every allocated page is either used or free
+        let report = db.check_integrity().unwrap();
+        assert_eq!(stats.page_count, report.page_count);
+        assert_eq!(stats.free_page_count, report.free_page_count);
+        assert_eq!(stats.pages.total() + stats.free_page_count + 1, stats.page_count, "{}", stats);
+
+        let mut collection = db.collection("numbers").unwrap();
+        let numbers = collection.stats().unwrap();
+        assert_eq!(numbers.name, "numbers");
+        assert_eq!(numbers.document_count, collection.count().unwrap());
+        assert!(numbers.btree_depth >= 2, "{}", numbers);
+        assert!(numbers.data_size > 10000);
+        assert_eq!(numbers.indexes.len(), 1);
+        assert_eq!(numbers.indexes[0].entry_count, numbers.document_count - 1);
+        assert!(numbers.indexes[0].btree_depth >= 1);
+
+        let empty = db.collection("empty").unwrap().stats().unwrap();
+        assert_eq!(empty.document_count, 0);
+        assert_eq!(empty.avg_document_size, 0);
+        assert_eq!(empty.btree_depth, 1);
+        assert_eq!(empty.pages.data, 0);
+    }
+
+    // the index entries are stored out of the B-tree like the documents
+    #[test]
+    fn test_stats_index_pages() {
+        let config = Config {
+            page_checksum: true,
+            ..Default::default()
+        };
+        let mut db = prepare_db_with_config("test-stats-index-pages", config).unwrap();
+        let mut collection = db.create_collection("names").unwrap();
+        collection.create_index(&doc! { "name": 1 }, None).unwrap();
+        for i in 0..5 {
+            let name = i.to_string().repeat(5000);
+            collection.insert(doc! { "_id": i, "name": name }.as_mut()).unwrap();
+        }
+        db.checkpoint(CheckpointMode::FailIfBusy).unwrap();
+
+        let stats = db.stats().unwrap();
+        assert_eq!(stats.pages.total() + stats.free_page_count + 1, stats.page_count, "{}", stats);
+        assert_eq!(stats.file_size, std::fs::metadata(mk_db_path("test-stats-index-pages")).unwrap().len());
+
+        let names = db.collection("names").unwrap().stats().unwrap();
+        assert_eq!(names.indexes[0].entry_count, 5);
+        assert_eq!(names.pages.large_data, stats.pages.large_data, "{}", names);
+        assert!(names.pages.large_data >= 20, "{}", names);
+    }
+
+    #[test]
+    fn test_metrics() {
+        let mut db = prepare_db("test-metrics").unwrap();
+        let before = db.metrics().unwrap();
+
+        let mut collection = db.create_collection("test").unwrap();
+        for i in 0..TEST_SIZE {
+            collection.insert(doc! { "_id": i as i64 }.as_mut()).unwrap();
+        }
+        collection.find_one(&doc! { "_id": 10 }).unwrap().unwrap();
+
+        let after_insert = db.metrics().unwrap();
+        assert!(after_insert.page_writes > before.page_writes);
+        assert_eq!(after_insert.page_reads, after_insert.cache_hits + after_insert.cache_misses);
+        assert!(after_insert.cache_hits > before.cache_hits);
+        assert!(after_insert.journal_frames_appended > before.journal_frames_appended);
+        assert!(after_insert.vm_instructions > before.vm_instructions);
+        assert!(after_insert.transactions_committed >= before.transactions_committed + TEST_SIZE as u64);
+        assert_eq!(after_insert.transactions_rolled_back, before.transactions_rolled_back);
+
+        // the read transactions are not commits
+        db.collection("test").unwrap().find_one(&doc! { "_id": 20 }).unwrap().unwrap();
+        let after_read = db.metrics().unwrap();
+        assert_eq!(after_read.transactions_committed, after_insert.transactions_committed);
+        assert!(after_read.read_transactions > after_insert.read_transactions);
+
+        db.start_transaction(None).unwrap();
+        db.collection("test").unwrap().insert(doc! { "_id": -1 }.as_mut()).unwrap();
+        db.rollback().unwrap();
+
+        assert!(db.checkpoint(CheckpointMode::FailIfBusy).unwrap());
+
+        let metrics = db.metrics().unwrap();
+        assert_eq!(metrics.transactions_rolled_back, after_insert.transactions_rolled_back + 1);
+        assert_eq!(metrics.checkpoints, after_insert.checkpoints + 1);
+        assert!(metrics.cache_hit_ratio() > 0.0 && metrics.cache_hit_ratio() <= 1.0);
+    }
+
+    fn salvage_to(db_name: &str) -> (PathBuf, SalvageReport) {
+        let dst_path = mk_db_path(&(String::from(db_name) + "-salvaged"));
+        let _ = std::fs::remove_file(&dst_path);
+        let _ = std::fs::remove_file(mk_journal_path(&(String::from(db_name) + "-salvaged")));
+
+        let report = Database::salvage(mk_db_path(db_name), &dst_path).unwrap();
+        (dst_path, report)
+    }
+
+    #[test]
+    fn test_salvage() {
+        let db = create_and_return_db_with_items("test-salvage", TEST_SIZE);
+        drop(db);
+
+        let (dst_path, report) = salvage_to("test-salvage");
+        assert!(report.damaged_pages.is_empty(), "{}", report);
+        assert_eq!(report.collections, vec![("test".to_string(), TEST_SIZE as u64)]);
+        assert_eq!(report.orphan_documents, 0);
+        assert_eq!(report.lost_documents, 0);
+
+        let mut db = Database::open_file(&dst_path).unwrap();
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), TEST_SIZE as u64);
+        assert!(db.check_integrity().unwrap().is_ok());
+
+        let err = Database::salvage(mk_db_path("test-salvage"), &dst_path);
+        assert!(matches!(err, Err(DbErr::IOErr(_))));
+    }
+
+    #[test]
+    fn test_salvage_damaged_db() {
+        let db_path = mk_db_path("test-salvage-damaged");
+        let mut db = create_and_return_db_with_items("test-salvage-damaged", TEST_SIZE);
+        {
+            let mut collection = db.create_collection("large").unwrap();
+            let mut doc = doc! {
+                "_id": 1,
+                "content": "a".repeat(10000),
+            };
+            collection.insert(&mut doc).unwrap();
+        }
+        drop(db);
+
+        // the first root of the collection "test", which is a child node now
+        {
+            let mut file = std::fs::OpenOptions::new().write(true).open(&db_path).unwrap();
+            file.seek(SeekFrom::Start(2 * 4096)).unwrap();
+            file.write_all(&[0xFF, 0x03]).unwrap();
+        }
+
+        let (dst_path, report) = salvage_to("test-salvage-damaged");
+        assert_eq!(report.damaged_pages, vec![2]);
+        assert_eq!(report.lost_documents, 0);
+        assert_eq!(report.recovered_documents(), TEST_SIZE as u64 + 1, "{}", report);
+
+        let mut db = Database::open_file(&dst_path).unwrap();
+        let orphan_count = db.collection(LOST_AND_FOUND).unwrap().count().unwrap();
+        assert!(orphan_count > 0);
+        assert_eq!(db.collection("test").unwrap().count().unwrap() + orphan_count, TEST_SIZE as u64);
+        let large = db.collection("large").unwrap().find_one(&doc! { "_id": 1 }).unwrap().unwrap();
+        assert_eq!(large.get("content").unwrap().unwrap_string().len(), 10000);
+        assert!(db.check_integrity().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_salvage_same_id_in_collections() {
+        let db_path = mk_db_path("test-salvage-same-id");
+        {
+            let mut db = prepare_db("test-salvage-same-id").unwrap();
+            for name in ["first", "second"] {
+                let mut collection = db.create_collection(name).unwrap();
+                for i in 0..TEST_SIZE {
+                    collection.insert(doc! { "_id": i as i64, "content": name }.as_mut()).unwrap();
+                }
+            }
+        }
+
+        // the first root of the collection "first", which is a child node now
+        {
+            let mut file = std::fs::OpenOptions::new().write(true).open(&db_path).unwrap();
+            file.seek(SeekFrom::Start(2 * 4096)).unwrap();
+            file.write_all(&[0xFF, 0x03]).unwrap();
+        }
+
+        // the orphans of "first" are not the duplicates of the documents of "second"
+        let (dst_path, report) = salvage_to("test-salvage-same-id");
+        assert_eq!(report.damaged_pages, vec![2]);
+        assert_eq!(report.duplicate_documents, 0, "{}", report);
+        assert_eq!(report.recovered_documents(), TEST_SIZE as u64 * 2, "{}", report);
+
+        let mut db = Database::open_file(&dst_path).unwrap();
+        let orphans = db.collection(LOST_AND_FOUND).unwrap().find_all().unwrap();
+        assert!(!orphans.is_empty());
+        assert!(orphans.iter().all(|doc| doc.get("content").unwrap().unwrap_string() == "first"));
+        assert_eq!(db.collection("second").unwrap().count().unwrap(), TEST_SIZE as u64);
+    }
+
+    #[test]
+    fn test_salvage_lost_index() {
+        let db_path = mk_db_path("test-salvage-lost-index");
+        {
+            let mut db = prepare_db("test-salvage-lost-index").unwrap();
+            let mut collection = db.create_collection("test").unwrap();
+            collection.create_index(&doc! { "name": 1 }, None).unwrap();
+            for i in 0..TEST_SIZE {
+                collection.insert(doc! { "_id": i as i64, "name": format!("name {}", i) }.as_mut()).unwrap();
+            }
+        }
+
+        // all the btrees are lost with the meta btree
+        {
+            let mut file = std::fs::OpenOptions::new().read(true).write(true).open(&db_path).unwrap();
+            let mut header = RawPage::new(0, NonZeroU32::new(4096).unwrap());
+            header.read_from_file(&mut file, 0).unwrap();
+            let meta_pid = HeaderPageWrapper::from_raw_page(header).get_meta_page_id();
+            file.seek(SeekFrom::Start(meta_pid as u64 * 4096)).unwrap();
+            file.write_all(&[0xFF, 0x03]).unwrap();
+        }
+
+        // the entries of the index are not documents
+        let (dst_path, report) = salvage_to("test-salvage-lost-index");
+        assert!(report.collections.is_empty(), "{}", report);
+        assert_eq!(report.orphan_documents, TEST_SIZE as u64, "{}", report);
+
+        let mut db = Database::open_file(&dst_path).unwrap();
+        let orphans = db.collection(LOST_AND_FOUND).unwrap().find_all().unwrap();
+        assert!(orphans.iter().all(|doc| doc.get("keys").is_none()));
+    }
+
+    #[test]
+    fn test_page_checksum() {
+        let db_path = mk_db_path("test-page-checksum");
+        {
+            let config = Config {
+                page_checksum: true,
+                ..Default::default()
+            };
+            let mut db = prepare_db_with_config("test-page-checksum", config).unwrap();
+            insert_secrets(&mut db).unwrap();
+        }
+        assert_eq!(std::fs::metadata(&db_path).unwrap().len() % (4096 + 8), 0);
+
+        // the setting in the header page is used
+        {
+            let mut db = Database::open_file(&db_path).unwrap();
+            assert_eq!(db.collection("test").unwrap().count().unwrap(), TEST_SIZE as u64);
+            assert!(db.check_integrity().unwrap().is_ok());
+        }
+
+        // the last byte of page 2 is not used, but the bit rot is detected
+        {
+            let mut file = std::fs::OpenOptions::new().write(true).open(&db_path).unwrap();
+            file.seek(SeekFrom::Start(2 * (4096 + 8) + 4095)).unwrap();
+            file.write_all(&[0xAA]).unwrap();
+        }
+
+        let mut db = Database::open_file(&db_path).unwrap();
+        let result = db.collection("test").unwrap().count();
+        assert!(matches!(result, Err(DbErr::ChecksumMismatch(2))));
+
+        let report = db.check_integrity().unwrap();
+        let is_reported = report.issues.iter()
+            .any(|issue| matches!(issue, IntegrityIssue::ChecksumMismatch { page_id: 2 }));
+        assert!(is_reported, "{}", report);
+        drop(db);
+
+        let (_, report) = salvage_to("test-page-checksum");
+        assert_eq!(report.damaged_pages, vec![2]);
+        assert_eq!(report.recovered_documents(), TEST_SIZE as u64, "{}", report);
+    }
+
+    #[test]
+    fn test_page_checksum_empty_slot() {
+        let db_path = mk_db_path("test-page-checksum-empty-slot");
+        {
+            let config = Config {
+                page_checksum: true,
+                ..Default::default()
+            };
+            let mut db = prepare_db_with_config("test-page-checksum-empty-slot", config).unwrap();
+            insert_secrets(&mut db).unwrap();
+        }
+
+        // a zeroed slot of the meta page is not taken as an empty page
+        {
+            let mut file = std::fs::OpenOptions::new().write(true).open(&db_path).unwrap();
+            file.seek(SeekFrom::Start(4096 + 8)).unwrap();
+            file.write_all(&[0; 4096 + 8]).unwrap();
+        }
+
+        let mut db = Database::open_file(&db_path).unwrap();
+        assert!(matches!(db.collection("test"), Err(DbErr::ChecksumMismatch(1))));
+
+        let report = db.check_integrity().unwrap();
+        let is_reported = report.issues.iter()
+            .any(|issue| matches!(issue, IntegrityIssue::ChecksumMismatch { page_id: 1 }));
+        assert!(is_reported, "{}", report);
+    }
+
+    #[test]
+    fn test_set_page_checksum() {
+        let db_path = mk_db_path("test-set-page-checksum");
+        let mut db = create_and_return_db_with_items("test-set-page-checksum", TEST_SIZE);
+        db.set_page_checksum(true).unwrap();
+        drop(db);
+        assert_eq!(std::fs::metadata(&db_path).unwrap().len() % (4096 + 8), 0);
+
+        let mut db = Database::open_file(&db_path).unwrap();
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), TEST_SIZE as u64);
+        assert!(db.check_integrity().unwrap().is_ok());
+
+        db.set_page_checksum(false).unwrap();
+        let mut doc = doc! { "content": "extra" };
+        db.collection("test").unwrap().insert(&mut doc).unwrap();
+        drop(db);
+        assert_eq!(std::fs::metadata(&db_path).unwrap().len() % 4096, 0);
+
+        let mut db = Database::open_file(&db_path).unwrap();
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), TEST_SIZE as u64 + 1);
+    }
+
+    fn read_db_version(db_path: &Path) -> [u8; 4] {
+        let mut file = File::open(db_path).unwrap();
+        let mut version = [0u8; 4];
+        file.seek(SeekFrom::Start(32)).unwrap();
+        file.read_exact(&mut version).unwrap();
+        version
+    }
+
+    #[test]
+    fn test_database_version() {
+        let db_path = mk_db_path("test-database-version");
+        let mut db = create_and_return_db_with_items("test-database-version", 10);
+        drop(db);
+        assert_eq!(read_db_version(&db_path), [0, 0, 2, 0]);
+
+        // the older versions reject a file with the new features
+        db = Database::open_file(&db_path).unwrap();
+        db.set_page_checksum(true).unwrap();
+        drop(db);
+        assert_eq!(read_db_version(&db_path), [0, 0, 3, 0]);
+
+        db = Database::open_file(&db_path).unwrap();
+        db.set_page_checksum(false).unwrap();
+        drop(db);
+        assert_eq!(read_db_version(&db_path), [0, 0, 2, 0]);
+
+        {
+            let mut file = std::fs::OpenOptions::new().write(true).open(&db_path).unwrap();
+            file.seek(SeekFrom::Start(32)).unwrap();
+            file.write_all(&[0, 0, 4, 0]).unwrap();
+        }
+        let result = Database::open_file(&db_path);
+        assert!(matches!(result, Err(DbErr::VersionMismatch(_))));
+    }
+
+    fn create_verbose_db(db_name: &str, compression: Compression) -> DbResult<u64> {
+        let config = Config {
+            compression,
+            ..Default::default()
+        };
+        let mut db = prepare_db_with_config(db_name, config)?;
+        let mut collection = db.create_collection("test")?;
+        for i in 0..TEST_SIZE {
+            let mut doc = doc! {
+                "_id": i as i64,
+                "description": "a verbose record with a lot of repeated keys and text",
+                "address": "somewhere over the rainbow, way up high",
+                "tags": "compression, database, storage, compression, database",
+            };
+            collection.insert(&mut doc)?;
+        }
+        let mut doc = doc! {
+            "_id": TEST_SIZE as i64,
+            "content": "large ".repeat(5000),
+        };
+        collection.insert(&mut doc)?;
+        drop(db);
+
+        Ok(std::fs::metadata(mk_db_path(db_name))?.len())
+    }
+
+    #[test]
+    #[cfg(feature = "compression")]
+    fn test_compression() {
+        let plain_size = create_verbose_db("test-compression-plain", Compression::None).unwrap();
+        let size = create_verbose_db("test-compression", Compression::Lz4).unwrap();
+        assert!(size < plain_size, "{} >= {}", size, plain_size);
+
+        // the setting in the header page is used
+        let mut db = Database::open_file(mk_db_path("test-compression")).unwrap();
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), TEST_SIZE as u64 + 1);
+        let doc = collection.find_one(&doc! { "_id": 10 }).unwrap().unwrap();
+        assert_eq!(doc.get("address").unwrap().unwrap_string(), "somewhere over the rainbow, way up high");
+        let doc = collection.find_one(&doc! { "_id": TEST_SIZE as i64 }).unwrap().unwrap();
+        assert_eq!(doc.get("content").unwrap().unwrap_string().len(), 30000);
+
+        collection.delete(Some(&doc! { "_id": 10 })).unwrap();
+        assert_eq!(collection.count().unwrap(), TEST_SIZE as u64);
+        assert!(db.check_integrity().unwrap().is_ok());
+    }
+
+    #[test]
+    #[cfg(not(feature = "compression"))]
+    fn test_compression_not_supported() {
+        let result = create_verbose_db("test-compression-not-supported", Compression::Lz4);
+        assert!(matches!(result, Err(DbErr::NotSupported(_))));
+    }
+
+    fn insert_secrets(db: &mut Database) -> DbResult<()> {
+        let mut collection = db.create_collection("test")?;
+        for i in 0..TEST_SIZE {
+            let mut doc = doc! {
+                "_id": i as i64,
+                "content": format!("top secret {}", i),
+            };
+            collection.insert(&mut doc)?;
+        }
+        Ok(())
+    }
+
+    #[cfg(feature = "encryption")]
+    fn contains_secrets(path: &std::path::Path) -> bool {
+        let bytes = std::fs::read(path).unwrap_or_default();
+        bytes.windows(10).any(|window| window == b"top secret")
+    }
+
+    #[cfg(feature = "encryption")]
+    fn encrypted_config(key: [u8; 32]) -> Config {
+        Config {
+            encryption_key: Some(key),
+            ..Default::default()
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_encryption() {
+        let db_path = mk_db_path("test-encryption");
+        let journal_path = mk_journal_path("test-encryption");
+        {
+            let mut db = prepare_db_with_config("test-encryption", encrypted_config([1; 32])).unwrap();
+            insert_secrets(&mut db).unwrap();
+
+            // the pages are still in the journal
+            assert!(!contains_secrets(&journal_path));
+        }
+        assert!(!contains_secrets(&db_path));
+
+        let mut db = Database::open_file_with_config(&db_path, encrypted_config([1; 32])).unwrap();
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), TEST_SIZE as u64);
+        let doc = collection.find_one(&doc! { "_id": 10 }).unwrap().unwrap();
+        assert_eq!(doc.get("content").unwrap().unwrap_string(), "top secret 10");
+        assert!(db.check_integrity().unwrap().is_ok());
+
+        // the copy is encrypted with the same key
+        db.vacuum().unwrap();
+        assert!(!contains_secrets(&db_path));
+        assert!(db.check_integrity().unwrap().is_ok());
+        drop(db);
+
+        let result = Database::open_file_with_config(&db_path, encrypted_config([2; 32]));
+        assert!(matches!(result, Err(DbErr::DecryptionFailed(0))));
+
+        let result = Database::open_file(&db_path);
+        assert!(matches!(result, Err(DbErr::NotAValidDatabase)));
+
+        // an empty slot before the null page bar is not an empty page
+        let codec = crate::backend::PageCodec::new(NonZeroU32::new(4096).unwrap(), Some(&[1; 32]), false);
+        let mut file = std::fs::OpenOptions::new().write(true).open(&db_path).unwrap();
+        file.seek(SeekFrom::Start(codec.slot_offset(2))).unwrap();
+        file.write_all(&vec![0; codec.slot_size() as usize]).unwrap();
+        drop(file);
+
+        let mut db = Database::open_file_with_config(&db_path, encrypted_config([1; 32])).unwrap();
+        assert!(matches!(db.check_integrity(), Err(DbErr::DecryptionFailed(2))));
+    }
+
+    #[test]
+    #[cfg(not(feature = "encryption"))]
+    fn test_encryption_not_supported() {
+        let config = Config {
+            encryption_key: Some([1; 32]),
+            ..Default::default()
+        };
+        let result = prepare_db_with_config("test-encryption-not-supported", config);
+        assert!(matches!(result, Err(DbErr::NotSupported(_))));
+
+        let mut db = Database::open_memory().unwrap();
+        assert!(matches!(db.rekey(Some([1; 32])), Err(DbErr::NotSupported(_))));
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_rekey() {
+        let db_path = mk_db_path("test-rekey");
+        {
+            let mut db = prepare_db_with_config("test-rekey", encrypted_config([1; 32])).unwrap();
+            insert_secrets(&mut db).unwrap();
+
+            db.start_transaction(None).unwrap();
+            assert!(matches!(db.rekey(Some([2; 32])), Err(DbErr::Busy)));
+            db.rollback().unwrap();
+
+            db.rekey(Some([2; 32])).unwrap();
+
+            // the database is still usable after the file is replaced
+            let mut collection = db.collection("test").unwrap();
+            let mut doc = doc! { "_id": TEST_SIZE as i64, "content": "top secret" };
+            collection.insert(&mut doc).unwrap();
+        }
+        assert!(!contains_secrets(&db_path));
+
+        let result = Database::open_file_with_config(&db_path, encrypted_config([1; 32]));
+        assert!(matches!(result, Err(DbErr::DecryptionFailed(0))));
+
+        {
+            let mut db = Database::open_file_with_config(&db_path, encrypted_config([2; 32])).unwrap();
+            assert_eq!(db.collection("test").unwrap().count().unwrap(), TEST_SIZE as u64 + 1);
+            db.rekey(None).unwrap();
+        }
+        assert!(contains_secrets(&db_path));
+
+        let mut db = Database::open_file(&db_path).unwrap();
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), TEST_SIZE as u64 + 1);
+        assert!(db.check_integrity().unwrap().is_ok());
+    }
+
+    fn create_db_with_page_size(db_name: &str, config: Config) -> DbResult<()> {
+        let mut db = prepare_db_with_config(db_name, config)?;
+        insert_secrets(&mut db)?;
+        let mut collection = db.collection("test")?;
+        let mut doc = doc! {
+            "_id": TEST_SIZE as i64,
+            "content": "large ".repeat(20000),
+        };
+        collection.insert(&mut doc)?;
+        collection.delete(Some(&doc! { "_id": 10 }))?;
+        collection.delete(Some(&doc! { "_id": 0 }))?;
+        Ok(())
+    }
+
+    #[test]
+    fn test_page_size() {
+        for page_size in [1024, 16384, 65536] {
+            let db_name = format!("test-page-size-{}", page_size);
+            let db_path = mk_db_path(&db_name);
+
+            let config = Config {
+                page_size,
+                cache_size_pages: NonZeroUsize::new(2).unwrap(),
+                ..Default::default()
+            };
+            create_db_with_page_size(&db_name, config).unwrap();
+            assert_eq!(std::fs::metadata(&db_path).unwrap().len() % (page_size as u64), 0);
+
+            // the page size in the header page is used
+            let mut db = Database::open_file(&db_path).unwrap();
+            let mut collection = db.collection("test").unwrap();
+            assert_eq!(collection.count().unwrap(), TEST_SIZE as u64 - 1);
+            let doc = collection.find_one(&doc! { "_id": TEST_SIZE as i64 }).unwrap().unwrap();
+            assert_eq!(doc.get("content").unwrap().unwrap_string().len(), 120000);
+            assert!(db.check_integrity().unwrap().is_ok(), "page size: {}", page_size);
+        }
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_page_size_encrypted() {
+        let db_path = mk_db_path("test-page-size-encrypted");
+        let mut config = encrypted_config([1; 32]);
+        config.page_size = 16384;
+        create_db_with_page_size("test-page-size-encrypted", config).unwrap();
+
+        let mut db = Database::open_file_with_config(&db_path, encrypted_config([1; 32])).unwrap();
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), TEST_SIZE as u64 - 1);
+    }
+
+    #[test]
+    fn test_invalid_page_size() {
+        for page_size in [0, 512, 3000, 131072] {
+            let config = Config {
+                page_size,
+                ..Default::default()
+            };
+            let result = Database::open_memory_wht_config(config);
+            assert!(matches!(result, Err(DbErr::InvalidPageSize(size)) if size == page_size));
+        }
+    }
+
+    #[test]
+    #[cfg(not(feature = "mmap"))]
+    fn test_mmap_not_supported() {
+        let config = Config {
+            mmap: true,
+            ..Default::default()
+        };
+        let result = prepare_db_with_config("test-mmap-not-supported", config);
+        assert!(matches!(result, Err(DbErr::NotSupported(_))));
+    }
+
+    #[test]
+    #[cfg(feature = "mmap")]
+    fn test_mmap() {
+        let db_path = mk_db_path("test-mmap");
+        let mmap_config = || Config {
+            journal_full_size: 10,
+            cache_size_pages: NonZeroUsize::new(2).unwrap(),
+            mmap: true,
+            ..Default::default()
+        };
+        let mut db = prepare_db_with_config("test-mmap", mmap_config()).unwrap();
+        let mut reader = db.new_connection().unwrap();
+
+        // the file grows and is checkpointed while it's mapped
+        let mut collection = db.create_collection("test").unwrap();
+        for i in 0..1000 {
+            collection.insert(doc! { "_id": i, "content": i.to_string() }.as_mut()).unwrap();
+            if i % 100 == 0 {
+                assert_eq!(reader.collection("test").unwrap().count().unwrap(), i as u64 + 1);
+            }
+        }
+        for i in 0..500 {
+            collection.delete(Some(&doc! { "_id": i })).unwrap();
+        }
+        assert_eq!(reader.collection("test").unwrap().count().unwrap(), 500);
+        drop(reader);
+        drop(db);
+
+        let mut db = Database::open_file_with_config(&db_path, mmap_config()).unwrap();
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), 500);
+        let doc = collection.find_one(&doc! { "_id": 999 }).unwrap().unwrap();
+        assert_eq!(doc.get("content").unwrap().unwrap_string(), "999");
+        assert!(db.check_integrity().unwrap().is_ok());
+    }
+
+    const BLOB_PAGE_SIZE: u32 = 4096;
+
+    #[derive(Clone, Default)]
+    struct Blobs {
+        pages:   BTreeMap<u32, Vec<u8>>,
+        db_size: u64,
+    }
+
+    // stores the pages in a shared map, like an in-process blob store
+    struct BlobBackend {
+        store:       Arc<Mutex<Blobs>>,
+        transaction: Option<(TransactionType, Blobs)>,
+        savepoints:  Vec<Blobs>,
+    }
+
+    impl BlobBackend {
+
+        fn new(store: Arc<Mutex<Blobs>>) -> BlobBackend {
+            BlobBackend {
+                store,
+                transaction: None,
+                savepoints: Vec::new(),
+            }
+        }
+
+        fn blobs(&self) -> Blobs {
+            match &self.transaction {
+                Some((_, blobs)) => blobs.clone(),
+                None => self.store.lock().unwrap().clone(),
+            }
+        }
+
+        fn writing(&mut self) -> DbResult<&mut Blobs> {
+            match &mut self.transaction {
+                Some((TransactionType::Write, blobs)) => Ok(blobs),
+                _ => Err(DbErr::CannotWriteDbWithoutTransaction),
+            }
+        }
+
+    }
+
+    impl Backend for BlobBackend {
+
+        fn page_size(&self) -> NonZeroU32 {
+            NonZeroU32::new(BLOB_PAGE_SIZE).unwrap()
+        }
+
+        fn read_page(&self, page_id: u32) -> DbResult<RawPage> {
+            let mut page = RawPage::new(page_id, NonZeroU32::new(BLOB_PAGE_SIZE).unwrap());
+            let blobs = self.blobs();
+            if (page_id as u64 + 1) * (BLOB_PAGE_SIZE as u64) <= blobs.db_size {
+                if let Some(data) = blobs.pages.get(&page_id) {
+                    page.data = data.clone();
+                }
+            }
+            Ok(page)
+        }
+
+        fn write_page(&mut self, page: &RawPage) -> DbResult<()> {
+            let blobs = self.writing()?;
+            blobs.pages.insert(page.page_id, page.data.clone());
+            blobs.db_size = blobs.db_size.max((page.page_id as u64 + 1) * (BLOB_PAGE_SIZE as u64));
+            Ok(())
+        }
+
+        fn commit(&mut self) -> DbResult<()> {
+            let (_, blobs) = self.transaction.take().ok_or(DbErr::CannotWriteDbWithoutTransaction)?;
+            self.savepoints.clear();
+            *self.store.lock().unwrap() = blobs;
+            Ok(())
+        }
+
+        fn db_size(&self) -> u64 {
+            self.blobs().db_size
+        }
+
+        fn expand_db_size(&mut self, size: u64) -> DbResult<()> {
+            self.writing()?.db_size += size;
+            Ok(())
+        }
+
+        fn truncate(&mut self, size: u64) -> DbResult<()> {
+            let blobs = self.writing()?;
+            blobs.db_size = size;
+            blobs.pages.retain(|page_id, _| (*page_id as u64 + 1) * (BLOB_PAGE_SIZE as u64) <= size);
+            Ok(())
+        }
+
+        fn transaction_type(&self) -> Option<TransactionType> {
+            self.transaction.as_ref().map(|(ty, _)| *ty)
+        }
+
+        fn upgrade_read_transaction_to_write(&mut self) -> DbResult<()> {
+            let (ty, _) = self.transaction.as_mut().ok_or(DbErr::CannotWriteDbWithoutTransaction)?;
+            *ty = TransactionType::Write;
+            Ok(())
+        }
+
+        fn rollback(&mut self) -> DbResult<()> {
+            self.transaction.take().ok_or(DbErr::RollbackNotInTransaction)?;
+            self.savepoints.clear();
+            Ok(())
+        }
+
+        fn start_transaction(&mut self, ty: TransactionType) -> DbResult<()> {
+            if self.transaction.is_some() {
+                return Err(DbErr::StartTransactionInAnotherTransaction);
+            }
+            self.transaction = Some((ty, self.blobs()));
+            Ok(())
+        }
+
+        fn savepoint(&mut self) -> DbResult<()> {
+            let blobs = self.transaction.as_ref().ok_or(DbErr::CannotWriteDbWithoutTransaction)?.1.clone();
+            self.savepoints.push(blobs);
+            Ok(())
+        }
+
+        fn rollback_to_savepoint(&mut self, index: usize) -> DbResult<()> {
+            let blobs = self.savepoints[index].clone();
+            self.savepoints.truncate(index + 1);
+            self.transaction.as_mut().ok_or(DbErr::RollbackNotInTransaction)?.1 = blobs;
+            Ok(())
+        }
+
+        fn release_savepoint(&mut self, index: usize) -> DbResult<()> {
+            self.savepoints.truncate(index);
+            Ok(())
+        }
+
+    }
+
+    #[test]
+    fn test_custom_backend() {
+        let store = Arc::new(Mutex::new(Blobs::default()));
+        let mut db = Database::open_with_backend(BlobBackend::new(store.clone()), Config::default()).unwrap();
+
+        let mut collection = db.create_collection("test").unwrap();
+        for i in 0..TEST_SIZE as i64 {
+            collection.insert(doc! { "_id": i, "content": i.to_string() }.as_mut()).unwrap();
+        }
+
+        // the changes are discarded in the store
+        db.start_transaction(Some(TransactionType::Write)).unwrap();
+        db.collection("test").unwrap().delete(Some(&doc! { "_id": 0 })).unwrap();
+        db.rollback().unwrap();
+
+        assert!(matches!(db.new_connection(), Err(DbErr::NotSupported(_))));
+        assert!(matches!(db.rekey(Some([1; 32])), Err(DbErr::NotSupported(_))));
+        drop(db);
+
+        let mut db = Database::open_with_backend(BlobBackend::new(store), Config::default()).unwrap();
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), TEST_SIZE as u64);
+        let doc = collection.find_one(&doc! { "_id": 0 }).unwrap().unwrap();
+        assert_eq!(doc.get("content").unwrap().unwrap_string(), "0");
+        assert!(db.check_integrity().unwrap().is_ok());
+    }
+
+    // fails the writes and the commits when the budget of them is used up,
+    // like a storage which runs out of space
+    struct FaultyBackend {
+        inner:  BlobBackend,
+        budget: Arc<Mutex<Option<usize>>>,
+    }
+
+    impl FaultyBackend {
+
+        fn spend(&self) -> DbResult<()> {
+            match self.budget.lock().unwrap().as_mut() {
+                Some(0) => Err(DbErr::IOErr(Box::new(std::io::Error::other("injected fault")))),
+                Some(left) => {
+                    *left -= 1;
+                    Ok(())
+                }
+                None => Ok(()),
+            }
+        }
+
+    }
+
+    impl Backend for FaultyBackend {
+
+        fn page_size(&self) -> NonZeroU32 {
+            self.inner.page_size()
+        }
+
+        fn read_page(&self, page_id: u32) -> DbResult<RawPage> {
+            self.inner.read_page(page_id)
+        }
+
+        fn write_page(&mut self, page: &RawPage) -> DbResult<()> {
+            self.spend()?;
+            self.inner.write_page(page)
+        }
+
+        fn commit(&mut self) -> DbResult<()> {
+            if self.inner.transaction_type() == Some(TransactionType::Write) {
+                self.spend()?;
+            }
+            self.inner.commit()
+        }
+
+        fn db_size(&self) -> u64 {
+            self.inner.db_size()
+        }
+
+        fn expand_db_size(&mut self, size: u64) -> DbResult<()> {
+            self.spend()?;
+            self.inner.expand_db_size(size)
+        }
+
+        fn truncate(&mut self, size: u64) -> DbResult<()> {
+            self.inner.truncate(size)
+        }
+
+        fn transaction_type(&self) -> Option<TransactionType> {
+            self.inner.transaction_type()
+        }
+
+        fn upgrade_read_transaction_to_write(&mut self) -> DbResult<()> {
+            self.inner.upgrade_read_transaction_to_write()
+        }
+
+        fn rollback(&mut self) -> DbResult<()> {
+            self.inner.rollback()
+        }
+
+        fn start_transaction(&mut self, ty: TransactionType) -> DbResult<()> {
+            self.inner.start_transaction(ty)
+        }
+
+        fn savepoint(&mut self) -> DbResult<()> {
+            self.inner.savepoint()
+        }
+
+        fn rollback_to_savepoint(&mut self, index: usize) -> DbResult<()> {
+            self.inner.rollback_to_savepoint(index)
+        }
+
+        fn release_savepoint(&mut self, index: usize) -> DbResult<()> {
+            self.inner.release_savepoint(index)
+        }
+
+    }
+
+    #[test]
+    fn test_faulty_backend() {
+        let store = Arc::new(Mutex::new(Blobs::default()));
+        let budget = Arc::new(Mutex::new(None));
+        let backend = FaultyBackend {
+            inner: BlobBackend::new(store.clone()),
+            budget: budget.clone(),
+        };
+        let mut db = Database::open_with_backend(backend, Config::default()).unwrap();
+        let mut collection = db.create_collection("test").unwrap();
+        for i in 0..100 {
+            collection.insert(doc! { "_id": i, "content": i.to_string() }.as_mut()).unwrap();
+        }
+
+        // the transaction fails at every write and at the commit in turn,
+        // until the budget is large enough
+        let mut faults = 0;
+        loop {
+            *budget.lock().unwrap() = Some(faults);
+            let result = db.transaction(TransactionType::Write, |tx| {
+                let mut collection = tx.collection("test")?;
+                for i in 100..200 {
+                    collection.insert(doc! { "_id": i, "content": "large ".repeat(100) }.as_mut())?;
+                }
+                collection.delete(Some(&doc! { "_id": 0 }))?;
+                Ok(())
+            });
+            *budget.lock().unwrap() = None;
+
+            match result {
+                Ok(()) => break,
+                Err(DbErr::IOErr(_)) => (),
+                Err(err) => panic!("unexpected error: {}", err),
+            }
+
+            let mut collection = db.collection("test").unwrap();
+            assert_eq!(collection.count().unwrap(), 100);
+            assert!(collection.find_one(&doc! { "_id": 0 }).unwrap().is_some());
+            faults += 1;
+        }
+        assert!(faults > 10);
+        assert!(db.check_integrity().unwrap().is_ok());
+        drop(db);
+
+        let mut db = Database::open_with_backend(BlobBackend::new(store), Config::default()).unwrap();
+        assert_eq!(db.collection("test").unwrap().count().unwrap(), 199);
+        assert!(db.check_integrity().unwrap().is_ok());
+    }
+
+    #[test]
+    #[cfg(feature = "encryption")]
+    fn test_encryption_memory() {
+        let mut db = Database::open_memory_wht_config(encrypted_config([1; 32])).unwrap();
+        insert_secrets(&mut db).unwrap();
+
+        db.rekey(Some([2; 32])).unwrap();
+        db.rekey(None).unwrap();
+
+        let mut collection = db.collection("test").unwrap();
+        assert_eq!(collection.count().unwrap(), TEST_SIZE as u64);
+        let doc = collection.find_one(&doc! { "_id": 10 }).unwrap().unwrap();
+        assert_eq!(doc.get("content").unwrap().unwrap_string(), "top secret 10");
+        assert!(db.check_integrity().unwrap().is_ok());
+    }
+
+    #[test]
+    fn test_create_collection_with_number_pkey() {
+        let mut db = {
+            let mut db = prepare_db("test-number-pkey").unwrap();
+            let mut collection = db.create_collection("test").unwrap();
 
             for i in 0..TEST_SIZE {
                 let content = i.to_string();
@@ -969,6 +3647,36 @@ mod tests {
         assert_eq!(one.get("content").unwrap().unwrap_string(), "3");
     }
 
+    // false is less than true, like the other databases
+    #[test]
+    fn test_find_boolean() {
+        let mut db = prepare_db("test-find-boolean").unwrap();
+        let mut collection = db.create_collection("test").unwrap();
+        for i in 0..10 {
+            collection.insert(doc! { "_id": i, "flag": i % 2 == 1 }.as_mut()).unwrap();
+        }
+
+        let result = collection.find(&doc! { "flag": doc! { "$gt": false } }).unwrap();
+        assert_eq!(result.len(), 5);
+        assert!(result.iter().all(|doc| doc.get("flag").unwrap().unwrap_boolean()));
+
+        let result = collection.find(&doc! { "flag": doc! { "$lte": false } }).unwrap();
+        assert_eq!(result.len(), 5);
+        assert!(result.iter().all(|doc| !doc.get("flag").unwrap().unwrap_boolean()));
+
+        // the booleans as the primary keys
+        let mut collection = db.create_collection("flags").unwrap();
+        collection.insert(doc! { "_id": true }.as_mut()).unwrap();
+        collection.insert(doc! { "_id": false }.as_mut()).unwrap();
+        let result = collection.insert(doc! { "_id": true }.as_mut());
+        assert!(matches!(result, Err(DbErr::DataExist(_))));
+
+        let keys: Vec<bool> = collection.find_all().unwrap().iter()
+            .map(|doc| doc.get("_id").unwrap().unwrap_boolean())
+            .collect();
+        assert_eq!(keys, vec![false, true]);
+    }
+
     #[test]
     fn test_create_collection_and_find_by_pkey() {
         let mut db = create_and_return_db_with_items("test-find-pkey", 10);
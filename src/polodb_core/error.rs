@@ -117,7 +117,7 @@ pub enum DbErr {
     DataExist(String),
     PageSpaceNotEnough,
     DataHasNoPrimaryKey,
-    ChecksumMismatch,
+    JournalChecksumMismatch,
     JournalPageSizeMismatch(u32, u32),
     SaltMismatch,
     PageMagicMismatch(u32),
@@ -128,6 +128,7 @@ pub enum DbErr {
     CannotWriteDbWithoutTransaction,
     StartTransactionInAnotherTransaction,
     RollbackNotInTransaction,
+    SavepointNotFound(String),
     IllegalCollectionName(String),
     UnexpectedHeaderForBtreePage(Box<UnexpectedHeader>),
     KeyTypeOfBtreeShouldNotBeZero,
@@ -144,6 +145,15 @@ pub enum DbErr {
     NotAValidDatabase,
     Busy,
     DatabaseOccupied,
+    NotSupported(&'static str),
+    DatabaseClosed,
+    DecompressionFailed(String),
+    DecryptionFailed(u32),
+    ChecksumMismatch(u32),
+    InvalidPageSize(u32),
+    ReadOnly,
+    InvalidJournalFrames(&'static str),
+    InvalidRestoreTarget(&'static str),
     Multiple(Vec<DbErr>),
     VersionMismatch(Box<VersionMismatchError>),
     EnumError(Box<num_enum::TryFromPrimitiveError<MsgTy>>),
@@ -198,7 +208,7 @@ impl fmt::Display for DbErr {
             DbErr::DataExist(value) => write!(f, "item with primary key exists, key: {}", value),
             DbErr::PageSpaceNotEnough => write!(f, "the space of page is not enough"),
             DbErr::DataHasNoPrimaryKey => write!(f, "DataHasNoPrimaryKey"),
-            DbErr::ChecksumMismatch => write!(f, "journal's checksum is mismatch with data, database maybe corrupt"),
+            DbErr::JournalChecksumMismatch => write!(f, "journal's checksum is mismatch with data, database maybe corrupt"),
             DbErr::JournalPageSizeMismatch(expect, actual) => {
                 write!(f, "journal's page size is mismatch with database. expect:{}, actual: {}", expect, actual)
             },
@@ -211,6 +221,7 @@ impl fmt::Display for DbErr {
             DbErr::CannotWriteDbWithoutTransaction => write!(f, "cannot write database without transaction"),
             DbErr::StartTransactionInAnotherTransaction => write!(f, "start transaction in another transaction"),
             DbErr::RollbackNotInTransaction => write!(f, "can not rollback because not in transaction"),
+            DbErr::SavepointNotFound(name) => write!(f, "savepoint \"{}\" not found", name),
             DbErr::IllegalCollectionName(name) => write!(f, "collection name \"{}\" is illegal", name),
             DbErr::UnexpectedHeaderForBtreePage(err) => write!(f, "unexpected header for btree page: {}", err),
             DbErr::KeyTypeOfBtreeShouldNotBeZero => write!(f, "key type of btree should not be zero"),
@@ -227,6 +238,16 @@ impl fmt::Display for DbErr {
             DbErr::UnableToUpdatePrimaryKey => write!(f, "it's illegal to update '_id' field"),
             DbErr::NotAValidDatabase => write!(f, "the file is not a valid database"),
             DbErr::DatabaseOccupied => write!(f, "this file is occupied by another connection"),
+            DbErr::NotSupported(op) => write!(f, "{} is not supported", op),
+            DbErr::DatabaseClosed => write!(f, "the database has been closed"),
+            DbErr::DecompressionFailed(reason) => write!(f, "failed to decompress the data: {}", reason),
+            DbErr::DecryptionFailed(page_id) => write!(f, "failed to decrypt page {}, the key may be wrong", page_id),
+            DbErr::ChecksumMismatch(page_id) => write!(f, "the checksum of page {} is mismatched, the file may be damaged", page_id),
+            DbErr::InvalidPageSize(page_size) =>
+                write!(f, "invalid page size {}, it should be a power of two between 1 KiB and 64 KiB", page_size),
+            DbErr::ReadOnly => write!(f, "the database is opened in read-only mode"),
+            DbErr::InvalidJournalFrames(reason) => write!(f, "invalid journal frames: {}", reason),
+            DbErr::InvalidRestoreTarget(reason) => write!(f, "invalid restore target: {}", reason),
             DbErr::Multiple(errors) => {
                 for (i, err) in errors.iter().enumerate() {
                     writeln!(f, "Multiple errors:")?;
@@ -0,0 +1,25 @@
+//! The entries of the fuzz targets in `fuzz/`,
+//! which can't get access to the private parsers of the crate.
+//!
+//! Enabled by the `fuzzing` feature.
+use std::num::NonZeroU32;
+use crate::{Config, DbContext};
+use crate::page::RawPage;
+
+/// Parse the bytes as a page of a B-tree node.
+///
+/// The errors are ignored, only the panics are reported by the fuzzer.
+pub fn parse_btree_node(data: &[u8]) {
+    let config = Config {
+        page_size: 1024,
+        ..Default::default()
+    };
+    let page_size = NonZeroU32::new(config.page_size).unwrap();
+    let mut ctx = DbContext::open_memory(config).expect("open memory database");
+
+    let mut page = RawPage::new(1, page_size);
+    let len = data.len().min(page_size.get() as usize);
+    page.data[0..len].copy_from_slice(&data[0..len]);
+
+    let _ = ctx.parse_btree_node(&page);
+}
@@ -0,0 +1,726 @@
+use std::fmt;
+use std::cmp::Ordering;
+use hashbrown::{HashMap, HashSet};
+use polodb_bson::{Document, Value};
+use crate::{DbResult, DbErr};
+use crate::SerializeType;
+use crate::btree::{BTreeNode, HEADER_SIZE, ITEM_SIZE};
+use crate::data_ticket::DataTicket;
+use crate::meta_doc_helper::meta_doc_key;
+use crate::page::{RawPage, PageType, FreeListDataWrapper};
+use crate::page::header_page_wrapper::HeaderPageWrapper;
+use crate::page::data_page_wrapper::DataPageWrapper;
+use crate::page::large_data_page_wrapper::LargeDataPageWrapper;
+use crate::page_handler::PageHandler;
+use crate::stats::PageCounts;
+
+/// The kind of a page, decided by the structure referring to it.
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
+pub enum PageKind {
+    BTree,
+    Data,
+    LargeData,
+    FreeList,
+}
+
+impl PageKind {
+
+    fn magic(self) -> [u8; 2] {
+        let page_type = match self {
+            PageKind::BTree => PageType::BTreeNode,
+            PageKind::Data => PageType::Data,
+            PageKind::LargeData => PageType::LargeData,
+            PageKind::FreeList => PageType::FreeList,
+        };
+        page_type.to_magic()
+    }
+
+}
+
+/// A problem found by [Database::check_integrity](crate::Database::check_integrity).
+#[derive(Debug, Clone)]
+pub enum IntegrityIssue {
+    InvalidHeader(String),
+
+    /// The page id is not allocated, or it's the header page.
+    PageOutOfRange { page_id: u32, referenced_by: u32 },
+
+    PageMagicMismatch { page_id: u32, expected: PageKind, actual: [u8; 2] },
+
+    /// The page is referred by two structures, or twice by the same one.
+    PageReferencedTwice { page_id: u32 },
+
+    InvalidBTreeNode { page_id: u32, reason: String },
+
+    /// The key of the item is not in order with the keys around it.
+    KeyOutOfOrder { page_id: u32, index: u32 },
+
+    /// The data of the item in the B-tree node can not be read.
+    InvalidDataTicket { page_id: u32, index: u32, ticket: String, reason: String },
+
+    InvalidCollectionMeta { page_id: u32, index: u32 },
+
+    /// The items in the data page referred by no ticket.
+    UnreferencedData { page_id: u32, count: u32 },
+
+    DuplicateFreePage { page_id: u32 },
+
+    /// The page is in the free list, but used by the database.
+    FreePageInUse { page_id: u32 },
+
+    /// The page is neither used nor in the free list.
+    OrphanPage { page_id: u32 },
+
+    /// The checksum of the page is mismatched, see [Config::page_checksum](crate::Config::page_checksum).
+    ChecksumMismatch { page_id: u32 },
+}
+
+impl fmt::Display for IntegrityIssue {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            IntegrityIssue::InvalidHeader(reason) =>
+                write!(f, "invalid header: {}", reason),
+
+            IntegrityIssue::PageOutOfRange { page_id, referenced_by } =>
+                write!(f, "page {} referenced by page {} is out of range", page_id, referenced_by),
+
+            IntegrityIssue::PageMagicMismatch { page_id, expected, actual } =>
+                write!(f, "page {} should be {:?}, but the magic is {:02X} {:02X}", page_id, expected, actual[0], actual[1]),
+
+            IntegrityIssue::PageReferencedTwice { page_id } =>
+                write!(f, "page {} is referenced twice", page_id),
+
+            IntegrityIssue::InvalidBTreeNode { page_id, reason } =>
+                write!(f, "invalid btree node {}: {}", page_id, reason),
+
+            IntegrityIssue::KeyOutOfOrder { page_id, index } =>
+                write!(f, "the key of item {} in page {} is out of order", index, page_id),
+
+            IntegrityIssue::InvalidDataTicket { page_id, index, ticket, reason } =>
+                write!(f, "invalid {} of item {} in page {}: {}", ticket, index, page_id, reason),
+
+            IntegrityIssue::InvalidCollectionMeta { page_id, index } =>
+                write!(f, "invalid collection meta of item {} in page {}", index, page_id),
+
+            IntegrityIssue::UnreferencedData { page_id, count } =>
+                write!(f, "{} items in data page {} are not referenced", count, page_id),
+
+            IntegrityIssue::DuplicateFreePage { page_id } =>
+                write!(f, "page {} is in the free list twice", page_id),
+
+            IntegrityIssue::FreePageInUse { page_id } =>
+                write!(f, "page {} is in the free list, but it's in use", page_id),
+
+            IntegrityIssue::OrphanPage { page_id } =>
+                write!(f, "page {} is neither used nor free", page_id),
+
+            IntegrityIssue::ChecksumMismatch { page_id } =>
+                write!(f, "the checksum of page {} is mismatched", page_id),
+        }
+    }
+
+}
+
+/// The result of [Database::check_integrity](crate::Database::check_integrity).
+#[derive(Debug, Clone, Default)]
+pub struct IntegrityReport {
+    /// The count of the allocated pages, including the header page.
+    pub page_count:       u32,
+    pub free_page_count:  u32,
+    pub collection_count: u32,
+    pub document_count:   u64,
+    pub issues:           Vec<IntegrityIssue>,
+}
+
+impl IntegrityReport {
+
+    #[inline]
+    pub fn is_ok(&self) -> bool {
+        self.issues.is_empty()
+    }
+
+}
+
+impl fmt::Display for IntegrityReport {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "pages: {}", self.page_count)?;
+        writeln!(f, "free pages: {}", self.free_page_count)?;
+        writeln!(f, "collections: {}", self.collection_count)?;
+        writeln!(f, "documents: {}", self.document_count)?;
+        if self.is_ok() {
+            return write!(f, "ok");
+        }
+        write!(f, "issues: {}", self.issues.len())?;
+        for issue in &self.issues {
+            write!(f, "\n  {}", issue)?;
+        }
+        Ok(())
+    }
+
+}
+
+// the root pid of the btree of a collection, or an index
+struct BTreeRoot {
+    pid:            u32,
+    is_collection:  bool,
+}
+
+// the pages and the items of a B-tree, see IntegrityChecker::measure_btree
+pub(crate) struct BTreeMeasure {
+    pub(crate) depth:       u32,
+    pub(crate) item_count:  u64,
+
+    // the size of the items as they are stored, after they are compressed
+    pub(crate) stored_size: u64,
+
+    // the data pages are counted even if they are shared with the other B-trees
+    pub(crate) pages:       PageCounts,
+    pub(crate) data_pages:  HashSet<u32>,
+}
+
+// Walk all the structures from the header page, every page
+// should be used only once, or be in the free list.
+//
+// The damaged pages are not parsed by the page wrappers,
+// which assume the data is valid and may panic.
+pub(crate) struct IntegrityChecker<'a> {
+    page_handler:   &'a mut PageHandler,
+    serialize_type: SerializeType,
+    item_size:      u32,
+    null_page_bar:  u32,
+    used_pages:     HashMap<u32, PageKind>,
+    page_counts:    PageCounts,
+
+    // data page id => count of the tickets referring to it
+    data_refs:      HashMap<u32, u32>,
+    tickets:        HashSet<(u32, u16)>,
+    free_pages:     HashSet<u32>,
+    report:         IntegrityReport,
+
+    // collected by the walk of a B-tree for measure_btree
+    btree_depth:    u32,
+    stored_size:    u64,
+    walk_data_pages: HashSet<u32>,
+}
+
+impl<'a> IntegrityChecker<'a> {
+
+    pub(crate) fn new(page_handler: &'a mut PageHandler, serialize_type: SerializeType) -> IntegrityChecker<'a> {
+        let item_size = (page_handler.page_size.get() - HEADER_SIZE) / ITEM_SIZE;
+        IntegrityChecker {
+            page_handler,
+            serialize_type,
+            item_size,
+            null_page_bar: 0,
+            used_pages: HashMap::new(),
+            page_counts: PageCounts::default(),
+            data_refs: HashMap::new(),
+            tickets: HashSet::new(),
+            free_pages: HashSet::new(),
+            report: IntegrityReport::default(),
+            btree_depth: 0,
+            stored_size: 0,
+            walk_data_pages: HashSet::new(),
+        }
+    }
+
+    pub(crate) fn check(mut self) -> DbResult<IntegrityReport> {
+        let header = self.start()?;
+
+        let meta_pid = header.get_meta_page_id();
+        if !self.is_in_range(meta_pid) {
+            self.add_issue(IntegrityIssue::PageOutOfRange { page_id: meta_pid, referenced_by: 0 });
+        } else {
+            let roots = self.check_meta_btree(meta_pid)?;
+            for root in roots {
+                self.check_btree(root.pid, root.is_collection)?;
+            }
+        }
+
+        self.check_data_pages()?;
+        self.check_unused_pages();
+
+        Ok(self.report)
+    }
+
+    // read the header page and the free list, before the B-trees are walked
+    pub(crate) fn start(&mut self) -> DbResult<HeaderPageWrapper> {
+        let header = HeaderPageWrapper::from_raw_page(self.page_handler.pipeline_read_page(0)?);
+
+        // the allocated pages may be not written yet,
+        // so the bar can be greater than the size of the database
+        self.null_page_bar = header.get_null_page_bar();
+        self.report.page_count = self.null_page_bar;
+
+        self.check_free_list(&header)?;
+
+        Ok(header)
+    }
+
+    // walk a B-tree as it's checked, the callback is called with the documents of the items,
+    // the pages which have been walked before are not counted again
+    pub(crate) fn measure_btree<F>(&mut self, root_pid: u32, f: &mut F) -> DbResult<BTreeMeasure>
+    where
+        F: FnMut(Document)
+    {
+        let before = self.page_counts;
+        self.btree_depth = 0;
+        self.stored_size = 0;
+        self.walk_data_pages.clear();
+
+        let mut item_count: u64 = 0;
+        if self.is_in_range(root_pid) {
+            self.walk_btree(root_pid, 0, 1, None, None, &mut |_, _, doc| {
+                item_count += 1;
+                f(doc);
+            })?;
+        }
+
+        Ok(BTreeMeasure {
+            depth: self.btree_depth,
+            item_count,
+            stored_size: self.stored_size,
+            pages: PageCounts {
+                btree: self.page_counts.btree - before.btree,
+                data: self.walk_data_pages.len() as u32,
+                large_data: self.page_counts.large_data - before.large_data,
+                free_list: 0,
+            },
+            data_pages: std::mem::take(&mut self.walk_data_pages),
+        })
+    }
+
+    // the pages used by all the structures walked so far
+    #[inline]
+    pub(crate) fn page_counts(&self) -> PageCounts {
+        self.page_counts
+    }
+
+    #[inline]
+    pub(crate) fn free_page_count(&self) -> u32 {
+        self.report.free_page_count
+    }
+
+    #[inline]
+    pub(crate) fn page_handler(&mut self) -> &mut PageHandler {
+        self.page_handler
+    }
+
+    #[inline]
+    fn add_issue(&mut self, issue: IntegrityIssue) {
+        self.report.issues.push(issue);
+    }
+
+    #[inline]
+    fn is_in_range(&self, page_id: u32) -> bool {
+        page_id != 0 && page_id < self.null_page_bar
+    }
+
+    // return false if the page is used by another structure
+    fn mark_page(&mut self, page_id: u32, kind: PageKind) -> bool {
+        match self.used_pages.get(&page_id) {
+            Some(PageKind::Data) if kind == PageKind::Data => true,
+            Some(_) => {
+                self.add_issue(IntegrityIssue::PageReferencedTwice { page_id });
+                false
+            }
+            None => {
+                self.used_pages.insert(page_id, kind);
+                match kind {
+                    PageKind::BTree => self.page_counts.btree += 1,
+                    PageKind::Data => self.page_counts.data += 1,
+                    PageKind::LargeData => self.page_counts.large_data += 1,
+                    PageKind::FreeList => self.page_counts.free_list += 1,
+                }
+                true
+            }
+        }
+    }
+
+    fn check_magic(&mut self, page: &RawPage, kind: PageKind) -> bool {
+        let actual = [page.data[0], page.data[1]];
+        let expected = kind.magic();
+        if actual == expected {
+            return true;
+        }
+        self.add_issue(IntegrityIssue::PageMagicMismatch {
+            page_id: page.page_id,
+            expected: kind,
+            actual,
+        });
+        false
+    }
+
+    fn add_free_page(&mut self, page_id: u32, referenced_by: u32) {
+        if !self.is_in_range(page_id) {
+            self.add_issue(IntegrityIssue::PageOutOfRange { page_id, referenced_by });
+            return;
+        }
+        if !self.free_pages.insert(page_id) {
+            self.add_issue(IntegrityIssue::DuplicateFreePage { page_id });
+        }
+    }
+
+    fn check_free_list(&mut self, header: &HeaderPageWrapper) -> DbResult<()> {
+        let free_list_size = header.get_free_list_size();
+        if (free_list_size as usize) > header.free_list_capacity() {
+            self.add_issue(IntegrityIssue::InvalidHeader(format!(
+                "the size of the free list {} is too large", free_list_size)));
+        } else {
+            for index in 0..free_list_size {
+                let page_id = header.get_free_list_content(index);
+                self.add_free_page(page_id, 0);
+            }
+        }
+
+        let size_cap = (self.page_handler.page_size.get() - 16) / 4;
+        let mut referenced_by = 0;
+        let mut next_pid = header.get_free_list_page_id();
+        while next_pid != 0 {
+            if !self.is_in_range(next_pid) {
+                self.add_issue(IntegrityIssue::PageOutOfRange { page_id: next_pid, referenced_by });
+                break;
+            }
+            if !self.mark_page(next_pid, PageKind::FreeList) {
+                break;
+            }
+
+            let page = match self.read_page(next_pid)? {
+                Some(page) => page,
+                None => break,
+            };
+            if !self.check_magic(&page, PageKind::FreeList) {
+                break;
+            }
+
+            let wrapper = FreeListDataWrapper::from_raw(page);
+            let size = wrapper.size();
+            if size > size_cap {
+                self.add_issue(IntegrityIssue::InvalidHeader(format!(
+                    "the size {} of free list page {} is too large", size, next_pid)));
+                break;
+            }
+            for index in 0..size {
+                let page_id = wrapper.borrow_page().get_u32(16 + index * 4);
+                self.add_free_page(page_id, next_pid);
+            }
+
+            referenced_by = next_pid;
+            next_pid = wrapper.next_pid();
+        }
+
+        self.report.free_page_count = self.free_pages.len() as u32;
+
+        Ok(())
+    }
+
+    // the meta btree is checked as the btree of a collection,
+    // and the roots of the collections and the indexes are collected
+    fn check_meta_btree(&mut self, meta_pid: u32) -> DbResult<Vec<BTreeRoot>> {
+        let mut items = vec![];
+        self.walk_btree(meta_pid, 0, 1, None, None, &mut |page_id, index, doc| {
+            items.push((page_id, index, doc));
+        })?;
+
+        let mut roots = vec![];
+        for (page_id, index, doc) in items {
+            let root_pid = match doc.get(meta_doc_key::ROOT_PID) {
+                Some(Value::Int(root_pid)) if doc.get(meta_doc_key::NAME).is_some() => *root_pid as u32,
+                _ => {
+                    self.add_issue(IntegrityIssue::InvalidCollectionMeta { page_id, index });
+                    continue;
+                }
+            };
+            roots.push(BTreeRoot {
+                pid: root_pid,
+                is_collection: true,
+            });
+            self.report.collection_count += 1;
+
+            if let Some(Value::Document(indexes)) = doc.get(meta_doc_key::INDEXES) {
+                for (_, options) in indexes.iter() {
+                    match options {
+                        Value::Document(options) => match options.get(meta_doc_key::index::ROOT_PID) {
+                            Some(Value::Int(root_pid)) => roots.push(BTreeRoot {
+                                pid: *root_pid as u32,
+                                is_collection: false,
+                            }),
+                            _ => self.add_issue(IntegrityIssue::InvalidCollectionMeta { page_id, index }),
+                        },
+                        _ => self.add_issue(IntegrityIssue::InvalidCollectionMeta { page_id, index }),
+                    }
+                }
+            }
+        }
+
+        Ok(roots)
+    }
+
+    fn check_btree(&mut self, root_pid: u32, is_collection: bool) -> DbResult<()> {
+        if !self.is_in_range(root_pid) {
+            self.add_issue(IntegrityIssue::PageOutOfRange { page_id: root_pid, referenced_by: 0 });
+            return Ok(());
+        }
+
+        let mut count: u64 = 0;
+        self.walk_btree(root_pid, 0, 1, None, None, &mut |_, _, _| count += 1)?;
+
+        if is_collection {
+            self.report.document_count += count;
+        }
+
+        Ok(())
+    }
+
+    // the keys of the node should be in (lower, upper),
+    // the callback is called with the documents of the items
+    fn walk_btree<F>(&mut self, pid: u32, parent_pid: u32, depth: u32, lower: Option<&Value>, upper: Option<&Value>, f: &mut F) -> DbResult<()>
+    where
+        F: FnMut(u32, u32, Document)
+    {
+        if !self.mark_page(pid, PageKind::BTree) {
+            return Ok(());
+        }
+        self.btree_depth = self.btree_depth.max(depth);
+
+        let page = match self.read_page(pid)? {
+            Some(page) => page,
+            None => return Ok(()),
+        };
+        if page.data[0..2] == [0, 0] {
+            // an empty btree is a null page
+            if parent_pid != 0 {
+                self.add_issue(IntegrityIssue::InvalidBTreeNode {
+                    page_id: pid,
+                    reason: format!("the child of page {} is empty", parent_pid),
+                });
+            }
+            return Ok(());
+        }
+        if !self.check_magic(&page, PageKind::BTree) {
+            return Ok(());
+        }
+
+        let len = page.get_u16(2) as u32;
+        if len > self.item_size {
+            self.add_issue(IntegrityIssue::InvalidBTreeNode {
+                page_id: pid,
+                reason: format!("the count of the items {} is too large", len),
+            });
+            return Ok(());
+        }
+
+        let mut keys: Vec<Option<Value>> = Vec::with_capacity(len as usize);
+        let mut children = vec![ page.get_u32(4) ];
+
+        for index in 0..len {
+            let offset = HEADER_SIZE + index * ITEM_SIZE;
+            children.push(page.get_u32(offset));
+
+            let ticket = BTreeNode::parse_data_item_ticket(&page, offset);
+            let doc = match self.check_ticket(&ticket) {
+                Ok(doc) => doc,
+                Err(reason) => {
+                    self.add_issue(IntegrityIssue::InvalidDataTicket {
+                        page_id: pid,
+                        index,
+                        ticket: ticket.to_string(),
+                        reason,
+                    });
+                    keys.push(None);
+                    continue;
+                }
+            };
+
+            let key = match BTreeNode::parse_node_data_item(&page, offset, self.page_handler) {
+                Ok(item) => item.key,
+                Err(err) => {
+                    self.add_issue(IntegrityIssue::InvalidBTreeNode {
+                        page_id: pid,
+                        reason: format!("the key of item {} is invalid: {}", index, err),
+                    });
+                    keys.push(None);
+                    continue;
+                }
+            };
+
+            let is_matched = match doc.pkey_id() {
+                Some(pkey) => matches!(pkey.value_cmp(&key), Ok(Ordering::Equal)),
+                None => false,
+            };
+            if !is_matched {
+                self.add_issue(IntegrityIssue::InvalidDataTicket {
+                    page_id: pid,
+                    index,
+                    ticket: ticket.to_string(),
+                    reason: "the _id of the document is not the key".into(),
+                });
+            }
+
+            let lower_key = keys.iter().rev().find_map(|key| key.as_ref()).or(lower);
+            if !is_key_in_range(&key, lower_key, upper) {
+                self.add_issue(IntegrityIssue::KeyOutOfOrder { page_id: pid, index });
+            }
+
+            f(pid, index, doc);
+            keys.push(Some(key));
+        }
+
+        let is_leaf = children[0] == 0;
+        if children.iter().any(|child| (*child == 0) != is_leaf) {
+            self.add_issue(IntegrityIssue::InvalidBTreeNode {
+                page_id: pid,
+                reason: "some of the children are missing".into(),
+            });
+            return Ok(());
+        }
+        if is_leaf {
+            return Ok(());
+        }
+
+        for (index, child) in children.iter().enumerate() {
+            if !self.is_in_range(*child) {
+                self.add_issue(IntegrityIssue::PageOutOfRange { page_id: *child, referenced_by: pid });
+                continue;
+            }
+
+            // the bounds are the nearest valid keys around the child
+            let child_lower = keys[..index].iter().rev().find_map(|key| key.as_ref()).or(lower);
+            let child_upper = keys[index..].iter().find_map(|key| key.as_ref()).or(upper);
+            let (child_lower, child_upper) = (child_lower.cloned(), child_upper.cloned());
+            self.walk_btree(*child, pid, depth + 1, child_lower.as_ref(), child_upper.as_ref(), f)?;
+        }
+
+        Ok(())
+    }
+
+    fn check_ticket(&mut self, ticket: &DataTicket) -> Result<Document, String> {
+        let bytes = if ticket.is_large_data() {
+            self.read_large_data(ticket.pid)?
+        } else {
+            self.read_data(ticket)?
+        };
+        self.stored_size += bytes.len() as u64;
+
+        let bytes = crate::compression::decompress(self.page_handler.compression, &bytes)
+            .map_err(|err| format!("the document is broken: {}", err))?;
+        let mut reader: &[u8] = bytes.as_ref();
+        crate::doc_serializer::deserialize(self.serialize_type, &mut reader)
+            .map_err(|err| format!("the document is broken: {}", err))
+    }
+
+    // a damaged page is reported, instead of failing the whole check
+    fn read_page(&mut self, page_id: u32) -> DbResult<Option<RawPage>> {
+        match self.page_handler.pipeline_read_page(page_id) {
+            Ok(page) => Ok(Some(page)),
+            Err(DbErr::ChecksumMismatch(page_id)) => {
+                self.add_issue(IntegrityIssue::ChecksumMismatch { page_id });
+                Ok(None)
+            }
+            Err(err) => Err(err),
+        }
+    }
+
+    fn read_page_for_ticket(&mut self, page_id: u32, kind: PageKind) -> Result<RawPage, String> {
+        if !self.is_in_range(page_id) {
+            return Err(format!("page {} is out of range", page_id));
+        }
+        if !self.mark_page(page_id, kind) {
+            return Err(format!("page {} is used by another structure", page_id));
+        }
+
+        let page = self.page_handler.pipeline_read_page(page_id)
+            .map_err(|err| err.to_string())?;
+        if !self.check_magic(&page, kind) {
+            return Err(format!("page {} is not a {:?} page", page_id, kind));
+        }
+
+        Ok(page)
+    }
+
+    fn read_data(&mut self, ticket: &DataTicket) -> Result<Vec<u8>, String> {
+        let page = self.read_page_for_ticket(ticket.pid, PageKind::Data)?;
+        *self.data_refs.entry(ticket.pid).or_insert(0) += 1;
+        self.walk_data_pages.insert(ticket.pid);
+
+        if !self.tickets.insert((ticket.pid, ticket.index)) {
+            return Err("the data is referenced twice".into());
+        }
+
+        let wrapper = DataPageWrapper::from_raw(page);
+        match wrapper.checked_get(ticket.index as u32)? {
+            Some(bytes) => Ok(bytes.to_vec()),
+            None => Err("the data has been removed".into()),
+        }
+    }
+
+    fn read_large_data(&mut self, pid: u32) -> Result<Vec<u8>, String> {
+        let mut bytes = vec![];
+        let mut next_pid = pid;
+
+        while next_pid != 0 {
+            let page = self.read_page_for_ticket(next_pid, PageKind::LargeData)?;
+            let wrapper = LargeDataPageWrapper::from_raw(page);
+            if wrapper.data_len() > wrapper.max_data_cap() {
+                return Err(format!("the data length of large data page {} is too large", next_pid));
+            }
+
+            wrapper.write_to_buffer(&mut bytes);
+            next_pid = wrapper.next_pid();
+        }
+
+        Ok(bytes)
+    }
+
+    // every item in the data pages should be referenced once
+    fn check_data_pages(&mut self) -> DbResult<()> {
+        let mut data_refs: Vec<(u32, u32)> = self.data_refs.iter()
+            .map(|(page_id, count)| (*page_id, *count))
+            .collect();
+        data_refs.sort_unstable();
+
+        for (page_id, ref_count) in data_refs {
+            let page = match self.read_page(page_id)? {
+                Some(page) => page,
+                None => continue,
+            };
+            let wrapper = DataPageWrapper::from_raw(page);
+            let data_len = wrapper.data_len();
+            if data_len > ref_count {
+                self.add_issue(IntegrityIssue::UnreferencedData {
+                    page_id,
+                    count: data_len - ref_count,
+                });
+            }
+        }
+
+        Ok(())
+    }
+
+    fn check_unused_pages(&mut self) {
+        for page_id in 1..self.null_page_bar {
+            let is_used = self.used_pages.contains_key(&page_id);
+            let is_free = self.free_pages.contains(&page_id);
+            if is_used && is_free {
+                self.add_issue(IntegrityIssue::FreePageInUse { page_id });
+            } else if !is_used && !is_free {
+                self.add_issue(IntegrityIssue::OrphanPage { page_id });
+            }
+        }
+    }
+
+}
+
+fn is_key_in_range(key: &Value, lower: Option<&Value>, upper: Option<&Value>) -> bool {
+    let after_lower = match lower {
+        Some(lower) => matches!(key.value_cmp(lower), Ok(Ordering::Greater)),
+        None => true,
+    };
+    let before_upper = match upper {
+        Some(upper) => matches!(key.value_cmp(upper), Ok(Ordering::Less)),
+        None => true,
+    };
+    after_lower && before_upper
+}
@@ -29,25 +29,40 @@ mod error;
 mod cursor;
 
 pub mod db;
+mod shared_db;
+#[cfg(feature = "async")]
+mod async_db;
 mod data_ticket;
 mod index_ctx;
 mod meta_doc_helper;
 mod context;
 mod db_handle;
 pub mod dump;
+pub mod integrity;
+pub mod stats;
+pub mod metrics;
+pub mod salvage;
+pub mod restore;
 mod config;
 mod macros;
 mod file_lock;
-mod backend;
+pub mod backend;
 mod transaction;
 mod page_handler;
 mod migration;
 mod doc_serializer;
+mod compression;
 pub mod msg_ty;
+#[cfg(feature = "fuzzing")]
+pub mod fuzzing;
 
 pub use db::{Database, DbResult};
-pub use config::Config;
+pub use shared_db::{SharedDatabase, Session};
+#[cfg(feature = "async")]
+pub use async_db::{AsyncDatabase, AsyncCollection, DocumentStream};
+pub use config::{Config, Synchronous, AutoCheckpoint};
 pub use doc_serializer::SerializeType;
+pub use compression::Compression;
 pub use transaction::TransactionType;
 pub use context::DbContext;
 pub use db_handle::DbHandle;
@@ -0,0 +1,76 @@
+use std::fmt;
+
+// the names of the counters in the facade of the metrics crate
+pub(crate) const PAGE_READS: &str = "polodb.page_reads";
+pub(crate) const PAGE_WRITES: &str = "polodb.page_writes";
+pub(crate) const CACHE_HITS: &str = "polodb.cache_hits";
+pub(crate) const CACHE_MISSES: &str = "polodb.cache_misses";
+pub(crate) const JOURNAL_FRAMES: &str = "polodb.journal_frames";
+pub(crate) const CHECKPOINTS: &str = "polodb.checkpoints";
+pub(crate) const TRANSACTIONS_COMMITTED: &str = "polodb.transactions_committed";
+pub(crate) const TRANSACTIONS_ROLLED_BACK: &str = "polodb.transactions_rolled_back";
+pub(crate) const READ_TRANSACTIONS: &str = "polodb.read_transactions";
+pub(crate) const VM_INSTRUCTIONS: &str = "polodb.vm_instructions";
+
+/// A snapshot of the counters, see [Database::metrics](crate::Database::metrics).
+///
+/// The counters start from zero when the database is opened. The journal
+/// counters are shared by all the connections of the database file,
+/// the others are counted by the connection only.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct Metrics {
+    /// The pages read by the database, including the pages in the cache.
+    pub page_reads:               u64,
+    pub page_writes:              u64,
+    pub cache_hits:               u64,
+    pub cache_misses:             u64,
+    pub journal_frames_appended:  u64,
+    pub checkpoints:              u64,
+
+    /// The write transactions committed.
+    pub transactions_committed:   u64,
+    pub transactions_rolled_back: u64,
+
+    /// The read transactions finished, they are not counted as commits.
+    pub read_transactions:        u64,
+    pub vm_instructions:          u64,
+}
+
+impl Metrics {
+
+    /// The ratio of the page reads served by the cache.
+    pub fn cache_hit_ratio(&self) -> f64 {
+        let total = self.cache_hits + self.cache_misses;
+        if total == 0 {
+            return 0.0;
+        }
+        self.cache_hits as f64 / total as f64
+    }
+
+}
+
+impl fmt::Display for Metrics {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "page reads: {}", self.page_reads)?;
+        writeln!(f, "page writes: {}", self.page_writes)?;
+        writeln!(f, "cache hits: {}, misses: {}, ratio: {:.2}",
+                 self.cache_hits, self.cache_misses, self.cache_hit_ratio())?;
+        writeln!(f, "journal frames appended: {}", self.journal_frames_appended)?;
+        writeln!(f, "checkpoints: {}", self.checkpoints)?;
+        writeln!(f, "transactions committed: {}, rolled back: {}",
+                 self.transactions_committed, self.transactions_rolled_back)?;
+        writeln!(f, "read transactions: {}", self.read_transactions)?;
+        write!(f, "vm instructions: {}", self.vm_instructions)
+    }
+
+}
+
+// the counter of the facade is increased at the same time
+#[inline]
+pub(crate) fn increase(counter: &mut u64, _name: &'static str, value: u64) {
+    *counter += value;
+
+    #[cfg(feature = "metrics")]
+    ::metrics::counter!(_name).increment(value);
+}
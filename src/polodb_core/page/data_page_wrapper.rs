@@ -11,6 +11,9 @@ const DATA_PAGE_HEADER_SIZE: u32 = 16;
  * Offset 6 (2 bytes): bar len
  * Offset 8: data begin
  * | 2 bytes | 2 bytes | 2 bytes | 2bytes(zero) |
+ *
+ * The bars are the offsets of the items, the end of a 64 KiB page
+ * can not be stored in 2 bytes, it's stored as zero.
  */
 pub(crate) struct DataPageWrapper {
     page: RawPage,
@@ -48,11 +51,25 @@ impl DataPageWrapper {
             raw_page.len() - DATA_PAGE_HEADER_SIZE - 2
         } else {
             let last_bar_index = DATA_PAGE_HEADER_SIZE + (bar_len - 1) * 2;
-            let last_bar = raw_page.get_u16(DATA_PAGE_HEADER_SIZE + (bar_len - 1) * 2);
-            (last_bar as u32) - last_bar_index - 2
+            let last_bar = DataPageWrapper::read_bar(raw_page, last_bar_index);
+            last_bar - last_bar_index - 2
+        }
+    }
+
+    #[inline]
+    fn read_bar(raw_page: &RawPage, pos: u32) -> u32 {
+        match raw_page.get_u16(pos) {
+            0 => raw_page.len(),
+            bar => bar as u32,
         }
     }
 
+    #[inline]
+    fn write_bar(&mut self, pos: u32, bar: u32) {
+        self.page.seek(pos);
+        self.page.put_u16(bar as u16);
+    }
+
     pub(crate) fn put(&mut self, data: &[u8]) {
         let data_size = data.len() as u32;
         let last_bar = self.get_last_bar();
@@ -63,7 +80,7 @@ impl DataPageWrapper {
         self.page.seek(begin_bar as u32);
         self.page.put(data);
 
-        self.append_bar(begin_bar as u16);
+        self.append_bar(begin_bar as u32);
 
         self.set_bar_len(self.bar_len() + 1);
         self.set_data_len(self.data_len() + 1);
@@ -86,21 +103,44 @@ impl DataPageWrapper {
         Some(&self.page.data[(begin_bar as usize)..(end_bar as usize)])
     }
 
-    fn get_bars_by_index(&self, index: u32) -> (u16, u16) {
-        let begin_bar = self.page.get_u16(DATA_PAGE_HEADER_SIZE + index * 2);
+    // the bars of a damaged page are checked instead of panicking,
+    // None representes the item has been removed
+    pub(crate) fn checked_get(&self, index: u32) -> Result<Option<&[u8]>, String> {
+        let bar_len = self.bar_len();
+        if DATA_PAGE_HEADER_SIZE + bar_len * 2 > self.page.len() {
+            return Err(format!("the count of the bars {} is too large", bar_len));
+        }
+        if index >= bar_len {
+            return Err(format!("index {} is greater than length {}", index, bar_len));
+        }
+
+        let (begin_bar, end_bar) = self.get_bars_by_index(index);
+        if begin_bar > end_bar || end_bar > self.page.len() ||
+            begin_bar < DATA_PAGE_HEADER_SIZE + bar_len * 2 {
+            return Err(format!("invalid bars {}..{} of index {}", begin_bar, end_bar, index));
+        }
+
+        if begin_bar == end_bar {
+            return Ok(None);
+        }
+
+        Ok(Some(&self.page.data[(begin_bar as usize)..(end_bar as usize)]))
+    }
+
+    fn get_bars_by_index(&self, index: u32) -> (u32, u32) {
+        let begin_bar = self.get_bar_value(index);
         let end_bar = if index == 0 {
-            self.page.len() as u16
+            self.page.len()
         } else {
-            self.page.get_u16(DATA_PAGE_HEADER_SIZE + (index - 1) * 2)
+            self.get_bar_value(index - 1)
         };
 
         (begin_bar, end_bar)
     }
 
-    fn append_bar(&mut self, bar: u16) {
+    fn append_bar(&mut self, bar: u32) {
         let index = DATA_PAGE_HEADER_SIZE + self.bar_len() * 2;
-        self.page.seek(index as u32);
-        self.page.put_u16(bar);
+        self.write_bar(index, bar);
     }
 
     // to preserve the index referred by other tickets
@@ -119,7 +159,7 @@ impl DataPageWrapper {
 
         let item_len = end_bar - begin_bar;
 
-        let last_bar = self.page.get_u16(DATA_PAGE_HEADER_SIZE + (self.bar_len() - 1) * 2);
+        let last_bar = self.get_bar_value(self.bar_len() - 1);
 
         let copy_len = begin_bar - last_bar;
 
@@ -131,41 +171,37 @@ impl DataPageWrapper {
         }
 
         // set the current bar to ZERO
-        self.page.seek(DATA_PAGE_HEADER_SIZE + index * 2);
-        self.page.put_u16(if index == 0 {
-            self.page.len() as u16
+        let end_bar = if index == 0 {
+            self.page.len()
         } else {
             self.get_bar_value(index - 1)
-        });
+        };
+        self.write_bar(DATA_PAGE_HEADER_SIZE + index * 2, end_bar);
 
         let mut iter_index = index + 1;
         while iter_index < total_len {
-            let bar_index = DATA_PAGE_HEADER_SIZE + iter_index * 2;
-            let old_value = self.page.get_u16(bar_index);
-
-            self.page.seek(bar_index);
-            self.page.put_u16(old_value + item_len);
+            let old_value = self.get_bar_value(iter_index);
+            self.write_bar(DATA_PAGE_HEADER_SIZE + iter_index * 2, old_value + item_len);
 
             iter_index += 1;
         }
 
         self.set_data_len(self.data_len() - 1);
         // no need to minus 2bytes for "bar"
-        self.remain_size += item_len as u32;
+        self.remain_size += item_len;
     }
 
     #[inline]
-    fn get_bar_value(&self, index: u32) -> u16 {
+    fn get_bar_value(&self, index: u32) -> u32 {
         let index = DATA_PAGE_HEADER_SIZE + index * 2;
-        self.page.get_u16(index)
+        DataPageWrapper::read_bar(&self.page, index)
     }
 
-    fn get_last_bar(&self) -> u16 {
+    fn get_last_bar(&self) -> u32 {
         if self.bar_len() == 0 {
-            return self.page.len() as u16;
+            return self.page.len();
         }
-        let last_bar_index = DATA_PAGE_HEADER_SIZE + (self.bar_len() - 1) * 2;
-        self.page.get_u16(last_bar_index as u32)
+        self.get_bar_value(self.bar_len() - 1)
     }
 
     #[inline]
@@ -299,4 +335,23 @@ mod tests {
         assert!(wrapper.get(1).is_none());
     }
 
+    // the end of the page is stored as zero
+    #[test]
+    fn test_remove_item_in_64k_page() {
+        let mut wrapper = DataPageWrapper::init(
+            1, NonZeroU32::new(65536).unwrap());
+
+        wrapper.put(&[1, 2, 3, 4]);
+        wrapper.put(&[5, 6]);
+        wrapper.remove(0);
+
+        let mut wrapper = DataPageWrapper::from_raw(wrapper.consume_page());
+        assert_eq!(wrapper.get(0), None);
+        assert_eq!(wrapper.get(1).unwrap(), [5, 6]);
+
+        wrapper.put(&[7]);
+        assert_eq!(wrapper.get(2).unwrap(), [7]);
+        assert_eq!(wrapper.checked_get(1).unwrap().unwrap(), [5, 6]);
+    }
+
 }
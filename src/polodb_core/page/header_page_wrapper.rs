@@ -1,32 +1,64 @@
 use std::num::NonZeroU32;
 use super::RawPage;
+use crate::db::JournalPosition;
 
 static HEADER_DESP: &str          = "PoloDB Format v2.0";
+pub(crate) const VERSION_OFFSET: u32 = 32;
 const SECTOR_SIZE_OFFSET: u32     = 40;
-const PAGE_SIZE_OFFSET: u32       = 44;
+pub(crate) const PAGE_SIZE_OFFSET: u32 = 44;
 const NULL_PAGE_BAR_OFFSET: u32   = 48;
 const META_PAGE_ID: u32           = 52;
 const META_VERSION_OFFSET: u32    = 56;
 const META_ID_COUNTER_OFFSET: u32 = 60;
-pub const FREE_LIST_OFFSET: u32   = 2048;
-const FREE_LIST_PAGE_LINK_OFFSET: u32 = 2048 + 4;
-pub const HEADER_FREE_LIST_MAX_SIZE: usize = (2048 - 8) / 4;
+const COMPRESSION_OFFSET: u32     = 64;
+pub(crate) const FEATURES_OFFSET: u32 = 68;
+pub(crate) const FEATURE_PAGE_CHECKSUM: u32 = 1;
+const JOURNAL_POSITION_OFFSET: u32 = 72;
 pub const DATABASE_VERSION: [u8; 4] = [0, 0, 2, 0];
+// the version of the files using any feature added after 0.0.2.0,
+// which are rejected by the older versions
+pub const FEATURES_DATABASE_VERSION: [u8; 4] = [0, 0, 3, 0];
 
 /**
  * Offset 0 (32 bytes) : "PoloDB Format v2.0";
- * Offset 32 (8 bytes) : Version 0.0.2.0;
+ * Offset 32 (8 bytes) : Version 0.0.2.0, 0.0.3.0 if a page size other than 4 KiB,
+ *                       the compression, the checksums, the encryption
+ *                       or the journal position is used;
  * Offset 40 (4 bytes) : SectorSize;
  * Offset 44 (4 bytes) : PageSize;
  * Offset 48 (4 bytes) : NullPageBarId;
  * Offset 52 (4 bytes) : MetaPageId(usually 1);
  * Offset 56 (4 bytes) : MetaVersionId;
  * Offset 60 (4 bytes) : MetaIdCounter;
+ * Offset 64 (4 bytes) : Compression(0 for none);
+ * Offset 68 (4 bytes) : Features(bit 0 for the page checksums);
+ * Offset 72 (12 bytes): JournalPosition(salt1, salt2, frame) of a replica, zeros if it's not;
  *
- * Free list offset: 2048;
+ * Free list offset: the half of the page size(2048 for 4 KiB pages);
  * | 4b   | 4b                  | 4b     | 4b    | ... |
  * | size | free list page link | free 1 | free2 | ... |
  */
+// the version for the data of the header page,
+// which is set by the codec when the page is written
+pub(crate) fn required_version(data: &[u8], encrypted: bool) -> [u8; 4] {
+    let get_u32 = |offset: u32| {
+        let offset = offset as usize;
+        let mut buffer = [0u8; 4];
+        buffer.copy_from_slice(&data[offset..(offset + 4)]);
+        u32::from_be_bytes(buffer)
+    };
+    let uses_features = encrypted
+        || data.len() != 4096
+        || get_u32(COMPRESSION_OFFSET) != 0
+        || get_u32(FEATURES_OFFSET) != 0
+        || get_u32(JOURNAL_POSITION_OFFSET + 4) != 0;
+    if uses_features {
+        FEATURES_DATABASE_VERSION
+    } else {
+        DATABASE_VERSION
+    }
+}
+
 pub(crate) struct HeaderPageWrapper(pub RawPage);
 
 impl HeaderPageWrapper {
@@ -37,7 +69,7 @@ impl HeaderPageWrapper {
         wrapper.set_title(HEADER_DESP);
         wrapper.set_version(&DATABASE_VERSION);
         wrapper.set_sector_size(4096);
-        wrapper.set_page_size(4096);
+        wrapper.set_page_size(page_size.get());
         wrapper.set_meta_page_id(1);
         wrapper.set_null_page_bar(2);
         wrapper
@@ -64,14 +96,15 @@ impl HeaderPageWrapper {
     }
 
     pub(crate) fn set_version(&mut self, version: &[u8]) {
-        self.0.seek(32);
+        self.0.seek(VERSION_OFFSET);
         self.0.put(version);
     }
 
     #[allow(dead_code)]
     pub(crate) fn get_version(&self) -> [u8; 4] {
         let mut version: [u8; 4] = [0; 4];
-        version[..4].clone_from_slice(&self.0.data[32..(4 + 32)]);
+        let offset = VERSION_OFFSET as usize;
+        version[..4].clone_from_slice(&self.0.data[offset..(offset + 4)]);
         version
     }
 
@@ -143,26 +176,75 @@ impl HeaderPageWrapper {
         self.0.put_u32(data);
     }
 
+    #[inline]
+    pub(crate) fn get_compression(&self) -> u32 {
+        self.0.get_u32(COMPRESSION_OFFSET)
+    }
+
+    #[inline]
+    pub(crate) fn set_compression(&mut self, compression: u32) {
+        self.0.seek(COMPRESSION_OFFSET);
+        self.0.put_u32(compression);
+    }
+
+    // the features are maintained by the backends
+    #[inline]
+    pub(crate) fn get_features(&self) -> u32 {
+        self.0.get_u32(FEATURES_OFFSET)
+    }
+
+    // the salt2 of a journal is never zero
+    pub(crate) fn get_journal_position(&self) -> Option<JournalPosition> {
+        let salt2 = self.0.get_u32(JOURNAL_POSITION_OFFSET + 4);
+        if salt2 == 0 {
+            return None;
+        }
+        Some(JournalPosition {
+            salt1: self.0.get_u32(JOURNAL_POSITION_OFFSET),
+            salt2,
+            frame: self.0.get_u32(JOURNAL_POSITION_OFFSET + 8),
+        })
+    }
+
+    pub(crate) fn set_journal_position(&mut self, position: Option<JournalPosition>) {
+        let position = position.unwrap_or(JournalPosition { salt1: 0, salt2: 0, frame: 0 });
+        self.0.seek(JOURNAL_POSITION_OFFSET);
+        self.0.put_u32(position.salt1);
+        self.0.put_u32(position.salt2);
+        self.0.put_u32(position.frame);
+    }
+
+    // the free list takes the second half of the page
+    #[inline]
+    fn free_list_offset(&self) -> u32 {
+        self.0.len() / 2
+    }
+
+    #[inline]
+    pub(crate) fn free_list_capacity(&self) -> usize {
+        ((self.free_list_offset() - 8) / 4) as usize
+    }
+
     #[inline]
     pub(crate) fn get_free_list_size(&self) -> u32 {
-        self.0.get_u32(FREE_LIST_OFFSET)
+        self.0.get_u32(self.free_list_offset())
     }
 
     #[inline]
     pub(crate) fn set_free_list_size(&mut self, size: u32) {
-        self.0.seek(FREE_LIST_OFFSET);
+        self.0.seek(self.free_list_offset());
         self.0.put_u32(size)
     }
 
     #[inline]
     pub(crate) fn get_free_list_content(&self, index: u32) -> u32 {
-        let offset = index * 4 + FREE_LIST_OFFSET + 8;
+        let offset = index * 4 + self.free_list_offset() + 8;
         self.0.get_u32(offset)
     }
 
     #[inline]
     pub(crate) fn set_free_list_content(&mut self, index: u32, pid: u32) {
-        let offset = index * 4 + FREE_LIST_OFFSET + 8;
+        let offset = index * 4 + self.free_list_offset() + 8;
         self.0.seek(offset);
         self.0.put_u32(pid);
     }
@@ -170,13 +252,13 @@ impl HeaderPageWrapper {
     #[inline]
     #[allow(dead_code)]
     pub(crate) fn set_free_list_page_id(&mut self, pid: u32) {
-        self.0.seek(FREE_LIST_PAGE_LINK_OFFSET);
+        self.0.seek(self.free_list_offset() + 4);
         self.0.put_u32(pid);
     }
 
     #[inline]
     pub(crate) fn get_free_list_page_id(&self) -> u32 {
-        self.0.get_u32(FREE_LIST_PAGE_LINK_OFFSET)
+        self.0.get_u32(self.free_list_offset() + 4)
     }
 
 
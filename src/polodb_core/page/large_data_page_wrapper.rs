@@ -46,6 +46,11 @@ impl LargeDataPageWrapper {
         self.page.put(data);
     }
 
+    #[inline]
+    pub(crate) fn data_len(&self) -> u32 {
+        self.page.get_u16(2) as u32
+    }
+
     pub(crate) fn next_pid(&self) -> u32 {
         self.page.get_u32(4)
     }
@@ -12,7 +12,16 @@ use std::io::{Seek, SeekFrom, Write, Read};
 use crate::DbResult;
 use crate::error::{DbErr};
 
+pub(crate) const MIN_PAGE_SIZE: u32 = 1024;
+pub(crate) const MAX_PAGE_SIZE: u32 = 65536;
+
+#[inline]
+pub(crate) fn is_valid_page_size(page_size: u32) -> bool {
+    page_size.is_power_of_two() && (MIN_PAGE_SIZE..=MAX_PAGE_SIZE).contains(&page_size)
+}
+
 #[repr(u8)]
+#[derive(Debug, Clone, Copy, Eq, PartialEq)]
 pub(crate) enum PageType {
     Undefined = 0,
 
@@ -49,7 +58,9 @@ impl PageType {
 
             3 => Ok(PageType::Data),
 
-            4 => Ok(PageType::LargeData),
+            4 => Ok(PageType::FreeList),
+
+            5 => Ok(PageType::LargeData),
 
             _ => Err(DbErr::UnexpectedPageType)
         }
@@ -57,8 +68,9 @@ impl PageType {
 
 }
 
+/// A page of the database, the backends store it by the page id.
 #[derive(Debug, Clone)]
-pub(crate) struct RawPage {
+pub struct RawPage {
     pub page_id:    u32,
     pub data:       Vec<u8>,
     pos:            u32,
@@ -66,6 +78,7 @@ pub(crate) struct RawPage {
 
 impl RawPage {
 
+    /// A page filled with zeros.
     pub fn new(page_id: u32, size: NonZeroU32) -> RawPage {
         let mut v: Vec<u8> = Vec::new();
         v.resize(size.get() as usize, 0);
@@ -76,17 +89,17 @@ impl RawPage {
         }
     }
 
-    pub unsafe fn copy_from_ptr(&mut self, ptr: *const u8) {
+    pub(crate) unsafe fn copy_from_ptr(&mut self, ptr: *const u8) {
         let target_ptr = self.data.as_mut_ptr();
         target_ptr.copy_from_nonoverlapping(ptr, self.data.len());
     }
 
-    pub unsafe fn copy_to_ptr(&self, ptr: *mut u8) {
+    pub(crate) unsafe fn copy_to_ptr(&self, ptr: *mut u8) {
         let target_ptr = self.data.as_ptr();
         target_ptr.copy_to_nonoverlapping(ptr, self.data.len());
     }
 
-    pub fn put(&mut self, data: &[u8]) {
+    pub(crate) fn put(&mut self, data: &[u8]) {
         if data.len() + self.pos as usize > self.data.len() {
             panic!("space is not enough for page");
         }
@@ -99,7 +112,7 @@ impl RawPage {
         self.pos += data.len() as u32;
     }
 
-    pub fn put_str(&mut self, str: &str) {
+    pub(crate) fn put_str(&mut self, str: &str) {
         if str.len() + self.pos as usize > self.data.len() {
             panic!("space is not enough for page");
         }
@@ -112,76 +125,77 @@ impl RawPage {
     }
 
     #[allow(dead_code)]
-    pub fn get_u8(&self, pos: u32) -> u8 {
+    pub(crate) fn get_u8(&self, pos: u32) -> u8 {
         self.data[pos as usize]
     }
 
     #[inline]
-    pub fn put_u8(&mut self, data: u8) {
+    pub(crate) fn put_u8(&mut self, data: u8) {
         self.data[self.pos as usize] = data;
         self.pos += 1;
     }
 
     #[inline]
-    pub fn get_u16(&self, pos: u32) -> u16 {
+    pub(crate) fn get_u16(&self, pos: u32) -> u16 {
         let mut buffer: [u8; 2] = [0; 2];
         buffer.copy_from_slice(&self.data[(pos as usize)..((pos as usize) + 2)]);
         u16::from_be_bytes(buffer)
     }
 
     #[inline]
-    pub fn put_u16(&mut self, data: u16) {
+    pub(crate) fn put_u16(&mut self, data: u16) {
         let data_be = data.to_be_bytes();
         self.put(&data_be)
     }
 
     #[inline]
-    pub fn get_u32(&self, pos: u32) -> u32 {
+    pub(crate) fn get_u32(&self, pos: u32) -> u32 {
         let mut buffer: [u8; 4] = [0; 4];
         buffer.copy_from_slice(&self.data[(pos as usize)..((pos as usize) + 4)]);
         u32::from_be_bytes(buffer)
     }
 
     #[inline]
-    pub fn put_u32(&mut self, data: u32) {
+    pub(crate) fn put_u32(&mut self, data: u32) {
         let data_be = data.to_be_bytes();
         self.put(&data_be)
     }
 
     #[inline]
     #[allow(dead_code)]
-    pub fn put_u64(&mut self, data: u64) {
+    pub(crate) fn put_u64(&mut self, data: u64) {
         let data_be = data.to_be_bytes();
         self.put(&data_be)
     }
 
     #[inline]
     #[allow(dead_code)]
-    pub fn get_u64(&self, pos: u32) -> u64 {
+    pub(crate) fn get_u64(&self, pos: u32) -> u64 {
         let mut buffer: [u8; 8] = [0; 8];
         buffer.copy_from_slice(&self.data[(pos as usize)..((pos as usize) + 8)]);
         u64::from_be_bytes(buffer)
     }
 
-    pub fn sync_to_file(&self, file: &mut File, offset: u64) -> std::io::Result<()> {
+    #[allow(dead_code)]
+    pub(crate) fn sync_to_file(&self, file: &mut File, offset: u64) -> std::io::Result<()> {
         file.seek(SeekFrom::Start(offset))?;
         file.write_all(self.data.as_slice())?;
         Ok(())
     }
 
-    pub fn read_from_file(&mut self, file: &mut File, offset: u64) -> std::io::Result<()> {
+    pub(crate) fn read_from_file(&mut self, file: &mut File, offset: u64) -> std::io::Result<()> {
         file.seek(SeekFrom::Start(offset))?;
         file.read_exact(self.data.as_mut_slice())?;
         Ok(())
     }
 
     #[inline]
-    pub fn seek(&mut self, pos: u32) {
+    pub(crate) fn seek(&mut self, pos: u32) {
         self.pos = pos;
     }
 
     #[inline]
-    pub fn len(&self) -> u32 {
+    pub(crate) fn len(&self) -> u32 {
         self.data.len() as u32
     }
 
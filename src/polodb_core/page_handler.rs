@@ -1,4 +1,5 @@
 use std::cell::Cell;
+use std::io::Write;
 use std::sync::Arc;
 use std::num::NonZeroU32;
 use std::rc::Rc;
@@ -6,11 +7,12 @@ use polodb_bson::Document;
 use crate::backend::file::pagecache::PageCache;
 use crate::transaction::{TransactionType, TransactionState};
 use crate::page::RawPage;
-use crate::backend::{Backend, AutoStartResult};
-use crate::page::header_page_wrapper;
+use crate::backend::{BackendExt, AutoStartResult, PageCodec};
 use crate::page::header_page_wrapper::HeaderPageWrapper;
 use crate::dump::JournalDump;
-use crate::{DbResult, Config};
+use crate::metrics::{self, Metrics};
+use crate::db::{CheckpointMode, CheckpointStats, JournalPosition};
+use crate::{DbResult, Config, SerializeType, Compression};
 use crate::error::DbErr;
 use crate::page::data_page_wrapper::DataPageWrapper;
 use crate::data_ticket::DataTicket;
@@ -82,42 +84,99 @@ impl DataPageAllocator {
         self.stash = None;
     }
 
+    fn clear(&mut self) {
+        if let Some(t) = self.stash.as_mut() {
+            t.clear();
+        }
+    }
+
+    #[inline]
+    fn snapshot(&self) -> Option<Vec<(u32, u32)>> {
+        self.stash.clone()
+    }
+
+    #[inline]
+    fn restore(&mut self, stash: Option<Vec<(u32, u32)>>) {
+        self.stash = stash;
+    }
+
+}
+
+struct Savepoint {
+    name:               String,
+    allocator_stash:    Option<Vec<(u32, u32)>>,
+    // the transaction is started by this savepoint,
+    // releasing it will commit the transaction
+    starts_transaction: bool,
 }
 
 pub(crate) struct PageHandler {
-    backend:             Box<dyn Backend + Send>,
+    backend:             Box<dyn BackendExt + Send>,
 
     pub page_size:       NonZeroU32,
     page_cache:          Box<PageCache>,
 
     data_page_allocator: DataPageAllocator,
 
+    savepoints:          Vec<Savepoint>,
+
     transaction_state:   TransactionState,
 
     config:              Arc<Config>,
 
+    pub(crate) compression: Compression,
+
+    // the cache and journal counters are kept by the others
+    metrics:             Metrics,
+
 }
 
 impl PageHandler {
 
-    pub fn new(backend: Box<dyn Backend + Send>, page_size: NonZeroU32, config: Arc<Config>) -> DbResult<PageHandler> {
-        let page_cache = PageCache::new_default(page_size);
+    // the page size is decided by the backend
+    pub fn new(backend: Box<dyn BackendExt + Send>, config: Arc<Config>) -> DbResult<PageHandler> {
+        let page_size = backend.codec().page_size();
+        let page_cache = PageHandler::new_page_cache(page_size, &config);
 
         Ok(PageHandler {
             backend,
 
             page_size,
-            page_cache: Box::new(page_cache),
+            page_cache,
 
             data_page_allocator: DataPageAllocator::new(),
 
+            savepoints: Vec::new(),
+
             transaction_state: TransactionState::NoTrans,
 
             config,
 
+            compression: Compression::None,
+
+            metrics: Metrics::default(),
+
         })
     }
 
+    #[inline]
+    fn new_page_cache(page_size: NonZeroU32, config: &Config) -> Box<PageCache> {
+        Box::new(PageCache::new(config.cache_size_pages.get(), page_size))
+    }
+
+    // the compression is fixed when the database is created,
+    // the v1 database has no such field
+    pub(crate) fn load_compression(&mut self) -> DbResult<()> {
+        if self.config.serialize_type == SerializeType::Legacy {
+            return Ok(());
+        }
+        let header = HeaderPageWrapper::from_raw_page(self.pipeline_read_page(0)?);
+        let compression = Compression::from_header(header.get_compression())?;
+        compression.check_supported()?;
+        self.compression = compression;
+        Ok(())
+    }
+
     pub(crate) fn distribute_data_page_wrapper(&mut self, data_size: u32) -> DbResult<DataPageWrapper> {
         let data_size = data_size + 2;  // preserve 2 bytes
         let try_result = self.data_page_allocator.try_allocate_data_page(data_size);
@@ -155,6 +214,7 @@ impl PageHandler {
     // 3. write to page_cache
     pub fn pipeline_write_page(&mut self, page: &RawPage) -> DbResult<()> {
         self.backend.write_page(page)?;
+        metrics::increase(&mut self.metrics.page_writes, metrics::PAGE_WRITES, 1);
 
         self.page_cache.insert_to_cache(page);
         Ok(())
@@ -170,6 +230,12 @@ impl PageHandler {
     // 2. read from journal, if none
     // 3. read from main db
     pub fn pipeline_read_page(&mut self, page_id: u32) -> DbResult<RawPage> {
+        if self.backend.transaction_type().is_none() {
+            self.sync_with_other_connections();
+        }
+
+        metrics::increase(&mut self.metrics.page_reads, metrics::PAGE_READS, 1);
+
         if let Some(page) = self.page_cache.get_from_cache(page_id) {
             return Ok(page);
         }
@@ -189,7 +255,8 @@ impl PageHandler {
         let wrapper = DataPageWrapper::from_raw(page);
         let bytes = wrapper.get(data_ticket.index as u32);
         if let Some(bytes) = bytes {
-            let mut my_ref: &[u8] = bytes;
+            let bytes = crate::compression::decompress(self.compression, bytes)?;
+            let mut my_ref: &[u8] = bytes.as_ref();
             let bytes: &mut &[u8] = &mut my_ref;
             let serialize_type = self.config.serialize_type;
             let doc = crate::doc_serializer::deserialize(serialize_type, bytes)?;
@@ -210,6 +277,7 @@ impl PageHandler {
             next_pid = wrapper.next_pid();
         }
 
+        let bytes = crate::compression::decompress(self.compression, &bytes)?;
         let mut my_ref: &[u8] = bytes.as_ref();
         let serialize_type = self.config.serialize_type;
         let doc = crate::doc_serializer::deserialize(serialize_type, &mut my_ref)?;
@@ -220,6 +288,7 @@ impl PageHandler {
         let mut bytes = Vec::with_capacity(512);
         let serialize_type = self.config.serialize_type;
         crate::doc_serializer::serialize(serialize_type, doc, &mut bytes)?;
+        let bytes = crate::compression::compress(self.compression, bytes);
 
         if bytes.len() >= self.page_size.get() as usize / 2 {
             return self.store_large_data(&bytes);
@@ -355,7 +424,7 @@ impl PageHandler {
         }
 
         let current_size = first_page_wrapper.get_free_list_size();
-        if (current_size as usize) + pages.len() >= header_page_wrapper::HEADER_FREE_LIST_MAX_SIZE {
+        if (current_size as usize) + pages.len() >= first_page_wrapper.free_list_capacity() {
             let free_list_pid = self.alloc_page_id()?;
             first_page_wrapper.set_free_list_page_id(free_list_pid);
             self.pipeline_write_page(&first_page_wrapper.0)?;
@@ -491,16 +560,34 @@ impl PageHandler {
 
         if (null_page_bar as u64) >= self.backend.db_size() {  // truncate file
             let exceed_size = self.config.init_block_count.get() * (self.page_size.get() as u64);
-            self.backend.set_db_size(exceed_size)?;
+            self.backend.expand_db_size(exceed_size)?;
         }
 
         self.pipeline_write_page(&first_page_wrapper.0)?;
 
+        // an empty slot before the null page bar is taken as damaged, see PageCodec
+        self.pipeline_write_null_page(null_page_bar)?;
+
         crate::polo_log!("alloc new page_id : {}", null_page_bar);
 
         Ok(null_page_bar)
     }
 
+    // the size of the database seen by the current transaction
+    #[inline]
+    pub(crate) fn db_size(&self) -> u64 {
+        self.backend.db_size()
+    }
+
+    // the pages after the size are discarded,
+    // the cached pages and the free space of the data pages are out of date
+    pub(crate) fn truncate(&mut self, size: u64) -> DbResult<()> {
+        self.backend.truncate(size)?;
+        self.page_cache.clear();
+        self.data_page_allocator.clear();
+        Ok(())
+    }
+
     #[inline]
     pub fn transaction_type(&mut self) -> Option<TransactionType> {
         self.backend.transaction_type()
@@ -567,8 +654,96 @@ impl PageHandler {
         Ok(())
     }
 
+    // the cached pages and the free space of the data pages
+    // are out of date if another connection has committed
+    fn sync_with_other_connections(&mut self) {
+        if self.backend.is_changed_by_others() {
+            self.page_cache.clear();
+            self.data_page_allocator = DataPageAllocator::new();
+        }
+    }
+
+    #[inline]
+    pub(crate) fn new_connection(&self) -> DbResult<Box<dyn BackendExt + Send>> {
+        self.backend.new_connection()
+    }
+
+    pub(crate) fn checkpoint(&mut self, mode: CheckpointMode) -> DbResult<bool> {
+        self.backend.checkpoint(mode)
+    }
+
+    pub(crate) fn checkpoint_stats(&self) -> DbResult<CheckpointStats> {
+        self.backend.checkpoint_stats()
+    }
+
+    #[inline]
+    pub(crate) fn add_vm_instructions(&mut self, count: u64) {
+        metrics::increase(&mut self.metrics.vm_instructions, metrics::VM_INSTRUCTIONS, count);
+    }
+
+    pub(crate) fn metrics(&self) -> DbResult<Metrics> {
+        let mut metrics = Metrics {
+            cache_hits: self.page_cache.hits(),
+            cache_misses: self.page_cache.misses(),
+            ..self.metrics
+        };
+        self.backend.collect_metrics(&mut metrics);
+        Ok(metrics)
+    }
+
+    #[inline]
+    pub(crate) fn export_journal_frames(&self, since: JournalPosition, writer: &mut dyn Write) -> DbResult<JournalPosition> {
+        self.backend.export_journal_frames(since, writer)
+    }
+
+    #[inline]
+    pub(crate) fn journal_position(&self) -> Option<JournalPosition> {
+        self.backend.journal_position()
+    }
+
+    // the pages are committed by another database,
+    // the free space of the data pages known by this one is out of date
+    pub(crate) fn apply_foreign_pages(&mut self, pages: &[RawPage], db_size: u64) -> DbResult<()> {
+        for page in pages {
+            self.pipeline_write_page(page)?;
+        }
+
+        let current_size = self.backend.db_size();
+        if db_size > current_size {
+            self.backend.expand_db_size(db_size - current_size)?;
+        } else if db_size < current_size {
+            self.truncate(db_size)?;
+        }
+
+        self.data_page_allocator.clear();
+        Ok(())
+    }
+
+    #[inline]
+    pub(crate) fn codec(&self) -> PageCodec {
+        self.backend.codec()
+    }
+
+    pub(crate) fn set_codec(&mut self, codec: PageCodec) -> DbResult<()> {
+        self.backend.set_codec(codec)
+    }
+
+    #[inline]
+    pub(crate) fn open_compacted(&self, config: Arc<Config>) -> DbResult<Option<Box<dyn BackendExt + Send>>> {
+        self.backend.open_compacted(config)
+    }
+
+    // all the pages are replaced
+    pub(crate) fn swap_compacted(&mut self) -> DbResult<u64> {
+        let db_size = self.backend.swap_compacted()?;
+        self.page_cache.clear();
+        self.data_page_allocator = DataPageAllocator::new();
+        Ok(db_size)
+    }
+
     pub fn start_transaction(&mut self, ty: TransactionType) -> DbResult<()> {
         self.backend.start_transaction(ty)?;
+        self.sync_with_other_connections();
         if ty == TransactionType::Write {
             self.data_page_allocator.start_transaction();
         }
@@ -576,8 +751,15 @@ impl PageHandler {
     }
 
     pub fn commit(&mut self) -> DbResult<()> {
+        let ty = self.backend.transaction_type();
         self.backend.commit()?;
         self.data_page_allocator.commit();
+        self.savepoints.clear();
+        if ty == Some(TransactionType::Read) {
+            metrics::increase(&mut self.metrics.read_transactions, metrics::READ_TRANSACTIONS, 1);
+        } else {
+            metrics::increase(&mut self.metrics.transactions_committed, metrics::TRANSACTIONS_COMMITTED, 1);
+        }
         Ok(())
     }
 
@@ -587,17 +769,64 @@ impl PageHandler {
     pub fn rollback(&mut self) -> DbResult<()> {
         self.backend.rollback()?;
         self.data_page_allocator.rollback();
-        self.page_cache = Box::new(PageCache::new_default(self.page_size));
+        self.savepoints.clear();
+        self.page_cache.clear();
+        metrics::increase(&mut self.metrics.transactions_rolled_back, metrics::TRANSACTIONS_ROLLED_BACK, 1);
+        Ok(())
+    }
+
+    pub fn savepoint(&mut self, name: &str, starts_transaction: bool) -> DbResult<()> {
+        self.backend.savepoint()?;
+        self.savepoints.push(Savepoint {
+            name: name.into(),
+            allocator_stash: self.data_page_allocator.snapshot(),
+            starts_transaction,
+        });
         Ok(())
     }
 
+    // the newest savepoint with the name is used
+    fn find_savepoint(&self, name: &str) -> DbResult<usize> {
+        self.savepoints
+            .iter()
+            .rposition(|savepoint| savepoint.name == name)
+            .ok_or_else(|| DbErr::SavepointNotFound(name.into()))
+    }
+
+    // the savepoint is kept, the savepoints created after it are released,
+    // the pages in cache may be written after the savepoint, clear it
+    pub fn rollback_to_savepoint(&mut self, name: &str) -> DbResult<()> {
+        let index = self.find_savepoint(name)?;
+        self.backend.rollback_to_savepoint(index)?;
+        self.savepoints.truncate(index + 1);
+
+        // the savepoint is taken before the transaction is upgraded,
+        // the allocator starts from the committed pages
+        match self.savepoints[index].allocator_stash.clone() {
+            None if self.transaction_type() == Some(TransactionType::Write) => {
+                self.data_page_allocator.start_transaction();
+            }
+            stash => self.data_page_allocator.restore(stash),
+        }
+        self.page_cache.clear();
+        Ok(())
+    }
+
+    // return if the released savepoint started the transaction
+    pub fn release_savepoint(&mut self, name: &str) -> DbResult<bool> {
+        let index = self.find_savepoint(name)?;
+        self.backend.release_savepoint(index)?;
+        let starts_transaction = self.savepoints[index].starts_transaction;
+        self.savepoints.truncate(index);
+        Ok(starts_transaction)
+    }
+
 }
 
 #[cfg(test)]
 mod test {
     use std::env;
     use std::collections::HashSet;
-    use std::num::NonZeroU32;
     use std::sync::Arc;
     use crate::backend::file::FileBackend;
     use crate::{Config, TransactionType};
@@ -620,11 +849,9 @@ mod test {
         let _ = std::fs::remove_file(db_path.as_path());
         let _ = std::fs::remove_file(journal_path);
 
-        let page_size = NonZeroU32::new(4096).unwrap();
         let config = Arc::new(Config::default());
-        let backend = Box::new(FileBackend::open(db_path.as_ref(), page_size, config.clone()).unwrap());
-        let mut page_handler = PageHandler::new(
-            backend, page_size, config).unwrap();
+        let backend = Box::new(FileBackend::open(db_path.as_ref(), config.clone()).unwrap());
+        let mut page_handler = PageHandler::new(backend, config).unwrap();
         page_handler.start_transaction(TransactionType::Write).unwrap();
 
         let (free_pid, free_size) = page_handler.first_page_free_list_pid_and_size().unwrap();
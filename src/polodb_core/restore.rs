@@ -0,0 +1,179 @@
+use std::fmt;
+use std::fs::File;
+use std::io::{self, BufReader};
+use std::path::Path;
+use std::time::{Duration, SystemTime, UNIX_EPOCH};
+use crate::{DbResult, DbErr, Config};
+use crate::context::DbContext;
+use crate::db::JournalPosition;
+use crate::backend::file::{read_journal_frames, list_archived_segments};
+
+/// Where [Database::restore](crate::Database::restore) stops.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RestoreTarget {
+    /// Apply all the archived transactions.
+    Latest,
+
+    /// Apply the transactions committed before or at the time,
+    /// the commit time is recorded in seconds.
+    Time(SystemTime),
+
+    /// Apply the transactions in the first frames of the archive.
+    /// A transaction is not applied if some of its frames are after the count.
+    Frame(u64),
+}
+
+impl RestoreTarget {
+
+    fn includes(&self, frame_end: u64, commit_time: u32) -> bool {
+        match self {
+            RestoreTarget::Latest => true,
+            RestoreTarget::Time(time) => {
+                let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+                (commit_time as u64) <= seconds
+            }
+            RestoreTarget::Frame(frame) => frame_end <= *frame,
+        }
+    }
+
+}
+
+/// The result of [Database::restore](crate::Database::restore).
+#[derive(Debug, Clone, Default)]
+pub struct RestoreReport {
+    /// The count of the archived segments which are replayed.
+    pub segments:         usize,
+
+    /// The count of the applied transactions.
+    pub transactions:     usize,
+
+    /// The count of the frames until the last applied transaction,
+    /// counted from the beginning of the archive,
+    /// the frames before the backup are included.
+    pub frames:           u64,
+
+    /// When the last applied transaction was committed,
+    /// `None` if nothing is applied or the time is unknown.
+    pub last_commit_time: Option<SystemTime>,
+}
+
+impl fmt::Display for RestoreReport {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "segments: {}", self.segments)?;
+        writeln!(f, "transactions: {}", self.transactions)?;
+        write!(f, "frames: {}", self.frames)?;
+        if let Some(time) = self.last_commit_time {
+            let seconds = time.duration_since(UNIX_EPOCH).map_or(0, |duration| duration.as_secs());
+            write!(f, "\nlast commit: @{}", seconds)?;
+        }
+        Ok(())
+    }
+
+}
+
+// The replay starts at the position of the journal kept in the backup,
+// the frames before it are skipped.
+//
+// Every segment is applied in a write transaction,
+// a segment is checked entirely before it's applied.
+pub(crate) fn restore(base: &Path, archive_dir: &Path, dst: &Path, until: RestoreTarget, config: Config) -> DbResult<RestoreReport> {
+    if dst.exists() {
+        let err = io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", dst.display()));
+        return Err(DbErr::from(err));
+    }
+
+    // the journal of an old database in the same place
+    // would be applied when it's opened
+    let mut journal_path = dst.as_os_str().to_owned();
+    journal_path.push(".journal");
+    match std::fs::remove_file(&journal_path) {
+        Err(err) if err.kind() != io::ErrorKind::NotFound => return Err(err.into()),
+        _ => (),
+    }
+
+    // a half-restored database is not left behind
+    let result = restore_to(base, archive_dir, dst, until, config);
+    if result.is_err() {
+        let _ = std::fs::remove_file(dst);
+        let _ = std::fs::remove_file(&journal_path);
+    }
+    result
+}
+
+fn restore_to(base: &Path, archive_dir: &Path, dst: &Path, until: RestoreTarget, config: Config) -> DbResult<RestoreReport> {
+    std::fs::copy(base, dst)?;
+
+    let mut ctx = DbContext::open_file(dst, config)?;
+    let codec = ctx.codec();
+    let position = ctx.journal_position()?
+        .ok_or(DbErr::InvalidJournalFrames("the base is not a backup, see backup_to"))?;
+    let mut report = RestoreReport::default();
+    let mut started = false;
+
+    for segment_path in list_archived_segments(archive_dir)? {
+        let mut reader = BufReader::new(File::open(&segment_path)?);
+        let frames = read_journal_frames(&mut reader, &codec)?;
+
+        // the frames before the backup, a segment holds
+        // all the frames of a journal from the beginning
+        let skipped_frames = if started {
+            0
+        } else if frames.to.salt1 == position.salt1 && frames.to.salt2 == position.salt2 {
+            started = true;
+            position.frame
+        } else if frames.from == position {
+            started = true;
+            0
+        } else {
+            u32::MAX
+        };
+
+        let mut begin = 0;
+        let mut end = 0;
+        let mut segment_frames = 0;
+        for transaction in &frames.transactions {
+            let frame_end = report.frames + transaction.pages.len() as u64;
+            if segment_frames < skipped_frames {
+                segment_frames += transaction.pages.len() as u32;
+                if !until.includes(frame_end, transaction.commit_time) {
+                    return Err(DbErr::InvalidRestoreTarget("the target is before the backup"));
+                }
+                report.frames = frame_end;
+                begin += 1;
+                end += 1;
+                continue;
+            }
+
+            if !until.includes(frame_end, transaction.commit_time) {
+                break;
+            }
+
+            end += 1;
+            segment_frames += transaction.pages.len() as u32;
+            report.transactions += 1;
+            report.frames = frame_end;
+            report.last_commit_time = match transaction.commit_time {
+                0 => None,
+                seconds => Some(UNIX_EPOCH + Duration::from_secs(seconds as u64)),
+            };
+        }
+
+        if end > begin {
+            let to = JournalPosition { frame: segment_frames, ..frames.to };
+            ctx.apply_journal_transactions(&frames.transactions[begin..end], to)?;
+            report.segments += 1;
+        }
+
+        if end < frames.transactions.len() {
+            break;
+        }
+    }
+
+    if !started {
+        return Err(DbErr::InvalidJournalFrames("the position of the base is not in the archive"));
+    }
+
+    Ok(report)
+}
+
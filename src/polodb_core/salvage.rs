@@ -0,0 +1,584 @@
+use std::fmt;
+use std::fs::File;
+use std::io;
+use std::num::NonZeroU32;
+use std::panic;
+use std::path::{Path, PathBuf};
+use hashbrown::{HashMap, HashSet};
+use polodb_bson::{Document, Value};
+use crate::{DbResult, DbErr, Database, Config, Compression, SerializeType, TransactionType};
+use crate::btree::{BTreeNode, HEADER_SIZE, ITEM_SIZE};
+use crate::data_ticket::DataTicket;
+use crate::meta_doc_helper::meta_doc_key;
+use crate::page::{RawPage, PageType, FreeListDataWrapper, MIN_PAGE_SIZE, is_valid_page_size};
+use crate::page::header_page_wrapper::{HeaderPageWrapper, FEATURE_PAGE_CHECKSUM};
+use crate::backend::PageCodec;
+use crate::page::data_page_wrapper::DataPageWrapper;
+use crate::page::large_data_page_wrapper::LargeDataPageWrapper;
+
+/// The collection of the documents which are found by scanning the pages,
+/// but the collection they belong to is unknown.
+pub static LOST_AND_FOUND: &str = "lost+found";
+
+/// The result of [Database::salvage](crate::Database::salvage).
+#[derive(Debug, Clone, Default)]
+pub struct SalvageReport {
+    /// The count of the pages in the damaged file, including the header page.
+    pub page_count:          u32,
+
+    /// The pages can not be parsed, sorted by page id.
+    pub damaged_pages:       Vec<u32>,
+
+    /// The name and the count of the documents of the recovered collections.
+    pub collections:         Vec<(String, u64)>,
+
+    /// The documents saved in [LOST_AND_FOUND].
+    pub orphan_documents:    u64,
+
+    /// The items which can not be decoded as documents.
+    pub lost_documents:      u64,
+
+    /// The documents whose `_id` has been recovered already in the same collection.
+    pub duplicate_documents: u64,
+
+    /// The journal of the damaged file is not applied,
+    /// the changes in it are lost.
+    pub journal_ignored:     bool,
+}
+
+impl SalvageReport {
+
+    pub fn recovered_documents(&self) -> u64 {
+        let count: u64 = self.collections.iter().map(|(_, count)| *count).sum();
+        count + self.orphan_documents
+    }
+
+}
+
+impl fmt::Display for SalvageReport {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "pages: {}", self.page_count)?;
+        for (name, count) in &self.collections {
+            writeln!(f, "collection '{}': {} documents", name, count)?;
+        }
+        writeln!(f, "orphan documents: {}", self.orphan_documents)?;
+        writeln!(f, "duplicate documents: {}", self.duplicate_documents)?;
+        write!(f, "lost documents: {}", self.lost_documents)?;
+        if !self.damaged_pages.is_empty() {
+            let pages: Vec<String> = self.damaged_pages.iter().map(|pid| pid.to_string()).collect();
+            write!(f, "\ndamaged pages: {}", pages.join(", "))?;
+        }
+        if self.journal_ignored {
+            write!(f, "\nthe journal is ignored")?;
+        }
+        Ok(())
+    }
+
+}
+
+struct SalvagedCollection {
+    name: String,
+    docs: Vec<Document>,
+}
+
+// Read the pages from the damaged file directly, the database is not opened,
+// because the header, or the meta btree may be broken.
+//
+// The collections are recovered by walking the btrees from the meta btree,
+// then the data pages and the large data chains are scanned by magic,
+// the documents not referenced by any btree are orphans.
+// The entries of the indexes are stored as documents too,
+// the ones of the index btrees lost with the meta btree are skipped.
+struct PageScanner {
+    file:          File,
+    page_size:     NonZeroU32,
+    codec:         PageCodec,
+    compression:   Compression,
+    page_count:    u32,
+    item_size:     u32,
+    free_pages:    HashSet<u32>,
+    btree_pages:   HashSet<u32>,
+
+    // the items referenced by the btrees
+    tickets:       HashSet<(u32, u16)>,
+    large_pages:   HashSet<u32>,
+    damaged_pages: HashSet<u32>,
+    lost_count:    u64,
+}
+
+impl PageScanner {
+
+    fn open(path: &Path) -> DbResult<PageScanner> {
+        let mut file = File::open(path)?;
+        let file_len = file.metadata()?.len();
+
+        // the fields are in the beginning of the header page
+        let mut header = RawPage::new(0, NonZeroU32::new(MIN_PAGE_SIZE).unwrap());
+        let mut page_size = 4096;
+        let mut compression = Compression::None;
+        let mut checksum = false;
+        if file_len >= MIN_PAGE_SIZE as u64 {
+            header.read_from_file(&mut file, 0)?;
+            let mut wrapper = HeaderPageWrapper::from_raw_page(header);
+            let size = wrapper.get_page_size();
+            if is_valid_page_size(size) {
+                page_size = size;
+            }
+            // a damaged field is taken as no compression
+            compression = Compression::from_header(wrapper.get_compression()).unwrap_or(Compression::None);
+            compression.check_supported()?;
+            checksum = wrapper.get_features() & FEATURE_PAGE_CHECKSUM != 0;
+        }
+
+        let page_size = NonZeroU32::new(page_size).unwrap();
+        let codec = PageCodec::new(page_size, None, checksum);
+        let page_count = (file_len / codec.slot_size()) as u32;
+        Ok(PageScanner {
+            file,
+            page_size,
+            codec,
+            compression,
+            page_count,
+            item_size: (page_size.get() - HEADER_SIZE) / ITEM_SIZE,
+            free_pages: HashSet::new(),
+            btree_pages: HashSet::new(),
+            tickets: HashSet::new(),
+            large_pages: HashSet::new(),
+            damaged_pages: HashSet::new(),
+            lost_count: 0,
+        })
+    }
+
+    fn read_page(&mut self, page_id: u32) -> DbResult<Option<RawPage>> {
+        if page_id >= self.page_count {
+            return Ok(None);
+        }
+        let slot = self.codec.read_slot(&mut self.file, self.codec.slot_offset(page_id))?;
+
+        // nothing has been written to the slot
+        if slot.iter().all(|byte| *byte == 0) {
+            return Ok(Some(RawPage::new(page_id, self.page_size)));
+        }
+
+        // the page with a wrong checksum is still parsed,
+        // some items may be intact
+        match self.codec.decode(page_id, slot.clone()) {
+            Err(DbErr::ChecksumMismatch(_)) => {
+                self.mark_damaged(page_id);
+                let mut page = RawPage::new(page_id, self.page_size);
+                page.data.copy_from_slice(&slot[0..(self.page_size.get() as usize)]);
+                Ok(Some(page))
+            }
+            result => result.map(Some),
+        }
+    }
+
+    fn mark_damaged(&mut self, page_id: u32) {
+        self.damaged_pages.insert(page_id);
+    }
+
+    fn scan(&mut self) -> DbResult<(Vec<SalvagedCollection>, Vec<Document>)> {
+        let header = match self.read_page(0)? {
+            Some(page) => HeaderPageWrapper::from_raw_page(page),
+            None => return Ok((vec![], vec![])),
+        };
+
+        self.read_free_list(&header)?;
+
+        let mut meta_pid = header.get_meta_page_id();
+        if meta_pid == 0 || meta_pid >= self.page_count {
+            self.mark_damaged(0);
+            meta_pid = 1;
+        }
+
+        let mut collections = vec![];
+        for meta_doc in self.read_btree(meta_pid)? {
+            let name = match meta_doc.get(meta_doc_key::NAME) {
+                Some(Value::String(name)) => name.to_string(),
+                _ => continue,
+            };
+            let root_pid = match meta_doc.get(meta_doc_key::ROOT_PID) {
+                Some(Value::Int(root_pid)) => *root_pid as u32,
+                _ => continue,
+            };
+
+            // the documents in the indexes are not needed,
+            // but they should not be taken as orphans
+            if let Some(Value::Document(indexes)) = meta_doc.get(meta_doc_key::INDEXES) {
+                for (_, options) in indexes.iter() {
+                    if let Value::Document(options) = options {
+                        if let Some(Value::Int(index_root_pid)) = options.get(meta_doc_key::index::ROOT_PID) {
+                            self.read_btree(*index_root_pid as u32)?;
+                        }
+                    }
+                }
+            }
+
+            let docs = self.read_btree(root_pid)?;
+            collections.push(SalvagedCollection { name, docs });
+        }
+
+        let orphans = self.scan_orphans()?;
+
+        Ok((collections, orphans))
+    }
+
+    fn read_free_list(&mut self, header: &HeaderPageWrapper) -> DbResult<()> {
+        let free_list_size = header.get_free_list_size();
+        if (free_list_size as usize) > header.free_list_capacity() {
+            self.mark_damaged(0);
+            return Ok(());
+        }
+        for index in 0..free_list_size {
+            let page_id = header.get_free_list_content(index);
+            self.free_pages.insert(page_id);
+        }
+
+        let size_cap = (self.page_size.get() - 16) / 4;
+        let mut visited = HashSet::new();
+        let mut next_pid = header.get_free_list_page_id();
+        while next_pid != 0 && visited.insert(next_pid) {
+            let page = match self.read_page(next_pid)? {
+                Some(page) if page_type_of(&page) == Some(PageType::FreeList) => page,
+                _ => {
+                    self.mark_damaged(next_pid);
+                    break;
+                }
+            };
+
+            let wrapper = FreeListDataWrapper::from_raw(page);
+            let size = wrapper.size();
+            if size > size_cap {
+                self.mark_damaged(next_pid);
+                break;
+            }
+            for index in 0..size {
+                let page_id = wrapper.borrow_page().get_u32(16 + index * 4);
+                self.free_pages.insert(page_id);
+            }
+
+            next_pid = wrapper.next_pid();
+        }
+
+        Ok(())
+    }
+
+    // the documents of the btree which can be decoded,
+    // the damaged nodes are skipped with their children
+    fn read_btree(&mut self, root_pid: u32) -> DbResult<Vec<Document>> {
+        let mut result = vec![];
+        let mut stack = vec![root_pid];
+
+        while let Some(pid) = stack.pop() {
+            if pid == 0 || !self.btree_pages.insert(pid) {
+                continue;
+            }
+
+            let page = match self.read_page(pid)? {
+                Some(page) => page,
+                None => {
+                    self.mark_damaged(pid);
+                    continue;
+                }
+            };
+            if page.data[0..2] == [0, 0] {
+                // an empty btree
+                continue;
+            }
+            if page_type_of(&page) != Some(PageType::BTreeNode) {
+                self.mark_damaged(pid);
+                continue;
+            }
+
+            let len = page.get_u16(2) as u32;
+            if len > self.item_size {
+                self.mark_damaged(pid);
+                continue;
+            }
+
+            stack.push(page.get_u32(4));
+            for index in 0..len {
+                let offset = HEADER_SIZE + index * ITEM_SIZE;
+                stack.push(page.get_u32(offset));
+
+                let ticket = BTreeNode::parse_data_item_ticket(&page, offset);
+                match self.read_ticket(&ticket)? {
+                    Some(doc) => result.push(doc),
+                    None => self.lost_count += 1,
+                }
+            }
+        }
+
+        Ok(result)
+    }
+
+    fn read_ticket(&mut self, ticket: &DataTicket) -> DbResult<Option<Document>> {
+        if ticket.is_large_data() {
+            return match self.read_large_data(ticket.pid)? {
+                Some(bytes) => Ok(self.decode_document(&bytes)),
+                None => Ok(None),
+            };
+        }
+
+        if !self.tickets.insert((ticket.pid, ticket.index)) {
+            return Ok(None);
+        }
+
+        let page = match self.read_page(ticket.pid)? {
+            Some(page) if page_type_of(&page) == Some(PageType::Data) => page,
+            _ => {
+                self.mark_damaged(ticket.pid);
+                return Ok(None);
+            }
+        };
+
+        let wrapper = DataPageWrapper::from_raw(page);
+        match wrapper.checked_get(ticket.index as u32) {
+            Ok(Some(bytes)) => Ok(self.decode_document(bytes)),
+            Ok(None) => Ok(None),
+            Err(_) => {
+                self.mark_damaged(ticket.pid);
+                Ok(None)
+            }
+        }
+    }
+
+    fn read_large_data(&mut self, pid: u32) -> DbResult<Option<Vec<u8>>> {
+        let mut bytes = vec![];
+        let mut next_pid = pid;
+
+        while next_pid != 0 {
+            if !self.large_pages.insert(next_pid) {
+                return Ok(None);
+            }
+
+            let page = match self.read_page(next_pid)? {
+                Some(page) if page_type_of(&page) == Some(PageType::LargeData) => page,
+                _ => {
+                    self.mark_damaged(next_pid);
+                    return Ok(None);
+                }
+            };
+
+            let wrapper = LargeDataPageWrapper::from_raw(page);
+            if wrapper.data_len() > wrapper.max_data_cap() {
+                self.mark_damaged(next_pid);
+                return Ok(None);
+            }
+
+            wrapper.write_to_buffer(&mut bytes);
+            next_pid = wrapper.next_pid();
+        }
+
+        Ok(Some(bytes))
+    }
+
+    // the data may be garbage, the decoder should not take the process down
+    fn decode_document(&self, bytes: &[u8]) -> Option<Document> {
+        let compression = self.compression;
+        let result = panic::catch_unwind(|| {
+            let bytes = crate::compression::decompress(compression, bytes)?;
+            let mut reader: &[u8] = bytes.as_ref();
+            crate::doc_serializer::deserialize(SerializeType::Default, &mut reader)
+        });
+        match result {
+            Ok(Ok(doc)) if doc.pkey_id().is_some() => Some(doc),
+            _ => None,
+        }
+    }
+
+    // the btree nodes not reached from the meta btree, whose items are
+    // index entries, their items are taken as referenced
+    fn skip_lost_index_nodes(&mut self) -> DbResult<()> {
+        for page_id in 1..self.page_count {
+            if self.free_pages.contains(&page_id) || self.btree_pages.contains(&page_id) {
+                continue;
+            }
+            let page = match self.read_page(page_id)? {
+                Some(page) if page_type_of(&page) == Some(PageType::BTreeNode) => page,
+                _ => continue,
+            };
+
+            let len = page.get_u16(2) as u32;
+            if len == 0 || len > self.item_size {
+                continue;
+            }
+
+            let first_ticket = BTreeNode::parse_data_item_ticket(&page, HEADER_SIZE);
+            if !self.peek_document(&first_ticket)?.is_some_and(|doc| is_index_entry(&doc)) {
+                continue;
+            }
+
+            self.btree_pages.insert(page_id);
+            for index in 0..len {
+                let ticket = BTreeNode::parse_data_item_ticket(&page, HEADER_SIZE + index * ITEM_SIZE);
+                self.read_ticket(&ticket)?;
+            }
+        }
+
+        Ok(())
+    }
+
+    // read the document without taking the item as referenced,
+    // the index entries are never large
+    fn peek_document(&mut self, ticket: &DataTicket) -> DbResult<Option<Document>> {
+        if ticket.is_large_data() {
+            return Ok(None);
+        }
+        let page = match self.read_page(ticket.pid)? {
+            Some(page) if page_type_of(&page) == Some(PageType::Data) => page,
+            _ => return Ok(None),
+        };
+        let wrapper = DataPageWrapper::from_raw(page);
+        match wrapper.checked_get(ticket.index as u32) {
+            Ok(Some(bytes)) => Ok(self.decode_document(bytes)),
+            _ => Ok(None),
+        }
+    }
+
+    // the items in the data pages and the large data chains
+    // which are not referenced by the btrees
+    fn scan_orphans(&mut self) -> DbResult<Vec<Document>> {
+        let mut result = vec![];
+
+        self.skip_lost_index_nodes()?;
+
+        // large data page id => next page id
+        let mut large_data_links: HashMap<u32, u32> = HashMap::new();
+
+        for page_id in 1..self.page_count {
+            if self.free_pages.contains(&page_id) {
+                continue;
+            }
+            let page = match self.read_page(page_id)? {
+                Some(page) => page,
+                None => continue,
+            };
+
+            match page_type_of(&page) {
+                Some(PageType::Data) => {
+                    let wrapper = DataPageWrapper::from_raw(page);
+                    for index in 0..wrapper.bar_len() {
+                        if self.tickets.contains(&(page_id, index as u16)) {
+                            continue;
+                        }
+                        match wrapper.checked_get(index) {
+                            Ok(Some(bytes)) => match self.decode_document(bytes) {
+                                Some(doc) => result.push(doc),
+                                None => self.lost_count += 1,
+                            },
+                            Ok(None) => (),
+                            Err(_) => {
+                                self.mark_damaged(page_id);
+                                break;
+                            }
+                        }
+                    }
+                }
+
+                Some(PageType::LargeData) if !self.large_pages.contains(&page_id) => {
+                    let wrapper = LargeDataPageWrapper::from_raw(page);
+                    large_data_links.insert(page_id, wrapper.next_pid());
+                }
+
+                _ => (),
+            }
+        }
+
+        // the chains start from the pages which are not the next of others
+        let next_pages: HashSet<u32> = large_data_links.values().cloned().collect();
+        let mut heads: Vec<u32> = large_data_links.keys()
+            .filter(|pid| !next_pages.contains(*pid))
+            .cloned()
+            .collect();
+        heads.sort_unstable();
+
+        for head in heads {
+            match self.read_large_data(head)?.and_then(|bytes| self.decode_document(&bytes)) {
+                Some(doc) => result.push(doc),
+                None => self.lost_count += 1,
+            }
+        }
+
+        Ok(result)
+    }
+
+}
+
+fn page_type_of(page: &RawPage) -> Option<PageType> {
+    PageType::from_magic([page.data[0], page.data[1]]).ok()
+}
+
+fn mk_journal_path(db_path: &Path) -> PathBuf {
+    let mut buf = db_path.to_path_buf();
+    let filename = buf.file_name().unwrap().to_str().unwrap();
+    let new_filename = String::from(filename) + ".journal";
+    buf.set_file_name(new_filename);
+    buf
+}
+
+// the entry of an index is { _id: the key, keys: [the primary keys] }
+fn is_index_entry(doc: &Document) -> bool {
+    doc.len() == 2 && matches!(doc.get("keys"), Some(Value::Array(_)))
+}
+
+pub(crate) fn salvage(src: &Path, dst: &Path) -> DbResult<SalvageReport> {
+    if dst.exists() {
+        let err = io::Error::new(io::ErrorKind::AlreadyExists, format!("{} already exists", dst.display()));
+        return Err(DbErr::from(err));
+    }
+
+    let mut scanner = PageScanner::open(src)?;
+    let (collections, orphans) = scanner.scan()?;
+
+    let mut report = SalvageReport {
+        page_count: scanner.page_count,
+        lost_documents: scanner.lost_count,
+        ..SalvageReport::default()
+    };
+    report.damaged_pages = scanner.damaged_pages.iter().cloned().collect();
+    report.damaged_pages.sort_unstable();
+    report.journal_ignored = match std::fs::metadata(mk_journal_path(src)) {
+        Ok(meta) => meta.len() > 0,
+        Err(_) => false,
+    };
+
+    let config = Config {
+        page_size: scanner.page_size.get(),
+        compression: scanner.compression,
+        ..Config::default()
+    };
+    let mut db = Database::open_file_with_config(dst, config)?;
+    db.start_transaction(Some(TransactionType::Write))?;
+
+    for SalvagedCollection { name, docs } in collections {
+        let mut collection = db.collection(&name)?;
+        let mut count: u64 = 0;
+        for mut doc in docs {
+            match collection.insert(&mut doc) {
+                Ok(_) => count += 1,
+                Err(DbErr::DataExist(_)) => report.duplicate_documents += 1,
+                Err(_) => report.lost_documents += 1,
+            }
+        }
+        report.collections.push((name, count));
+    }
+
+    // the collection of the orphans is unknown, the documents with
+    // the same _id may be in different collections
+    if !orphans.is_empty() {
+        let mut collection = db.collection(LOST_AND_FOUND)?;
+        for mut doc in orphans {
+            match collection.insert(&mut doc) {
+                Ok(_) => report.orphan_documents += 1,
+                Err(DbErr::DataExist(_)) => report.duplicate_documents += 1,
+                Err(_) => report.lost_documents += 1,
+            }
+        }
+    }
+
+    db.commit()?;
+
+    Ok(report)
+}
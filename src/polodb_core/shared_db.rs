@@ -0,0 +1,385 @@
+use std::convert::TryFrom;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::sync::{Arc, Mutex, MutexGuard, PoisonError};
+use byteorder::{BigEndian, ReadBytesExt};
+use crate::db::{Database, Transaction, ReadTransaction, DbResult, CheckpointMode};
+use crate::msg_ty::MsgTy;
+use crate::{Config, TransactionType};
+
+struct SharedState {
+    writer:    Mutex<Database>,
+
+    // opens the reader connections, None for the memory database,
+    // whose reads go through the writer
+    connector: Option<Mutex<Database>>,
+
+    // the idle reader connections
+    readers:   Mutex<Vec<Database>>,
+}
+
+///
+/// A database handle which can be cloned and shared between threads.
+///
+/// [read]: #method.read
+/// [write]: #method.write
+///
+/// Every [read] runs on a connection of its own, in a read transaction
+/// which sees the data committed before it started, so the reads run
+/// in parallel with each other and with the writes. The [write]s are
+/// serialized internally.
+///
+/// The documents returned by the closures are not shared between
+/// the threads, they are created by the thread calling the closure.
+///
+/// # Example
+///
+/// ```rust
+/// use polodb_core::SharedDatabase;
+/// use polodb_bson::doc;
+///
+/// let db = SharedDatabase::open_file("/tmp/test-shared-polo.db").unwrap();
+/// db.write(|tx| {
+///     tx.collection("test")?.insert(doc! { "name": "PoloDB" }.as_mut())?;
+///     Ok(())
+/// }).unwrap();
+///
+/// let other = db.clone();
+/// let count = std::thread::spawn(move || {
+///     other.read(|tx| tx.collection("test")?.count()).unwrap()
+/// }).join().unwrap();
+/// assert!(count > 0);
+/// ```
+#[derive(Clone)]
+pub struct SharedDatabase {
+    state: Arc<SharedState>,
+}
+
+// The closures run in transactions which are rolled back if they panic,
+// so the connection is still usable after a poisoned lock.
+fn recover_lock<'a, T>(result: Result<MutexGuard<'a, T>, PoisonError<MutexGuard<'a, T>>>) -> MutexGuard<'a, T> {
+    result.unwrap_or_else(PoisonError::into_inner)
+}
+
+impl SharedDatabase {
+
+    pub fn open_file<P: AsRef<Path>>(path: P) -> DbResult<SharedDatabase> {
+        SharedDatabase::open_file_with_config(path, Config::default())
+    }
+
+    pub fn open_file_with_config<P: AsRef<Path>>(path: P, config: Config) -> DbResult<SharedDatabase> {
+        let connector = Database::open_file_with_config(path, config)?;
+        let writer = connector.new_connection()?;
+        let state = SharedState {
+            writer: Mutex::new(writer),
+            connector: Some(Mutex::new(connector)),
+            readers: Mutex::new(Vec::new()),
+        };
+        Ok(SharedDatabase {
+            state: Arc::new(state),
+        })
+    }
+
+    pub fn open_memory() -> DbResult<SharedDatabase> {
+        SharedDatabase::open_memory_with_config(Config::default())
+    }
+
+    pub fn open_memory_with_config(config: Config) -> DbResult<SharedDatabase> {
+        let writer = Database::open_memory_wht_config(config)?;
+        let state = SharedState {
+            writer: Mutex::new(writer),
+            connector: None,
+            readers: Mutex::new(Vec::new()),
+        };
+        Ok(SharedDatabase {
+            state: Arc::new(state),
+        })
+    }
+
+    /// Run the closure in a read transaction.
+    ///
+    /// The collections can only be queried, a collection which doesn't
+    /// exist is not created, [DbErr::CollectionNotFound] is returned.
+    ///
+    /// [DbErr::CollectionNotFound]: crate::DbErr::CollectionNotFound
+    pub fn read<T, F>(&self, f: F) -> DbResult<T>
+    where
+        F: FnOnce(&mut ReadTransaction) -> DbResult<T>
+    {
+        self.with_reader(|reader| reader.read_transaction(f))?
+    }
+
+    // run on an idle reader connection,
+    // or on the writer of the memory database
+    fn with_reader<T, F>(&self, f: F) -> DbResult<T>
+    where
+        F: FnOnce(&mut Database) -> T
+    {
+        let connector = match &self.state.connector {
+            Some(connector) => connector,
+            None => {
+                let mut writer = recover_lock(self.state.writer.lock());
+                return Ok(f(&mut writer));
+            }
+        };
+
+        let idle_reader = recover_lock(self.state.readers.lock()).pop();
+        let mut reader = match idle_reader {
+            Some(reader) => reader,
+            None => recover_lock(connector.lock()).new_connection()?,
+        };
+
+        let result = f(&mut reader);
+
+        recover_lock(self.state.readers.lock()).push(reader);
+
+        Ok(result)
+    }
+
+    /// Run the closure in a write transaction,
+    /// after the writes of the other threads are finished.
+    pub fn write<T, F>(&self, f: F) -> DbResult<T>
+    where
+        F: FnOnce(&mut Transaction) -> DbResult<T>
+    {
+        let mut writer = recover_lock(self.state.writer.lock());
+        writer.transaction(TransactionType::Write, f)
+    }
+
+    /// See [Database::checkpoint], it waits for the other writes.
+    pub fn checkpoint(&self, mode: CheckpointMode) -> DbResult<bool> {
+        let mut writer = recover_lock(self.state.writer.lock());
+        writer.checkpoint(mode)
+    }
+
+    /// Start a session of a client of the server.
+    #[inline]
+    pub fn session(&self) -> Session<'_> {
+        Session {
+            db: self,
+            writer: None,
+        }
+    }
+
+}
+
+///
+/// The requests of a client of the server, see [Database::handle_request].
+///
+/// The queries run on the reader connections in parallel, the other
+/// requests are serialized on the writer. The writer is held by the session
+/// from the transaction started by the client until it's finished,
+/// and the transaction is rolled back if the session is dropped.
+///
+pub struct Session<'a> {
+    db:     &'a SharedDatabase,
+    writer: Option<MutexGuard<'a, Database>>,
+}
+
+impl<'a> Session<'a> {
+
+    pub fn handle_request<R: Read, W: Write>(&mut self, pipe_in: &mut R, pipe_out: &mut W) -> std::io::Result<MsgTy> {
+        // the type is read again by the database
+        let msg_ty_int = pipe_in.read_i32::<BigEndian>()?;
+        let head = msg_ty_int.to_be_bytes();
+        let mut pipe_in = head.as_ref().chain(pipe_in);
+
+        if let Some(writer) = &mut self.writer {
+            let result = writer.handle_request(&mut pipe_in, pipe_out);
+            if !writer.is_in_transaction() {
+                self.writer = None;
+            }
+            return result;
+        }
+
+        if let Ok(MsgTy::Find) | Ok(MsgTy::FindOne) | Ok(MsgTy::Count) = MsgTy::try_from(msg_ty_int) {
+            if let Ok(result) = self.db.with_reader(|reader| reader.handle_request(&mut pipe_in, pipe_out)) {
+                return result;
+            }
+            // no connection can be opened, run on the writer
+        }
+
+        let mut writer = recover_lock(self.db.state.writer.lock());
+        let result = writer.handle_request(&mut pipe_in, pipe_out);
+        if writer.is_in_transaction() {
+            self.writer = Some(writer);
+        }
+        result
+    }
+
+}
+
+impl<'a> Drop for Session<'a> {
+
+    fn drop(&mut self) {
+        if let Some(writer) = &mut self.writer {
+            let _ = writer.rollback();
+        }
+    }
+
+}
+
+#[cfg(test)]
+mod tests {
+    use std::env;
+    use std::thread;
+    use byteorder::{BigEndian, ReadBytesExt, WriteBytesExt};
+    use polodb_bson::{doc, Value};
+    use crate::{SharedDatabase, Session, DbErr};
+    use crate::msg_ty::MsgTy;
+
+    fn prepare_db(db_name: &str) -> SharedDatabase {
+        let mut db_path = env::temp_dir();
+        db_path.push(String::from(db_name) + ".db");
+        let mut journal_path = env::temp_dir();
+        journal_path.push(String::from(db_name) + ".db.journal");
+
+        let _ = std::fs::remove_file(db_path.as_path());
+        let _ = std::fs::remove_file(journal_path.as_path());
+
+        SharedDatabase::open_file(db_path.as_path()).unwrap()
+    }
+
+    #[test]
+    fn test_shared_db_is_send_and_sync() {
+        fn assert_send_sync<T: Send + Sync>() {}
+        assert_send_sync::<SharedDatabase>();
+    }
+
+    #[test]
+    fn test_write_in_threads() {
+        const THREAD_COUNT: i64 = 4;
+        const ITEM_COUNT: i64 = 50;
+
+        let db = prepare_db("test-shared-write-in-threads");
+        let threads: Vec<_> = (0..THREAD_COUNT).map(|thread_id| {
+            let db = db.clone();
+            thread::spawn(move || {
+                for i in 0..ITEM_COUNT {
+                    db.write(|tx| {
+                        tx.collection("test")?.insert(doc! {
+                            "_id": thread_id * ITEM_COUNT + i,
+                        }.as_mut())?;
+                        Ok(())
+                    }).unwrap();
+
+                    let count = db.read(|tx| tx.collection("test")?.count()).unwrap();
+                    assert!(count as i64 > i);
+                }
+            })
+        }).collect();
+
+        for t in threads {
+            t.join().unwrap();
+        }
+
+        let count = db.read(|tx| tx.collection("test")?.count()).unwrap();
+        assert_eq!(count as i64, THREAD_COUNT * ITEM_COUNT);
+    }
+
+    #[test]
+    fn test_shared_memory_db() {
+        let db = SharedDatabase::open_memory().unwrap();
+        let result: Result<(), DbErr> = db.write(|tx| {
+            tx.collection("test")?.insert(doc! { "_id": 1 }.as_mut())?;
+            Err(DbErr::Busy)
+        });
+        assert!(result.is_err());
+
+        let other = db.clone();
+        thread::spawn(move || {
+            other.write(|tx| {
+                tx.collection("test")?.insert(doc! { "_id": 2 }.as_mut())?;
+                Ok(())
+            }).unwrap();
+        }).join().unwrap();
+
+        let count = db.read(|tx| tx.collection("test")?.count()).unwrap();
+        assert_eq!(count, 1);
+    }
+
+    #[test]
+    fn test_read_does_not_create_collection() {
+        let db = prepare_db("test-shared-read-not-create");
+        let result = db.read(|tx| tx.collection("test")?.count());
+        assert!(matches!(result, Err(DbErr::CollectionNotFound(_))));
+
+        let result = db.read(|tx| tx.collection("test")?.count());
+        assert!(matches!(result, Err(DbErr::CollectionNotFound(_))));
+
+        db.write(|tx| {
+            tx.collection("test")?.insert(doc! { "_id": 1 }.as_mut())?;
+            Ok(())
+        }).unwrap();
+        let count = db.read(|tx| tx.collection("test")?.count()).unwrap();
+        assert_eq!(count, 1);
+    }
+
+    fn send_request(session: &mut Session, msg_ty: MsgTy, body: Option<Value>) -> Value {
+        let mut body_bytes = vec![];
+        if let Some(body) = body {
+            body.to_msgpack(&mut body_bytes).unwrap();
+        }
+        let mut request = vec![];
+        request.write_i32::<BigEndian>(msg_ty as i32).unwrap();
+        request.write_u32::<BigEndian>(body_bytes.len() as u32).unwrap();
+        request.extend_from_slice(&body_bytes);
+
+        let mut response = vec![];
+        session.handle_request(&mut request.as_slice(), &mut response).unwrap();
+
+        let mut response = response.as_slice();
+        assert_eq!(response.read_i32::<BigEndian>().unwrap(), msg_ty as i32);
+        let len = response.read_u32::<BigEndian>().unwrap();
+        if len == 0 {
+            return Value::Null;
+        }
+        Value::from_msgpack(&mut response).unwrap()
+    }
+
+    #[test]
+    fn test_sessions() {
+        let db = prepare_db("test-shared-sessions");
+        db.write(|tx| {
+            tx.create_collection("test")?;
+            Ok(())
+        }).unwrap();
+
+        let count = |session: &mut Session| {
+            send_request(session, MsgTy::Count, Some(Value::from(doc! { "cl": "test" }))).unwrap_int()
+        };
+
+        let mut writer = db.session();
+        send_request(&mut writer, MsgTy::StartTransaction, Some(Value::Int(2)));
+        send_request(&mut writer, MsgTy::Insert, Some(Value::from(doc! {
+            "cl": "test",
+            "data": doc! { "_id": 1 },
+        })));
+
+        // the queries are not blocked by the transaction of the other session
+        let other = db.clone();
+        let other_count = thread::spawn(move || {
+            let mut reader = other.session();
+            send_request(&mut reader, MsgTy::Count, Some(Value::from(doc! { "cl": "test" }))).unwrap_int()
+        }).join().unwrap();
+        assert_eq!(other_count, 0);
+
+        send_request(&mut writer, MsgTy::Commit, None);
+
+        let mut reader = db.session();
+        assert_eq!(count(&mut reader), 1);
+
+        // the transaction is rolled back when the session is dropped
+        send_request(&mut writer, MsgTy::StartTransaction, Some(Value::Int(2)));
+        send_request(&mut writer, MsgTy::Insert, Some(Value::from(doc! {
+            "cl": "test",
+            "data": doc! { "_id": 2 },
+        })));
+        drop(writer);
+
+        assert_eq!(count(&mut reader), 1);
+        let written_count = db.write(|tx| tx.collection("test")?.count()).unwrap();
+        assert_eq!(written_count, 1);
+    }
+
+}
@@ -0,0 +1,258 @@
+use std::fmt;
+use hashbrown::HashSet;
+use polodb_bson::{Document, Value};
+use crate::{DbResult, DbErr, SerializeType};
+use crate::meta_doc_helper::meta_doc_key;
+use crate::integrity::IntegrityChecker;
+use crate::page::data_page_wrapper::DataPageWrapper;
+use crate::page_handler::PageHandler;
+
+/// The count of the pages used by each kind of structure.
+#[derive(Debug, Clone, Copy, Default, Eq, PartialEq)]
+pub struct PageCounts {
+    pub btree:      u32,
+    pub data:       u32,
+    pub large_data: u32,
+    pub free_list:  u32,
+}
+
+impl PageCounts {
+
+    #[inline]
+    pub fn total(&self) -> u32 {
+        self.btree + self.data + self.large_data + self.free_list
+    }
+
+}
+
+impl fmt::Display for PageCounts {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(f, "btree: {}, data: {}, large data: {}, free list: {}",
+               self.btree, self.data, self.large_data, self.free_list)
+    }
+
+}
+
+/// The statistics of an index of a collection.
+#[derive(Debug, Clone, Default)]
+pub struct IndexStats {
+    pub name:        String,
+    pub entry_count: u64,
+    pub btree_depth: u32,
+    pub btree_pages: u32,
+
+    /// The size of the pages of the B-tree in bytes.
+    pub size:        u64,
+}
+
+/// The result of [Collection::stats](crate::Collection::stats).
+#[derive(Debug, Clone, Default)]
+pub struct CollectionStats {
+    pub name:                 String,
+    pub document_count:       u64,
+
+    /// The size of the stored documents in bytes,
+    /// after they are serialized and compressed.
+    pub data_size:            u64,
+    pub avg_document_size:    u64,
+
+    /// The count of the levels of the B-tree, an empty collection has one.
+    pub btree_depth:          u32,
+    pub pages:                PageCounts,
+    pub indexes:              Vec<IndexStats>,
+
+    /// The used space of the data pages holding the documents and the index entries,
+    /// the other collections may also store data in them.
+    pub data_page_fill_ratio: f64,
+}
+
+impl fmt::Display for CollectionStats {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "collection: {}", self.name)?;
+        writeln!(f, "  documents: {}", self.document_count)?;
+        writeln!(f, "  data size: {} bytes, average: {} bytes", self.data_size, self.avg_document_size)?;
+        writeln!(f, "  btree depth: {}", self.btree_depth)?;
+        writeln!(f, "  pages: {}", self.pages)?;
+        write!(f, "  data page fill ratio: {:.2}", self.data_page_fill_ratio)?;
+        for index in &self.indexes {
+            write!(f, "\n  index {}: entries: {}, btree depth: {}, pages: {}, size: {} bytes",
+                   index.name, index.entry_count, index.btree_depth, index.btree_pages, index.size)?;
+        }
+        Ok(())
+    }
+
+}
+
+/// The result of [Database::stats](crate::Database::stats).
+#[derive(Debug, Clone, Default)]
+pub struct DatabaseStats {
+    /// The size of the database file, the journal is not included.
+    pub file_size:            u64,
+    pub page_size:            u32,
+
+    /// The count of the allocated pages, including the header page.
+    pub page_count:           u32,
+
+    /// The pages used by the database, the header page is not included.
+    pub pages:                PageCounts,
+    pub free_page_count:      u32,
+    pub document_count:       u64,
+    pub avg_document_size:    u64,
+    pub data_page_fill_ratio: f64,
+    pub collections:          Vec<CollectionStats>,
+}
+
+impl fmt::Display for DatabaseStats {
+
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        writeln!(f, "file size: {} bytes", self.file_size)?;
+        writeln!(f, "page size: {}", self.page_size)?;
+        writeln!(f, "pages: {} ({})", self.page_count, self.pages)?;
+        writeln!(f, "free pages: {}", self.free_page_count)?;
+        writeln!(f, "documents: {}, average size: {} bytes", self.document_count, self.avg_document_size)?;
+        write!(f, "data page fill ratio: {:.2}", self.data_page_fill_ratio)?;
+        for collection in &self.collections {
+            write!(f, "\n{}", collection)?;
+        }
+        Ok(())
+    }
+
+}
+
+// Walk the structures from the header page with IntegrityChecker,
+// and sum up the pages. The issues found by the checker are ignored.
+pub(crate) struct StatsCollector<'a> {
+    checker: IntegrityChecker<'a>,
+}
+
+impl<'a> StatsCollector<'a> {
+
+    pub(crate) fn new(page_handler: &'a mut PageHandler, serialize_type: SerializeType) -> StatsCollector<'a> {
+        StatsCollector {
+            checker: IntegrityChecker::new(page_handler, serialize_type),
+        }
+    }
+
+    pub(crate) fn database_stats(mut self) -> DbResult<DatabaseStats> {
+        let header = self.checker.start()?;
+
+        let page_handler = self.checker.page_handler();
+        let mut stats = DatabaseStats {
+            file_size: page_handler.codec().file_size_of(page_handler.db_size()),
+            page_size: page_handler.page_size.get(),
+            page_count: header.get_null_page_bar(),
+            ..DatabaseStats::default()
+        };
+
+        let mut meta_docs = vec![];
+        let meta_tree = self.checker.measure_btree(header.get_meta_page_id(), &mut |doc| meta_docs.push(doc))?;
+        let mut data_pages = meta_tree.data_pages;
+
+        let mut data_size: u64 = 0;
+        for meta_doc in &meta_docs {
+            let (collection, collection_data_pages) = self.measure_collection(meta_doc)?;
+
+            stats.document_count += collection.document_count;
+            data_size += collection.data_size;
+            data_pages.extend(collection_data_pages);
+
+            stats.collections.push(collection);
+        }
+
+        stats.avg_document_size = average(data_size, stats.document_count);
+        stats.pages = self.checker.page_counts();
+        stats.free_page_count = self.checker.free_page_count();
+
+        let data_pages: Vec<u32> = data_pages.into_iter().collect();
+        stats.data_page_fill_ratio = self.fill_ratio(&data_pages)?;
+
+        Ok(stats)
+    }
+
+    pub(crate) fn collection_stats(mut self, meta_doc: &Document) -> DbResult<CollectionStats> {
+        self.checker.start()?;
+        let (stats, _) = self.measure_collection(meta_doc)?;
+        Ok(stats)
+    }
+
+    // the data pages of the collection are returned with the stats
+    fn measure_collection(&mut self, meta_doc: &Document) -> DbResult<(CollectionStats, HashSet<u32>)> {
+        let mut stats = CollectionStats::default();
+        if let Some(Value::String(name)) = meta_doc.get(meta_doc_key::NAME) {
+            stats.name = name.to_string();
+        }
+
+        // the meta document may be damaged
+        let root_pid = match meta_doc.get(meta_doc_key::ROOT_PID) {
+            Some(Value::Int(root_pid)) => *root_pid as u32,
+            _ => {
+                let error_msg = format!("the root pid of collection {} is not found", stats.name);
+                return Err(DbErr::ParseError(error_msg));
+            }
+        };
+        let tree = self.checker.measure_btree(root_pid, &mut |_| ())?;
+        stats.btree_depth = tree.depth;
+        stats.pages = tree.pages;
+        stats.document_count = tree.item_count;
+        stats.data_size = tree.stored_size;
+        stats.avg_document_size = average(stats.data_size, stats.document_count);
+
+        // the index entries are stored in the data pages too
+        let mut data_pages = tree.data_pages;
+        let page_size = self.checker.page_handler().page_size.get() as u64;
+        if let Some(Value::Document(indexes)) = meta_doc.get(meta_doc_key::INDEXES) {
+            for (name, options) in indexes.iter() {
+                let root_pid = match options {
+                    Value::Document(options) => match options.get(meta_doc_key::index::ROOT_PID) {
+                        Some(Value::Int(root_pid)) => *root_pid as u32,
+                        _ => continue,
+                    },
+                    _ => continue,
+                };
+
+                let index_tree = self.checker.measure_btree(root_pid, &mut |_| ())?;
+                stats.pages.btree += index_tree.pages.btree;
+                stats.pages.large_data += index_tree.pages.large_data;
+                data_pages.extend(index_tree.data_pages);
+                stats.indexes.push(IndexStats {
+                    name: name.to_string(),
+                    entry_count: index_tree.item_count,
+                    btree_depth: index_tree.depth,
+                    btree_pages: index_tree.pages.btree,
+                    size: (index_tree.pages.btree as u64) * page_size,
+                });
+            }
+        }
+
+        stats.pages.data = data_pages.len() as u32;
+        let data_page_ids: Vec<u32> = data_pages.iter().copied().collect();
+        stats.data_page_fill_ratio = self.fill_ratio(&data_page_ids)?;
+
+        Ok((stats, data_pages))
+    }
+
+    fn fill_ratio(&mut self, data_pages: &[u32]) -> DbResult<f64> {
+        if data_pages.is_empty() {
+            return Ok(0.0);
+        }
+
+        let page_handler = self.checker.page_handler();
+        let page_size = page_handler.page_size.get() as u64;
+        let mut used_size: u64 = 0;
+        for pid in data_pages {
+            let page = page_handler.pipeline_read_page(*pid)?;
+            let wrapper = DataPageWrapper::from_raw(page);
+            used_size += page_size - wrapper.remain_size() as u64;
+        }
+
+        Ok(used_size as f64 / (data_pages.len() as u64 * page_size) as f64)
+    }
+
+}
+
+#[inline]
+fn average(size: u64, count: u64) -> u64 {
+    size.checked_div(count).unwrap_or(0)
+}
@@ -51,6 +51,9 @@ pub struct VM<'a> {
     stack:               Vec<Value>,
     pub(crate) program:  Box<SubProgram>,
     rollback_on_drop:    bool,
+
+    // added to the metrics when the vm is dropped
+    instructions:        u64,
 }
 
 fn generic_cmp(op: DbOp, val1: &Value, val2: &Value) -> DbResult<bool> {
@@ -83,6 +86,7 @@ impl<'a> VM<'a> {
             stack,
             program,
             rollback_on_drop: false,
+            instructions: 0,
         }
     }
 
@@ -379,6 +383,7 @@ impl<'a> VM<'a> {
         self.state = VmState::Running;
         unsafe {
             loop {
+                self.instructions += 1;
                 let op = self.pc.cast::<DbOp>().read();
                 match op {
                     DbOp::Goto => {
@@ -690,8 +695,9 @@ impl<'a> VM<'a> {
 impl<'a> Drop for VM<'a> {
 
     fn drop(&mut self) {
+        self.page_handler.add_vm_instructions(self.instructions);
         if self.rollback_on_drop {
-            let _result = self.page_handler.rollback();
+            let _result = self.page_handler.auto_rollback();
             #[cfg(debug_assertions)]
             if let Err(err) = _result {
                 panic!("rollback fatal: {}", err);